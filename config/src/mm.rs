@@ -24,10 +24,14 @@ pub const KERNEL_START: usize = VIRT_START + KERNEL_OFFSET;
 
 pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
 
+/// Size of the heap for the linked list allocator, which can not grow.
 #[cfg(not(feature = "vf2"))]
 pub const KERNEL_HEAP_SIZE: usize = 64 * 1024 * 1024;
 #[cfg(feature = "vf2")]
 pub const KERNEL_HEAP_SIZE: usize = 256 * 1024 * 1024;
+/// Heap space in the kernel image for the buddy allocator, which grows from
+/// the frame allocator once it is used up.
+pub const KERNEL_HEAP_INIT_SIZE: usize = 4 * 1024 * 1024;
 
 pub const HART_START_ADDR: usize = RAM_START + KERNEL_START;
 
@@ -45,6 +49,10 @@ pub const PTES_PER_PAGE: usize = PAGE_SIZE / PTE_SIZE;
 /// 3 level for sv39 page table
 pub const PAGE_TABLE_LEVEL_NUM: usize = 3;
 
+/// Pages in a huge page, which is mapped by a leaf pte at the middle level.
+pub const HUGE_PAGE_PAGES: usize = PTES_PER_PAGE;
+pub const HUGE_PAGE_SIZE: usize = PAGE_SIZE * HUGE_PAGE_PAGES;
+
 pub const MMAP_PRE_ALLOC_PAGES: usize = 8;
 
 /// Dynamic linked interpreter address range in user space
@@ -8,3 +8,6 @@ pub const INIT_PROC_PID: usize = 1;
 
 pub const USER_STACK_SIZE: usize = 8 * 1024 * 1024;
 pub const USER_STACK_PRE_ALLOC_SIZE: usize = 4 * PAGE_SIZE;
+/// Space kept free below a stack, so that it does not grow into the mapping
+/// below it, like `stack_guard_gap` of Linux.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE;
@@ -180,6 +180,22 @@ impl<U: Ord + Copy + Add<usize, Output = U>, V> RangeMap<U, V> {
         Ok(())
     }
 
+    /// Extend the segment starting at `start` from front to `new_start`.
+    ///
+    /// # Panic
+    ///
+    /// The segment pointed by `start` must exist.
+    pub fn extend_front(&mut self, start: U, new_start: U) -> Result<(), ()> {
+        if new_start >= start {
+            return Err(());
+        }
+        self.is_range_free(new_start..start)?;
+
+        let node = self.0.remove(&start).unwrap();
+        self.0.insert(new_start, node);
+        Ok(())
+    }
+
     /// Reduce a segment backwards. Return the range reduced when success, or
     /// error when fail.
     ///
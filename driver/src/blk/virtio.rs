@@ -1,11 +1,11 @@
-use alloc::{string::ToString, sync::Arc};
+use alloc::{string::ToString, sync::Arc, vec::Vec};
 use core::ptr::NonNull;
 
 use config::board::BLOCK_SIZE;
 use device_core::{BlockDevice, DevId, Device, DeviceMajor, DeviceMeta, DeviceType};
 use log::error;
 use memory::{PhysAddr, PhysPageNum, VirtAddr, alloc_frames, dealloc_frame};
-use page::BufferCache;
+use page::{BufferCache, IoScheduler};
 use sync::mutex::SpinNoIrqLock;
 use virtio_drivers::{BufferDirection, device::blk::VirtIOBlk, transport::mmio::MmioTransport};
 
@@ -17,6 +17,8 @@ pub struct VirtIoBlkDev {
     meta: DeviceMeta,
     device: SpinNoIrqLock<VirtIOBlk<VirtioHalImpl, MmioTransport>>,
     pub cache: SpinNoIrqLock<BufferCache>,
+    /// Request queue shared by all readers of blocks missing from `cache`.
+    sched: IoScheduler,
 }
 
 unsafe impl Send for VirtIoBlkDev {}
@@ -63,7 +65,22 @@ impl BlockDevice for VirtIoBlkDev {
     }
 
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.cache.lock().read_block(block_id, buf)
+        self.read_blocks(block_id, buf)
+    }
+
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        let missing = self.cache.lock().read_cached_blocks(block_id, buf);
+        // Submit all runs before waiting, so that they are sorted and merged
+        // together with the requests of other readers.
+        let ids: Vec<_> = missing
+            .iter()
+            .map(|blocks| self.sched.submit(blocks.clone()))
+            .collect();
+        for (blocks, id) in missing.into_iter().zip(ids) {
+            let buf_range =
+                (blocks.start - block_id) * BLOCK_SIZE..(blocks.end - block_id) * BLOCK_SIZE;
+            self.sched.wait(self, id, &mut buf[buf_range]);
+        }
     }
 
     fn write_block(&self, block_id: usize, buf: &[u8]) {
@@ -96,6 +113,7 @@ impl VirtIoBlkDev {
                     meta,
                     device,
                     cache: SpinNoIrqLock::new(BufferCache::new()),
+                    sched: IoScheduler::new(),
                 });
                 blk_dev.cache.lock().init_device(blk_dev.clone());
                 Some(blk_dev)
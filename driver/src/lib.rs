@@ -98,6 +98,16 @@ pub(crate) fn kernel_page_table_mut() -> &'static mut PageTable {
     call_interface!(KernelPageTableIf::kernel_page_table_mut())
 }
 
+#[crate_interface::def_interface]
+pub trait KernelHartIf: Send + Sync {
+    /// Id of the hart running the caller.
+    fn hart_id() -> usize;
+}
+
+pub(crate) fn hart_id() -> usize {
+    call_interface!(KernelHartIf::hart_id())
+}
+
 struct Stdout;
 
 impl Write for Stdout {
@@ -2,7 +2,7 @@
 //!
 //! Adapted from MankorOS
 
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
 use core::char;
 
 use arch::interrupts::{disable_interrupt, enable_external_interrupt};
@@ -10,7 +10,7 @@ use config::{
     board,
     mm::{K_SEG_DTB_BEG, VIRT_RAM_OFFSET},
 };
-use device_core::{DevId, Device, DeviceMajor, DeviceMeta, DeviceType};
+use device_core::{DevId, Device, DeviceMajor, DeviceMeta, DeviceType, NetDevice};
 use log::{info, warn};
 use memory::{PhysAddr, pte::PTEFlags};
 use net::init_network;
@@ -19,7 +19,11 @@ use crate::{
     blk::{probe_sdio_blk, probe_vf2_sd, probe_virtio_blk},
     cpu::{CPU, probe_cpu},
     kernel_page_table_mut,
-    net::{loopback::LoopbackDev, probe_virtio_net, virtio::VirtIoNetDevImpl},
+    net::{
+        loopback::LoopbackDev,
+        probe_virtio_net,
+        virtio::{VirtIoNetDevImpl, ack_mmio_interrupt},
+    },
     plic::{PLIC, probe_plic},
     println,
     serial::probe_char_device,
@@ -44,7 +48,9 @@ pub struct DeviceManager {
     /// module.
     pub devices: BTreeMap<DevId, Arc<dyn Device>>,
 
-    pub net: Option<DeviceMeta>,
+    /// Probed virtio-net devices, each of them becomes a network interface in
+    /// `init_net`.
+    pub net: Vec<DeviceMeta>,
 
     /// A BTreeMap that maps interrupt numbers (irq_no) to device instances
     /// (Arc<dyn Device>). This map is used to quickly locate the device
@@ -61,7 +67,7 @@ impl DeviceManager {
             plic: None,
             cpus: Vec::with_capacity(8),
             devices: BTreeMap::new(),
-            net: None,
+            net: Vec::new(),
             irq_map: BTreeMap::new(),
         }
     }
@@ -117,19 +123,23 @@ impl DeviceManager {
     }
 
     pub fn init_net(&self) {
-        if let Some(net_meta) = &self.net {
+        let mut net_devs: Vec<Box<dyn NetDevice>> = Vec::new();
+        for net_meta in self.net.iter() {
             let transport = probe_mmio_device(
                 PhysAddr::from(net_meta.mmio_base).to_vaddr().as_mut_ptr(),
                 net_meta.mmio_size,
                 Some(device_core::DeviceType::Net),
             )
             .unwrap();
-            let dev = VirtIoNetDevImpl::try_new(transport).unwrap();
-            init_network(dev, false);
-        } else {
-            log::info!("[init_net] can't find qemu virtio-net. use LoopbackDev to test");
-            init_network(LoopbackDev::new(), true);
+            match VirtIoNetDevImpl::try_new(transport, self.cpus.len()) {
+                Ok(dev) => net_devs.push(dev),
+                Err(e) => log::warn!("[init_net] failed to init {}: {e:?}", net_meta.name),
+            }
+        }
+        if net_devs.is_empty() {
+            log::info!("[init_net] can't find qemu virtio-net. only loopback is available");
         }
+        init_network(LoopbackDev::new(), net_devs);
     }
 
     pub fn map_devices(&self) {
@@ -142,7 +152,7 @@ impl DeviceManager {
                 PTEFlags::R | PTEFlags::W,
             );
         }
-        if let Some(net_meta) = &self.net {
+        for net_meta in self.net.iter() {
             kernel_page_table_mut().ioremap(
                 net_meta.mmio_base,
                 net_meta.mmio_size,
@@ -182,6 +192,12 @@ impl DeviceManager {
                     info!("Enable external interrupt:{irq}, context:{i}");
                 }
             }
+            for net_meta in self.net.iter() {
+                if let Some(irq) = net_meta.irq_no {
+                    self.plic().enable_irq(irq, i);
+                    info!("Enable external interrupt:{irq}, context:{i}");
+                }
+            }
         }
         unsafe { enable_external_interrupt() }
     }
@@ -203,6 +219,17 @@ impl DeviceManager {
                 self.plic().complete_irq(irq_number, self.irq_context());
                 return;
             }
+            if let Some(net_meta) = self.net.iter().find(|meta| meta.irq_no == Some(irq_number)) {
+                log::trace!(
+                    "Handling interrupt from {}, irq: {irq_number}",
+                    net_meta.name
+                );
+                ack_mmio_interrupt(PhysAddr::from(net_meta.mmio_base).to_vaddr().bits());
+                // Packets are handled by the softirq of the network stack.
+                ::net::softirq::raise();
+                self.plic().complete_irq(irq_number, self.irq_context());
+                return;
+            }
             warn!("Unknown interrupt: {}", irq_number);
         } else {
             warn!("No interrupt available");
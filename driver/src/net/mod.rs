@@ -1,5 +1,6 @@
 pub mod loopback;
 pub mod virtio;
+mod virtqueue;
 
 use alloc::{boxed::Box, string::ToString, sync::Arc, vec, vec::Vec};
 use core::ptr::NonNull;
@@ -14,13 +15,18 @@ use memory::{PhysAddr, pte::PTEFlags};
 use self::virtio::NetBufPtr;
 use crate::{Mutex, kernel_page_table_mut, virtio::probe_mmio_device};
 
-pub fn probe_virtio_net(root: &Fdt) -> Option<DeviceMeta> {
+pub fn probe_virtio_net(root: &Fdt) -> Vec<DeviceMeta> {
     let device_tree = root;
-    let mut net_meta = None;
+    let mut net_metas = Vec::new();
     for node in device_tree.find_all_nodes("/soc/virtio_mmio") {
-        for reg in node.reg()? {
+        let Some(regs) = node.reg() else {
+            continue;
+        };
+        for reg in regs {
             let mmio_base_paddr = PhysAddr::from(reg.starting_address as usize);
-            let mmio_size = reg.size?;
+            let Some(mmio_size) = reg.size else {
+                continue;
+            };
             kernel_page_table_mut().ioremap(
                 mmio_base_paddr.bits(),
                 mmio_size,
@@ -34,30 +40,25 @@ pub fn probe_virtio_net(root: &Fdt) -> Option<DeviceMeta> {
             .is_some()
             {
                 log::warn!("[probe_virtio_net] find a net device");
-                net_meta = {
-                    Some(DeviceMeta {
-                        mmio_base: mmio_base_paddr.bits(),
-                        mmio_size,
-                        name: "virtio-blk".to_string(),
-                        dtype: DeviceType::Net,
-                        dev_id: DevId {
-                            major: DeviceMajor::Net,
-                            minor: 0,
-                        },
-                        irq_no: None,
-                    })
-                }
+                net_metas.push(DeviceMeta {
+                    mmio_base: mmio_base_paddr.bits(),
+                    mmio_size,
+                    name: "virtio-net".to_string(),
+                    dtype: DeviceType::Net,
+                    dev_id: DevId {
+                        major: DeviceMajor::Net,
+                        minor: net_metas.len(),
+                    },
+                    irq_no: node.property("interrupts").and_then(|i| i.as_usize()),
+                });
             }
             kernel_page_table_mut().iounmap(mmio_base_paddr.to_vaddr().bits(), mmio_size);
-            if net_meta.is_some() {
-                break;
-            }
         }
     }
-    if net_meta.is_none() {
+    if net_metas.is_empty() {
         log::warn!("No virtio net device found");
     }
-    net_meta
+    net_metas
 }
 
 /// TODO：或许这个应该写在device-core中比较好？
@@ -1,114 +1,514 @@
-use alloc::{boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
 use core::{any::Any, ptr::NonNull};
 
+use bitflags::bitflags;
 use device_core::{
-    DevId, Device, DeviceCapabilities, DeviceMajor, DeviceMeta, DeviceType, EthernetAddress,
-    Medium, NetBufPtrOps, NetDevice,
+    Checksum, DeviceCapabilities, EthernetAddress, Medium, NetBufPtrOps, NetDevice,
     error::{DevError, DevResult},
 };
 use virtio_drivers::{
-    device::net::VirtIONetRaw,
-    transport::{Transport, mmio::MmioTransport},
+    BufferDirection, Hal, PAGE_SIZE,
+    transport::{DeviceStatus, Transport, mmio::MmioTransport},
 };
 
 use crate::{
-    net::{NET_BUF_LEN, NetBuf, NetBufBox, NetBufPool},
-    virtio::{VirtioHalImpl, as_dev_err},
+    hart_id,
+    net::{MAX_BUFFER_LEN, NET_BUF_LEN, NetBuf, NetBufBox, NetBufPool, virtqueue::VirtQueue},
+    virtio::VirtioHalImpl,
 };
 
 pub type VirtIoNetDevImpl = VirtIoNetDev<MmioTransport, 32>;
 
+bitflags! {
+    /// Feature bits of virtio-net, from section 5.1.3 of the VirtIO 1.2 spec.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Features: u64 {
+        /// The device completes partial checksums of sent packets.
+        const CSUM = 1 << 0;
+        /// The device may deliver packets with partial or validated checksums.
+        const GUEST_CSUM = 1 << 1;
+        const MAC = 1 << 5;
+        /// The device may deliver coalesced TCP packets larger than the MTU.
+        const GUEST_TSO4 = 1 << 7;
+        const GUEST_TSO6 = 1 << 8;
+        /// The device segments TCP packets larger than the MTU.
+        const HOST_TSO4 = 1 << 11;
+        const HOST_TSO6 = 1 << 12;
+        /// A received packet may span several buffers.
+        const MRG_RXBUF = 1 << 15;
+        const STATUS = 1 << 16;
+        const CTRL_VQ = 1 << 17;
+        const MQ = 1 << 22;
+        const ANY_LAYOUT = 1 << 27;
+        const VERSION_1 = 1 << 32;
+    }
+}
+
+const SUPPORTED_FEATURES: Features = Features::all();
+
+/// `struct virtio_net_hdr` flags.
+const HDR_F_NEEDS_CSUM: u8 = 1;
+const HDR_F_DATA_VALID: u8 = 2;
+/// `struct virtio_net_hdr` gso types.
+const HDR_GSO_NONE: u8 = 0;
+const HDR_GSO_TCPV4: u8 = 1;
+const HDR_GSO_TCPV6: u8 = 4;
+/// Length of `struct virtio_net_hdr`, without and with `num_buffers`.
+const LEGACY_HDR_LEN: usize = 10;
+const HDR_LEN: usize = 12;
+
+const CTRL_MQ: u8 = 4;
+const CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+const CTRL_OK: u8 = 0;
+
+/// Offsets in `struct virtio_net_config`.
+const CONFIG_MAC: usize = 0;
+const CONFIG_MAX_VIRTQUEUE_PAIRS: usize = 8;
+
+/// Buffers for packets larger than the MTU, gathered from several receive
+/// buffers.
+const LARGE_BUFS: usize = 4;
+
+/// Offsets of the interrupt registers of the MMIO transport.
+const MMIO_INTERRUPT_STATUS: usize = 0x60;
+const MMIO_INTERRUPT_ACK: usize = 0x64;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Largest frame on the wire, which the device segments larger TCP packets to.
+const ETHERNET_FRAME_LEN: usize = 1514;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// A receive queue and a transmit queue, with the buffers the device holds
+/// indexed by token.
+struct QueuePair<const QS: usize> {
+    rx: VirtQueue<QS>,
+    tx: VirtQueue<QS>,
+    rx_buffers: Vec<Option<NetBufBox>>,
+    tx_buffers: Vec<Option<NetBufBox>>,
+}
+
 /// The VirtIO network device driver.
 ///
-/// `QS` is the VirtIO queue size.
+/// `QS` is the VirtIO queue size. With multiqueue, there is a queue pair per
+/// hart, and packets are sent on the one of the current hart.
 pub struct VirtIoNetDev<T: Transport, const QS: usize> {
-    rx_buffers: [Option<NetBufBox>; QS],
-    tx_buffers: [Option<NetBufBox>; QS],
+    transport: T,
+    features: Features,
+    mac: [u8; 6],
+    /// Length of the header before each packet in the buffers.
+    hdr_len: usize,
+    queue_pairs: Vec<QueuePair<QS>>,
+    ctrl_queue: Option<VirtQueue<QS>>,
+    /// Receive queue polled first, so that no queue starves the others.
+    next_rx_queue: usize,
     free_tx_bufs: Vec<NetBufBox>,
     buf_pool: Arc<NetBufPool>,
-    inner: VirtIONetRaw<VirtioHalImpl, T, QS>,
+    large_buf_pool: Option<Arc<NetBufPool>>,
 }
 
 unsafe impl<T: Transport, const QS: usize> Send for VirtIoNetDev<T, QS> {}
 unsafe impl<T: Transport, const QS: usize> Sync for VirtIoNetDev<T, QS> {}
 
 impl<T: Transport, const QS: usize> VirtIoNetDev<T, QS> {
-    /// Creates a new driver instance and initializes the device, or returns
-    /// an error if any step fails.
-    pub fn try_new(transport: T) -> DevResult<Box<Self>> {
-        // 0. Create a new driver instance.
-        const NONE_BUF: Option<NetBufBox> = None;
-        let inner = VirtIONetRaw::new(transport).map_err(as_dev_err)?;
-        let rx_buffers = [NONE_BUF; QS];
-        let tx_buffers = [NONE_BUF; QS];
-        let buf_pool = NetBufPool::new(2 * QS, NET_BUF_LEN)?;
-        let free_tx_bufs = Vec::with_capacity(QS);
+    /// Creates a new driver instance with up to `max_queue_pairs` queue pairs
+    /// and initializes the device, or returns an error if any step fails.
+    pub fn try_new(mut transport: T, max_queue_pairs: usize) -> DevResult<Box<Self>> {
+        // 0. Negotiate features.
+        transport.set_status(DeviceStatus::empty());
+        transport.set_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+        let mut features =
+            Features::from_bits_truncate(transport.read_device_features()) & SUPPORTED_FEATURES;
+        if !features.contains(Features::CSUM) {
+            features -= Features::HOST_TSO4 | Features::HOST_TSO6;
+        }
+        if !features.contains(Features::GUEST_CSUM | Features::MRG_RXBUF) {
+            // Coalesced packets would need receive buffers of 64 KiB.
+            features -= Features::GUEST_TSO4 | Features::GUEST_TSO6;
+        }
+        if !features.contains(Features::CTRL_VQ) {
+            features -= Features::MQ;
+        }
+        transport.write_driver_features(features.bits());
+        transport.set_status(
+            DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::FEATURES_OK,
+        );
+        transport.set_guest_page_size(PAGE_SIZE as u32);
+        log::info!("[VirtIoNetDev::try_new] features {features:?}");
+
+        let mut mac = [0; 6];
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = transport
+                .read_config_space::<u8>(CONFIG_MAC + i)
+                .map_err(|_| DevError::BadState)?;
+        }
+        let max_virtqueue_pairs = if features.contains(Features::MQ) {
+            transport
+                .read_config_space::<u16>(CONFIG_MAX_VIRTQUEUE_PAIRS)
+                .map_err(|_| DevError::BadState)? as usize
+        } else {
+            1
+        };
+        let num_queue_pairs = max_virtqueue_pairs.clamp(1, max_queue_pairs.max(1));
+        let hdr_len = if features.intersects(Features::VERSION_1 | Features::MRG_RXBUF) {
+            HDR_LEN
+        } else {
+            LEGACY_HDR_LEN
+        };
+        let buf_pool = NetBufPool::new(num_queue_pairs * QS, NET_BUF_LEN)?;
+        let large_buf_pool = if features.contains(Features::MRG_RXBUF) {
+            Some(NetBufPool::new(LARGE_BUFS, MAX_BUFFER_LEN)?)
+        } else {
+            None
+        };
+        // Packets sent are only segmented by the device with TSO.
+        let tx_buf_pool = if features.intersects(Features::HOST_TSO4 | Features::HOST_TSO6) {
+            NetBufPool::new(QS, MAX_BUFFER_LEN)?
+        } else {
+            NetBufPool::new(QS, NET_BUF_LEN)?
+        };
 
         let mut dev = Self {
-            rx_buffers,
-            inner,
-            tx_buffers,
-            free_tx_bufs,
+            transport,
+            features,
+            mac,
+            hdr_len,
+            queue_pairs: Vec::with_capacity(num_queue_pairs),
+            ctrl_queue: None,
+            next_rx_queue: 0,
+            free_tx_bufs: Vec::with_capacity(QS),
             buf_pool,
+            large_buf_pool,
         };
 
-        // 1. Fill all rx buffers.
-        for (i, rx_buf_place) in dev.rx_buffers.iter_mut().enumerate() {
-            let mut rx_buf = dev.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
-            // Safe because the buffer lives as long as the queue.
-            let token = unsafe {
-                dev.inner
-                    .receive_begin(rx_buf.raw_buf_mut())
-                    .map_err(as_dev_err)?
-            };
-            assert_eq!(token, i as u16);
-            *rx_buf_place = Some(rx_buf);
+        // 1. Set up the queues. Receive queue `i` is queue `2 * i`, and the control
+        //    queue follows the last pair the device supports.
+        for i in 0..num_queue_pairs {
+            let rx = VirtQueue::new(&mut dev.transport, 2 * i as u16)?;
+            let tx = VirtQueue::new(&mut dev.transport, 2 * i as u16 + 1)?;
+            dev.queue_pairs.push(QueuePair {
+                rx,
+                tx,
+                rx_buffers: (0..QS).map(|_| None).collect(),
+                tx_buffers: (0..QS).map(|_| None).collect(),
+            });
+        }
+        if features.contains(Features::CTRL_VQ) {
+            let index = 2 * max_virtqueue_pairs as u16;
+            dev.ctrl_queue = Some(VirtQueue::new(&mut dev.transport, index)?);
         }
+        dev.transport.set_status(
+            DeviceStatus::ACKNOWLEDGE
+                | DeviceStatus::DRIVER
+                | DeviceStatus::FEATURES_OK
+                | DeviceStatus::DRIVER_OK,
+        );
 
-        // 2. Allocate all tx buffers.
+        // 2. Fill all rx buffers.
+        for i in 0..num_queue_pairs {
+            for _ in 0..QS {
+                let rx_buf = dev.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
+                dev.post_rx_buffer(i, rx_buf)?;
+            }
+        }
+
+        // 3. Allocate all tx buffers.
         for _ in 0..QS {
-            let mut tx_buf = dev.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
-            // Fill header
-            let hdr_len = dev
-                .inner
-                .fill_buffer_header(tx_buf.raw_buf_mut())
-                .or(Err(DevError::InvalidParam))?;
+            let mut tx_buf = tx_buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
             tx_buf.set_header_len(hdr_len);
             dev.free_tx_bufs.push(tx_buf);
         }
 
-        // 3. Return the driver instance.
+        // 4. Enable the other queue pairs, only the first one is used until then.
+        if num_queue_pairs > 1 {
+            dev.set_queue_pairs(num_queue_pairs as u16)?;
+        }
+
+        // 5. Return the driver instance.
         Ok(Box::new(dev))
     }
+
+    /// Give `rx_buf` to receive queue `queue`.
+    fn post_rx_buffer(&mut self, queue: usize, mut rx_buf: NetBufBox) -> DevResult {
+        let pair = &mut self.queue_pairs[queue];
+        // Safe because we keep `rx_buf` in `rx_buffers` until the device
+        // uses it.
+        let token = unsafe {
+            let raw_buf = rx_buf.raw_buf_mut();
+            let len = raw_buf.len();
+            pair.rx
+                .add(&[(share(raw_buf, BufferDirection::DeviceToDriver), len, true)])?
+        };
+        if pair.rx_buffers[token as usize].is_some() {
+            return Err(DevError::BadState);
+        }
+        pair.rx_buffers[token as usize] = Some(rx_buf);
+        pair.rx.notify(&mut self.transport);
+        Ok(())
+    }
+
+    /// Send a `VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET` command and wait for the
+    /// device to handle it.
+    fn set_queue_pairs(&mut self, num: u16) -> DevResult {
+        let ctrl_queue = self.ctrl_queue.as_mut().ok_or(DevError::Unsupported)?;
+        let [lo, hi] = num.to_le_bytes();
+        // Class, command and data, then the status the device writes.
+        let mut cmd = vec![CTRL_MQ, CTRL_MQ_VQ_PAIRS_SET, lo, hi, !CTRL_OK];
+        let (data, status) = cmd.split_at_mut(4);
+        // Safe because we wait for the device to use the buffers.
+        unsafe {
+            ctrl_queue.add(&[
+                (
+                    share(data, BufferDirection::DriverToDevice),
+                    data.len(),
+                    false,
+                ),
+                (
+                    share(status, BufferDirection::DeviceToDriver),
+                    status.len(),
+                    true,
+                ),
+            ])?;
+        }
+        ctrl_queue.notify(&mut self.transport);
+        while ctrl_queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+        if cmd[4] != CTRL_OK {
+            return Err(DevError::Io);
+        }
+        Ok(())
+    }
+
+    /// Pop a packet from receive queue `queue`. Returns `Ok(None)` if the queue
+    /// is empty, and `Err(DevError::Again)` if the packet was dropped.
+    fn receive_from(&mut self, queue: usize) -> DevResult<Option<NetBufBox>> {
+        let hdr_len = self.hdr_len;
+        let pair = &mut self.queue_pairs[queue];
+        let Some((token, len)) = pair.rx.pop_used() else {
+            return Ok(None);
+        };
+        log::warn!("[VirtioNetDev::receive] token {}", token);
+        let mut rx_buf = pair.rx_buffers[token as usize]
+            .take()
+            .ok_or(DevError::BadState)?;
+        if len < hdr_len {
+            self.post_rx_buffer(queue, rx_buf)?;
+            return Err(DevError::Again);
+        }
+        let hdr = VirtioNetHdr::read(rx_buf.raw_buf());
+        rx_buf.set_header_len(hdr_len);
+        rx_buf.set_packet_len(len - hdr_len);
+        let num_buffers = if self.features.contains(Features::MRG_RXBUF) {
+            hdr.num_buffers as usize
+        } else {
+            1
+        };
+        if num_buffers > 1 {
+            rx_buf = self.merge_rx_buffers(queue, rx_buf, num_buffers)?;
+        }
+        let valid = if hdr.flags & HDR_F_NEEDS_CSUM != 0 {
+            complete_checksum(
+                rx_buf.packet_mut(),
+                hdr.csum_start as usize,
+                hdr.csum_offset as usize,
+            )
+        } else {
+            // Checksums the device did not validate are left to smoltcp,
+            // unless it was told they are.
+            hdr.flags & HDR_F_DATA_VALID != 0
+                || !self.features.contains(Features::GUEST_CSUM)
+                || verify_checksum(rx_buf.packet())
+        };
+        if !valid {
+            self.recycle_rx_buf(rx_buf)?;
+            return Err(DevError::Again);
+        }
+        Ok(Some(rx_buf))
+    }
+
+    /// Gather a packet spanning `num_buffers` buffers of receive queue `queue`,
+    /// starting with `first`, into a large buffer. The device publishes all of
+    /// them at once.
+    fn merge_rx_buffers(
+        &mut self,
+        queue: usize,
+        first: NetBufBox,
+        num_buffers: usize,
+    ) -> DevResult<NetBufBox> {
+        let mut merged = self
+            .large_buf_pool
+            .as_ref()
+            .and_then(|pool| pool.alloc_boxed());
+        let mut len = 0;
+        let mut append = |data: &[u8]| {
+            if let Some(merged) = merged.as_mut() {
+                if len + data.len() > merged.capacity() {
+                    return false;
+                }
+                merged.raw_buf_mut()[len..len + data.len()].copy_from_slice(data);
+                len += data.len();
+            }
+            true
+        };
+        let mut fits = append(first.packet());
+        self.post_rx_buffer(queue, first)?;
+        for _ in 1..num_buffers {
+            let pair = &mut self.queue_pairs[queue];
+            let (token, buf_len) = pair.rx.pop_used().ok_or(DevError::BadState)?;
+            let mut rx_buf = pair.rx_buffers[token as usize]
+                .take()
+                .ok_or(DevError::BadState)?;
+            // Buffers after the first one have no header.
+            rx_buf.set_header_len(0);
+            rx_buf.set_packet_len(buf_len);
+            fits &= append(rx_buf.packet());
+            self.post_rx_buffer(queue, rx_buf)?;
+        }
+        match merged {
+            Some(mut merged) if fits => {
+                merged.set_packet_len(len);
+                Ok(merged)
+            }
+            _ => Err(DevError::Again),
+        }
+    }
+
+    /// Give a buffer returned by the stack back to the receive queue with the
+    /// most room. Large buffers go back to their pool, their content was
+    /// copied from receive buffers already given back.
+    fn recycle_rx_buf(&mut self, mut rx_buf: NetBufBox) -> DevResult {
+        if rx_buf.capacity() != self.buf_pool.buffer_len() {
+            return Ok(());
+        }
+        rx_buf.set_header_len(0);
+        rx_buf.set_packet_len(0);
+        let queue = (0..self.queue_pairs.len())
+            .max_by_key(|&i| self.queue_pairs[i].rx.num_free())
+            .ok_or(DevError::BadState)?;
+        self.post_rx_buffer(queue, rx_buf)
+    }
+
+    /// Queue pair of the current hart.
+    fn tx_queue(&self) -> usize {
+        hart_id() % self.queue_pairs.len()
+    }
+
+    /// Write the header of a packet to send, asking the device to complete the
+    /// checksum of TCP and UDP packets if it can, and to segment TCP packets
+    /// larger than the MTU. Returns false if the packet cannot be sent.
+    fn fill_tx_header(&self, tx_buf: &mut NetBuf) -> bool {
+        let mut hdr = VirtioNetHdr::default();
+        let packet = tx_buf.packet_mut();
+        let frame_len = packet.len();
+        if self.features.contains(Features::CSUM) {
+            if let Some(l4) = L4Info::parse(packet) {
+                let csum_offset = if l4.protocol == IPPROTO_TCP { 16 } else { 6 };
+                if l4.len >= csum_offset + 2 {
+                    // The device expects the checksum of the pseudo header in
+                    // the checksum field.
+                    let csum = fold(l4.pseudo_sum).to_be_bytes();
+                    let at = l4.offset + csum_offset;
+                    packet[at..at + 2].copy_from_slice(&csum);
+                    hdr.flags = HDR_F_NEEDS_CSUM;
+                    hdr.csum_start = l4.offset as u16;
+                    hdr.csum_offset = csum_offset as u16;
+                }
+                if frame_len > ETHERNET_FRAME_LEN && l4.protocol == IPPROTO_TCP && l4.len >= 20 {
+                    let (gso_type, tso) = if l4.is_ipv6 {
+                        (HDR_GSO_TCPV6, Features::HOST_TSO6)
+                    } else {
+                        (HDR_GSO_TCPV4, Features::HOST_TSO4)
+                    };
+                    if self.features.contains(tso) {
+                        // Headers copied in front of each segment, up to the
+                        // end of the TCP options.
+                        let hdr_len = l4.offset + (packet[l4.offset + 12] >> 4) as usize * 4;
+                        hdr.gso_type = gso_type;
+                        hdr.hdr_len = hdr_len as u16;
+                        hdr.gso_size = (ETHERNET_FRAME_LEN - hdr_len) as u16;
+                    }
+                }
+            }
+        }
+        hdr.write(&mut tx_buf.raw_buf_mut()[..self.hdr_len]);
+        frame_len <= ETHERNET_FRAME_LEN || hdr.gso_type != HDR_GSO_NONE
+    }
+
+    /// Largest frame the device takes, which is larger than the one on the
+    /// wire with TSO.
+    fn max_frame_len(&self) -> usize {
+        if self
+            .features
+            .intersects(Features::HOST_TSO4 | Features::HOST_TSO6)
+        {
+            MAX_BUFFER_LEN - self.hdr_len
+        } else {
+            ETHERNET_FRAME_LEN
+        }
+    }
+}
+
+/// Acknowledge the pending interrupts of the virtio-mmio device mapped at
+/// `mmio_vaddr`, without going through its driver, which the network stack
+/// owns.
+pub fn ack_mmio_interrupt(mmio_vaddr: usize) {
+    unsafe {
+        let status = ((mmio_vaddr + MMIO_INTERRUPT_STATUS) as *const u32).read_volatile();
+        ((mmio_vaddr + MMIO_INTERRUPT_ACK) as *mut u32).write_volatile(status);
+    }
+}
+
+impl<T: Transport, const QS: usize> Drop for VirtIoNetDev<T, QS> {
+    fn drop(&mut self) {
+        // Reset the device, so that it no longer accesses the queues.
+        self.transport.set_status(DeviceStatus::empty());
+    }
 }
 
 impl<T: Transport + 'static, const QS: usize> NetDevice for VirtIoNetDev<T, QS> {
     #[inline]
     fn capabilities(&self) -> DeviceCapabilities {
         let mut cap = DeviceCapabilities::default();
-        cap.max_transmission_unit = 1514;
+        cap.max_transmission_unit = self.max_frame_len();
         cap.max_burst_size = None;
         cap.medium = Medium::Ethernet;
+        // The device computes TCP and UDP checksums of sent packets, and those
+        // of received ones are checked in `receive`.
+        let checksum = match (
+            self.features.contains(Features::CSUM),
+            self.features.contains(Features::GUEST_CSUM),
+        ) {
+            (true, true) => Checksum::None,
+            (true, false) => Checksum::Rx,
+            (false, true) => Checksum::Tx,
+            (false, false) => Checksum::Both,
+        };
+        cap.checksum.tcp = checksum;
+        cap.checksum.udp = checksum;
         cap
     }
+
     #[inline]
     fn mac_address(&self) -> EthernetAddress {
-        EthernetAddress(self.inner.mac_address())
+        EthernetAddress(self.mac)
     }
 
     #[inline]
     fn can_transmit(&self) -> bool {
-        !self.free_tx_bufs.is_empty() && self.inner.can_send()
+        !self.free_tx_bufs.is_empty() && self.queue_pairs[self.tx_queue()].tx.num_free() > 0
     }
 
     #[inline]
     fn can_receive(&self) -> bool {
-        self.inner.poll_receive().is_some()
+        self.queue_pairs.iter().any(|pair| pair.rx.can_pop())
     }
 
     #[inline]
     fn rx_queue_size(&self) -> usize {
-        QS
+        QS * self.queue_pairs.len()
     }
 
     #[inline]
@@ -121,35 +521,19 @@ impl<T: Transport + 'static, const QS: usize> NetDevice for VirtIoNetDev<T, QS>
     fn recycle_rx_buffer(&mut self, rx_buf: Box<dyn NetBufPtrOps>) -> DevResult {
         let rx_buf =
             unsafe { core::mem::transmute::<Box<dyn NetBufPtrOps>, Box<dyn Any + Send>>(rx_buf) };
-        let mut rx_buf = unsafe { NetBuf::from_buf_ptr(rx_buf.downcast::<NetBufPtr>().unwrap()) };
-        // Safe because we take the ownership of `rx_buf` back to `rx_buffers`,
-        // it lives as long as the queue.
-        let new_token = unsafe {
-            self.inner
-                .receive_begin(rx_buf.raw_buf_mut())
-                .map_err(as_dev_err)?
-        };
-        // `rx_buffers[new_token]` is expected to be `None` since it was taken
-        // away at `Self::receive()` and has not been added back.
-        if self.rx_buffers[new_token as usize].is_some() {
-            return Err(DevError::BadState);
-        }
-        self.rx_buffers[new_token as usize] = Some(rx_buf);
-        Ok(())
+        let rx_buf = unsafe { NetBuf::from_buf_ptr(rx_buf.downcast::<NetBufPtr>().unwrap()) };
+        self.recycle_rx_buf(rx_buf)
     }
 
     fn recycle_tx_buffers(&mut self) -> DevResult {
-        while let Some(token) = self.inner.poll_transmit() {
-            let tx_buf = self.tx_buffers[token as usize]
-                .take()
-                .ok_or(DevError::BadState)?;
-            unsafe {
-                self.inner
-                    .transmit_complete(token, tx_buf.packet_with_header())
-                    .map_err(as_dev_err)?;
+        for pair in self.queue_pairs.iter_mut() {
+            while let Some((token, _)) = pair.tx.pop_used() {
+                let tx_buf = pair.tx_buffers[token as usize]
+                    .take()
+                    .ok_or(DevError::BadState)?;
+                // Recycle the buffer.
+                self.free_tx_bufs.push(tx_buf);
             }
-            // Recycle the buffer.
-            self.free_tx_bufs.push(tx_buf);
         }
         Ok(())
     }
@@ -158,36 +542,52 @@ impl<T: Transport + 'static, const QS: usize> NetDevice for VirtIoNetDev<T, QS>
         let tx_buf =
             unsafe { core::mem::transmute::<Box<dyn NetBufPtrOps>, Box<dyn Any + Send>>(tx_buf) };
         // 0. prepare tx buffer.
-        let tx_buf = unsafe { NetBuf::from_buf_ptr(tx_buf.downcast::<NetBufPtr>().unwrap()) };
+        let mut tx_buf = unsafe { NetBuf::from_buf_ptr(tx_buf.downcast::<NetBufPtr>().unwrap()) };
+        if !self.fill_tx_header(&mut tx_buf) {
+            // Larger than the MTU and not segmented by the device, like UDP
+            // packets, it would be dropped on the wire anyway.
+            log::warn!(
+                "[VirtIoNetDev::transmit] drop frame of {} bytes",
+                tx_buf.packet().len()
+            );
+            self.free_tx_bufs.push(tx_buf);
+            return Ok(());
+        }
         // 1. transmit packet.
+        let queue = self.tx_queue();
+        let pair = &mut self.queue_pairs[queue];
+        // Safe because we keep `tx_buf` in `tx_buffers` until the device
+        // uses it.
         let token = unsafe {
-            self.inner
-                .transmit_begin(tx_buf.packet_with_header())
-                .map_err(as_dev_err)?
+            let buf = tx_buf.packet_with_header();
+            pair.tx.add(&[(
+                share(buf, BufferDirection::DriverToDevice),
+                buf.len(),
+                false,
+            )])?
         };
-        self.tx_buffers[token as usize] = Some(tx_buf);
+        pair.tx_buffers[token as usize] = Some(tx_buf);
+        pair.tx.notify(&mut self.transport);
         Ok(())
     }
 
     fn receive(&mut self) -> DevResult<Box<dyn NetBufPtrOps>> {
-        if let Some(token) = self.inner.poll_receive() {
-            log::warn!("[VirtioNetDev::receive] token {}", token);
-            let mut rx_buf = self.rx_buffers[token as usize]
-                .take()
-                .ok_or(DevError::BadState)?;
-            // Safe because the buffer lives as long as the queue.
-            let (hdr_len, pkt_len) = unsafe {
-                self.inner
-                    .receive_complete(token, rx_buf.raw_buf_mut())
-                    .map_err(as_dev_err)?
-            };
-            rx_buf.set_header_len(hdr_len);
-            rx_buf.set_packet_len(pkt_len);
-
-            Ok(rx_buf.into_buf_ptr())
-        } else {
-            Err(DevError::Again)
+        let num_queues = self.queue_pairs.len();
+        for i in 0..num_queues {
+            let queue = (self.next_rx_queue + i) % num_queues;
+            loop {
+                match self.receive_from(queue) {
+                    Ok(Some(rx_buf)) => {
+                        self.next_rx_queue = (queue + 1) % num_queues;
+                        return Ok(rx_buf.into_buf_ptr());
+                    }
+                    Ok(None) => break,
+                    Err(DevError::Again) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
         }
+        Err(DevError::Again)
     }
 
     fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<Box<dyn NetBufPtrOps>> {
@@ -198,6 +598,7 @@ impl<T: Transport + 'static, const QS: usize> NetDevice for VirtIoNetDev<T, QS>
         // 1. Check if the buffer is large enough.
         let hdr_len = net_buf.header_len();
         if hdr_len + pkt_len > net_buf.capacity() {
+            self.free_tx_bufs.push(net_buf);
             return Err(DevError::InvalidParam);
         }
         net_buf.set_packet_len(pkt_len);
@@ -207,6 +608,154 @@ impl<T: Transport + 'static, const QS: usize> NetDevice for VirtIoNetDev<T, QS>
     }
 }
 
+/// `struct virtio_net_hdr`, with `num_buffers` only there if the header is
+/// [`HDR_LEN`] long.
+#[derive(Debug, Default, Clone, Copy)]
+struct VirtioNetHdr {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+    num_buffers: u16,
+}
+
+impl VirtioNetHdr {
+    fn read(buf: &[u8]) -> Self {
+        let field = |at: usize| {
+            buf.get(at..at + 2)
+                .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+        Self {
+            flags: buf[0],
+            gso_type: buf[1],
+            hdr_len: field(2),
+            gso_size: field(4),
+            csum_start: field(6),
+            csum_offset: field(8),
+            num_buffers: field(10),
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        buf[0] = self.flags;
+        buf[1] = self.gso_type;
+        let fields = [
+            self.hdr_len,
+            self.gso_size,
+            self.csum_start,
+            self.csum_offset,
+            self.num_buffers,
+        ];
+        for (chunk, field) in buf[2..].chunks_exact_mut(2).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+    }
+}
+
+/// Where the TCP or UDP header of an Ethernet frame is, and the sum of its
+/// pseudo header.
+struct L4Info {
+    offset: usize,
+    len: usize,
+    protocol: u8,
+    is_ipv6: bool,
+    pseudo_sum: u64,
+}
+
+impl L4Info {
+    fn parse(frame: &[u8]) -> Option<Self> {
+        let ip = frame.get(ETHERNET_HEADER_LEN..)?;
+        let (header_len, len, protocol, is_ipv6, addrs) =
+            match u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]) {
+                ETHERTYPE_IPV4 if ip.len() >= 20 => {
+                    // Fragments are left to the stack.
+                    if u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0 {
+                        return None;
+                    }
+                    let header_len = (ip[0] & 0xf) as usize * 4;
+                    let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+                    let len = total_len.checked_sub(header_len)?;
+                    (header_len, len, ip[9], false, &ip[12..20])
+                }
+                ETHERTYPE_IPV6 if ip.len() >= 40 => {
+                    let len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+                    (40, len, ip[6], true, &ip[8..40])
+                }
+                _ => return None,
+            };
+        let offset = ETHERNET_HEADER_LEN + header_len;
+        if !matches!(protocol, IPPROTO_TCP | IPPROTO_UDP) || offset + len > frame.len() {
+            return None;
+        }
+        Some(Self {
+            offset,
+            len,
+            protocol,
+            is_ipv6,
+            pseudo_sum: sum16(addrs) + protocol as u64 + len as u64,
+        })
+    }
+}
+
+/// Finish the partial checksum of a packet the device left to us. The
+/// checksum field holds the sum of the pseudo header.
+fn complete_checksum(packet: &mut [u8], start: usize, offset: usize) -> bool {
+    let at = start + offset;
+    if at + 2 > packet.len() {
+        return false;
+    }
+    let csum = !fold(sum16(&packet[start..]));
+    packet[at..at + 2].copy_from_slice(&csum.to_be_bytes());
+    true
+}
+
+/// Whether the TCP or UDP checksum of `frame` is correct. Other packets are
+/// left to smoltcp.
+fn verify_checksum(frame: &[u8]) -> bool {
+    let Some(l4) = L4Info::parse(frame) else {
+        return true;
+    };
+    let segment = &frame[l4.offset..l4.offset + l4.len];
+    // A zero UDP checksum means there is none over IPv4.
+    if l4.protocol == IPPROTO_UDP && !l4.is_ipv6 && segment.get(6..8) == Some(&[0, 0][..]) {
+        return true;
+    }
+    fold(l4.pseudo_sum + sum16(segment)) == 0xffff
+}
+
+/// Sum of the big-endian 16-bit words of `data`, padded with a zero byte.
+fn sum16(data: &[u8]) -> u64 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = chunks
+        .by_ref()
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u64)
+        .sum::<u64>();
+    if let [byte] = chunks.remainder() {
+        sum += (*byte as u64) << 8;
+    }
+    sum
+}
+
+/// One's complement sum of `sum`.
+fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Physical address of a buffer given to the device.
+///
+/// # Safety
+///
+/// The buffer must stay valid while the device uses it.
+unsafe fn share(buf: &[u8], direction: BufferDirection) -> usize {
+    unsafe { VirtioHalImpl::share(NonNull::from(buf), direction) }
+}
+
 /// A raw buffer struct for network device.
 pub struct NetBufPtr {
     // The raw pointer of the original object.
@@ -0,0 +1,207 @@
+//! A split virtqueue, as described in section 2.7 of the VirtIO 1.2 spec.
+//!
+//! Buffers are added as chains of descriptors, and a chain is identified by
+//! the index of its head descriptor, the token. The rings are laid out so that
+//! the used ring starts on a page boundary, which suits both the legacy and the
+//! modern transports.
+
+use alloc::vec::Vec;
+use core::{
+    mem::size_of,
+    ptr::{self, NonNull},
+    sync::atomic::{Ordering, fence},
+};
+
+use device_core::error::{DevError, DevResult};
+use virtio_drivers::{BufferDirection, Hal, PAGE_SIZE, transport::Transport};
+
+use crate::virtio::VirtioHalImpl;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[allow(unused)]
+struct AvailRing<const QS: usize> {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QS],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+#[allow(unused)]
+struct UsedRing<const QS: usize> {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QS],
+    avail_event: u16,
+}
+
+/// A virtqueue of `QS` descriptors.
+pub struct VirtQueue<const QS: usize> {
+    index: u16,
+    dma_paddr: usize,
+    dma_vaddr: NonNull<u8>,
+    dma_pages: usize,
+    desc: NonNull<[Descriptor; QS]>,
+    avail: NonNull<AvailRing<QS>>,
+    used: NonNull<UsedRing<QS>>,
+    /// Descriptors not in any chain.
+    free: Vec<u16>,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+unsafe impl<const QS: usize> Send for VirtQueue<QS> {}
+unsafe impl<const QS: usize> Sync for VirtQueue<QS> {}
+
+impl<const QS: usize> VirtQueue<QS> {
+    /// Allocate the rings of queue `index` and hand them to the device.
+    pub fn new<T: Transport>(transport: &mut T, index: u16) -> DevResult<Self> {
+        if transport.queue_used(index) {
+            return Err(DevError::AlreadyExists);
+        }
+        if (transport.max_queue_size(index) as usize) < QS || !QS.is_power_of_two() {
+            return Err(DevError::InvalidParam);
+        }
+        let desc_size = size_of::<Descriptor>() * QS;
+        let used_offset = (desc_size + size_of::<AvailRing<QS>>()).next_multiple_of(PAGE_SIZE);
+        let dma_pages = (used_offset + size_of::<UsedRing<QS>>()).div_ceil(PAGE_SIZE);
+        // The pages are zeroed, so both rings start empty with no flags set.
+        let (dma_paddr, dma_vaddr) = VirtioHalImpl::dma_alloc(dma_pages, BufferDirection::Both);
+        let queue = unsafe {
+            Self {
+                index,
+                dma_paddr,
+                dma_vaddr,
+                dma_pages,
+                desc: dma_vaddr.cast(),
+                avail: dma_vaddr.add(desc_size).cast(),
+                used: dma_vaddr.add(used_offset).cast(),
+                free: (0..QS as u16).rev().collect(),
+                avail_idx: 0,
+                last_used_idx: 0,
+            }
+        };
+        transport.queue_set(
+            index,
+            QS as u32,
+            dma_paddr,
+            dma_paddr + desc_size,
+            dma_paddr + used_offset,
+        );
+        Ok(queue)
+    }
+
+    /// Number of descriptors not in any chain.
+    pub fn num_free(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Add a chain of buffers given by their physical address, length and
+    /// whether the device writes them, and return its token. Readable buffers
+    /// must come first.
+    ///
+    /// # Safety
+    ///
+    /// The buffers must stay valid until the token is popped.
+    pub unsafe fn add(&mut self, bufs: &[(usize, usize, bool)]) -> DevResult<u16> {
+        if bufs.is_empty() || bufs.len() > self.free.len() {
+            return Err(DevError::NoMemory);
+        }
+        let ids: Vec<u16> = (0..bufs.len()).map(|_| self.free.pop().unwrap()).collect();
+        let desc = self.desc.as_ptr();
+        for (i, &(paddr, len, device_writable)) in bufs.iter().enumerate() {
+            let next = ids.get(i + 1).copied();
+            let mut flags = if device_writable { DESC_F_WRITE } else { 0 };
+            if next.is_some() {
+                flags |= DESC_F_NEXT;
+            }
+            unsafe {
+                ptr::write_volatile(&raw mut (*desc)[ids[i] as usize], Descriptor {
+                    addr: paddr as u64,
+                    len: len as u32,
+                    flags,
+                    next: next.unwrap_or(0),
+                });
+            }
+        }
+        let head = ids[0];
+        let avail = self.avail.as_ptr();
+        unsafe {
+            ptr::write_volatile(&raw mut (*avail).ring[self.avail_idx as usize % QS], head);
+            // The device must see the ring entry before the new index.
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            ptr::write_volatile(&raw mut (*avail).idx, self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// Tell the device about the chains added.
+    pub fn notify<T: Transport>(&self, transport: &mut T) {
+        transport.notify(self.index);
+    }
+
+    /// Whether the device has used a chain not popped yet.
+    pub fn can_pop(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.used_idx() != self.last_used_idx
+    }
+
+    /// Token of the next used chain and the number of bytes the device wrote
+    /// in it, which frees its descriptors.
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        if !self.can_pop() {
+            return None;
+        }
+        let used = self.used.as_ptr();
+        let elem = unsafe {
+            ptr::read_volatile(&raw const (*used).ring[self.last_used_idx as usize % QS])
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        let desc = self.desc.as_ptr();
+        let mut id = elem.id as u16;
+        loop {
+            let descriptor = unsafe { ptr::read_volatile(&raw const (*desc)[id as usize]) };
+            self.free.push(id);
+            if descriptor.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            id = descriptor.next;
+        }
+        Some((elem.id as u16, elem.len as usize))
+    }
+
+    fn used_idx(&self) -> u16 {
+        unsafe { ptr::read_volatile(&raw const (*self.used.as_ptr()).idx) }
+    }
+}
+
+impl<const QS: usize> Drop for VirtQueue<QS> {
+    /// The device must have been reset before, so that it no longer accesses
+    /// the rings.
+    fn drop(&mut self) {
+        unsafe {
+            VirtioHalImpl::dma_dealloc(self.dma_paddr, self.dma_vaddr, self.dma_pages);
+        }
+    }
+}
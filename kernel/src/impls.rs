@@ -1,19 +1,32 @@
 //! Impls of traits defined in other crates.
 
-use alloc::{fmt, string::ToString, sync::Arc};
+use alloc::{
+    boxed::Box,
+    fmt,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{future::Future, pin::Pin};
 
-use config::mm::VIRT_RAM_OFFSET;
-use driver::KernelPageTableIf;
+use config::mm::{PAGE_SIZE, VIRT_RAM_OFFSET};
+use driver::{KernelHartIf, KernelPageTableIf};
 use log::Level;
 use logging::{ColorCode, LogIf};
 use memory::{KernelMappingIf, PageTable, PhysAddr, VirtAddr};
 use net::HasSignalIf;
+use systype::{SysError, SysResult};
 use vfs::{procfs::KernelProcIf, sys_root_dentry};
-use vfs_core::{Dentry, SysRootDentryIf};
+use vfs_core::{Dentry, ReadaheadIf, SysRootDentryIf};
 
 use crate::{
-    mm::kernel_page_table_mut,
+    mm::{
+        kernel_page_table_mut,
+        oom::{self, OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN},
+    },
     processor::hart::{current_task_ref, local_hart},
+    task::{TASK_MANAGER, Task, procfs, ptrace, spawn_kernel_task},
 };
 
 /// Print msg with color
@@ -83,6 +96,15 @@ impl KernelPageTableIf for KernelPageTableIfImpl {
     }
 }
 
+struct KernelHartIfImpl;
+
+#[crate_interface::impl_interface]
+impl KernelHartIf for KernelHartIfImpl {
+    fn hart_id() -> usize {
+        local_hart().hart_id()
+    }
+}
+
 struct HasSignalIfImpl;
 #[crate_interface::impl_interface]
 impl HasSignalIf for HasSignalIfImpl {
@@ -117,6 +139,82 @@ impl KernelProcIf for KernelProcIfImpl {
     fn exe() -> alloc::string::String {
         current_task_ref().elf().dentry().path()
     }
+
+    fn has_process(pid: usize) -> bool {
+        process(pid).is_ok()
+    }
+
+    fn pids() -> Vec<usize> {
+        TASK_MANAGER
+            .tasks()
+            .iter()
+            .filter(|task| task.is_leader())
+            .map(|task| task.tid())
+            .collect()
+    }
+
+    fn maps(pid: usize) -> SysResult<String> {
+        process(pid).map(|task| procfs::show_maps(&task))
+    }
+
+    fn smaps(pid: usize) -> SysResult<String> {
+        process(pid).map(|task| procfs::show_smaps(&task))
+    }
+
+    fn status(pid: usize) -> SysResult<String> {
+        process(pid).map(|task| procfs::show_status(&task))
+    }
+
+    fn statm(pid: usize) -> SysResult<String> {
+        process(pid).map(|task| procfs::show_statm(&task))
+    }
+
+    // The memory is copied through a kernel buffer, so that the memory space
+    // of the target is never locked while the one of this task is accessed.
+
+    fn read_mem(pid: usize, addr: usize, buf: &mut [u8]) -> SysResult<usize> {
+        let task = process(pid)?;
+        ptrace::may_access(current_task_ref(), &task)?;
+        let mut data = vec![0; buf.len().min(PROC_MEM_CHUNK_SIZE)];
+        let len = ptrace::read_process_vm(&task, addr.into(), &mut data, true)
+            .map_err(|_| SysError::EIO)?;
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn write_mem(pid: usize, addr: usize, buf: &[u8]) -> SysResult<usize> {
+        let task = process(pid)?;
+        ptrace::may_access(current_task_ref(), &task)?;
+        let data = buf[..buf.len().min(PROC_MEM_CHUNK_SIZE)].to_vec();
+        ptrace::write_process_vm(&task, addr.into(), &data, true).map_err(|_| SysError::EIO)
+    }
+
+    fn oom_score(pid: usize) -> SysResult<usize> {
+        process(pid).map(|task| oom::oom_score(&task))
+    }
+
+    fn oom_score_adj(pid: usize) -> SysResult<i32> {
+        process(pid).map(|task| task.oom_score_adj())
+    }
+
+    fn set_oom_score_adj(pid: usize, adj: i32) -> SysResult<()> {
+        if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&adj) {
+            return Err(SysError::EINVAL);
+        }
+        process(pid)?.set_oom_score_adj(adj);
+        Ok(())
+    }
+}
+
+/// Most bytes of `/proc/<pid>/mem` accessed at once.
+const PROC_MEM_CHUNK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// The leader of process `pid`.
+fn process(pid: usize) -> SysResult<Arc<Task>> {
+    TASK_MANAGER
+        .get(pid)
+        .filter(|task| task.is_leader())
+        .ok_or(SysError::ESRCH)
 }
 
 struct SysRootDentryIfImpl;
@@ -127,3 +225,12 @@ impl SysRootDentryIf for SysRootDentryIfImpl {
         sys_root_dentry()
     }
 }
+
+struct ReadaheadIfImpl;
+
+#[crate_interface::impl_interface]
+impl ReadaheadIf for ReadaheadIfImpl {
+    fn spawn_readahead(future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        spawn_kernel_task(future)
+    }
+}
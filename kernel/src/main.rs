@@ -28,8 +28,6 @@ use core::{
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use ::net::poll_interfaces;
-
 use crate::processor::hart;
 
 extern crate alloc;
@@ -71,12 +69,12 @@ fn rust_main(hart_id: usize, dtb_addr: usize) {
             task::spawn_init_proc();
         });
 
-        // utils::spawn_timer_tasks_ms(
-        //     || {
-        //         poll_interfaces();
-        //     },
-        //     10,
-        // );
+        if ::net::dhcp::dhcp_enabled() {
+            task::spawn_kernel_task(::net::dhcp::dhcp_client("eth0"));
+        }
+
+        task::spawn_kernel_task(::net::softirq::softirq_task());
+        task::spawn_kernel_task(::net::dns::stub_resolver());
 
         #[cfg(feature = "debug")]
         utils::spawn_timer_tasks(utils::print_proc_tree, 10);
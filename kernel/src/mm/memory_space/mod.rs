@@ -14,23 +14,28 @@ use arch::memory::sfence_vma_vaddr;
 use async_utils::block_on;
 use config::{
     mm::{
-        DL_INTERP_OFFSET, MMAP_PRE_ALLOC_PAGES, PAGE_SIZE, U_SEG_FILE_BEG, U_SEG_FILE_END,
-        U_SEG_HEAP_BEG, U_SEG_HEAP_END, U_SEG_SHARE_BEG, U_SEG_SHARE_END, U_SEG_STACK_BEG,
-        U_SEG_STACK_END, USER_ELF_PRE_ALLOC_PAGE_CNT, is_aligned_to_page, round_down_to_page,
+        DL_INTERP_OFFSET, HUGE_PAGE_SIZE, MMAP_PRE_ALLOC_PAGES, PAGE_SIZE, U_SEG_FILE_BEG,
+        U_SEG_FILE_END, U_SEG_HEAP_BEG, U_SEG_HEAP_END, U_SEG_SHARE_BEG, U_SEG_SHARE_END,
+        U_SEG_STACK_BEG, U_SEG_STACK_END, USER_ELF_PRE_ALLOC_PAGE_CNT, is_aligned_to_page,
+        round_down_to_page,
     },
-    process::USER_STACK_PRE_ALLOC_SIZE,
+    process::{STACK_GUARD_GAP, USER_STACK_PRE_ALLOC_SIZE, USER_STACK_SIZE},
 };
-use memory::{PageTable, PhysAddr, VirtAddr, VirtPageNum, pte::PTEFlags};
+use memory::{PageTable, PhysAddr, VirtAddr, VirtPageNum, commit, pte::PTEFlags};
 use page::Page;
 use range_map::RangeMap;
-use systype::{SysError, SysResult};
+use signal::SigInfo;
+use systype::{RLIM_INFINITY, RLimit, SysError, SysResult};
 use vfs_core::{Dentry, File};
 use xmas_elf::ElfFile;
 
 use self::vm_area::VmArea;
 use super::{PageFaultAccessType, kernel_page_table};
 use crate::{
-    mm::memory_space::vm_area::{MapPerm, VmAreaType},
+    mm::{
+        memory_space::vm_area::{MapPerm, VmAreaType},
+        userfaultfd::UserfaultfdCtx,
+    },
     processor::{env::SumGuard, hart::current_task_ref},
     syscall::MmapFlags,
     task::{
@@ -50,14 +55,37 @@ pub struct MemorySpace {
     /// Map of `VmArea`s in this memory space.
     /// NOTE: stores range that is lazy allocated
     areas: SyncUnsafeCell<RangeMap<VirtAddr, VmArea>>,
+    /// Pages charged to the commit charge, the sum of `commit_pages` of the
+    /// areas.
+    committed: usize,
+    /// Limit of the size of this memory space, i.e. `RLIMIT_AS`.
+    rlimit_as: RLimit,
+    /// Limit of the size of the data areas, i.e. `RLIMIT_DATA`.
+    rlimit_data: RLimit,
+    /// Limit of the size each stack grows to, i.e. `RLIMIT_STACK`.
+    rlimit_stack: RLimit,
 }
 
+const NO_RLIMIT: RLimit = RLimit {
+    rlim_cur: RLIM_INFINITY,
+    rlim_max: RLIM_INFINITY,
+};
+
+const DEFAULT_RLIMIT_STACK: RLimit = RLimit {
+    rlim_cur: USER_STACK_SIZE,
+    rlim_max: RLIM_INFINITY,
+};
+
 impl MemorySpace {
     /// Create an empty `MemorySpace`
     pub fn new() -> Self {
         Self {
             page_table: SyncUnsafeCell::new(PageTable::new()),
             areas: SyncUnsafeCell::new(RangeMap::new()),
+            committed: 0,
+            rlimit_as: NO_RLIMIT,
+            rlimit_data: NO_RLIMIT,
+            rlimit_stack: DEFAULT_RLIMIT_STACK,
         }
     }
 
@@ -66,6 +94,10 @@ impl MemorySpace {
         Self {
             page_table: SyncUnsafeCell::new(PageTable::from_kernel(kernel_page_table())),
             areas: SyncUnsafeCell::new(RangeMap::new()),
+            committed: 0,
+            rlimit_as: NO_RLIMIT,
+            rlimit_data: NO_RLIMIT,
+            rlimit_stack: DEFAULT_RLIMIT_STACK,
         }
     }
 
@@ -85,6 +117,116 @@ impl MemorySpace {
         unsafe { &mut *self.page_table.get() }
     }
 
+    pub fn rlimit_as(&self) -> RLimit {
+        self.rlimit_as
+    }
+
+    pub fn set_rlimit_as(&mut self, limit: RLimit) {
+        self.rlimit_as = limit;
+    }
+
+    pub fn rlimit_data(&self) -> RLimit {
+        self.rlimit_data
+    }
+
+    pub fn set_rlimit_data(&mut self, limit: RLimit) {
+        self.rlimit_data = limit;
+    }
+
+    pub fn rlimit_stack(&self) -> RLimit {
+        self.rlimit_stack
+    }
+
+    pub fn set_rlimit_stack(&mut self, limit: RLimit) {
+        self.rlimit_stack = limit;
+    }
+
+    /// Number of pages of all the areas.
+    pub fn total_pages(&self) -> usize {
+        self.areas()
+            .iter()
+            .map(|(_, vma)| vma.end_vpn() - vma.start_vpn())
+            .sum()
+    }
+
+    /// Number of pages of the areas counting for `RLIMIT_DATA`.
+    pub fn data_pages(&self) -> usize {
+        self.areas()
+            .iter()
+            .filter(|(_, vma)| vma.is_data())
+            .map(|(_, vma)| vma.end_vpn() - vma.start_vpn())
+            .sum()
+    }
+
+    /// Number of pages mapped in, i.e. the resident set.
+    pub fn resident_pages(&self) -> usize {
+        self.areas().iter().map(|(_, vma)| vma.pages.len()).sum()
+    }
+
+    /// Unmap all the areas but shared memory, to give back the memory of an
+    /// exited process before it is reaped.
+    pub fn release(&mut self) {
+        let ranges: Vec<_> = self
+            .areas()
+            .iter()
+            .filter(|(_, vma)| vma.vma_type != VmAreaType::Shm)
+            .map(|(range, _)| range)
+            .collect();
+        for range in ranges {
+            let mut vma = self.areas_mut().force_remove_one(range);
+            vma.unmap(self.page_table_mut());
+        }
+        self.settle_commit();
+    }
+
+    /// Check that `pages` more pages, `data_pages` of which are data, fit in
+    /// the limits of this memory space, and charge `commit_pages` of them.
+    /// `ENOMEM` if they do not fit or the overcommit policy refuses.
+    fn charge_pages(
+        &mut self,
+        pages: usize,
+        data_pages: usize,
+        commit_pages: usize,
+    ) -> SysResult<()> {
+        let exceeds = |used: usize, more: usize, limit: RLimit| {
+            more > 0 && (used + more).saturating_mul(PAGE_SIZE) > limit.rlim_cur
+        };
+        if exceeds(self.total_pages(), pages, self.rlimit_as)
+            || exceeds(self.data_pages(), data_pages, self.rlimit_data)
+        {
+            log::warn!("[MemorySpace::charge_pages] {pages} pages exceed rlimit");
+            return Err(SysError::ENOMEM);
+        }
+        if !commit::vm_commit(commit_pages) {
+            log::warn!("[MemorySpace::charge_pages] can not commit {commit_pages} pages");
+            return Err(SysError::ENOMEM);
+        }
+        self.committed += commit_pages;
+        Ok(())
+    }
+
+    /// Charge `vma` before it is added, see [`Self::charge_pages`].
+    fn charge(&mut self, vma: &VmArea) -> SysResult<()> {
+        let pages = vma.end_vpn() - vma.start_vpn();
+        let data_pages = if vma.is_data() { pages } else { 0 };
+        self.charge_pages(pages, data_pages, vma.commit_pages())
+    }
+
+    /// Bring the commit charge in line with the areas, giving back the charge
+    /// of areas removed or shrunk.
+    ///
+    /// Areas added without [`Self::charge`], i.e. the image of a program
+    /// loaded by execve, are charged here whatever the overcommit policy.
+    fn settle_commit(&mut self) {
+        let committed: usize = self.areas().iter().map(|(_, vma)| vma.commit_pages()).sum();
+        if committed < self.committed {
+            commit::vm_uncommit(self.committed - committed);
+        } else {
+            commit::vm_commit_force(committed - self.committed);
+        }
+        self.committed = committed;
+    }
+
     /// Map the sections in the elf.
     ///
     /// Return the max end vpn and the first section's va.
@@ -125,6 +267,10 @@ impl MemorySpace {
                 map_perm |= MapPerm::X;
             }
             let mut vm_area = VmArea::new(start_va..end_va, map_perm, VmAreaType::Elf);
+            // Only shown in `/proc/<pid>/maps`, the pages are loaded below.
+            vm_area.backed_file = Some(elf_file.clone());
+            vm_area.offset =
+                (ph.offset() as usize).saturating_sub(start_va - start_va.round_down());
 
             log::debug!("[map_elf] [{start_va:#x}, {end_va:#x}], map_perm: {map_perm:?} start...",);
 
@@ -187,6 +333,7 @@ impl MemorySpace {
                 );
             }
         }
+        self.settle_commit();
 
         (max_end_vpn, header_va.into())
     }
@@ -349,15 +496,24 @@ impl MemorySpace {
     ///
     /// Return the address of the stack top, which is aligned to 16 bytes.
     ///
-    /// The stack has a range of [sp - size, sp].
+    /// The stack has a range of [sp - size, sp], with `size` no more than
+    /// `RLIMIT_STACK`. Room is left below it to grow down to `RLIMIT_STACK`,
+    /// and [`STACK_GUARD_GAP`] below that.
     pub fn alloc_stack_lazily(&mut self, size: usize) -> VirtAddr {
         const STACK_RANGE: Range<VirtAddr> =
             VirtAddr::from_usize_range(U_SEG_STACK_BEG..U_SEG_STACK_END);
 
-        let range = self
+        let limit = round_down_to_page(self.rlimit_stack.rlim_cur);
+        let size = cmp::max(cmp::min(size, limit), PAGE_SIZE);
+        let reserved = cmp::min(
+            limit.saturating_add(STACK_GUARD_GAP),
+            U_SEG_STACK_END - U_SEG_STACK_BEG,
+        );
+        let reserved = self
             .areas()
-            .find_free_range(STACK_RANGE, size)
+            .find_free_range(STACK_RANGE, reserved)
             .expect("too many stack!");
+        let range = reserved.end - size..reserved.end;
 
         // align to 16 bytes
         let sp_init = VirtAddr::from((range.end.bits() - 1) & !0xf);
@@ -366,9 +522,10 @@ impl MemorySpace {
         let mut vm_area = VmArea::new(range.clone(), MapPerm::URW, VmAreaType::Stack);
         vm_area.map_range(
             self.page_table_mut(),
-            range.end - USER_STACK_PRE_ALLOC_SIZE..range.end,
+            range.end - cmp::min(USER_STACK_PRE_ALLOC_SIZE, size)..range.end,
         );
         self.push_vma_lazily(vm_area);
+        self.settle_commit();
         sp_init
     }
 
@@ -382,6 +539,7 @@ impl MemorySpace {
 
         let vm_area = VmArea::new(range, MapPerm::URW, VmAreaType::Heap);
         self.push_vma_lazily(vm_area);
+        self.settle_commit();
     }
 
     pub fn get_heap_break(&self) -> VirtAddr {
@@ -404,7 +562,11 @@ impl MemorySpace {
             .unwrap();
         log::debug!("[MemorySpace::reset_heap_break] heap range: {range:?}, new_brk: {new_brk:?}");
         let result = if new_brk > range.end {
-            let ret = self.areas_mut().extend_back(range.start..new_brk);
+            let pages = new_brk.ceil() - range.end.ceil();
+            let ret = match self.charge_pages(pages, pages, pages) {
+                Ok(()) => self.areas_mut().extend_back(range.start..new_brk),
+                Err(_) => Err(()),
+            };
             if ret.is_ok() {
                 let (range_va, vm_area) = self.areas_mut().get_key_value_mut(range.start).unwrap();
                 vm_area.set_range_va(range_va);
@@ -414,6 +576,7 @@ impl MemorySpace {
             let ret = self.areas_mut().reduce_back(range.start, new_brk);
             if ret.is_ok() {
                 let (range_va, _) = self.areas_mut().get_key_value(range.start).unwrap();
+                self.page_table_mut().split_at(range_va.end.ceil());
                 let vma = self.areas_mut().force_remove_one(range_va.clone());
                 let (left, middle, right) = vma.split(range_va);
                 debug_assert!(left.is_none());
@@ -427,6 +590,7 @@ impl MemorySpace {
         } else {
             Ok(())
         };
+        self.settle_commit();
         match result {
             Ok(_) => new_brk,
             Err(_) => range.end,
@@ -434,11 +598,24 @@ impl MemorySpace {
     }
 
     /// Clone a same `MemorySpace` lazily.
-    pub fn from_user_lazily(user_space: &mut Self) -> Self {
+    ///
+    /// The clone is charged as much as `user_space`, `ENOMEM` if the
+    /// overcommit policy refuses.
+    pub fn from_user_lazily(user_space: &mut Self) -> SysResult<Self> {
+        if !commit::vm_commit(user_space.committed) {
+            log::warn!("[MemorySpace::from_user_lazily] can not commit a clone");
+            return Err(SysError::ENOMEM);
+        }
         let mut memory_space = Self::new_user();
+        memory_space.committed = user_space.committed;
+        memory_space.rlimit_as = user_space.rlimit_as;
+        memory_space.rlimit_data = user_space.rlimit_data;
+        memory_space.rlimit_stack = user_space.rlimit_stack;
         for (range, area) in user_space.areas().iter() {
             log::debug!("[MemorySpace::from_user_lazily] cloning {area:?}");
             let mut new_area = area.clone();
+            // Faults of the child are not delivered to the userfaultfd.
+            new_area.uffd = None;
             debug_assert_eq!(range, new_area.range_va());
             for vpn in area.range_vpn() {
                 if let Some(page) = area.pages.get(&vpn) {
@@ -468,7 +645,7 @@ impl MemorySpace {
             }
             memory_space.push_vma_lazily(new_area);
         }
-        memory_space
+        Ok(memory_space)
     }
 
     /// Push `VmArea` into `MemorySpace` and map it in page table.
@@ -505,10 +682,11 @@ impl MemorySpace {
         } else {
             self.areas_mut()
                 .find_free_range(SHARED_RANGE, length)
-                .expect("shared range is full")
+                .ok_or(SysError::ENOMEM)?
         };
         let start = range.start;
         let vma = VmArea::new(range, perm, VmAreaType::Shm);
+        self.charge(&vma)?;
         self.push_vma(vma);
         // self.areas_mut().try_insert(vma.range_va(), vma).unwrap();
         Ok(start)
@@ -526,16 +704,34 @@ impl MemorySpace {
         let range = if flags.contains(MmapFlags::MAP_FIXED) {
             addr..addr + length
         } else {
-            self.areas_mut()
-                .find_free_range(MMAP_RANGE, length)
-                .expect("mmap range is full")
+            self.find_huge_aligned_range(MMAP_RANGE, length)
+                .ok_or(SysError::ENOMEM)?
         };
         let start = range.start;
         let vma = VmArea::new_mmap(range, perm, flags, None, 0);
+        self.charge(&vma)?;
         self.areas_mut().try_insert(vma.range_va(), vma).unwrap();
         Ok(start)
     }
 
+    /// Find a free range of `length` bytes in `range`, which starts at a huge
+    /// page if it is large enough to hold one, so that its pages can be
+    /// mapped by huge pages.
+    fn find_huge_aligned_range(
+        &self,
+        range: Range<VirtAddr>,
+        length: usize,
+    ) -> Option<Range<VirtAddr>> {
+        if length >= HUGE_PAGE_SIZE {
+            let padded = length + HUGE_PAGE_SIZE - PAGE_SIZE;
+            if let Some(free) = self.areas().find_free_range(range.clone(), padded) {
+                let start = VirtAddr::from(free.start.bits().next_multiple_of(HUGE_PAGE_SIZE));
+                return Some(start..start + length);
+            }
+        }
+        self.areas().find_free_range(range, length)
+    }
+
     // NOTE: can not alloc all pages from `PageCache`, otherwise lmbench
     // lat_pagefault will test page fault time as zero.
     pub fn alloc_mmap_area_lazily(
@@ -557,13 +753,14 @@ impl MemorySpace {
         } else {
             self.areas_mut()
                 .find_free_range(MMAP_RANGE, length)
-                .expect("mmap range is full")
+                .ok_or(SysError::ENOMEM)?
         };
         let start = range.start;
 
+        let mut vma = VmArea::new_mmap(range, perm, flags, Some(file.clone()), offset);
+        self.charge(&vma)?;
         let page_table = self.page_table_mut();
         let inode = file.inode();
-        let mut vma = VmArea::new_mmap(range, perm, flags, Some(file.clone()), offset);
         let mut range_vpn = vma.range_vpn();
         let length = cmp::min(length, MMAP_PRE_ALLOC_PAGES * PAGE_SIZE);
         for offset_aligned in (offset..offset + length).step_by(PAGE_SIZE) {
@@ -601,6 +798,9 @@ impl MemorySpace {
         Option<&mut VmArea>,
         Option<&mut VmArea>,
     ) {
+        // Huge pages must not cross the bounds of the new areas.
+        self.page_table_mut().split_at(split_range.start.floor());
+        self.page_table_mut().split_at(split_range.end.ceil());
         let area = self.areas_mut().force_remove_one(old_range);
         let (left, middle, right) = area.split(split_range);
         let left_ret = left.map(|left| self.areas_mut().try_insert(left.range_va(), left).unwrap());
@@ -662,15 +862,27 @@ impl MemorySpace {
                 }
             }
         }
+        self.settle_commit();
         Ok(())
     }
 
     pub fn mprotect(&mut self, range: Range<VirtAddr>, perm: MapPerm) -> SysResult<()> {
         debug_assert!(range.start.is_aligned() && range.end.is_aligned());
-        let (old_range, area) = self
-            .areas_mut()
-            .get_key_value_mut(range.start)
-            .ok_or(SysError::ENOMEM)?;
+        let area = self.areas().get(range.start).ok_or(SysError::ENOMEM)?;
+        // Making a private mapping writable charges it.
+        let pages = range.end.ceil() - range.start.floor();
+        let data_pages = if !area.is_data() && area.is_data_with(perm) {
+            pages
+        } else {
+            0
+        };
+        let commit_pages = if !area.is_charged_with(area.map_perm) && area.is_charged_with(perm) {
+            pages
+        } else {
+            0
+        };
+        self.charge_pages(0, data_pages, commit_pages)?;
+        let (old_range, area) = self.areas_mut().get_key_value_mut(range.start).unwrap();
         if range == old_range {
             area.set_perm_and_flush(self.page_table_mut(), perm);
         } else {
@@ -681,28 +893,455 @@ impl MemorySpace {
                 middle.set_perm_and_flush(self.page_table_mut(), perm);
             }
         }
+        self.settle_commit();
+        Ok(())
+    }
+
+    /// Resize the mapping of `old_range` to `new_len` bytes, in place or at a
+    /// new address if `may_move`, or at `fixed`. Return its new start.
+    ///
+    /// `old_range` must be in a single mmap area. Pages are carried over to
+    /// the new range with their page table flags, so copy on write goes on.
+    pub fn mremap(
+        &mut self,
+        old_range: Range<VirtAddr>,
+        new_len: usize,
+        may_move: bool,
+        fixed: Option<VirtAddr>,
+    ) -> SysResult<VirtAddr> {
+        debug_assert!(old_range.start.is_aligned() && old_range.end.is_aligned());
+        debug_assert!(is_aligned_to_page(new_len));
+        const MMAP_RANGE: Range<VirtAddr> =
+            VirtAddr::from_usize_range(U_SEG_FILE_BEG..U_SEG_FILE_END);
+
+        let (area_range, area) = self
+            .areas()
+            .get_key_value(old_range.start)
+            .ok_or(SysError::EFAULT)?;
+        if area_range.end < old_range.end {
+            return Err(SysError::EFAULT);
+        }
+        // The heap and the stack are found by their type, and shared memory
+        // by its address.
+        if area.vma_type != VmAreaType::Mmap {
+            return Err(SysError::EINVAL);
+        }
+        let old_len = old_range.end - old_range.start;
+        if new_len > old_len {
+            let pages = (new_len - old_len) / PAGE_SIZE;
+            let data_pages = if area.is_data() { pages } else { 0 };
+            let commit_pages = if area.commit_pages() > 0 { pages } else { 0 };
+            self.charge_pages(pages, data_pages, commit_pages)?;
+        }
+        let new_start = match fixed {
+            Some(new_start) => {
+                let new_range = new_start..new_start + new_len;
+                if new_range.start < old_range.end && old_range.start < new_range.end {
+                    self.settle_commit();
+                    return Err(SysError::EINVAL);
+                }
+                self.unmap(new_range)?;
+                new_start
+            }
+            None if new_len <= old_len => {
+                if new_len < old_len {
+                    self.unmap(old_range.start + new_len..old_range.end)?;
+                }
+                return Ok(old_range.start);
+            }
+            None => {
+                let new_end = old_range.start + new_len;
+                if area_range.end == old_range.end
+                    && self
+                        .areas_mut()
+                        .extend_back(area_range.start..new_end)
+                        .is_ok()
+                {
+                    let (range_va, vma) = self
+                        .areas_mut()
+                        .get_key_value_mut(area_range.start)
+                        .unwrap();
+                    vma.set_range_va(range_va);
+                    return Ok(old_range.start);
+                }
+                let free_range = self
+                    .areas()
+                    .find_free_range(MMAP_RANGE, new_len)
+                    .filter(|_| may_move);
+                match free_range {
+                    Some(range) => range.start,
+                    None => {
+                        self.settle_commit();
+                        return Err(SysError::ENOMEM);
+                    }
+                }
+            }
+        };
+        self.move_area(old_range, new_start, new_len);
+        self.settle_commit();
+        Ok(new_start)
+    }
+
+    /// Move the part `old_range` of an area to `new_start`, keeping its first
+    /// `new_len` bytes. The destination must be free.
+    fn move_area(&mut self, old_range: Range<VirtAddr>, new_start: VirtAddr, new_len: usize) {
+        let (area_range, _) = self.areas().get_key_value(old_range.start).unwrap();
+        let range_va = if area_range == old_range {
+            old_range
+        } else {
+            let (_, middle, _) = self.split_area(area_range, old_range);
+            middle.unwrap().range_va()
+        };
+        let mut vma = self.areas_mut().force_remove_one(range_va);
+        let old_start_vpn = vma.start_vpn();
+        let new_start_vpn = new_start.floor();
+        let page_table = self.page_table_mut();
+        for (vpn, page) in core::mem::take(&mut vma.pages) {
+            let pte_flags = page_table.find_leaf_pte(vpn).unwrap().flags();
+            page_table.unmap(vpn);
+            unsafe { sfence_vma_vaddr(vpn.to_vaddr().into()) };
+            let index = vpn - old_start_vpn;
+            if index * PAGE_SIZE < new_len {
+                let new_vpn = new_start_vpn + index;
+                page_table.map(new_vpn, page.ppn(), pte_flags);
+                unsafe { sfence_vma_vaddr(new_vpn.to_vaddr().into()) };
+                vma.pages.insert(new_vpn, page);
+            }
+        }
+        vma.set_range_va(new_start..new_start + new_len);
+        self.push_vma_lazily(vma);
+    }
+
+    /// Ranges of the areas `range` overlaps, `ENOMEM` if part of it is not
+    /// mapped.
+    fn areas_in(&self, range: Range<VirtAddr>) -> SysResult<Vec<Range<VirtAddr>>> {
+        let mut ranges = Vec::new();
+        let mut next = range.start;
+        while next < range.end {
+            let (area_range, _) = self.areas().get_key_value(next).ok_or(SysError::ENOMEM)?;
+            next = area_range.end;
+            ranges.push(area_range);
+        }
+        Ok(ranges)
+    }
+
+    /// Drop the pages in `range`, so that they refault as zero or from the
+    /// backing file. With `anonymous_only`, `range` must be in private
+    /// anonymous areas.
+    ///
+    /// Areas that can not refault are left alone.
+    pub fn discard_pages(&mut self, range: Range<VirtAddr>, anonymous_only: bool) -> SysResult<()> {
+        let area_ranges = self.areas_in(range.clone())?;
+        if anonymous_only
+            && area_ranges
+                .iter()
+                .any(|r| !self.areas().get(r.start).unwrap().is_private_anonymous())
+        {
+            return Err(SysError::EINVAL);
+        }
+        for area_range in area_ranges {
+            let vma = self.areas_mut().get_mut(area_range.start).unwrap();
+            if !vma.can_refault() {
+                continue;
+            }
+            let start = cmp::max(range.start, area_range.start);
+            let end = cmp::min(range.end, area_range.end);
+            vma.discard_pages(self.page_table_mut(), start.floor()..end.ceil());
+        }
+        Ok(())
+    }
+
+    /// Read the file pages backing `range` into the page cache.
+    pub fn prefetch_pages(&self, range: Range<VirtAddr>) -> SysResult<()> {
+        for area_range in self.areas_in(range.clone())? {
+            let vma = self.areas().get(area_range.start).unwrap();
+            let start = cmp::max(range.start, area_range.start);
+            let end = cmp::min(range.end, area_range.end);
+            vma.prefetch_pages(start.floor()..end.ceil())?;
+        }
+        Ok(())
+    }
+
+    /// Whether each page of `range` is resident, one byte per page with the
+    /// lowest bit set if it is.
+    pub fn mincore(&self, range: Range<VirtAddr>) -> SysResult<Vec<u8>> {
+        // The range is checked to be mapped before anything is allocated for it.
+        let area_ranges = self.areas_in(range.clone())?;
+        let mut residency = Vec::with_capacity(range.end.ceil() - range.start.floor());
+        for area_range in area_ranges {
+            let vma = self.areas().get(area_range.start).unwrap();
+            let start = cmp::max(range.start, area_range.start);
+            let end = cmp::min(range.end, area_range.end);
+            residency
+                .extend((start.floor()..end.ceil()).map(|vpn| vma.pages.contains_key(&vpn) as u8));
+        }
+        Ok(residency)
+    }
+
+    /// Register `range` with the userfaultfd `ctx` for its missing pages. The
+    /// areas in it must be private anonymous, and not registered with another
+    /// open userfaultfd.
+    pub fn userfault_register(
+        &mut self,
+        range: Range<VirtAddr>,
+        ctx: &Arc<UserfaultfdCtx>,
+    ) -> SysResult<()> {
+        let area_ranges = self.areas_in(range.clone())?;
+        for area_range in area_ranges.iter() {
+            let vma = self.areas().get(area_range.start).unwrap();
+            if !vma.is_private_anonymous() {
+                return Err(SysError::EINVAL);
+            }
+            if vma
+                .userfaultfd()
+                .is_some_and(|other| !Arc::ptr_eq(&other, ctx))
+            {
+                return Err(SysError::EBUSY);
+            }
+        }
+        self.set_userfault(range, area_ranges, Some(Arc::downgrade(ctx)));
+        Ok(())
+    }
+
+    /// Unregister the areas in `range` registered with the userfaultfd `ctx`.
+    pub fn userfault_unregister(
+        &mut self,
+        range: Range<VirtAddr>,
+        ctx: &Arc<UserfaultfdCtx>,
+    ) -> SysResult<()> {
+        let mut area_ranges = self.areas_in(range.clone())?;
+        if area_ranges
+            .iter()
+            .any(|r| !self.areas().get(r.start).unwrap().is_private_anonymous())
+        {
+            return Err(SysError::EINVAL);
+        }
+        area_ranges.retain(|r| self.areas().get(r.start).unwrap().is_registered_with(ctx));
+        self.set_userfault(range, area_ranges, None);
+        Ok(())
+    }
+
+    /// Set the userfaultfd of the parts in `range` of the areas `area_ranges`,
+    /// which are split at its bounds.
+    fn set_userfault(
+        &mut self,
+        range: Range<VirtAddr>,
+        area_ranges: Vec<Range<VirtAddr>>,
+        uffd: Option<Weak<UserfaultfdCtx>>,
+    ) {
+        for area_range in area_ranges {
+            let split_range =
+                cmp::max(range.start, area_range.start)..cmp::min(range.end, area_range.end);
+            let vma = if split_range == area_range {
+                self.areas_mut().get_mut(area_range.start).unwrap()
+            } else {
+                self.split_area(area_range, split_range).1.unwrap()
+            };
+            vma.uffd = uffd.clone();
+        }
+    }
+
+    /// The userfaultfd the fault at `va` goes to, if it is on a missing page
+    /// of an area registered with an open one, and `access_type` is allowed.
+    pub fn userfault(
+        &self,
+        va: VirtAddr,
+        access_type: PageFaultAccessType,
+    ) -> Option<Arc<UserfaultfdCtx>> {
+        let vma = self.areas().get(va.round_down())?;
+        if !access_type.can_access(vma.perm()) || vma.pages.contains_key(&va.floor()) {
+            return None;
+        }
+        vma.userfaultfd()
+    }
+
+    /// Map `page` at the missing page `vpn` of an area registered with the
+    /// userfaultfd `ctx`, for `UFFDIO_COPY` and `UFFDIO_ZEROPAGE`.
+    pub fn userfault_fill(
+        &mut self,
+        vpn: VirtPageNum,
+        page: Arc<Page>,
+        ctx: &Arc<UserfaultfdCtx>,
+    ) -> SysResult<()> {
+        let vma = self
+            .areas_mut()
+            .get_mut(vpn.to_vaddr())
+            .filter(|vma| vma.is_registered_with(ctx))
+            .ok_or(SysError::ENOENT)?;
+        if vma.pages.contains_key(&vpn) {
+            return Err(SysError::EEXIST);
+        }
+        vma.map_page(self.page_table_mut(), vpn, page);
         Ok(())
     }
 
+    /// Grow the stack, or a `MAP_GROWSDOWN` area, right above `va` down to
+    /// the page of `va`, and return whether it did.
+    ///
+    /// It grows to no more than `RLIMIT_STACK`, and keeps [`STACK_GUARD_GAP`]
+    /// free above the area below it, so that an overflow faults in no area
+    /// rather than running into the mapping below.
+    fn grow_stack(&mut self, va: VirtAddr) -> bool {
+        let new_start = va.round_down();
+        let Some((range, vma)) = self
+            .areas()
+            .range(new_start..VirtAddr::from_usize(usize::MAX))
+            .next()
+        else {
+            return false;
+        };
+        if !vma.can_grow_down() || range.end - new_start > self.rlimit_stack.rlim_cur {
+            return false;
+        }
+        let gap_start = VirtAddr::from_usize(new_start.bits().saturating_sub(STACK_GUARD_GAP));
+        if self.areas().is_range_free(gap_start..range.start).is_err() {
+            log::info!("[MemorySpace::grow_stack] {va:?} is in the guard gap of {range:?}");
+            return false;
+        }
+        let pages = (range.start - new_start) / PAGE_SIZE;
+        let data_pages = if vma.is_data() { pages } else { 0 };
+        let commit_pages = if vma.commit_pages() > 0 { pages } else { 0 };
+        if self.charge_pages(pages, data_pages, commit_pages).is_err() {
+            return false;
+        }
+        self.areas_mut()
+            .extend_front(range.start, new_start)
+            .unwrap();
+        let vma = self.areas_mut().get_mut(new_start).unwrap();
+        vma.set_range_va(new_start..range.end);
+        log::debug!("[MemorySpace::grow_stack] grow {range:?} down to {new_start:?}");
+        true
+    }
+
+    /// `si_code` of the `SIGSEGV` of a bad access at `va`: `SEGV_ACCERR` if it
+    /// is in an area, whose permission does not allow it, or `SEGV_MAPERR`.
+    pub fn segv_code(&self, va: VirtAddr) -> i32 {
+        if self.areas().get(va.round_down()).is_some() {
+            SigInfo::SEGV_ACCERR
+        } else {
+            SigInfo::SEGV_MAPERR
+        }
+    }
+
     pub fn handle_page_fault(
         &mut self,
         va: VirtAddr,
         access_type: PageFaultAccessType,
     ) -> SysResult<()> {
         log::trace!("[MemorySpace::handle_page_fault] {va:?}");
-        let vm_area = self.areas_mut().get_mut(va.round_down()).ok_or_else(|| {
+        if self.areas().get(va.round_down()).is_none() && !self.grow_stack(va) {
             log::error!("[handle_page_fault] no area containing {va:?}");
-            SysError::EFAULT
-        })?;
+            return Err(SysError::EFAULT);
+        }
+        let vm_area = self.areas_mut().get_mut(va.round_down()).unwrap();
+        // Faults from user mode on such pages go to the userfaultfd before,
+        // and the kernel can not wait for the handler.
+        if vm_area.userfaultfd().is_some() && !vm_area.pages.contains_key(&va.floor()) {
+            log::warn!("[handle_page_fault] {va:?} is missing for a userfaultfd");
+            return Err(SysError::EFAULT);
+        }
         vm_area.handle_page_fault(self.page_table_mut(), va.floor(), access_type)?;
         Ok(())
     }
 
+    /// Access `len` bytes at `va` of this memory space, which need not be the
+    /// current one, through its areas rather than the page table in use. `f`
+    /// is called with each piece of a page and its offset in the access.
+    ///
+    /// Missing pages of mapped areas are faulted in, and copy-on-write pages
+    /// are copied before they are written. No area is created or grown. With
+    /// `force`, like ptrace, unreadable areas are read and read-only
+    /// private areas are written.
+    ///
+    /// Returns the bytes accessed, which fall short at the first page that can
+    /// not be accessed, or `EFAULT` if it is the first one.
+    pub fn access_remote(
+        &mut self,
+        va: VirtAddr,
+        len: usize,
+        write: bool,
+        force: bool,
+        mut f: impl FnMut(usize, &mut [u8]),
+    ) -> SysResult<usize> {
+        let mut done = 0;
+        while done < len {
+            let cur = va + done;
+            let Some(page) = self.remote_page(cur, write, force) else {
+                break;
+            };
+            let offset = cur.page_offset();
+            let n = cmp::min(PAGE_SIZE - offset, len - done);
+            f(done, &mut page.bytes_array()[offset..offset + n]);
+            done += n;
+        }
+        if done == 0 && len > 0 {
+            return Err(SysError::EFAULT);
+        }
+        Ok(done)
+    }
+
+    /// The page at `va` for [`Self::access_remote`], faulted in, and private
+    /// to this memory space if it is to be written. Unmapped addresses give
+    /// `None`, the stack is not grown for them.
+    fn remote_page(&mut self, va: VirtAddr, write: bool, force: bool) -> Option<Arc<Page>> {
+        let vpn = va.floor();
+        let vma = self.areas().get(va.round_down())?;
+        let writable = vma.perm().contains(MapPerm::W);
+        let allowed = if write {
+            writable || force && !vma.is_shared()
+        } else {
+            vma.perm().contains(MapPerm::R) || force
+        };
+        if !allowed {
+            return None;
+        }
+        let is_cow = self
+            .page_table()
+            .find_leaf_pte(vpn)
+            .is_some_and(|pte| pte.flags().contains(PTEFlags::COW));
+        if !vma.pages.contains_key(&vpn) || write && writable && is_cow {
+            let access_type = if write && writable {
+                PageFaultAccessType::RW
+            } else {
+                PageFaultAccessType::RO
+            };
+            self.handle_page_fault(va, access_type).ok()?;
+        }
+        if write && !writable {
+            self.unshare_page(vpn)?;
+        }
+        self.areas().get(va.round_down())?.pages.get(&vpn).cloned()
+    }
+
+    /// Give the read-only page at `vpn` a copy of its own if it is shared,
+    /// e.g. with the page cache, so that it can be written by the kernel.
+    fn unshare_page(&mut self, vpn: VirtPageNum) -> Option<()> {
+        let vma = self.areas_mut().get_mut(vpn.to_vaddr())?;
+        let page = vma.pages.get(&vpn)?;
+        if Arc::strong_count(page) == 1 {
+            return Some(());
+        }
+        let new_page = Page::try_new()?;
+        new_page.copy_from_slice(page.bytes_array());
+        let page_table = self.page_table_mut();
+        page_table.split(vpn);
+        page_table.map_force(vpn, new_page.ppn(), vma.perm().into());
+        vma.pages.insert(vpn, new_page);
+        unsafe { sfence_vma_vaddr(vpn.to_vaddr().into()) };
+        Some(())
+    }
+
     pub unsafe fn switch_page_table(&self) {
         unsafe { self.page_table().switch() };
     }
 }
 
+impl Drop for MemorySpace {
+    fn drop(&mut self) {
+        commit::vm_uncommit(self.committed);
+    }
+}
+
 pub fn init_stack(
     sp_init: VirtAddr,
     args: Vec<String>,
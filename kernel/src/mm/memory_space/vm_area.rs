@@ -1,16 +1,23 @@
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
-use core::ops::{Range, RangeBounds};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    ops::{Range, RangeBounds},
+    ptr,
+};
 
 use arch::memory::sfence_vma_vaddr;
 use async_utils::block_on;
-use config::mm::{PAGE_SIZE, round_down_to_page};
-use memory::{VirtAddr, VirtPageNum, pte::PTEFlags};
+use config::mm::{HUGE_PAGE_PAGES, PAGE_SIZE, round_down_to_page};
+use memory::{PageLevel, VirtAddr, VirtPageNum, pte::PTEFlags};
 use page::Page;
 use systype::{SysError, SysResult};
 use vfs_core::File;
 
 use crate::{
-    mm::{PageFaultAccessType, PageTable},
+    mm::{PageFaultAccessType, PageTable, userfaultfd::UserfaultfdCtx},
     processor::env::SumGuard,
     syscall::MmapFlags,
 };
@@ -115,6 +122,10 @@ pub struct VmArea {
     pub backed_file: Option<Arc<dyn File>>,
     /// Start offset in the file.
     pub offset: usize,
+
+    // For userfaultfd.
+    /// The userfaultfd that handles faults on missing pages of this area.
+    pub uffd: Option<Weak<UserfaultfdCtx>>,
 }
 
 impl core::fmt::Debug for VmArea {
@@ -145,6 +156,7 @@ impl VmArea {
             backed_file: None,
             mmap_flags: MmapFlags::default(),
             offset: 0,
+            uffd: None,
         };
         log::debug!("[VmArea::new] {new:?}");
         new
@@ -166,6 +178,7 @@ impl VmArea {
             backed_file: file,
             mmap_flags,
             offset,
+            uffd: None,
         };
         log::debug!("[VmArea::new_mmap] {new:?}");
         new
@@ -181,6 +194,7 @@ impl VmArea {
             backed_file: another.backed_file.clone(),
             mmap_flags: another.mmap_flags,
             offset: another.offset,
+            uffd: another.uffd.clone(),
         }
     }
 
@@ -269,6 +283,13 @@ impl VmArea {
         }
     }
 
+    /// Map `page` at `vpn` with the permission of this area.
+    pub fn map_page(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, page: Arc<Page>) {
+        page_table.map(vpn, page.ppn(), self.map_perm.into());
+        unsafe { sfence_vma_vaddr(vpn.to_vaddr().into()) };
+        self.pages.insert(vpn, page);
+    }
+
     pub fn map_range(&mut self, page_table: &mut PageTable, range: Range<VirtAddr>) {
         let range_vpn = range.start.into()..range.end.into();
         assert!(self.start_vpn() <= range_vpn.start && self.end_vpn() >= range_vpn.end);
@@ -284,12 +305,185 @@ impl VmArea {
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         let vpns: Vec<_> = self.pages.keys().cloned().collect();
         for vpn in vpns {
-            page_table.unmap(vpn);
+            // Huge pages never cross the bounds of an area, so they are
+            // unmapped as a whole at their first page.
+            page_table.unmap_leaf(vpn);
             unsafe { sfence_vma_vaddr(vpn.to_vaddr().into()) };
             self.pages.remove(&vpn);
         }
     }
 
+    /// Whether writes to this area are seen by other memory spaces.
+    pub fn is_shared(&self) -> bool {
+        self.vma_type == VmAreaType::Shm || self.mmap_flags.contains(MmapFlags::MAP_SHARED)
+    }
+
+    /// Whether pages of this area are private and refault as zero.
+    pub fn is_private_anonymous(&self) -> bool {
+        match self.vma_type {
+            VmAreaType::Heap | VmAreaType::Stack => true,
+            // Shared anonymous mappings are `Shm` areas.
+            VmAreaType::Mmap => self.mmap_flags.contains(MmapFlags::MAP_ANONYMOUS),
+            _ => false,
+        }
+    }
+
+    /// The userfaultfd this area is registered with, if it is still open.
+    pub fn userfaultfd(&self) -> Option<Arc<UserfaultfdCtx>> {
+        self.uffd.as_ref().and_then(Weak::upgrade)
+    }
+
+    /// Whether this area is registered with the userfaultfd `ctx`.
+    pub fn is_registered_with(&self, ctx: &Arc<UserfaultfdCtx>) -> bool {
+        self.uffd
+            .as_ref()
+            .is_some_and(|uffd| ptr::eq(uffd.as_ptr(), Arc::as_ptr(ctx)))
+    }
+
+    /// Whether pages of this area may be mapped by huge pages, i.e. it is
+    /// private anonymous memory other than the stack.
+    pub fn can_be_huge(&self) -> bool {
+        match self.vma_type {
+            VmAreaType::Heap => true,
+            VmAreaType::Mmap => {
+                self.mmap_flags.contains(MmapFlags::MAP_ANONYMOUS)
+                    && !self.mmap_flags.contains(MmapFlags::MAP_SHARED)
+            }
+            _ => false,
+        }
+    }
+
+    /// Map a zeroed huge page containing `vpn` on a page fault, return
+    /// whether it did. It does not if the huge page is not all in this area,
+    /// if some of its pages are mapped already, or if there are no contiguous
+    /// frames for it, and the fault is handled with a page then.
+    fn fault_huge_page(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let start_vpn = VirtPageNum(vpn.0 / HUGE_PAGE_PAGES * HUGE_PAGE_PAGES);
+        let range_vpn = start_vpn..start_vpn + HUGE_PAGE_PAGES;
+        if range_vpn.start < self.start_vpn()
+            || range_vpn.end > self.end_vpn()
+            || self.pages.range(range_vpn.clone()).next().is_some()
+        {
+            return false;
+        }
+        let Some(pages) = Page::try_new_contiguous(HUGE_PAGE_PAGES) else {
+            log::debug!("[VmArea::fault_huge_page] no contiguous frames for {start_vpn:?}");
+            return false;
+        };
+        for page in pages.iter() {
+            page.fill_zero();
+        }
+        let pte_flags = self.map_perm.into();
+        if !page_table.map_huge(start_vpn, pages[0].ppn(), PageLevel::Mega, pte_flags) {
+            return false;
+        }
+        unsafe { sfence_vma_vaddr(start_vpn.to_vaddr().into()) };
+        self.pages.extend(range_vpn.zip(pages));
+        true
+    }
+
+    /// Pages of this area charged to the commit charge, see
+    /// [`Self::is_charged_with`].
+    pub fn commit_pages(&self) -> usize {
+        if self.is_charged_with(self.map_perm) {
+            self.end_vpn() - self.start_vpn()
+        } else {
+            0
+        }
+    }
+
+    /// Whether this area is charged to the commit charge with permission
+    /// `perm`, i.e. it is private and writable, since each page may get
+    /// copied on write.
+    ///
+    /// Shared memory is not charged, as its pages are shared by the attached
+    /// spaces rather than owned by one of them.
+    pub fn is_charged_with(&self, perm: MapPerm) -> bool {
+        match self.vma_type {
+            VmAreaType::Heap | VmAreaType::Stack => true,
+            VmAreaType::Elf => perm.contains(MapPerm::W),
+            VmAreaType::Mmap => {
+                perm.contains(MapPerm::W)
+                    && !self
+                        .mmap_flags
+                        .intersects(MmapFlags::MAP_SHARED | MmapFlags::MAP_NORESERVE)
+            }
+            VmAreaType::Shm => false,
+        }
+    }
+
+    /// Whether this area counts for `RLIMIT_DATA`.
+    pub fn is_data(&self) -> bool {
+        self.is_data_with(self.map_perm)
+    }
+
+    /// Whether this area counts for `RLIMIT_DATA` with permission `perm`, i.e.
+    /// it is private, writable and not the stack.
+    pub fn is_data_with(&self, perm: MapPerm) -> bool {
+        match self.vma_type {
+            VmAreaType::Heap => true,
+            VmAreaType::Elf => perm.contains(MapPerm::W),
+            VmAreaType::Mmap => {
+                perm.contains(MapPerm::W) && !self.mmap_flags.contains(MmapFlags::MAP_SHARED)
+            }
+            VmAreaType::Stack | VmAreaType::Shm => false,
+        }
+    }
+
+    /// Whether the page fault handler brings back pages dropped from this
+    /// area. Other areas are mapped in full when created.
+    pub fn can_refault(&self) -> bool {
+        matches!(
+            self.vma_type,
+            VmAreaType::Heap | VmAreaType::Stack | VmAreaType::Mmap
+        )
+    }
+
+    /// Whether this area grows down on a fault right below it, i.e. a stack
+    /// or a private anonymous `MAP_GROWSDOWN` mapping.
+    pub fn can_grow_down(&self) -> bool {
+        match self.vma_type {
+            VmAreaType::Stack => true,
+            VmAreaType::Mmap => {
+                self.mmap_flags.contains(MmapFlags::MAP_GROWSDOWN)
+                    && self.backed_file.is_none()
+                    && !self.mmap_flags.contains(MmapFlags::MAP_SHARED)
+            }
+            _ => false,
+        }
+    }
+
+    /// Drop the pages in `range_vpn`, so that they refault as zero or from the
+    /// backing file.
+    pub fn discard_pages(&mut self, page_table: &mut PageTable, range_vpn: Range<VirtPageNum>) {
+        page_table.split_at(range_vpn.start);
+        page_table.split_at(range_vpn.end);
+        let vpns: Vec<_> = self.pages.range(range_vpn).map(|(&vpn, _)| vpn).collect();
+        for vpn in vpns {
+            page_table.unmap_leaf(vpn);
+            unsafe { sfence_vma_vaddr(vpn.to_vaddr().into()) };
+            self.pages.remove(&vpn);
+        }
+    }
+
+    /// Read the file pages in `range_vpn` not mapped yet into the page cache.
+    pub fn prefetch_pages(&self, range_vpn: Range<VirtPageNum>) -> SysResult<()> {
+        let Some(file) = self.backed_file.as_ref() else {
+            return Ok(());
+        };
+        for vpn in range_vpn {
+            if self.pages.contains_key(&vpn) {
+                continue;
+            }
+            let offset = self.offset + (vpn - self.start_vpn()) * PAGE_SIZE;
+            if block_on(async { file.get_page_at(offset).await })?.is_none() {
+                // past the end of the file
+                break;
+            }
+        }
+        Ok(())
+    }
+
     /// Copy the data to start_va + offset.
     ///
     /// # Safety
@@ -396,6 +590,8 @@ impl VmArea {
         }
 
         let page: Arc<Page>;
+        // Copy on write is done with pages, so a huge page is split first.
+        page_table.split(vpn);
         let pte = page_table.find_leaf_pte(vpn);
         if let Some(pte) = pte {
             // if PTE is valid, then it must be COW
@@ -416,7 +612,7 @@ impl VmArea {
                 );
 
                 // copy the data
-                page = Page::new();
+                page = Page::try_new().ok_or(SysError::ENOMEM)?;
                 page.copy_from_slice(old_page.bytes_array());
 
                 // unmap old page and map new page
@@ -436,6 +632,8 @@ impl VmArea {
                 pte.set_flags(pte_flags);
                 unsafe { sfence_vma_vaddr(vpn.to_vaddr().into()) };
             }
+        } else if self.can_be_huge() && self.fault_huge_page(page_table, vpn) {
+            log::debug!("[VmArea::handle_page_fault] mapped a huge page for {vpn:?}");
         } else {
             log::debug!(
                 "[VmArea::handle_page_fault] handle for type {:?}",
@@ -444,7 +642,7 @@ impl VmArea {
             match self.vma_type {
                 VmAreaType::Heap | VmAreaType::Stack => {
                     // lazy allcation for heap
-                    page = Page::new();
+                    page = Page::try_new().ok_or(SysError::ENOMEM)?;
                     page.fill_zero();
                     page_table.map(vpn, page.ppn(), self.map_perm.into());
                     self.pages.insert(vpn, page);
@@ -466,7 +664,7 @@ impl VmArea {
                             let page = block_on(async { file.get_page_at(offset_aligned).await })?
                                 .unwrap();
                             if access_type.contains(PageFaultAccessType::WRITE) {
-                                let new_page = Page::new();
+                                let new_page = Page::try_new().ok_or(SysError::ENOMEM)?;
                                 new_page.copy_from_slice(page.bytes_array());
                                 page_table.map(vpn, new_page.ppn(), self.map_perm.into());
                                 self.pages.insert(vpn, new_page);
@@ -487,7 +685,7 @@ impl VmArea {
                             todo!()
                         } else {
                             // private anonymous area
-                            page = Page::new();
+                            page = Page::try_new().ok_or(SysError::ENOMEM)?;
                             page.fill_zero();
                             page_table.map(vpn, page.ppn(), self.map_perm.into());
                             self.pages.insert(vpn, page);
@@ -7,7 +7,9 @@
 //! Every task or process has a memory_space to control its virtual memory.
 
 pub mod memory_space;
+pub mod oom;
 mod user_ptr;
+pub mod userfaultfd;
 
 use core::cmp;
 
@@ -16,19 +18,25 @@ use config::{
     mm::{K_SEG_DTB_BEG, MAX_DTB_SIZE, VIRT_RAM_OFFSET},
 };
 pub use memory::page_table::PageTable;
-use memory::{VirtAddr, frame, heap, pte::PTEFlags};
+use memory::{VirtAddr, frame, heap, pte::PTEFlags, slab};
 pub use memory_space::MemorySpace;
+use page::Page;
 use sync::cell::static_cell::StaticCell;
 pub use user_ptr::{
     FutexAddr, PageFaultAccessType, UserMut, UserRdWrPtr, UserReadPtr, UserSlice, UserWritePtr,
 };
 
+use crate::task::Task;
+
 /// Initialize heap allocator, frame allocator and kernel page table.
 pub fn init() {
     unsafe extern "C" {
         fn _ekernel();
     }
     heap::init_heap_allocator();
+    // Tasks and pages are made and dropped all the time.
+    slab::create_slab_cache("task", slab::arc_layout::<Task>());
+    slab::create_slab_cache("page", slab::arc_layout::<Page>());
     frame::init_frame_allocator(
         VirtAddr::from(_ekernel as usize).to_paddr().ceil(),
         VirtAddr::from(MEMORY_END).to_paddr().floor(),
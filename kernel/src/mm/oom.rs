@@ -0,0 +1,106 @@
+//! Out-of-memory killer.
+//!
+//! When a page fault of a user task finds no frame left, the process with the
+//! highest badness is sent SIGKILL, and the faulting task waits until the
+//! victim has released its memory before it retries. The badness of a process
+//! is its resident pages, moved by `oom_score_adj` in thousandths of the
+//! memory, the same as in Linux.
+
+use alloc::sync::{Arc, Weak};
+
+use async_utils::yield_now;
+use config::{mm::PAGE_SIZE, process::INIT_PROC_PID};
+use memory::total_frames;
+use signal::{Sig, SigDetails, SigInfo};
+use sync::mutex::SpinNoIrqLock;
+
+use crate::task::{TASK_MANAGER, Task};
+
+/// `oom_score_adj` of a process never killed.
+pub const OOM_SCORE_ADJ_MIN: i32 = -1000;
+pub const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
+/// The process killed last, waited for by other tasks running out of memory
+/// rather than killing one more.
+static VICTIM: SpinNoIrqLock<Option<Weak<Task>>> = SpinNoIrqLock::new(None);
+
+/// Badness of the process led by `task`, `None` if it is never killed.
+fn badness(task: &Arc<Task>) -> Option<usize> {
+    let adj = task.oom_score_adj();
+    if task.tid() == INIT_PROC_PID || adj == OOM_SCORE_ADJ_MIN || task.is_zombie() {
+        return None;
+    }
+    let resident = task.with_memory_space(|m| m.resident_pages()) as isize;
+    let points = resident + adj as isize * total_frames() as isize / 1000;
+    // A process that may be killed scores at least 1.
+    Some(points.max(1) as usize)
+}
+
+/// `/proc/<pid>/oom_score` of the process led by `task`, the badness scaled to
+/// `0..=1000`.
+pub fn oom_score(task: &Arc<Task>) -> usize {
+    badness(task).map_or(0, |points| {
+        (points * 1000 / total_frames().max(1)).min(1000)
+    })
+}
+
+/// The process with the highest badness.
+fn select_victim() -> Option<Arc<Task>> {
+    TASK_MANAGER
+        .tasks()
+        .into_iter()
+        .filter(|t| t.is_leader())
+        .filter_map(|t| badness(&t).map(|points| (points, t)))
+        .max_by_key(|(points, _)| *points)
+        .map(|(_, t)| t)
+}
+
+/// Whether `victim` has released its memory.
+fn has_released(victim: &Weak<Task>) -> bool {
+    victim.upgrade().is_none_or(|t| t.is_zombie())
+}
+
+/// Kill a process to get memory back for `task`, which found no frame left,
+/// and wait until it is released. The task kills its own process if it is
+/// the worst one, or if there is no other one.
+pub async fn out_of_memory(task: &Arc<Task>) {
+    let victim = {
+        let mut last_victim = VICTIM.lock();
+        match last_victim.as_ref().filter(|v| !has_released(v)) {
+            Some(victim) => victim.clone(),
+            None => {
+                let victim = select_victim().unwrap_or_else(|| task.leader());
+                kill(&victim);
+                let victim = Arc::downgrade(&victim);
+                *last_victim = Some(victim.clone());
+                victim
+            }
+        }
+    };
+    if victim.upgrade().is_some_and(|v| v.pid() == task.pid()) {
+        return;
+    }
+    while !has_released(&victim) && !task.is_terminated() {
+        yield_now().await;
+    }
+}
+
+fn kill(victim: &Arc<Task>) {
+    let (total_vm, resident) = victim.with_memory_space(|m| (m.total_pages(), m.resident_pages()));
+    log::error!(
+        "Out of memory: Killed process {} ({}) total-vm:{}kB, rss:{}kB, oom_score_adj:{}",
+        victim.pid(),
+        victim.elf().dentry().name(),
+        total_vm * PAGE_SIZE / 1024,
+        resident * PAGE_SIZE / 1024,
+        victim.oom_score_adj(),
+    );
+    victim.receive_siginfo(
+        SigInfo {
+            sig: Sig::SIGKILL,
+            code: SigInfo::KERNEL,
+            details: SigDetails::None,
+        },
+        false,
+    );
+}
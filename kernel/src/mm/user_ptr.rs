@@ -22,7 +22,7 @@ use super::memory_space::vm_area::MapPerm;
 use crate::{
     net::{
         SaFamily,
-        addr::{SockAddr, SockAddrIn, SockAddrIn6, SockAddrUn},
+        addr::{SockAddr, SockAddrIn, SockAddrIn6, SockAddrLl, SockAddrUn},
     },
     processor::{env::SumGuard, hart::current_task_ref},
     task::Task,
@@ -655,6 +655,15 @@ impl Task {
                     ipv6: unsafe { *(addr as *const _) },
                 })
             }
+            SaFamily::AF_PACKET => {
+                if unlikely(addrlen < mem::size_of::<SockAddrLl>()) {
+                    log::error!("[audit_sockaddr] AF_PACKET addrlen error");
+                    return Err(SysError::EINVAL);
+                }
+                Ok(SockAddr {
+                    ll: unsafe { *(addr as *const _) },
+                })
+            }
         }
     }
 
@@ -685,6 +694,11 @@ impl Task {
                     UserWritePtr::<u32>::from(addrlen)
                         .write(self, mem::size_of::<SockAddrUn>() as u32)?;
                 }
+                SaFamily::AF_PACKET => {
+                    UserWritePtr::<SockAddrLl>::from(addr).write(self, sockaddr.ll)?;
+                    UserWritePtr::<u32>::from(addrlen)
+                        .write(self, mem::size_of::<SockAddrLl>() as u32)?;
+                }
             }
         }
         Ok(())
@@ -0,0 +1,531 @@
+//! userfaultfd(2), with which user space handles the page faults in ranges of
+//! its memory space it registers.
+//!
+//! A fault on a missing page of a registered area is not handled by
+//! `VmArea::handle_page_fault`: the faulting task queues a message to be read
+//! from the userfaultfd and sleeps, until the handler maps the page with
+//! `UFFDIO_COPY` or `UFFDIO_ZEROPAGE`, or wakes it with `UFFDIO_WAKE`. The task
+//! then returns to user mode and the instruction runs again, so a task woken
+//! before its page is there just faults to the handler once more.
+//!
+//! Areas hold the context weakly, so once the userfaultfd is closed their
+//! faults are handled as usual again, and the tasks waiting on it are woken.
+//!
+//! Faults of the kernel on user memory, e.g. when `read(2)` fills a registered
+//! buffer, can not sleep for the handler and fail with `EFAULT` instead.
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    mem::size_of,
+    ops::Range,
+    pin::Pin,
+    ptr, slice,
+    task::{Context, Poll, Waker},
+};
+
+use async_trait::async_trait;
+use async_utils::{get_waker, suspend_now};
+use config::mm::{PAGE_SIZE, is_aligned_to_page};
+use memory::{VirtAddr, VirtPageNum};
+use page::Page;
+use strum::FromRepr;
+use sync::mutex::SpinNoIrqLock;
+use systype::{SysError, SysResult, SyscallResult};
+use vfs_core::{File, FileMeta, OpenFlags, PollEvents, arc_zero};
+
+use super::{PageFaultAccessType, UserReadPtr};
+use crate::{
+    processor::hart::current_task,
+    task::{Task, Tid},
+};
+
+/// Version of the API, checked by `UFFDIO_API`.
+const UFFD_API: u64 = 0xaa;
+
+/// Defined in <linux/userfaultfd.h>
+#[derive(FromRepr, Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+#[repr(usize)]
+pub enum UffdIoctlCmd {
+    UFFDIO_REGISTER = 0xc020aa00,
+    UFFDIO_UNREGISTER = 0x8010aa01,
+    UFFDIO_WAKE = 0x8010aa02,
+    UFFDIO_COPY = 0xc028aa03,
+    UFFDIO_ZEROPAGE = 0xc020aa04,
+    UFFDIO_API = 0xc018aa3f,
+}
+
+/// `ioctls` returned by `UFFDIO_API`, a bit for each `_UFFDIO_*` number
+/// supported on the userfaultfd.
+const UFFD_API_IOCTLS: u64 = (1 << 0x00) | (1 << 0x01) | (1 << 0x3f);
+/// `ioctls` returned by `UFFDIO_REGISTER`, those supported on the range.
+const UFFD_API_RANGE_IOCTLS: u64 = (1 << 0x02) | (1 << 0x03) | (1 << 0x04);
+
+/// Faults on missing pages, the only mode supported.
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1 << 0;
+/// Do not wake the tasks waiting for the range, for both `UFFDIO_COPY` and
+/// `UFFDIO_ZEROPAGE`.
+const UFFDIO_MODE_DONTWAKE: u64 = 1 << 0;
+
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+const UFFD_PAGEFAULT_FLAG_WRITE: u64 = 1 << 0;
+
+bitflags! {
+    /// Features negotiated by `UFFDIO_API`.
+    #[derive(Debug, Clone, Copy)]
+    struct UffdFeatures: u64 {
+        /// Report the thread id of the faulting task.
+        const THREAD_ID = 1 << 8;
+        /// Report the faulting address as is, rather than its page.
+        const EXACT_ADDRESS = 1 << 11;
+    }
+}
+
+/// `struct uffdio_api`
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+/// `struct uffdio_range`
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+/// `struct uffdio_register`
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+/// `struct uffdio_copy`
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    /// Bytes copied, or a negated error number.
+    copy: i64,
+}
+
+/// `struct uffdio_zeropage`
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UffdioZeropage {
+    range: UffdioRange,
+    mode: u64,
+    /// Bytes zeroed, or a negated error number.
+    zeropage: i64,
+}
+
+/// `struct uffd_msg` of a page fault, the only event reported.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UffdMsg {
+    event: u8,
+    reserved1: u8,
+    reserved2: u16,
+    reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u32,
+    pad: u32,
+}
+
+impl UffdMsg {
+    fn page_fault(features: UffdFeatures, tid: Tid, va: VirtAddr, write: bool) -> Self {
+        let address = if features.contains(UffdFeatures::EXACT_ADDRESS) {
+            va.bits()
+        } else {
+            va.round_down().bits()
+        };
+        Self {
+            event: UFFD_EVENT_PAGEFAULT,
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+            flags: if write { UFFD_PAGEFAULT_FLAG_WRITE } else { 0 },
+            address: address as u64,
+            ptid: if features.contains(UffdFeatures::THREAD_ID) {
+                tid as u32
+            } else {
+                0
+            },
+            pad: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
+
+/// A task sleeping until the handler resolves its fault.
+struct FaultWaiter {
+    tid: Tid,
+    vpn: VirtPageNum,
+    waker: Waker,
+}
+
+struct UffdInner {
+    /// Features negotiated by `UFFDIO_API`, `None` before it.
+    features: Option<UffdFeatures>,
+    /// Faults not read yet, with the tasks they are of.
+    pending: VecDeque<(Tid, UffdMsg)>,
+    /// Tasks waiting for their faults to be resolved, read or not.
+    waiters: Vec<FaultWaiter>,
+    /// Readers waiting for faults.
+    read_wakers: VecDeque<Waker>,
+}
+
+/// Context of a userfaultfd, which areas registered with it refer to.
+pub struct UserfaultfdCtx {
+    inner: SpinNoIrqLock<UffdInner>,
+}
+
+impl UserfaultfdCtx {
+    fn new() -> Self {
+        Self {
+            inner: SpinNoIrqLock::new(UffdInner {
+                features: None,
+                pending: VecDeque::new(),
+                waiters: Vec::new(),
+                read_wakers: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Queue the fault of `task` at `va`, which sleeps for it then.
+    fn queue_fault(&self, task: &Arc<Task>, va: VirtAddr, access_type: PageFaultAccessType) {
+        let mut inner = self.inner.lock();
+        let features = inner.features.unwrap_or(UffdFeatures::empty());
+        let write = access_type.contains(PageFaultAccessType::WRITE);
+        inner.pending.push_back((
+            task.tid(),
+            UffdMsg::page_fault(features, task.tid(), va, write),
+        ));
+        inner.waiters.push(FaultWaiter {
+            tid: task.tid(),
+            vpn: va.floor(),
+            waker: task.waker().clone().unwrap(),
+        });
+        while let Some(waker) = inner.read_wakers.pop_front() {
+            waker.wake();
+        }
+    }
+
+    /// Forget the fault of `tid`, whether it was read or not.
+    fn remove_fault(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        inner.pending.retain(|(t, _)| *t != tid);
+        inner.waiters.retain(|waiter| waiter.tid != tid);
+    }
+
+    /// Wake the tasks waiting for faults in `range`.
+    fn wake(&self, range: Range<VirtPageNum>) {
+        self.inner.lock().waiters.retain(|waiter| {
+            if range.contains(&waiter.vpn) {
+                waiter.waker.wake_by_ref();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl Drop for UserfaultfdCtx {
+    fn drop(&mut self) {
+        // Their faults are handled as usual when they fault again.
+        for waiter in self.inner.lock().waiters.drain(..) {
+            waiter.waker.wake();
+        }
+    }
+}
+
+/// Deliver the fault of `task` at `va` to the userfaultfd it is registered
+/// with, if any, and sleep until the handler wakes it up or a signal comes.
+/// Return whether it was delivered.
+pub async fn handle_userfault(
+    task: &Arc<Task>,
+    va: VirtAddr,
+    access_type: PageFaultAccessType,
+) -> bool {
+    // Queued with the memory space locked, so that the page can not be
+    // filled in between without waking the task.
+    let ctx = task.with_memory_space(|m| {
+        let ctx = m.userfault(va, access_type)?;
+        ctx.queue_fault(task, va, access_type);
+        Some(Arc::downgrade(&ctx))
+    });
+    let Some(ctx) = ctx else {
+        return false;
+    };
+    log::info!("[handle_userfault] task {} waits for {va:?}", task.tid());
+    task.set_interruptable();
+    task.set_wake_up_signal(!*task.sig_mask_ref());
+    suspend_now().await;
+    task.set_running();
+    // Woken up by the handler, a signal or the close of the userfaultfd. The
+    // fault is delivered again if the page is still missing.
+    if let Some(ctx) = ctx.upgrade() {
+        ctx.remove_fault(task.tid());
+    }
+    true
+}
+
+struct UffdReadFuture<'a> {
+    ctx: &'a UserfaultfdCtx,
+    max_msgs: usize,
+    nonblock: bool,
+}
+
+impl Future for UffdReadFuture<'_> {
+    type Output = SysResult<Vec<UffdMsg>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.ctx.inner.lock();
+        if !inner.pending.is_empty() {
+            let len = self.max_msgs.min(inner.pending.len());
+            Poll::Ready(Ok(inner.pending.drain(..len).map(|(_, msg)| msg).collect()))
+        } else if self.nonblock {
+            Poll::Ready(Err(SysError::EAGAIN))
+        } else {
+            inner.read_wakers.push_back(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A userfaultfd, from which the faults of the areas registered with it are
+/// read, and on which its ioctls resolve them.
+pub struct Userfaultfd {
+    meta: FileMeta,
+    ctx: Arc<UserfaultfdCtx>,
+}
+
+impl Userfaultfd {
+    pub fn new(flags: OpenFlags) -> Self {
+        let meta = FileMeta::new(arc_zero(), arc_zero());
+        *meta.flags.lock() = OpenFlags::O_RDWR | (flags & OpenFlags::O_NONBLOCK);
+        Self {
+            meta,
+            ctx: Arc::new(UserfaultfdCtx::new()),
+        }
+    }
+
+    fn api(&self, arg: *mut UffdioApi) -> SyscallResult {
+        let mut api = unsafe { ptr::read_unaligned(arg) };
+        let features = UffdFeatures::from_bits(api.features);
+        let mut inner = self.ctx.inner.lock();
+        let ret = match features {
+            _ if inner.features.is_some() => Err(SysError::EINVAL),
+            Some(features) if api.api == UFFD_API => {
+                inner.features = Some(features);
+                api.features = UffdFeatures::all().bits();
+                api.ioctls = UFFD_API_IOCTLS;
+                Ok(0)
+            }
+            _ => {
+                api.features = 0;
+                api.ioctls = 0;
+                Err(SysError::EINVAL)
+            }
+        };
+        unsafe { ptr::write_unaligned(arg, api) };
+        ret
+    }
+
+    fn register(&self, arg: *mut UffdioRegister) -> SyscallResult {
+        let mut reg = unsafe { ptr::read_unaligned(arg) };
+        if reg.mode != UFFDIO_REGISTER_MODE_MISSING {
+            log::warn!("[Userfaultfd::register] mode {:#x} not supported", reg.mode);
+            return Err(SysError::EINVAL);
+        }
+        let range = user_range(reg.range.start, reg.range.len)?;
+        current_task().with_mut_memory_space(|m| m.userfault_register(range, &self.ctx))?;
+        reg.ioctls = UFFD_API_RANGE_IOCTLS;
+        unsafe { ptr::write_unaligned(arg, reg) };
+        Ok(0)
+    }
+
+    fn unregister(&self, arg: *const UffdioRange) -> SyscallResult {
+        let range = unsafe { ptr::read_unaligned(arg) };
+        let range = user_range(range.start, range.len)?;
+        current_task()
+            .with_mut_memory_space(|m| m.userfault_unregister(range.clone(), &self.ctx))?;
+        self.ctx.wake(range.start.floor()..range.end.ceil());
+        Ok(0)
+    }
+
+    fn wake(&self, arg: *const UffdioRange) -> SyscallResult {
+        let range = unsafe { ptr::read_unaligned(arg) };
+        let range = user_range(range.start, range.len)?;
+        self.ctx.wake(range.start.floor()..range.end.ceil());
+        Ok(0)
+    }
+
+    fn copy(&self, arg: *mut UffdioCopy) -> SyscallResult {
+        let mut copy = unsafe { ptr::read_unaligned(arg) };
+        if copy.mode & !UFFDIO_MODE_DONTWAKE != 0 || !is_aligned_to_page(copy.src as usize) {
+            return Err(SysError::EINVAL);
+        }
+        let range = user_range(copy.dst, copy.len)?;
+        let ret = self.fill(range.clone(), Some(copy.src as usize));
+        copy.copy = ret.map_or_else(|e| -(e as i64), |len| len as i64);
+        unsafe { ptr::write_unaligned(arg, copy) };
+        self.finish_fill(range, ret?, copy.mode)
+    }
+
+    fn zeropage(&self, arg: *mut UffdioZeropage) -> SyscallResult {
+        let mut zeropage = unsafe { ptr::read_unaligned(arg) };
+        if zeropage.mode & !UFFDIO_MODE_DONTWAKE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let range = user_range(zeropage.range.start, zeropage.range.len)?;
+        let ret = self.fill(range.clone(), None);
+        zeropage.zeropage = ret.map_or_else(|e| -(e as i64), |len| len as i64);
+        unsafe { ptr::write_unaligned(arg, zeropage) };
+        self.finish_fill(range, ret?, zeropage.mode)
+    }
+
+    /// Map new pages at the missing pages of `range`, copied from user memory
+    /// at `src` or zeroed. Return the bytes mapped, which stop at the first
+    /// page that can not be, e.g. one mapped already.
+    fn fill(&self, range: Range<VirtAddr>, src: Option<usize>) -> SysResult<usize> {
+        let task = current_task();
+        let mut filled = 0;
+        for vpn in range.start.floor()..range.end.ceil() {
+            match self.fill_page(&task, vpn, src.map(|src| src + filled)) {
+                Ok(()) => filled += PAGE_SIZE,
+                Err(e) if filled == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(filled)
+    }
+
+    fn fill_page(&self, task: &Arc<Task>, vpn: VirtPageNum, src: Option<usize>) -> SysResult<()> {
+        let page = Page::try_new().ok_or(SysError::ENOMEM)?;
+        match src {
+            Some(src) => {
+                page.copy_from_slice(&UserReadPtr::<u8>::from(src).into_slice(task, PAGE_SIZE)?)
+            }
+            None => page.fill_zero(),
+        }
+        task.with_mut_memory_space(|m| m.userfault_fill(vpn, page, &self.ctx))
+    }
+
+    /// Wake the tasks waiting for the `filled` bytes at the start of `range`
+    /// unless told not to, and fail with `EAGAIN` if not all of it was.
+    fn finish_fill(&self, range: Range<VirtAddr>, filled: usize, mode: u64) -> SyscallResult {
+        if mode & UFFDIO_MODE_DONTWAKE == 0 {
+            self.ctx
+                .wake(range.start.floor()..(range.start + filled).ceil());
+        }
+        if range.start + filled == range.end {
+            Ok(0)
+        } else {
+            Err(SysError::EAGAIN)
+        }
+    }
+}
+
+/// Range of `len` bytes at `start`, both page aligned.
+fn user_range(start: u64, len: u64) -> SysResult<Range<VirtAddr>> {
+    let (start, len) = (start as usize, len as usize);
+    if len == 0 || !is_aligned_to_page(start) || !is_aligned_to_page(len) {
+        return Err(SysError::EINVAL);
+    }
+    let end = start.checked_add(len).ok_or(SysError::EINVAL)?;
+    Ok(VirtAddr::from(start)..VirtAddr::from(end))
+}
+
+#[async_trait]
+impl File for Userfaultfd {
+    fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    async fn base_read_at(&self, _offset: usize, _buf: &mut [u8]) -> SyscallResult {
+        unreachable!()
+    }
+
+    async fn base_write_at(&self, _offset: usize, _buf: &[u8]) -> SyscallResult {
+        unreachable!()
+    }
+
+    async fn read_at(&self, _offset: usize, buf: &mut [u8]) -> SyscallResult {
+        const MSG_SIZE: usize = size_of::<UffdMsg>();
+        if buf.len() < MSG_SIZE || self.ctx.inner.lock().features.is_none() {
+            return Err(SysError::EINVAL);
+        }
+        let msgs = UffdReadFuture {
+            ctx: &self.ctx,
+            max_msgs: buf.len() / MSG_SIZE,
+            nonblock: self.meta.flags.lock().contains(OpenFlags::O_NONBLOCK),
+        }
+        .await?;
+        for (msg, dst) in msgs.iter().zip(buf.chunks_exact_mut(MSG_SIZE)) {
+            dst.copy_from_slice(msg.as_bytes());
+        }
+        Ok(msgs.len() * MSG_SIZE)
+    }
+
+    async fn write_at(&self, _offset: usize, _buf: &[u8]) -> SyscallResult {
+        Err(SysError::EINVAL)
+    }
+
+    async fn base_poll(&self, events: PollEvents) -> PollEvents {
+        let waker = get_waker().await;
+        let mut inner = self.ctx.inner.lock();
+        if inner.features.is_none() {
+            return PollEvents::ERR;
+        }
+        let mut res = PollEvents::empty();
+        if events.contains(PollEvents::IN) && !inner.pending.is_empty() {
+            res |= PollEvents::IN;
+        } else {
+            inner.read_wakers.push_back(waker);
+        }
+        res
+    }
+
+    fn ioctl(&self, cmd: usize, arg: usize) -> SyscallResult {
+        use UffdIoctlCmd::*;
+        let Some(cmd) = UffdIoctlCmd::from_repr(cmd) else {
+            log::warn!("[Userfaultfd::ioctl] cmd {cmd:#x} not supported");
+            return Err(SysError::EINVAL);
+        };
+        log::info!("[Userfaultfd::ioctl] cmd {cmd:?}, arg {arg:#x}");
+        if arg == 0 {
+            return Err(SysError::EFAULT);
+        }
+        if let UFFDIO_API = cmd {
+            return self.api(arg as *mut UffdioApi);
+        }
+        if self.ctx.inner.lock().features.is_none() {
+            return Err(SysError::EINVAL);
+        }
+        match cmd {
+            UFFDIO_REGISTER => self.register(arg as *mut UffdioRegister),
+            UFFDIO_UNREGISTER => self.unregister(arg as *const UffdioRange),
+            UFFDIO_WAKE => self.wake(arg as *const UffdioRange),
+            UFFDIO_COPY => self.copy(arg as *mut UffdioCopy),
+            UFFDIO_ZEROPAGE => self.zeropage(arg as *mut UffdioZeropage),
+            UFFDIO_API => unreachable!(),
+        }
+    }
+}
@@ -9,7 +9,12 @@ use core::{
     panic,
 };
 
-use net::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv6Address};
+use net::{
+    IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv6Address,
+    addr::{to_ipv4_mapped, unmap_ipv4},
+    packet::LinkAddr,
+};
+use systype::{SysError, SysResult};
 
 use super::SaFamily;
 
@@ -88,6 +93,60 @@ impl fmt::Display for SockAddrUn {
     }
 }
 
+#[derive(Clone, Copy)]
+#[repr(C)]
+/// Link level address of a packet socket
+pub struct SockAddrLl {
+    pub family: u16,
+    /// Ethernet type in network byte order
+    pub protocol: [u8; 2],
+    pub ifindex: i32,
+    pub hatype: u16,
+    pub pkttype: u8,
+    pub halen: u8,
+    pub addr: [u8; 8],
+}
+
+impl fmt::Display for SockAddrLl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let halen = (self.halen as usize).min(self.addr.len());
+        write!(
+            f,
+            "AF_PACKET: ifindex {} protocol {:#06x} addr {:02x?}",
+            self.ifindex,
+            u16::from_be_bytes(self.protocol),
+            &self.addr[..halen]
+        )
+    }
+}
+
+impl From<SockAddrLl> for LinkAddr {
+    fn from(ll: SockAddrLl) -> Self {
+        Self {
+            ifindex: ll.ifindex.max(0) as usize,
+            protocol: u16::from_be_bytes(ll.protocol),
+            hatype: ll.hatype,
+            pkttype: ll.pkttype,
+            halen: ll.halen,
+            addr: ll.addr,
+        }
+    }
+}
+
+impl From<LinkAddr> for SockAddrLl {
+    fn from(addr: LinkAddr) -> Self {
+        Self {
+            family: SaFamily::AF_PACKET.into(),
+            protocol: addr.protocol.to_be_bytes(),
+            ifindex: addr.ifindex as i32,
+            hatype: addr.hatype,
+            pkttype: addr.pkttype,
+            halen: addr.halen,
+            addr: addr.addr,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 /// `SockAddr` is a superset of `SocketAddr` in `core::net` since it also
@@ -99,6 +158,7 @@ pub union SockAddr {
     pub ipv4: SockAddrIn,
     pub ipv6: SockAddrIn6,
     pub unix: SockAddrUn,
+    pub ll: SockAddrLl,
 }
 
 impl fmt::Display for SockAddr {
@@ -108,6 +168,7 @@ impl fmt::Display for SockAddr {
                 1 => write!(f, "{}", self.unix),  // AF_UNIX
                 2 => write!(f, "{}", self.ipv4),  // AF_INET
                 10 => write!(f, "{}", self.ipv6), // AF_INET6
+                17 => write!(f, "{}", self.ll),   // AF_PACKET
                 _ => write!(f, "Unknown address family: {}", self.family),
             }
         }
@@ -116,6 +177,8 @@ impl fmt::Display for SockAddr {
 
 impl SockAddr {
     /// You should make sure that `SockAddr` is IpEndpoint
+    ///
+    /// An IPv4-mapped IPv6 address is turned into the IPv4 address it maps.
     pub fn into_endpoint(&self) -> IpEndpoint {
         unsafe {
             match SaFamily::try_from(self.family).unwrap() {
@@ -123,11 +186,8 @@ impl SockAddr {
                     IpAddress::Ipv4(Ipv4Address(self.ipv4.addr)),
                     u16::from_be_bytes(self.ipv4.port),
                 ),
-                SaFamily::AF_INET6 => IpEndpoint::new(
-                    IpAddress::Ipv6(Ipv6Address(self.ipv6.addr)),
-                    u16::from_be_bytes(self.ipv6.port),
-                ),
-                SaFamily::AF_UNIX => panic!("Shouldn't get there"),
+                SaFamily::AF_INET6 => self.ipv6.into(),
+                SaFamily::AF_UNIX | SaFamily::AF_PACKET => panic!("Shouldn't get there"),
             }
         }
     }
@@ -137,11 +197,23 @@ impl SockAddr {
             match SaFamily::try_from(self.family).unwrap() {
                 SaFamily::AF_INET => self.ipv4.into(),
                 SaFamily::AF_INET6 => self.ipv6.into(),
-                SaFamily::AF_UNIX => panic!("Shouldn't get there"),
+                SaFamily::AF_UNIX | SaFamily::AF_PACKET => panic!("Shouldn't get there"),
             }
         }
     }
 
+    /// Fails with `EINVAL` if `SockAddr` is not `AF_PACKET`
+    pub fn into_link_addr(&self) -> SysResult<LinkAddr> {
+        if unsafe { self.family } != u16::from(SaFamily::AF_PACKET) {
+            return Err(SysError::EINVAL);
+        }
+        Ok(unsafe { self.ll.into() })
+    }
+
+    pub fn from_link_addr(addr: LinkAddr) -> Self {
+        Self { ll: addr.into() }
+    }
+
     pub fn from_endpoint(endpoint: IpEndpoint) -> Self {
         match endpoint.addr {
             IpAddress::Ipv4(v4) => Self {
@@ -152,6 +224,22 @@ impl SockAddr {
             },
         }
     }
+
+    /// Convert an address to the form a socket of `family` reports to user
+    /// space, i.e. an `AF_INET6` socket sees IPv4 peers as IPv4-mapped
+    /// addresses.
+    pub fn to_family(self, family: SaFamily) -> Self {
+        if family != SaFamily::AF_INET6 || unsafe { self.family } != u16::from(SaFamily::AF_INET) {
+            return self;
+        }
+        let endpoint = IpEndpoint::from(unsafe { self.ipv4 });
+        let addr = match endpoint.addr {
+            IpAddress::Ipv4(v4) if v4.is_unspecified() => Ipv6Address::UNSPECIFIED,
+            IpAddress::Ipv4(v4) => to_ipv4_mapped(v4),
+            IpAddress::Ipv6(v6) => v6,
+        };
+        Self::from_endpoint(IpEndpoint::new(IpAddress::Ipv6(addr), endpoint.port))
+    }
 }
 
 impl From<SockAddrIn> for IpEndpoint {
@@ -166,7 +254,7 @@ impl From<SockAddrIn> for IpEndpoint {
 impl From<SockAddrIn6> for IpEndpoint {
     fn from(v6: SockAddrIn6) -> Self {
         IpEndpoint::new(
-            IpAddress::Ipv6(Ipv6Address(v6.addr)),
+            unmap_ipv4(IpAddress::Ipv6(Ipv6Address(v6.addr))),
             u16::from_be_bytes(v6.port),
         )
     }
@@ -221,11 +309,11 @@ impl From<SockAddrIn> for IpListenEndpoint {
 
 impl From<SockAddrIn6> for IpListenEndpoint {
     fn from(v6: SockAddrIn6) -> Self {
-        let addr = Ipv6Address(v6.addr);
+        let addr = unmap_ipv4(IpAddress::Ipv6(Ipv6Address(v6.addr)));
         let addr = if addr.is_unspecified() {
             None
         } else {
-            Some(IpAddress::Ipv6(addr))
+            Some(addr)
         };
         Self {
             addr,
@@ -252,6 +340,12 @@ impl fmt::Debug for SockAddrUn {
     }
 }
 
+impl fmt::Debug for SockAddrLl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 impl fmt::Debug for SockAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         unsafe {
@@ -259,6 +353,7 @@ impl fmt::Debug for SockAddr {
                 2 => fmt::Display::fmt(&self.ipv4, f),  // AF_INET
                 10 => fmt::Display::fmt(&self.ipv6, f), // AF_INET6
                 1 => fmt::Display::fmt(&self.unix, f),  // AF_UNIX
+                17 => fmt::Display::fmt(&self.ll, f),   // AF_PACKET
                 _ => write!(f, "Unknown address family: {}", self.family),
             }
         }
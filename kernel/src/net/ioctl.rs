@@ -0,0 +1,204 @@
+//! Interface configuration ioctls on sockets, see `netdevice(7)`.
+
+use core::{mem::size_of, ptr, str};
+
+use net::{
+    Ipv4Address,
+    iface::{self, InterfaceInfo},
+};
+use strum::FromRepr;
+use systype::{SysError, SysResult, SyscallResult};
+
+use super::{SaFamily, addr::SockAddrIn};
+
+/// Defined in <linux/sockios.h>
+#[derive(FromRepr, Debug, Clone, Copy)]
+#[allow(non_camel_case_types)]
+#[repr(usize)]
+pub enum SockIoctlCmd {
+    SIOCGIFNAME = 0x8910,
+    SIOCGIFCONF = 0x8912,
+    SIOCGIFFLAGS = 0x8913,
+    SIOCSIFFLAGS = 0x8914,
+    SIOCGIFADDR = 0x8915,
+    SIOCSIFADDR = 0x8916,
+    SIOCGIFBRDADDR = 0x8919,
+    SIOCGIFNETMASK = 0x891b,
+    SIOCSIFNETMASK = 0x891c,
+    SIOCGIFMTU = 0x8921,
+    SIOCGIFHWADDR = 0x8927,
+    SIOCGIFINDEX = 0x8933,
+}
+
+const IFNAMSIZ: usize = 16;
+
+/// Generic `struct sockaddr`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SockAddrRaw {
+    family: u16,
+    data: [u8; 14],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+union IfReqData {
+    addr: SockAddrIn,
+    hwaddr: SockAddrRaw,
+    flags: i16,
+    ivalue: i32,
+    raw: [u8; 24],
+}
+
+/// `struct ifreq`, defined in <net/if.h>
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IfReq {
+    name: [u8; IFNAMSIZ],
+    data: IfReqData,
+}
+
+/// `struct ifconf`, defined in <net/if.h>
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IfConf {
+    len: i32,
+    buf: usize,
+}
+
+impl IfReq {
+    fn new(name: &str) -> Self {
+        let mut req = Self {
+            name: [0; IFNAMSIZ],
+            data: IfReqData { raw: [0; 24] },
+        };
+        let len = name.len().min(IFNAMSIZ - 1);
+        req.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        req
+    }
+
+    fn name(&self) -> SysResult<&str> {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(IFNAMSIZ);
+        str::from_utf8(&self.name[..len]).map_err(|_| SysError::EINVAL)
+    }
+
+    fn ipv4_addr(&self) -> SysResult<Ipv4Address> {
+        let addr = unsafe { self.data.addr };
+        if addr.family != u16::from(SaFamily::AF_INET) {
+            return Err(SysError::EINVAL);
+        }
+        Ok(Ipv4Address(addr.addr))
+    }
+
+    fn set_ipv4_addr(&mut self, addr: Ipv4Address) {
+        self.data.addr = SockAddrIn {
+            family: SaFamily::AF_INET.into(),
+            port: [0; 2],
+            addr: addr.0,
+            zero: [0; 8],
+        };
+    }
+}
+
+fn info_by_name(req: &IfReq) -> SysResult<InterfaceInfo> {
+    iface::interface_by_name(req.name()?).ok_or(SysError::ENODEV)
+}
+
+/// Handle an interface ioctl `cmd`, `arg` points to a `struct ifreq` or
+/// `struct ifconf` in user space.
+pub fn sock_ioctl(cmd: SockIoctlCmd, arg: usize) -> SyscallResult {
+    use SockIoctlCmd::*;
+    if arg == 0 {
+        return Err(SysError::EFAULT);
+    }
+    if let SIOCGIFCONF = cmd {
+        return get_ifconf(arg as *mut IfConf);
+    }
+
+    let req_ptr = arg as *mut IfReq;
+    let mut req = unsafe { ptr::read_unaligned(req_ptr) };
+    match cmd {
+        SIOCGIFNAME => {
+            let ifindex = unsafe { req.data.ivalue } as usize;
+            let info = iface::interface_by_index(ifindex).ok_or(SysError::ENODEV)?;
+            req.name = IfReq::new(&info.name).name;
+        }
+        SIOCGIFFLAGS => {
+            let info = info_by_name(&req)?;
+            req.data.flags = info.flags as i16;
+        }
+        SIOCSIFFLAGS => {
+            let flags = unsafe { req.data.flags } as u16 as u32;
+            iface::set_interface_flags(req.name()?, flags)?;
+            return Ok(0);
+        }
+        SIOCGIFADDR => {
+            let info = info_by_name(&req)?;
+            let cidr = info.ipv4_addr().ok_or(SysError::EADDRNOTAVAIL)?;
+            req.set_ipv4_addr(cidr.address());
+        }
+        SIOCSIFADDR => {
+            iface::set_interface_ipv4_addr(req.name()?, req.ipv4_addr()?)?;
+            return Ok(0);
+        }
+        SIOCGIFBRDADDR => {
+            let info = info_by_name(&req)?;
+            let cidr = info.ipv4_addr().ok_or(SysError::EADDRNOTAVAIL)?;
+            req.set_ipv4_addr(cidr.broadcast().unwrap_or(Ipv4Address::UNSPECIFIED));
+        }
+        SIOCGIFNETMASK => {
+            let info = info_by_name(&req)?;
+            let cidr = info.ipv4_addr().ok_or(SysError::EADDRNOTAVAIL)?;
+            req.set_ipv4_addr(cidr.netmask());
+        }
+        SIOCSIFNETMASK => {
+            iface::set_interface_ipv4_netmask(req.name()?, req.ipv4_addr()?)?;
+            return Ok(0);
+        }
+        SIOCGIFMTU => {
+            let info = info_by_name(&req)?;
+            req.data.ivalue = info.mtu as i32;
+        }
+        SIOCGIFHWADDR => {
+            let info = info_by_name(&req)?;
+            let mut hwaddr = SockAddrRaw {
+                family: info.hatype,
+                data: [0; 14],
+            };
+            hwaddr.data[..6].copy_from_slice(&info.ether_addr);
+            req.data.hwaddr = hwaddr;
+        }
+        SIOCGIFINDEX => {
+            let info = info_by_name(&req)?;
+            req.data.ivalue = info.index as i32;
+        }
+        SIOCGIFCONF => unreachable!(),
+    }
+    unsafe { ptr::write_unaligned(req_ptr, req) };
+    Ok(0)
+}
+
+/// Return a list of interfaces with an IPv4 address. When the buffer is null,
+/// only the length needed is returned.
+fn get_ifconf(conf_ptr: *mut IfConf) -> SyscallResult {
+    let mut conf = unsafe { ptr::read_unaligned(conf_ptr) };
+    let ifaces = iface::interfaces();
+    let ifaces = ifaces
+        .iter()
+        .filter_map(|info| Some((info, info.ipv4_addr()?)));
+    if conf.buf == 0 {
+        conf.len = (ifaces.count() * size_of::<IfReq>()) as i32;
+    } else {
+        let cap = conf.len.max(0) as usize / size_of::<IfReq>();
+        let mut n = 0;
+        for (info, cidr) in ifaces.take(cap) {
+            let mut req = IfReq::new(&info.name);
+            req.set_ipv4_addr(cidr.address());
+            unsafe { ptr::write_unaligned((conf.buf as *mut IfReq).add(n), req) };
+            n += 1;
+        }
+        conf.len = (n * size_of::<IfReq>()) as i32;
+    }
+    unsafe { ptr::write_unaligned(conf_ptr, conf) };
+    Ok(0)
+}
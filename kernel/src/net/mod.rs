@@ -1,6 +1,8 @@
 use systype::SysError;
 pub mod addr;
+mod ioctl;
 pub mod socket;
+mod sockopt;
 mod unix;
 
 #[repr(u16)]
@@ -13,6 +15,8 @@ pub enum SaFamily {
     AF_INET = 2,
     /// ipv6
     AF_INET6 = 10,
+    /// link level packets
+    AF_PACKET = 17,
 }
 
 impl TryFrom<u16> for SaFamily {
@@ -23,6 +27,7 @@ impl TryFrom<u16> for SaFamily {
             1 => Ok(Self::AF_UNIX),
             2 => Ok(Self::AF_INET),
             10 => Ok(Self::AF_INET6),
+            17 => Ok(Self::AF_PACKET),
             _ => Err(Self::Error::EINVAL),
         }
     }
@@ -34,6 +39,7 @@ impl From<SaFamily> for u16 {
             SaFamily::AF_UNIX => 1,
             SaFamily::AF_INET => 2,
             SaFamily::AF_INET6 => 10,
+            SaFamily::AF_PACKET => 17,
         }
     }
 }
@@ -68,6 +74,12 @@ impl TryFrom<i32> for SocketType {
     }
 }
 
+// Protocol numbers passed to `socket`, defined in <netinet/in.h>
+pub const IPPROTO_ICMP: usize = 1;
+pub const IPPROTO_TCP: usize = 6;
+pub const IPPROTO_UDP: usize = 17;
+pub const IPPROTO_ICMPV6: usize = 58;
+
 /// Set O_NONBLOCK flag on the open fd
 pub const NONBLOCK: i32 = 0x800;
 /// Set FD_CLOEXEC flag on the new fd
@@ -83,6 +95,7 @@ pub enum SocketLevel {
     IPPROTO_TCP = 6,
     /// IPv6-in-IPv4 tunnelling
     IPPROTO_IPV6 = 41,
+    SOL_PACKET = 263,
 }
 
 impl TryFrom<usize> for SocketLevel {
@@ -94,6 +107,7 @@ impl TryFrom<usize> for SocketLevel {
             1 => Ok(Self::SOL_SOCKET),
             6 => Ok(Self::IPPROTO_TCP),
             41 => Ok(Self::IPPROTO_IPV6),
+            263 => Ok(Self::SOL_PACKET),
             level => {
                 log::warn!("[SocketLevel] unsupported level: {level}");
                 Err(Self::Error::EINVAL)
@@ -186,9 +200,61 @@ impl TryFrom<usize> for SocketOpt {
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[allow(non_camel_case_types)]
+/// Options of level `IPPROTO_IP`
+///
+/// see https://www.man7.org/linux/man-pages/man7/ip.7.html
+pub enum IpSocketOpt {
+    TOS = 1,
+    TTL = 2,
+    HDRINCL = 3,
+    OPTIONS = 4,
+    PKTINFO = 8,
+    RECVERR = 11,
+    MULTICAST_IF = 32,
+    MULTICAST_TTL = 33,
+    MULTICAST_LOOP = 34,
+    ADD_MEMBERSHIP = 35,
+    DROP_MEMBERSHIP = 36,
+}
+
+impl TryFrom<usize> for IpSocketOpt {
+    type Error = SysError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::TOS),
+            2 => Ok(Self::TTL),
+            3 => Ok(Self::HDRINCL),
+            4 => Ok(Self::OPTIONS),
+            8 => Ok(Self::PKTINFO),
+            11 => Ok(Self::RECVERR),
+            32 => Ok(Self::MULTICAST_IF),
+            33 => Ok(Self::MULTICAST_TTL),
+            34 => Ok(Self::MULTICAST_LOOP),
+            35 => Ok(Self::ADD_MEMBERSHIP),
+            36 => Ok(Self::DROP_MEMBERSHIP),
+            opt => {
+                log::warn!("[IpSocketOpt] unsupported option: {opt}");
+                Err(Self::Error::ENOPROTOOPT)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+/// Options of level `IPPROTO_TCP`
+///
+/// see https://www.man7.org/linux/man-pages/man7/tcp.7.html
 pub enum TcpSocketOpt {
     NODELAY = 1, // disable nagle algorithm and flush
     MAXSEG = 2,
+    /// Idle time in seconds before keepalive probes are sent
+    KEEPIDLE = 4,
+    /// Time in seconds between keepalive probes
+    KEEPINTVL = 5,
+    /// Number of unanswered keepalive probes before the connection is dropped
+    KEEPCNT = 6,
     INFO = 11,
     CONGESTION = 13,
 }
@@ -200,11 +266,83 @@ impl TryFrom<usize> for TcpSocketOpt {
         match value {
             1 => Ok(Self::NODELAY),
             2 => Ok(Self::MAXSEG),
+            4 => Ok(Self::KEEPIDLE),
+            5 => Ok(Self::KEEPINTVL),
+            6 => Ok(Self::KEEPCNT),
             11 => Ok(Self::INFO),
             13 => Ok(Self::CONGESTION),
-            level => {
-                log::warn!("[TcpSocketOpt] unsupported option: {level}");
-                Err(Self::Error::EINVAL)
+            opt => {
+                log::warn!("[TcpSocketOpt] unsupported option: {opt}");
+                Err(Self::Error::ENOPROTOOPT)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+/// Options of level `IPPROTO_IPV6`
+///
+/// see https://www.man7.org/linux/man-pages/man7/ipv6.7.html
+pub enum Ipv6SocketOpt {
+    UNICAST_HOPS = 16,
+    MULTICAST_IF = 17,
+    MULTICAST_HOPS = 18,
+    MULTICAST_LOOP = 19,
+    ADD_MEMBERSHIP = 20,
+    DROP_MEMBERSHIP = 21,
+    /// Restrict an `AF_INET6` socket to IPv6 communication only
+    V6ONLY = 26,
+    RECVPKTINFO = 49,
+    TCLASS = 67,
+}
+
+impl TryFrom<usize> for Ipv6SocketOpt {
+    type Error = SysError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            16 => Ok(Self::UNICAST_HOPS),
+            17 => Ok(Self::MULTICAST_IF),
+            18 => Ok(Self::MULTICAST_HOPS),
+            19 => Ok(Self::MULTICAST_LOOP),
+            20 => Ok(Self::ADD_MEMBERSHIP),
+            21 => Ok(Self::DROP_MEMBERSHIP),
+            26 => Ok(Self::V6ONLY),
+            49 => Ok(Self::RECVPKTINFO),
+            67 => Ok(Self::TCLASS),
+            opt => {
+                log::warn!("[Ipv6SocketOpt] unsupported option: {opt}");
+                Err(Self::Error::ENOPROTOOPT)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+/// Options of level `SOL_PACKET`
+///
+/// see https://www.man7.org/linux/man-pages/man7/packet.7.html
+pub enum PacketSocketOpt {
+    ADD_MEMBERSHIP = 1,
+    DROP_MEMBERSHIP = 2,
+    STATISTICS = 6,
+    AUXDATA = 8,
+}
+
+impl TryFrom<usize> for PacketSocketOpt {
+    type Error = SysError;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::ADD_MEMBERSHIP),
+            2 => Ok(Self::DROP_MEMBERSHIP),
+            6 => Ok(Self::STATISTICS),
+            8 => Ok(Self::AUXDATA),
+            opt => {
+                log::warn!("[PacketSocketOpt] unsupported option: {opt}");
+                Err(Self::Error::ENOPROTOOPT)
             }
         }
     }
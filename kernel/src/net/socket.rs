@@ -1,26 +1,35 @@
 use alloc::{boxed::Box, sync::Arc};
+use core::{future::Future, time::Duration};
 
 use addr::SockAddr;
 use async_trait::async_trait;
 use log::warn;
 use net::{
-    IpEndpoint, IpListenEndpoint, NetPollState,
-    addr::{UNSPECIFIED_ENDPOINT_V4, UNSPECIFIED_IPV4},
-    poll_interfaces,
-    tcp::TcpSocket,
-    udp::UdpSocket,
+    IpListenEndpoint, MsgFlags, NetPollState, addr::UNSPECIFIED_ENDPOINT_V4, icmp::PingSocket,
+    packet::PacketSocket, poll_interfaces, raw::RawSocket, tcp::TcpSocket, udp::UdpSocket,
 };
+use signal::{Sig, SigDetails, SigInfo};
 use spin::Mutex;
 use systype::{SysError, SysResult, SyscallResult};
+use timer::timelimited_task::{TimeLimitedTaskFuture, TimeLimitedTaskOutput};
 use unix::UnixSocket;
 use vfs_core::*;
 
-use super::*;
+use super::{
+    ioctl::{SockIoctlCmd, sock_ioctl},
+    *,
+};
 use crate::processor::hart::current_task;
 
 pub enum Sock {
     Tcp(TcpSocket),
     Udp(UdpSocket),
+    /// `SOCK_RAW`
+    Raw(RawSocket),
+    /// `SOCK_DGRAM` with `IPPROTO_ICMP` or `IPPROTO_ICMPV6`
+    Ping(PingSocket),
+    /// `AF_PACKET`
+    Packet(PacketSocket),
     Unix(UnixSocket),
 }
 
@@ -29,22 +38,16 @@ impl Sock {
         match self {
             Sock::Tcp(tcp) => tcp.set_nonblocking(true),
             Sock::Udp(udp) => udp.set_nonblocking(true),
+            Sock::Raw(raw) => raw.set_nonblocking(true),
+            Sock::Ping(ping) => ping.set_nonblocking(true),
+            Sock::Packet(packet) => packet.set_nonblocking(true),
             Sock::Unix(_) => unimplemented!(),
         }
     }
 
     pub fn bind(&self, sockfd: usize, local_addr: SockAddr) -> SysResult<()> {
         match self {
-            Sock::Tcp(tcp) => {
-                // HACK
-                let local_addr = local_addr.into_listen_endpoint();
-                let addr = if local_addr.addr.is_none() {
-                    UNSPECIFIED_IPV4
-                } else {
-                    local_addr.addr.unwrap()
-                };
-                tcp.bind(IpEndpoint::new(addr, local_addr.port))
-            }
+            Sock::Tcp(tcp) => tcp.bind(local_addr.into_endpoint()),
             Sock::Udp(udp) => {
                 let local_addr = local_addr.into_listen_endpoint();
                 if let Some(prev_fd) = udp.check_bind(sockfd, local_addr) {
@@ -54,14 +57,76 @@ impl Sock {
                 }
                 udp.bind(local_addr)
             }
+            Sock::Raw(raw) => raw.bind(local_addr.into_endpoint().addr),
+            Sock::Ping(ping) => ping.bind(local_addr.into_endpoint().port),
+            Sock::Packet(packet) => {
+                let addr = local_addr.into_link_addr()?;
+                packet.bind(addr.protocol, addr.ifindex)
+            }
             Sock::Unix(_) => unimplemented!(),
         }
     }
 
-    pub fn listen(&self) -> SysResult<()> {
+    pub fn v6only(&self) -> SysResult<bool> {
+        match self {
+            Sock::Tcp(tcp) => Ok(tcp.v6only()),
+            Sock::Udp(udp) => Ok(udp.v6only()),
+            // Raw and ping sockets never handle the other family.
+            Sock::Raw(_) | Sock::Ping(_) => Ok(true),
+            Sock::Packet(_) | Sock::Unix(_) => Err(SysError::ENOPROTOOPT),
+        }
+    }
+
+    pub fn set_v6only(&self, v6only: bool) -> SysResult<()> {
         match self {
-            Sock::Tcp(tcp) => tcp.listen(current_task().waker_ref().as_ref().unwrap()),
-            Sock::Udp(_udp) => Err(SysError::EOPNOTSUPP),
+            Sock::Tcp(tcp) => tcp.set_v6only(v6only),
+            Sock::Udp(udp) => udp.set_v6only(v6only),
+            Sock::Raw(_) | Sock::Ping(_) => Ok(()),
+            Sock::Packet(_) | Sock::Unix(_) => Err(SysError::ENOPROTOOPT),
+        }
+    }
+
+    pub fn set_reuse_addr(&self, reuse_addr: bool) -> SysResult<()> {
+        match self {
+            Sock::Tcp(tcp) => tcp.set_reuse_addr(reuse_addr),
+            // UDP sockets sharing a port are handled in `bind`.
+            Sock::Udp(_) | Sock::Raw(_) | Sock::Ping(_) | Sock::Packet(_) | Sock::Unix(_) => {}
+        }
+        Ok(())
+    }
+
+    pub fn reuse_addr(&self) -> SysResult<bool> {
+        match self {
+            Sock::Tcp(tcp) => Ok(tcp.reuse_addr()),
+            Sock::Udp(_) | Sock::Raw(_) | Sock::Ping(_) | Sock::Packet(_) | Sock::Unix(_) => {
+                Ok(false)
+            }
+        }
+    }
+
+    pub fn set_reuse_port(&self, reuse_port: bool) -> SysResult<()> {
+        match self {
+            Sock::Tcp(tcp) => tcp.set_reuse_port(reuse_port),
+            Sock::Udp(_) | Sock::Raw(_) | Sock::Ping(_) | Sock::Packet(_) | Sock::Unix(_) => {}
+        }
+        Ok(())
+    }
+
+    pub fn reuse_port(&self) -> SysResult<bool> {
+        match self {
+            Sock::Tcp(tcp) => Ok(tcp.reuse_port()),
+            Sock::Udp(_) | Sock::Raw(_) | Sock::Ping(_) | Sock::Packet(_) | Sock::Unix(_) => {
+                Ok(false)
+            }
+        }
+    }
+
+    pub fn listen(&self, backlog: usize) -> SysResult<()> {
+        match self {
+            Sock::Tcp(tcp) => tcp.listen(current_task().waker_ref().as_ref().unwrap(), backlog),
+            Sock::Udp(_) | Sock::Raw(_) | Sock::Ping(_) | Sock::Packet(_) => {
+                Err(SysError::EOPNOTSUPP)
+            }
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -72,7 +137,9 @@ impl Sock {
                 let new_tcp = tcp.accept().await?;
                 Ok(new_tcp)
             }
-            Sock::Udp(_udp) => Err(SysError::EOPNOTSUPP),
+            Sock::Udp(_) | Sock::Raw(_) | Sock::Ping(_) | Sock::Packet(_) => {
+                Err(SysError::EOPNOTSUPP)
+            }
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -87,6 +154,9 @@ impl Sock {
                 let remote_addr = remote_addr.into_endpoint();
                 udp.connect(remote_addr)
             }
+            Sock::Raw(raw) => raw.connect(remote_addr.into_endpoint().addr),
+            Sock::Ping(ping) => ping.connect(remote_addr.into_endpoint().addr),
+            Sock::Packet(_) => Err(SysError::EOPNOTSUPP),
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -101,6 +171,9 @@ impl Sock {
                 let peer_addr = SockAddr::from_endpoint(udp.peer_addr()?);
                 Ok(peer_addr)
             }
+            Sock::Raw(raw) => Ok(SockAddr::from_endpoint(raw.peer_addr()?)),
+            Sock::Ping(ping) => Ok(SockAddr::from_endpoint(ping.peer_addr()?)),
+            Sock::Packet(_) => Err(SysError::ENOTCONN),
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -115,29 +188,63 @@ impl Sock {
                 let local_addr = SockAddr::from_endpoint(udp.local_addr()?);
                 Ok(local_addr)
             }
+            Sock::Raw(raw) => Ok(SockAddr::from_endpoint(raw.local_addr()?)),
+            Sock::Ping(ping) => Ok(SockAddr::from_endpoint(ping.local_addr()?)),
+            Sock::Packet(packet) => Ok(SockAddr::from_link_addr(packet.local_addr())),
             Sock::Unix(_) => unimplemented!(),
         }
     }
-    pub async fn sendto(&self, buf: &[u8], remote_addr: Option<SockAddr>) -> SysResult<usize> {
+    pub async fn sendto(
+        &self,
+        buf: &[u8],
+        remote_addr: Option<SockAddr>,
+        flags: MsgFlags,
+    ) -> SysResult<usize> {
         match self {
-            Sock::Tcp(tcp) => tcp.send(buf).await,
+            Sock::Tcp(tcp) => tcp.send(buf, flags).await,
             Sock::Udp(udp) => match remote_addr {
-                Some(addr) => udp.send_to(buf, addr.into_endpoint()).await,
-                None => udp.send(buf).await,
+                Some(addr) => udp.send_to(buf, addr.into_endpoint(), flags).await,
+                None => udp.send(buf, flags).await,
+            },
+            Sock::Raw(raw) => match remote_addr {
+                Some(addr) => raw.send_to(buf, addr.into_endpoint().addr, flags).await,
+                None => raw.send(buf, flags).await,
+            },
+            Sock::Ping(ping) => match remote_addr {
+                Some(addr) => ping.send_to(buf, addr.into_endpoint().addr, flags).await,
+                None => ping.send(buf, flags).await,
             },
+            Sock::Packet(packet) => {
+                let addr = remote_addr.map(|addr| addr.into_link_addr()).transpose()?;
+                packet.send_to(buf, addr, flags).await
+            }
             Sock::Unix(_) => unimplemented!(),
         }
     }
-    pub async fn recvfrom(&self, buf: &mut [u8]) -> SysResult<(usize, SockAddr)> {
+    /// Receives into `buf`. For datagram sockets the length of the datagram is
+    /// returned, which is larger than `buf` if it was truncated.
+    pub async fn recvfrom(&self, buf: &mut [u8], flags: MsgFlags) -> SysResult<(usize, SockAddr)> {
         match self {
             Sock::Tcp(tcp) => {
-                let bytes = tcp.recv(buf).await?;
+                let bytes = tcp.recv(buf, flags).await?;
                 Ok((bytes, SockAddr::from_endpoint(tcp.peer_addr()?)))
             }
             Sock::Udp(udp) => {
-                let (len, endpoint) = udp.recv_from(buf).await?;
+                let (len, endpoint) = udp.recv_from(buf, flags).await?;
+                Ok((len, SockAddr::from_endpoint(endpoint)))
+            }
+            Sock::Raw(raw) => {
+                let (len, endpoint) = raw.recv_from(buf, flags).await?;
+                Ok((len, SockAddr::from_endpoint(endpoint)))
+            }
+            Sock::Ping(ping) => {
+                let (len, endpoint) = ping.recv_from(buf, flags).await?;
                 Ok((len, SockAddr::from_endpoint(endpoint)))
             }
+            Sock::Packet(packet) => {
+                let (len, addr) = packet.recv_from(buf, flags).await?;
+                Ok((len, SockAddr::from_link_addr(addr)))
+            }
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -145,6 +252,9 @@ impl Sock {
         match self {
             Sock::Tcp(tcp) => tcp.poll().await,
             Sock::Udp(udp) => udp.poll().await,
+            Sock::Raw(raw) => raw.poll().await,
+            Sock::Ping(ping) => ping.poll().await,
+            Sock::Packet(packet) => packet.poll().await,
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -153,6 +263,9 @@ impl Sock {
         match self {
             Sock::Tcp(tcp) => tcp.shutdown(how),
             Sock::Udp(udp) => udp.shutdown(),
+            Sock::Raw(raw) => raw.shutdown(),
+            Sock::Ping(ping) => ping.shutdown(),
+            Sock::Packet(packet) => packet.shutdown(),
             Sock::Unix(_) => unimplemented!(),
         }
     }
@@ -160,26 +273,57 @@ impl Sock {
 
 /// linux中，socket面向用户空间，sock面向内核空间
 pub struct Socket {
+    /// The address family of socket (such as AF_INET, AF_INET6)
+    pub domain: SaFamily,
     /// The type of socket (such as STREAM, DGRAM)
     pub types: SocketType,
     /// The core of a socket, which includes TCP, UDP, or Unix domain sockets
     pub sk: Sock,
     /// File metadata, including metadata information related to sockets
     pub meta: FileMeta,
+    /// Timeout of blocking receives, i.e. `SO_RCVTIMEO`
+    rcvtimeo: Mutex<Option<Duration>>,
+    /// Timeout of blocking sends, i.e. `SO_SNDTIMEO`
+    sndtimeo: Mutex<Option<Duration>>,
 }
 
 unsafe impl Sync for Socket {}
 unsafe impl Send for Socket {}
 
 impl Socket {
-    pub fn new(domain: SaFamily, types: SocketType, nonblock: bool) -> Self {
+    pub fn new(
+        domain: SaFamily,
+        types: SocketType,
+        protocol: usize,
+        nonblock: bool,
+    ) -> SysResult<Self> {
         let sk = match domain {
             SaFamily::AF_UNIX => Sock::Unix(UnixSocket {}),
-            SaFamily::AF_INET | SaFamily::AF_INET6 => match types {
-                SocketType::STREAM => Sock::Tcp(TcpSocket::new_v4()),
-                SocketType::DGRAM => Sock::Udp(UdpSocket::new()),
-                _ => unimplemented!(),
-            },
+            SaFamily::AF_INET | SaFamily::AF_INET6 => {
+                let ipv6 = domain == SaFamily::AF_INET6;
+                let icmp = if ipv6 { IPPROTO_ICMPV6 } else { IPPROTO_ICMP };
+                match (types, protocol) {
+                    (SocketType::STREAM, 0 | IPPROTO_TCP) if ipv6 => Sock::Tcp(TcpSocket::new_v6()),
+                    (SocketType::STREAM, 0 | IPPROTO_TCP) => Sock::Tcp(TcpSocket::new_v4()),
+                    (SocketType::DGRAM, 0 | IPPROTO_UDP) if ipv6 => Sock::Udp(UdpSocket::new_v6()),
+                    (SocketType::DGRAM, 0 | IPPROTO_UDP) => Sock::Udp(UdpSocket::new()),
+                    (SocketType::DGRAM, p) if p == icmp => Sock::Ping(PingSocket::new(ipv6)),
+                    (SocketType::RAW, 1..=255) => Sock::Raw(RawSocket::new(ipv6, protocol as u8)),
+                    (SocketType::STREAM | SocketType::DGRAM | SocketType::RAW, _) => {
+                        return Err(SysError::EPROTONOSUPPORT);
+                    }
+                    _ => return Err(SysError::ESOCKTNOSUPPORT),
+                }
+            }
+            SaFamily::AF_PACKET => {
+                // The protocol is in network byte order.
+                let protocol = u16::from_be(protocol as u16);
+                match types {
+                    SocketType::RAW => Sock::Packet(PacketSocket::new(false, protocol)),
+                    SocketType::DGRAM => Sock::Packet(PacketSocket::new(true, protocol)),
+                    _ => return Err(SysError::ESOCKTNOSUPPORT),
+                }
+            }
         };
         let flags = if nonblock {
             sk.set_nonblocking();
@@ -187,7 +331,8 @@ impl Socket {
         } else {
             OpenFlags::O_RDWR
         };
-        Self {
+        Ok(Self {
+            domain,
             types,
             sk,
             meta: FileMeta {
@@ -195,12 +340,16 @@ impl Socket {
                 inode: Arc::<usize>::new_zeroed(),
                 pos: 0.into(),
                 flags: Mutex::new(flags),
+                ra: Mutex::new(ReadaheadState::new()),
             },
-        }
+            rcvtimeo: Mutex::new(None),
+            sndtimeo: Mutex::new(None),
+        })
     }
 
     pub fn from_another(another: &Self, sk: Sock) -> Self {
         Self {
+            domain: another.domain,
             types: another.types,
             sk,
             meta: FileMeta {
@@ -208,8 +357,75 @@ impl Socket {
                 inode: Arc::<usize>::new_zeroed(),
                 pos: 0.into(),
                 flags: Mutex::new(OpenFlags::O_RDWR),
+                ra: Mutex::new(ReadaheadState::new()),
             },
+            // Timeouts are inherited by accepted sockets, as on Linux.
+            rcvtimeo: Mutex::new(another.recv_timeout()),
+            sndtimeo: Mutex::new(another.send_timeout()),
+        }
+    }
+
+    pub fn recv_timeout(&self) -> Option<Duration> {
+        *self.rcvtimeo.lock()
+    }
+
+    pub fn set_recv_timeout(&self, timeout: Option<Duration>) {
+        *self.rcvtimeo.lock() = timeout;
+    }
+
+    pub fn send_timeout(&self) -> Option<Duration> {
+        *self.sndtimeo.lock()
+    }
+
+    pub fn set_send_timeout(&self, timeout: Option<Duration>) {
+        *self.sndtimeo.lock() = timeout;
+    }
+
+    /// [`Sock::recvfrom`] bounded by `SO_RCVTIMEO`.
+    pub async fn recvfrom(&self, buf: &mut [u8], flags: MsgFlags) -> SysResult<(usize, SockAddr)> {
+        with_timeout(self.recv_timeout(), self.sk.recvfrom(buf, flags)).await
+    }
+
+    /// [`Sock::sendto`] bounded by `SO_SNDTIMEO`. `SIGPIPE` is sent to the
+    /// current thread if the stream is closed for writing, unless
+    /// `MSG_NOSIGNAL` is given.
+    pub async fn sendto(
+        &self,
+        buf: &[u8],
+        remote_addr: Option<SockAddr>,
+        flags: MsgFlags,
+    ) -> SysResult<usize> {
+        let ret = with_timeout(self.send_timeout(), self.sk.sendto(buf, remote_addr, flags)).await;
+        if matches!(ret, Err(SysError::EPIPE)) && !flags.contains(MsgFlags::NOSIGNAL) {
+            current_task().receive_siginfo(
+                SigInfo {
+                    sig: Sig::SIGPIPE,
+                    code: SigInfo::KERNEL,
+                    details: SigDetails::None,
+                },
+                true,
+            );
         }
+        ret
+    }
+
+    /// [`Sock::accept`] bounded by `SO_RCVTIMEO`.
+    pub async fn accept(&self) -> SysResult<TcpSocket> {
+        with_timeout(self.recv_timeout(), self.sk.accept()).await
+    }
+}
+
+/// Run `fut`, failing with `EAGAIN` if it does not complete within `timeout`.
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl Future<Output = SysResult<T>>,
+) -> SysResult<T> {
+    let Some(timeout) = timeout else {
+        return fut.await;
+    };
+    match TimeLimitedTaskFuture::new(timeout, fut).await {
+        TimeLimitedTaskOutput::Ok(ret) => ret,
+        TimeLimitedTaskOutput::TimeOut => Err(SysError::EAGAIN),
     }
 }
 
@@ -233,7 +449,11 @@ impl File for Socket {
         }
         // TODO: should add this?
         // poll_interfaces();
-        let bytes = self.sk.recvfrom(buf).await.map(|e| e.0)?;
+        let bytes = self
+            .recvfrom(buf, MsgFlags::empty())
+            .await?
+            .0
+            .min(buf.len());
         warn!(
             "[Socket::File::read_at] expect to recv: {:?} exact: {bytes}",
             buf.len()
@@ -247,7 +467,7 @@ impl File for Socket {
         }
         // TODO: should add this?
         // poll_interfaces();
-        let bytes = self.sk.sendto(buf, None).await?;
+        let bytes = self.sendto(buf, None, MsgFlags::empty()).await?;
         warn!(
             "[Socket::File::write_at] expect to send: {:?} bytes exact: {bytes}",
             buf.len()
@@ -273,9 +493,13 @@ impl File for Socket {
         res
     }
 
-    fn ioctl(&self, _cmd: usize, _arg: usize) -> SyscallResult {
-        log::warn!("[Socket::File::ioctl] not supported now, return 0 instead");
-        Ok(0)
+    fn ioctl(&self, cmd: usize, arg: usize) -> SyscallResult {
+        let Some(cmd) = SockIoctlCmd::from_repr(cmd) else {
+            log::warn!("[Socket::File::ioctl] cmd {cmd:#x} not supported now, return 0 instead");
+            return Ok(0);
+        };
+        log::info!("[Socket::File::ioctl] cmd {cmd:?}, arg {arg:#x}");
+        sock_ioctl(cmd, arg)
     }
 }
 
@@ -0,0 +1,623 @@
+//! Socket options, i.e. `setsockopt` and `getsockopt`.
+//!
+//! Option values are passed as raw bytes in the layout of the C structures in
+//! <sys/socket.h> and <netinet/in.h>. Most options are an `int`, the
+//! `IPPROTO_IP` ones also accept a single byte like Linux does.
+
+use alloc::{vec, vec::Vec};
+use core::{mem::size_of, ptr, time::Duration};
+
+use net::{
+    DEFAULT_HOP_LIMIT, IpAddress, Ipv4Address, Ipv6Address, TCP_RX_BUF_LEN, TCP_TX_BUF_LEN,
+    bpf::{BpfProgram, SockFilter},
+    iface,
+};
+use systype::{SysError, SysResult};
+use time::timeval::TimeVal;
+
+use super::{
+    socket::{Sock, Socket},
+    *,
+};
+use crate::{mm::UserReadPtr, processor::hart::current_task};
+
+/// Maximum segment size reported by `TCP_MAXSEG`.
+const TCP_MSS: i32 = 1460;
+/// Length of the algorithm name returned by `TCP_CONGESTION`.
+const TCP_CA_NAME_MAX: usize = 16;
+/// Size of `struct tcp_info`.
+const TCP_INFO_LEN: usize = 232;
+
+/// ```c
+/// struct sock_fprog {
+///     unsigned short       len;    /* Number of filter blocks */
+///     struct sock_filter  *filter;
+/// };
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockFprog {
+    len: u16,
+    filter: usize,
+}
+
+/// ```c
+/// struct tpacket_stats {
+///     unsigned int tp_packets;
+///     unsigned int tp_drops;
+/// };
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TpacketStats {
+    packets: u32,
+    drops: u32,
+}
+
+/// ```c
+/// struct linger {
+///     int l_onoff;    /* linger active */
+///     int l_linger;   /* how many seconds to linger for */
+/// };
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Linger {
+    onoff: i32,
+    linger: i32,
+}
+
+/// ```c
+/// struct ip_mreqn {
+///     struct in_addr imr_multiaddr; /* IP multicast group address */
+///     struct in_addr imr_address;   /* IP address of local interface */
+///     int            imr_ifindex;   /* interface index */
+/// };
+/// ```
+///
+/// `struct ip_mreq` is the same without `imr_ifindex`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IpMreqn {
+    multiaddr: [u8; 4],
+    address: [u8; 4],
+    ifindex: i32,
+}
+
+/// ```c
+/// struct ipv6_mreq {
+///     struct in6_addr ipv6mr_multiaddr; /* IPv6 multicast address */
+///     unsigned int    ipv6mr_interface; /* interface index */
+/// };
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Ipv6Mreq {
+    multiaddr: [u8; 16],
+    ifindex: u32,
+}
+
+impl Socket {
+    /// Set option `optname` of `level` to `optval`.
+    pub fn setsockopt(&self, level: SocketLevel, optname: usize, optval: &[u8]) -> SysResult<()> {
+        match level {
+            SocketLevel::SOL_SOCKET => self.set_socket_opt(SocketOpt::try_from(optname)?, optval),
+            SocketLevel::IPPROTO_IP => self.set_ip_opt(IpSocketOpt::try_from(optname)?, optval),
+            SocketLevel::IPPROTO_TCP => self.set_tcp_opt(TcpSocketOpt::try_from(optname)?, optval),
+            SocketLevel::IPPROTO_IPV6 => {
+                if self.domain != SaFamily::AF_INET6 {
+                    return Err(SysError::ENOPROTOOPT);
+                }
+                self.set_ipv6_opt(Ipv6SocketOpt::try_from(optname)?, optval)
+            }
+            SocketLevel::SOL_PACKET => {
+                self.set_packet_opt(PacketSocketOpt::try_from(optname)?, optval)
+            }
+        }
+    }
+
+    /// Returns the value of option `optname` of `level`.
+    pub fn getsockopt(&self, level: SocketLevel, optname: usize) -> SysResult<Vec<u8>> {
+        match level {
+            SocketLevel::SOL_SOCKET => self.get_socket_opt(SocketOpt::try_from(optname)?),
+            SocketLevel::IPPROTO_IP => self.get_ip_opt(IpSocketOpt::try_from(optname)?),
+            SocketLevel::IPPROTO_TCP => self.get_tcp_opt(TcpSocketOpt::try_from(optname)?),
+            SocketLevel::IPPROTO_IPV6 => {
+                if self.domain != SaFamily::AF_INET6 {
+                    return Err(SysError::ENOPROTOOPT);
+                }
+                self.get_ipv6_opt(Ipv6SocketOpt::try_from(optname)?)
+            }
+            SocketLevel::SOL_PACKET => self.get_packet_opt(PacketSocketOpt::try_from(optname)?),
+        }
+    }
+
+    fn set_socket_opt(&self, opt: SocketOpt, optval: &[u8]) -> SysResult<()> {
+        log::info!(
+            "[Socket::setsockopt] SOL_SOCKET {opt:?} optlen:{}",
+            optval.len()
+        );
+        match opt {
+            SocketOpt::REUSEADDR => self.sk.set_reuse_addr(read_int(optval)? != 0),
+            SocketOpt::REUSEPORT => self.sk.set_reuse_port(read_int(optval)? != 0),
+            SocketOpt::RCVBUF | SocketOpt::RCVBUFFORCE => {
+                // Linux doubles the value to leave room for bookkeeping.
+                let size = read_int(optval)?.max(0) as usize * 2;
+                match &self.sk {
+                    Sock::Tcp(tcp) => tcp.set_options(|opts| opts.recv_buf_size = size),
+                    Sock::Udp(udp) => udp.set_options(|opts| opts.recv_buf_size = size),
+                    _ => {}
+                }
+                Ok(())
+            }
+            SocketOpt::SNDBUF | SocketOpt::SNDBUFFORCE => {
+                let size = read_int(optval)?.max(0) as usize * 2;
+                match &self.sk {
+                    Sock::Tcp(tcp) => tcp.set_options(|opts| opts.send_buf_size = size),
+                    Sock::Udp(udp) => udp.set_options(|opts| opts.send_buf_size = size),
+                    _ => {}
+                }
+                Ok(())
+            }
+            SocketOpt::KEEPALIVE => {
+                let keep_alive = read_int(optval)? != 0;
+                if let Sock::Tcp(tcp) = &self.sk {
+                    tcp.set_options(|opts| opts.keep_alive = keep_alive);
+                }
+                Ok(())
+            }
+            SocketOpt::LINGER => {
+                // `close` never blocks. A zero timeout resets the connection
+                // on close, any other one only bounds how long queued data is
+                // still sent afterwards.
+                let linger: Linger = read_struct(optval)?;
+                let linger =
+                    (linger.onoff != 0).then(|| Duration::from_secs(linger.linger.max(0) as u64));
+                if let Sock::Tcp(tcp) = &self.sk {
+                    tcp.set_options(|opts| opts.linger = linger);
+                }
+                Ok(())
+            }
+            SocketOpt::BROADCAST => {
+                let broadcast = read_int(optval)? != 0;
+                if let Sock::Udp(udp) = &self.sk {
+                    udp.set_options(|opts| opts.broadcast = broadcast);
+                }
+                Ok(())
+            }
+            SocketOpt::ATTACH_FILTER => {
+                let fprog: SockFprog = read_struct(optval)?;
+                if fprog.filter == 0 {
+                    return Err(SysError::EFAULT);
+                }
+                let insns = UserReadPtr::<SockFilter>::from(fprog.filter)
+                    .read_array(&current_task(), fprog.len as usize)?;
+                let filter = BpfProgram::new(insns)?;
+                match &self.sk {
+                    Sock::Packet(packet) => packet.set_filter(Some(filter)),
+                    _ => log::warn!("[Socket::setsockopt] filter is only run on packet sockets"),
+                }
+                Ok(())
+            }
+            SocketOpt::DETACH_FILTER => {
+                if let Sock::Packet(packet) = &self.sk {
+                    packet.set_filter(None);
+                }
+                Ok(())
+            }
+            SocketOpt::RCVTIMEO_OLD | SocketOpt::SNDTIMEO_OLD => {
+                let timeval: TimeVal = read_struct(optval)?;
+                if !timeval.is_valid() {
+                    return Err(SysError::EDOM);
+                }
+                let timeout = (!timeval.is_zero()).then(|| timeval.into());
+                if opt == SocketOpt::RCVTIMEO_OLD {
+                    self.set_recv_timeout(timeout);
+                } else {
+                    self.set_send_timeout(timeout);
+                }
+                Ok(())
+            }
+            opt => {
+                log::warn!("[Socket::setsockopt] SOL_SOCKET {opt:?} is ignored");
+                Ok(())
+            }
+        }
+    }
+
+    fn get_socket_opt(&self, opt: SocketOpt) -> SysResult<Vec<u8>> {
+        let value = match opt {
+            SocketOpt::TYPE => self.types as i32,
+            SocketOpt::ERROR => match &self.sk {
+                Sock::Tcp(tcp) => tcp.take_error().map_or(0, |e| e.code()),
+                _ => 0,
+            },
+            SocketOpt::REUSEADDR => self.sk.reuse_addr()? as i32,
+            SocketOpt::REUSEPORT => self.sk.reuse_port()? as i32,
+            SocketOpt::RCVBUF => match &self.sk {
+                Sock::Tcp(tcp) => tcp.options().recv_buf_size as i32,
+                Sock::Udp(udp) => udp.options().recv_buf_size as i32,
+                _ => TCP_RX_BUF_LEN as i32,
+            },
+            SocketOpt::SNDBUF => match &self.sk {
+                Sock::Tcp(tcp) => tcp.options().send_buf_size as i32,
+                Sock::Udp(udp) => udp.options().send_buf_size as i32,
+                _ => TCP_TX_BUF_LEN as i32,
+            },
+            SocketOpt::KEEPALIVE => match &self.sk {
+                Sock::Tcp(tcp) => tcp.options().keep_alive as i32,
+                _ => 0,
+            },
+            SocketOpt::BROADCAST => match &self.sk {
+                Sock::Udp(udp) => udp.options().broadcast as i32,
+                _ => 0,
+            },
+            SocketOpt::LINGER => {
+                let linger = match &self.sk {
+                    Sock::Tcp(tcp) => tcp.options().linger,
+                    _ => None,
+                };
+                return Ok(struct_bytes(&Linger {
+                    onoff: linger.is_some() as i32,
+                    linger: linger.map_or(0, |linger| linger.as_secs() as i32),
+                }));
+            }
+            SocketOpt::RCVTIMEO_OLD | SocketOpt::SNDTIMEO_OLD => {
+                let timeout = if opt == SocketOpt::RCVTIMEO_OLD {
+                    self.recv_timeout()
+                } else {
+                    self.send_timeout()
+                };
+                let timeval = timeout.map_or(TimeVal::ZERO, TimeVal::from);
+                return Ok(struct_bytes(&timeval));
+            }
+            opt => {
+                log::warn!("[Socket::getsockopt] SOL_SOCKET {opt:?} is not supported, return 0");
+                0
+            }
+        };
+        Ok(int_bytes(value))
+    }
+
+    fn set_ip_opt(&self, opt: IpSocketOpt, optval: &[u8]) -> SysResult<()> {
+        log::info!(
+            "[Socket::setsockopt] IPPROTO_IP {opt:?} optlen:{}",
+            optval.len()
+        );
+        match opt {
+            IpSocketOpt::TTL => {
+                let hop_limit = match read_ip_int(optval)? {
+                    -1 => None,
+                    ttl @ 1..=255 => Some(ttl as u8),
+                    _ => return Err(SysError::EINVAL),
+                };
+                self.set_hop_limit(hop_limit)
+            }
+            IpSocketOpt::MULTICAST_TTL => {
+                let ttl = match read_ip_int(optval)? {
+                    -1 => 1,
+                    ttl @ 0..=255 => ttl as u8,
+                    _ => return Err(SysError::EINVAL),
+                };
+                self.udp()?
+                    .set_options(|opts| opts.multicast_hop_limit = ttl);
+                Ok(())
+            }
+            IpSocketOpt::MULTICAST_LOOP => {
+                let multicast_loop = read_ip_int(optval)? != 0;
+                self.udp()?
+                    .set_options(|opts| opts.multicast_loop = multicast_loop);
+                Ok(())
+            }
+            IpSocketOpt::MULTICAST_IF => {
+                let ifindex = if optval.len() >= size_of::<IpMreqn>() {
+                    let mreqn: IpMreqn = read_struct(optval)?;
+                    match mreqn.ifindex {
+                        0 => ifindex_of(Ipv4Address(mreqn.address).into())?,
+                        ifindex => ifindex as usize,
+                    }
+                } else {
+                    let addr: [u8; 4] = read_struct(optval)?;
+                    ifindex_of(Ipv4Address(addr).into())?
+                };
+                self.udp()?
+                    .set_options(|opts| opts.multicast_ifindex = ifindex);
+                Ok(())
+            }
+            IpSocketOpt::ADD_MEMBERSHIP | IpSocketOpt::DROP_MEMBERSHIP => {
+                let (group, local, ifindex) = if optval.len() >= size_of::<IpMreqn>() {
+                    let mreqn: IpMreqn = read_struct(optval)?;
+                    (
+                        mreqn.multiaddr,
+                        mreqn.address,
+                        mreqn.ifindex.max(0) as usize,
+                    )
+                } else {
+                    let mreq: [[u8; 4]; 2] = read_struct(optval)?;
+                    (mreq[0], mreq[1], 0)
+                };
+                let group = Ipv4Address(group).into();
+                let udp = self.udp()?;
+                if opt == IpSocketOpt::ADD_MEMBERSHIP {
+                    udp.join_multicast_group(group, Some(Ipv4Address(local).into()), ifindex)
+                } else {
+                    let ifindex = match ifindex {
+                        0 => ifindex_of(Ipv4Address(local).into())?,
+                        ifindex => ifindex,
+                    };
+                    udp.leave_multicast_group(group, ifindex)
+                }
+            }
+            opt => {
+                log::warn!("[Socket::setsockopt] IPPROTO_IP {opt:?} is ignored");
+                Ok(())
+            }
+        }
+    }
+
+    fn get_ip_opt(&self, opt: IpSocketOpt) -> SysResult<Vec<u8>> {
+        let value = match opt {
+            IpSocketOpt::TTL => self.hop_limit()? as i32,
+            IpSocketOpt::MULTICAST_TTL => self.udp()?.options().multicast_hop_limit as i32,
+            IpSocketOpt::MULTICAST_LOOP => self.udp()?.options().multicast_loop as i32,
+            IpSocketOpt::MULTICAST_IF => {
+                let ifindex = self.udp()?.options().multicast_ifindex;
+                let addr = iface::interface_by_index(ifindex)
+                    .and_then(|info| info.ipv4_addr())
+                    .map_or(Ipv4Address::UNSPECIFIED, |cidr| cidr.address());
+                return Ok(addr.0.to_vec());
+            }
+            IpSocketOpt::ADD_MEMBERSHIP | IpSocketOpt::DROP_MEMBERSHIP => {
+                return Err(SysError::ENOPROTOOPT);
+            }
+            IpSocketOpt::TOS
+            | IpSocketOpt::HDRINCL
+            | IpSocketOpt::OPTIONS
+            | IpSocketOpt::PKTINFO
+            | IpSocketOpt::RECVERR => 0,
+        };
+        Ok(int_bytes(value))
+    }
+
+    fn set_tcp_opt(&self, opt: TcpSocketOpt, optval: &[u8]) -> SysResult<()> {
+        log::info!(
+            "[Socket::setsockopt] IPPROTO_TCP {opt:?} optlen:{}",
+            optval.len()
+        );
+        let Sock::Tcp(tcp) = &self.sk else {
+            return Err(SysError::ENOPROTOOPT);
+        };
+        match opt {
+            TcpSocketOpt::NODELAY => {
+                let nodelay = read_int(optval)? != 0;
+                tcp.set_options(|opts| opts.nodelay = nodelay);
+            }
+            TcpSocketOpt::KEEPIDLE | TcpSocketOpt::KEEPINTVL => {
+                let secs = read_int(optval)?;
+                if !(1..=32767).contains(&secs) {
+                    return Err(SysError::EINVAL);
+                }
+                tcp.set_options(|opts| match opt {
+                    TcpSocketOpt::KEEPIDLE => opts.keep_idle = secs as u32,
+                    _ => opts.keep_intvl = secs as u32,
+                });
+            }
+            TcpSocketOpt::KEEPCNT => {
+                let cnt = read_int(optval)?;
+                if !(1..=127).contains(&cnt) {
+                    return Err(SysError::EINVAL);
+                }
+                tcp.set_options(|opts| opts.keep_cnt = cnt as u32);
+            }
+            TcpSocketOpt::INFO => return Err(SysError::ENOPROTOOPT),
+            TcpSocketOpt::MAXSEG | TcpSocketOpt::CONGESTION => {
+                log::warn!("[Socket::setsockopt] IPPROTO_TCP {opt:?} is ignored");
+            }
+        }
+        Ok(())
+    }
+
+    fn get_tcp_opt(&self, opt: TcpSocketOpt) -> SysResult<Vec<u8>> {
+        let Sock::Tcp(tcp) = &self.sk else {
+            return Err(SysError::ENOPROTOOPT);
+        };
+        let opts = tcp.options();
+        let value = match opt {
+            TcpSocketOpt::NODELAY => opts.nodelay as i32,
+            TcpSocketOpt::MAXSEG => TCP_MSS,
+            TcpSocketOpt::KEEPIDLE => opts.keep_idle as i32,
+            TcpSocketOpt::KEEPINTVL => opts.keep_intvl as i32,
+            TcpSocketOpt::KEEPCNT => opts.keep_cnt as i32,
+            TcpSocketOpt::CONGESTION => {
+                let mut name = b"reno".to_vec();
+                name.resize(TCP_CA_NAME_MAX, 0);
+                return Ok(name);
+            }
+            // No statistics are kept, report all zeros.
+            TcpSocketOpt::INFO => return Ok(vec![0; TCP_INFO_LEN]),
+        };
+        Ok(int_bytes(value))
+    }
+
+    fn set_ipv6_opt(&self, opt: Ipv6SocketOpt, optval: &[u8]) -> SysResult<()> {
+        log::info!(
+            "[Socket::setsockopt] IPPROTO_IPV6 {opt:?} optlen:{}",
+            optval.len()
+        );
+        match opt {
+            Ipv6SocketOpt::V6ONLY => self.sk.set_v6only(read_int(optval)? != 0),
+            Ipv6SocketOpt::UNICAST_HOPS => {
+                let hop_limit = match read_int(optval)? {
+                    -1 => None,
+                    hops @ 1..=255 => Some(hops as u8),
+                    _ => return Err(SysError::EINVAL),
+                };
+                self.set_hop_limit(hop_limit)
+            }
+            Ipv6SocketOpt::MULTICAST_HOPS => {
+                let hops = match read_int(optval)? {
+                    -1 => 1,
+                    hops @ 0..=255 => hops as u8,
+                    _ => return Err(SysError::EINVAL),
+                };
+                self.udp()?
+                    .set_options(|opts| opts.multicast_hop_limit = hops);
+                Ok(())
+            }
+            Ipv6SocketOpt::MULTICAST_LOOP => {
+                let multicast_loop = read_int(optval)? != 0;
+                self.udp()?
+                    .set_options(|opts| opts.multicast_loop = multicast_loop);
+                Ok(())
+            }
+            Ipv6SocketOpt::MULTICAST_IF => {
+                let ifindex = read_int(optval)?.max(0) as usize;
+                if ifindex != 0 && iface::interface_by_index(ifindex).is_none() {
+                    return Err(SysError::ENODEV);
+                }
+                self.udp()?
+                    .set_options(|opts| opts.multicast_ifindex = ifindex);
+                Ok(())
+            }
+            Ipv6SocketOpt::ADD_MEMBERSHIP | Ipv6SocketOpt::DROP_MEMBERSHIP => {
+                let mreq: Ipv6Mreq = read_struct(optval)?;
+                let group = Ipv6Address(mreq.multiaddr).into();
+                let udp = self.udp()?;
+                if opt == Ipv6SocketOpt::ADD_MEMBERSHIP {
+                    udp.join_multicast_group(group, None, mreq.ifindex as usize)
+                } else {
+                    udp.leave_multicast_group(group, mreq.ifindex as usize)
+                }
+            }
+            // The traffic class and packet info are accepted but have no
+            // effect yet.
+            Ipv6SocketOpt::RECVPKTINFO | Ipv6SocketOpt::TCLASS => Ok(()),
+        }
+    }
+
+    fn get_ipv6_opt(&self, opt: Ipv6SocketOpt) -> SysResult<Vec<u8>> {
+        let value = match opt {
+            Ipv6SocketOpt::V6ONLY => self.sk.v6only()? as i32,
+            Ipv6SocketOpt::UNICAST_HOPS => self.hop_limit()? as i32,
+            Ipv6SocketOpt::MULTICAST_HOPS => self.udp()?.options().multicast_hop_limit as i32,
+            Ipv6SocketOpt::MULTICAST_LOOP => self.udp()?.options().multicast_loop as i32,
+            Ipv6SocketOpt::MULTICAST_IF => self.udp()?.options().multicast_ifindex as i32,
+            Ipv6SocketOpt::RECVPKTINFO | Ipv6SocketOpt::TCLASS => 0,
+            Ipv6SocketOpt::ADD_MEMBERSHIP | Ipv6SocketOpt::DROP_MEMBERSHIP => {
+                return Err(SysError::ENOPROTOOPT);
+            }
+        };
+        Ok(int_bytes(value))
+    }
+
+    fn set_packet_opt(&self, opt: PacketSocketOpt, optval: &[u8]) -> SysResult<()> {
+        log::info!(
+            "[Socket::setsockopt] SOL_PACKET {opt:?} optlen:{}",
+            optval.len()
+        );
+        if !matches!(self.sk, Sock::Packet(_)) {
+            return Err(SysError::ENOPROTOOPT);
+        }
+        match opt {
+            // Every frame is already delivered, as if the interface were in
+            // promiscuous mode.
+            PacketSocketOpt::ADD_MEMBERSHIP | PacketSocketOpt::DROP_MEMBERSHIP => Ok(()),
+            PacketSocketOpt::AUXDATA => {
+                read_int(optval)?;
+                log::warn!("[Socket::setsockopt] SOL_PACKET AUXDATA is ignored");
+                Ok(())
+            }
+            PacketSocketOpt::STATISTICS => Err(SysError::ENOPROTOOPT),
+        }
+    }
+
+    fn get_packet_opt(&self, opt: PacketSocketOpt) -> SysResult<Vec<u8>> {
+        let Sock::Packet(packet) = &self.sk else {
+            return Err(SysError::ENOPROTOOPT);
+        };
+        match opt {
+            PacketSocketOpt::STATISTICS => {
+                let (packets, drops) = packet.take_stats();
+                Ok(struct_bytes(&TpacketStats { packets, drops }))
+            }
+            PacketSocketOpt::AUXDATA => Ok(int_bytes(0)),
+            PacketSocketOpt::ADD_MEMBERSHIP | PacketSocketOpt::DROP_MEMBERSHIP => {
+                Err(SysError::ENOPROTOOPT)
+            }
+        }
+    }
+
+    /// Set the hop limit of unicast packets, `None` for the default.
+    fn set_hop_limit(&self, hop_limit: Option<u8>) -> SysResult<()> {
+        match &self.sk {
+            Sock::Tcp(tcp) => tcp.set_options(|opts| opts.hop_limit = hop_limit),
+            Sock::Udp(udp) => udp.set_options(|opts| opts.hop_limit = hop_limit),
+            Sock::Raw(raw) => raw.set_hop_limit(hop_limit),
+            Sock::Ping(ping) => ping.set_hop_limit(hop_limit),
+            Sock::Packet(_) | Sock::Unix(_) => return Err(SysError::ENOPROTOOPT),
+        }
+        Ok(())
+    }
+
+    fn hop_limit(&self) -> SysResult<u8> {
+        match &self.sk {
+            Sock::Tcp(tcp) => Ok(tcp.options().hop_limit.unwrap_or(DEFAULT_HOP_LIMIT)),
+            Sock::Udp(udp) => Ok(udp.options().hop_limit.unwrap_or(DEFAULT_HOP_LIMIT)),
+            Sock::Raw(raw) => Ok(raw.hop_limit()),
+            Sock::Ping(ping) => Ok(ping.hop_limit()),
+            Sock::Packet(_) | Sock::Unix(_) => Err(SysError::ENOPROTOOPT),
+        }
+    }
+
+    /// Multicast options are only supported on UDP sockets.
+    fn udp(&self) -> SysResult<&net::udp::UdpSocket> {
+        match &self.sk {
+            Sock::Udp(udp) => Ok(udp),
+            _ => Err(SysError::ENOPROTOOPT),
+        }
+    }
+}
+
+/// Index of the interface owning `addr`, 0 for the unspecified address.
+fn ifindex_of(addr: IpAddress) -> SysResult<usize> {
+    if addr.is_unspecified() {
+        return Ok(0);
+    }
+    iface::interfaces()
+        .into_iter()
+        .find(|info| info.ip_addrs.iter().any(|cidr| cidr.address() == addr))
+        .map(|info| info.index)
+        .ok_or(SysError::EADDRNOTAVAIL)
+}
+
+/// Reads an `int` option, `EINVAL` if `optval` is shorter than that.
+fn read_int(optval: &[u8]) -> SysResult<i32> {
+    read_struct(optval)
+}
+
+/// Reads an `int` option of `IPPROTO_IP`, where a single byte is also
+/// accepted.
+fn read_ip_int(optval: &[u8]) -> SysResult<i32> {
+    match optval.len() {
+        0 => Err(SysError::EINVAL),
+        1..=3 => Ok(optval[0] as i32),
+        _ => read_int(optval),
+    }
+}
+
+fn read_struct<T: Copy>(optval: &[u8]) -> SysResult<T> {
+    if optval.len() < size_of::<T>() {
+        return Err(SysError::EINVAL);
+    }
+    // SAFETY: the length is checked and only plain old data is read here.
+    Ok(unsafe { ptr::read_unaligned(optval.as_ptr() as *const T) })
+}
+
+fn int_bytes(value: i32) -> Vec<u8> {
+    value.to_ne_bytes().to_vec()
+}
+
+fn struct_bytes<T: Copy>(value: &T) -> Vec<u8> {
+    // SAFETY: only plain old data without padding is passed here.
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }.to_vec()
+}
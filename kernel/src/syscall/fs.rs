@@ -13,9 +13,9 @@ use systype::{SysError, SyscallResult};
 use time::timespec::TimeSpec;
 use vfs::{FS_MANAGER, fd_table::FdFlags, pipefs::new_pipe, simplefs::dentry, sys_root_dentry};
 use vfs_core::{
-    AT_REMOVEDIR, AT_SYMLINK_FOLLOW, AT_SYMLINK_NOFOLLOW, AtFd, Dentry, Inode, InodeMode,
-    InodeType, MountFlags, OpenFlags, Path, RenameFlags, SeekFrom, Stat, StatFs, is_absolute_path,
-    split_parent_and_name,
+    AT_REMOVEDIR, AT_SYMLINK_FOLLOW, AT_SYMLINK_NOFOLLOW, AtFd, Dentry, FileAdvice, Inode,
+    InodeMode, InodeType, MountFlags, OpenFlags, Path, RenameFlags, SeekFrom, Stat, StatFs,
+    is_absolute_path, split_parent_and_name,
 };
 
 use super::Syscall;
@@ -47,6 +47,19 @@ pub enum FcntlOp {
     F_UNIMPL,
 }
 
+// Defined in <linux/fadvise.h>
+#[derive(FromRepr, Debug, Eq, PartialEq, Clone, Copy)]
+#[allow(non_camel_case_types)]
+#[repr(i32)]
+pub enum FadviseAdvice {
+    POSIX_FADV_NORMAL = 0,
+    POSIX_FADV_RANDOM = 1,
+    POSIX_FADV_SEQUENTIAL = 2,
+    POSIX_FADV_WILLNEED = 3,
+    POSIX_FADV_DONTNEED = 4,
+    POSIX_FADV_NOREUSE = 5,
+}
+
 // Defined in <bits/struct_stat.h>
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -1077,4 +1090,67 @@ impl Syscall<'_> {
 
         Ok(out_len)
     }
+
+    /// Programs can use posix_fadvise() to announce an intention to access
+    /// file data in a specific pattern in the future, thus allowing the kernel
+    /// to perform appropriate optimizations.
+    ///
+    /// If len is 0, then the advice applies to all bytes from offset to the
+    /// end of the file.
+    pub fn sys_fadvise64(
+        &self,
+        fd: usize,
+        offset: isize,
+        len: isize,
+        advice: i32,
+    ) -> SyscallResult {
+        let task = self.task;
+        let file = task.with_fd_table(|table| table.get_file(fd))?;
+        let advice = FadviseAdvice::from_repr(advice).ok_or(SysError::EINVAL)?;
+        log::info!(
+            "[sys_fadvise64] file {}, offset {offset}, len {len}, advice {advice:?}",
+            file.dentry().path()
+        );
+        if file.itype().is_fifo() {
+            return Err(SysError::ESPIPE);
+        }
+        if offset < 0 || len < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let (offset, len) = (offset as usize, len as usize);
+        let len = if len == 0 { usize::MAX - offset } else { len };
+        match advice {
+            FadviseAdvice::POSIX_FADV_NORMAL => {
+                file.meta().ra.lock().set_advice(FileAdvice::Normal)
+            }
+            FadviseAdvice::POSIX_FADV_RANDOM => {
+                file.meta().ra.lock().set_advice(FileAdvice::Random)
+            }
+            FadviseAdvice::POSIX_FADV_SEQUENTIAL => {
+                file.meta().ra.lock().set_advice(FileAdvice::Sequential)
+            }
+            FadviseAdvice::POSIX_FADV_WILLNEED => file.meta().readahead(offset, len),
+            // NOTE: cached pages may be dirty and are only written back when
+            // the inode gets dropped, so they can not be discarded here.
+            FadviseAdvice::POSIX_FADV_DONTNEED | FadviseAdvice::POSIX_FADV_NOREUSE => {}
+        }
+        Ok(0)
+    }
+
+    /// readahead() initiates readahead on a file so that subsequent reads from
+    /// that file will be satisfied from the cache, and not block on disk I/O.
+    ///
+    /// readahead() does not block until the specified data has been read.
+    pub fn sys_readahead(&self, fd: usize, offset: isize, count: usize) -> SyscallResult {
+        let task = self.task;
+        let file = task.with_fd_table(|table| table.get_file(fd))?;
+        if !file.flags().readable() {
+            return Err(SysError::EBADF);
+        }
+        if offset < 0 || file.inode().page_cache().is_none() {
+            return Err(SysError::EINVAL);
+        }
+        file.meta().readahead(offset as usize, count);
+        Ok(0)
+    }
 }
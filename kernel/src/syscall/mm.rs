@@ -1,11 +1,19 @@
-use config::mm::{PAGE_MASK, is_aligned_to_page};
-use memory::VirtAddr;
-use systype::{SysError, SyscallResult};
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::cmp;
 
-use super::Syscall;
+use config::mm::{HUGE_PAGE_SIZE, PAGE_MASK, PAGE_SIZE, is_aligned_to_page, round_up_to_page};
+use memory::{
+    VirtAddr,
+    commit::{OvercommitMode, overcommit_mode},
+};
+use systype::{SysError, SysResult, SyscallResult};
+use vfs_core::OpenFlags;
+
+use super::{Syscall, fs::IoVec, net::UIO_MAXIOV};
 use crate::{
     ipc::shm::{SHARED_MEMORY_KEY_ALLOCATOR, SHARED_MEMORY_MANAGER, SharedMemory},
-    mm::{UserWritePtr, memory_space::vm_area::MapPerm},
+    mm::{UserReadPtr, UserWritePtr, memory_space::vm_area::MapPerm, userfaultfd::Userfaultfd},
+    task::{TASK_MANAGER, Task, ptrace},
 };
 
 bitflags! {
@@ -26,11 +34,20 @@ bitflags! {
         const MAP_FIXED = 0x10;
         /// Don't use a file.
         const MAP_ANONYMOUS = 0x20;
+        /// Stack-like segment.
+        const MAP_GROWSDOWN = 0x0100;
         /// Don't check for reservations.
         const MAP_NORESERVE = 0x04000;
+        /// Create huge page mapping.
+        const MAP_HUGETLB = 0x40000;
     }
 }
 
+/// Bits of mmap flags above `MAP_HUGE_SHIFT` encode the log2 of the huge page
+/// size with `MAP_HUGETLB`, 0 for the default one.
+const MAP_HUGE_SHIFT: i32 = 26;
+const MAP_HUGE_MASK: i32 = 0x3f;
+
 bitflags! {
     // Defined in <bits/mman-linux.h>
     // NOTE: Zero bit flag is discouraged. See https://docs.rs/bitflags/latest/bitflags/#zero-bit-flags
@@ -45,6 +62,26 @@ bitflags! {
     }
 }
 
+bitflags! {
+    // Defined in <linux/mman.h>
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct MremapFlags: i32 {
+        /// The mapping may be moved to a new address.
+        const MREMAP_MAYMOVE = 1;
+        /// Move the mapping to the address given, which implies
+        /// `MREMAP_MAYMOVE`.
+        const MREMAP_FIXED = 2;
+    }
+}
+
+// Advices of madvise(2), defined in <bits/mman-linux.h>
+const MADV_NORMAL: i32 = 0;
+const MADV_RANDOM: i32 = 1;
+const MADV_SEQUENTIAL: i32 = 2;
+const MADV_WILLNEED: i32 = 3;
+const MADV_DONTNEED: i32 = 4;
+const MADV_FREE: i32 = 8;
+
 impl From<MmapProt> for MapPerm {
     fn from(prot: MmapProt) -> Self {
         let mut ret = Self::U;
@@ -112,7 +149,8 @@ impl Syscall<'_> {
         offset: usize,
     ) -> SyscallResult {
         let task = self.task;
-        let flags = MmapFlags::from_bits_truncate(flags);
+        let huge_shift = (flags >> MAP_HUGE_SHIFT) & MAP_HUGE_MASK;
+        let mut flags = MmapFlags::from_bits_truncate(flags);
         let prot = MmapProt::from_bits_truncate(prot);
         let perm = MapPerm::from(prot);
 
@@ -125,6 +163,24 @@ impl Syscall<'_> {
         } else if !is_aligned_to_page(offset) {
             return Err(SysError::EINVAL);
         }
+        let mut length = length;
+        if flags.contains(MmapFlags::MAP_HUGETLB) {
+            // There is only the default huge page size, and no hugetlbfs for
+            // file mappings. Shared anonymous mappings are mapped when created
+            // and keep using pages.
+            if huge_shift != 0 && huge_shift as u32 != HUGE_PAGE_SIZE.trailing_zeros() {
+                return Err(SysError::EINVAL);
+            } else if !flags.contains(MmapFlags::MAP_ANONYMOUS) {
+                return Err(SysError::EINVAL);
+            } else if flags.contains(MmapFlags::MAP_FIXED) && addr.bits() % HUGE_PAGE_SIZE != 0 {
+                return Err(SysError::EINVAL);
+            }
+            length = length.next_multiple_of(HUGE_PAGE_SIZE);
+        }
+        // Every mapping is charged when overcommit is never allowed.
+        if overcommit_mode() == OvercommitMode::Never {
+            flags.remove(MmapFlags::MAP_NORESERVE);
+        }
 
         if flags.contains(MmapFlags::MAP_FIXED) {
             task.with_mut_memory_space(|m| m.unmap(addr..(addr + length).round_up()))?;
@@ -199,6 +255,132 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// mremap() expands (or shrinks) an existing memory mapping, potentially
+    /// moving it at the same time (controlled by the flags argument and the
+    /// available virtual address space).
+    ///
+    /// `old_address` is the old address of the virtual memory block that you
+    /// want to expand (or shrink). Note that `old_address` has to be page
+    /// aligned. `old_size` is the old size of the virtual memory block.
+    /// `new_size` is the requested size of the virtual memory block after the
+    /// resize. An optional fifth argument, `new_address`, may be provided with
+    /// `MREMAP_FIXED`.
+    ///
+    /// On success mremap() returns a pointer to the new virtual memory area.
+    // NOTE: `old_size` of zero, which duplicates a shared mapping, and
+    // MREMAP_DONTUNMAP are not supported.
+    pub fn sys_mremap(
+        &self,
+        old_address: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        flags: i32,
+        new_address: VirtAddr,
+    ) -> SyscallResult {
+        let flags = MremapFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        log::info!(
+            "[sys_mremap] {old_address:?} {old_size:#x} -> {new_size:#x}, flags:{flags:?}, new address:{new_address:?}"
+        );
+        if !old_address.is_aligned() || old_size == 0 || new_size == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let fixed = if flags.contains(MremapFlags::MREMAP_FIXED) {
+            if !flags.contains(MremapFlags::MREMAP_MAYMOVE) || !new_address.is_aligned() {
+                return Err(SysError::EINVAL);
+            }
+            Some(new_address)
+        } else {
+            None
+        };
+        let old_range = old_address..(old_address + old_size).round_up();
+        let new_len = round_up_to_page(new_size);
+        let may_move = flags.contains(MremapFlags::MREMAP_MAYMOVE);
+        self.task
+            .with_mut_memory_space(|m| m.mremap(old_range, new_len, may_move, fixed))
+            .map(|start| start.bits())
+    }
+
+    /// The madvise() system call is used to give advice or directions to the
+    /// kernel about the address range beginning at address `addr` and with
+    /// size `length`.
+    ///
+    /// - `MADV_DONTNEED`: pages are dropped, later accesses refault them as
+    ///   zero for anonymous mappings, or from the file for file mappings.
+    /// - `MADV_FREE`: the same for private anonymous mappings only. Pages are
+    ///   freed at once instead of under memory pressure.
+    /// - `MADV_WILLNEED`: file pages are read into the page cache.
+    ///
+    /// Other advices are accepted and ignored.
+    pub fn sys_madvise(&self, addr: VirtAddr, length: usize, advice: i32) -> SyscallResult {
+        log::info!("[sys_madvise] {addr:?} {length:#x}, advice:{advice}");
+        if !addr.is_aligned() {
+            return Err(SysError::EINVAL);
+        }
+        let range = addr..(addr + length).round_up();
+        if range.is_empty() {
+            return Ok(0);
+        }
+        let task = self.task;
+        match advice {
+            MADV_DONTNEED => task.with_mut_memory_space(|m| m.discard_pages(range, false))?,
+            MADV_FREE => task.with_mut_memory_space(|m| m.discard_pages(range, true))?,
+            MADV_WILLNEED => task.with_memory_space(|m| m.prefetch_pages(range))?,
+            MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL => {}
+            advice => log::warn!("[sys_madvise] ignore advice {advice}"),
+        }
+        Ok(0)
+    }
+
+    /// mincore() returns a vector that indicates whether pages of the calling
+    /// process's virtual memory are resident in core (RAM), and so will not
+    /// cause a disk access (page fault) if referenced.
+    ///
+    /// The vector `vec` gets one byte per page of the range starting at the
+    /// page aligned `addr` and spanning `length` bytes, with the least
+    /// significant bit set if the page is resident.
+    pub fn sys_mincore(
+        &self,
+        addr: VirtAddr,
+        length: usize,
+        vec: UserWritePtr<u8>,
+    ) -> SyscallResult {
+        if !addr.is_aligned() {
+            return Err(SysError::EINVAL);
+        }
+        // Not mapped, as any range above the user space.
+        let end = addr
+            .bits()
+            .checked_add(length)
+            .filter(|end| *end <= usize::MAX - PAGE_SIZE)
+            .ok_or(SysError::ENOMEM)?;
+        let range = addr..VirtAddr::from(end).round_up();
+        let task = self.task;
+        let residency = task.with_memory_space(|m| m.mincore(range))?;
+        if !residency.is_empty() {
+            vec.write_array(task, &residency)?;
+        }
+        Ok(0)
+    }
+
+    /// userfaultfd() creates a new userfaultfd object that can be used for
+    /// delegation of page-fault handling to a user-space application, and
+    /// returns a file descriptor that refers to the new object.
+    ///
+    /// `flags` may be `O_CLOEXEC`, `O_NONBLOCK` and `UFFD_USER_MODE_ONLY`, the
+    /// last of which changes nothing since faults of the kernel are never
+    /// delivered.
+    pub fn sys_userfaultfd(&self, flags: i32) -> SyscallResult {
+        const UFFD_USER_MODE_ONLY: i32 = 1;
+        let open_flags = OpenFlags::O_CLOEXEC | OpenFlags::O_NONBLOCK;
+        if flags & !(open_flags.bits() | UFFD_USER_MODE_ONLY) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let flags = OpenFlags::from_bits_truncate(flags) & open_flags;
+        let file = Arc::new(Userfaultfd::new(flags));
+        self.task
+            .with_mut_fd_table(|table| table.alloc(file, flags))
+    }
+
     /// allocates a System V shared memory segment
     ///
     /// shmget() returns the identifier of the System V shared memory segment
@@ -397,4 +579,207 @@ impl Syscall<'_> {
         task.with_mut_memory_space(|m| m.mprotect(new_range, perm))
             .map(|_| 0)
     }
+
+    /// Like mprotect, with the protection key `pkey` of the pages. RISC-V has
+    /// no protection keys, so only -1, i.e. the default key, is accepted.
+    pub fn sys_pkey_mprotect(
+        &self,
+        addr: VirtAddr,
+        len: usize,
+        prot: i32,
+        pkey: i32,
+    ) -> SyscallResult {
+        if pkey != -1 {
+            return Err(SysError::EINVAL);
+        }
+        self.sys_mprotect(addr, len, prot)
+    }
+
+    /// Allocate a protection key. None is available, as with a CPU without
+    /// protection keys on Linux.
+    pub fn sys_pkey_alloc(&self, flags: usize, access_rights: usize) -> SyscallResult {
+        log::info!("[sys_pkey_alloc] flags:{flags:#x}, access_rights:{access_rights:#x}");
+        if flags != 0 {
+            return Err(SysError::EINVAL);
+        }
+        Err(SysError::ENOSPC)
+    }
+
+    /// Free a protection key, none of which is ever allocated.
+    pub fn sys_pkey_free(&self, pkey: i32) -> SyscallResult {
+        log::info!("[sys_pkey_free] pkey:{pkey}");
+        Err(SysError::EINVAL)
+    }
+
+    /// Read the memory of process `pid` described by `remote_iov` into the
+    /// buffers described by `local_iov`, and return the bytes read.
+    ///
+    /// It stops at the first remote page that can not be read, returning the
+    /// bytes read before, or `EFAULT` if there are none.
+    pub fn sys_process_vm_readv(
+        &self,
+        pid: usize,
+        local_iov: UserReadPtr<IoVec>,
+        liovcnt: usize,
+        remote_iov: UserReadPtr<IoVec>,
+        riovcnt: usize,
+        flags: usize,
+    ) -> SyscallResult {
+        let local_iovs = read_process_vm_iovs(self.task, local_iov, liovcnt)?;
+        let remote_iovs = read_process_vm_iovs(self.task, remote_iov, riovcnt)?;
+        self.process_vm_rw(pid, local_iovs, remote_iovs, flags, false)
+    }
+
+    /// Write the buffers described by `local_iov` into the memory of process
+    /// `pid` described by `remote_iov`, and return the bytes written.
+    pub fn sys_process_vm_writev(
+        &self,
+        pid: usize,
+        local_iov: UserReadPtr<IoVec>,
+        liovcnt: usize,
+        remote_iov: UserReadPtr<IoVec>,
+        riovcnt: usize,
+        flags: usize,
+    ) -> SyscallResult {
+        let local_iovs = read_process_vm_iovs(self.task, local_iov, liovcnt)?;
+        let remote_iovs = read_process_vm_iovs(self.task, remote_iov, riovcnt)?;
+        self.process_vm_rw(pid, local_iovs, remote_iovs, flags, true)
+    }
+
+    /// Copy between the buffers of `local_iov` and the memory of `remote_iov`
+    /// of process `pid`, through a kernel buffer, so that the memory space of
+    /// the target is never locked while the one of this task is accessed.
+    fn process_vm_rw(
+        &self,
+        pid: usize,
+        local_iovs: Vec<IoVec>,
+        remote_iovs: Vec<IoVec>,
+        flags: usize,
+        write: bool,
+    ) -> SyscallResult {
+        /// Size of the kernel buffer the data is copied through.
+        const CHUNK_SIZE: usize = 16 * PAGE_SIZE;
+
+        let task = self.task;
+        if flags != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let target = TASK_MANAGER.get(pid).ok_or(SysError::ESRCH)?;
+        ptrace::may_access(task, &target)?;
+        let mut local = IovCursor::new(local_iovs);
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut total_len = 0;
+        'remote: for iov in remote_iovs {
+            let mut done = 0;
+            while done < iov.len {
+                let len = cmp::min(cmp::min(CHUNK_SIZE, iov.len - done), local.remaining());
+                if len == 0 {
+                    break 'remote;
+                }
+                let va = VirtAddr::from(iov.base + done);
+                let result = if write {
+                    local
+                        .copy_in(task, &mut buf[..len])
+                        .and_then(|_| ptrace::write_process_vm(&target, va, &buf[..len], false))
+                } else {
+                    ptrace::read_process_vm(&target, va, &mut buf[..len], false)
+                        .and_then(|n| local.copy_out(task, &buf[..n]).map(|_| n))
+                };
+                let n = match result {
+                    Ok(n) => n,
+                    Err(e) if total_len == 0 => return Err(e),
+                    Err(_) => break 'remote,
+                };
+                total_len += n;
+                done += n;
+                if n < len {
+                    break 'remote;
+                }
+            }
+        }
+        Ok(total_len)
+    }
+}
+
+fn read_process_vm_iovs(
+    task: &Arc<Task>,
+    iov: UserReadPtr<IoVec>,
+    iovcnt: usize,
+) -> SysResult<Vec<IoVec>> {
+    match iovcnt {
+        0 => Ok(Vec::new()),
+        1..=UIO_MAXIOV => iov.read_array(task, iovcnt),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+/// Position in the buffers of a list of iovecs of the current task.
+struct IovCursor {
+    iovs: Vec<IoVec>,
+    /// Index of the current iovec.
+    idx: usize,
+    /// Offset in the current iovec.
+    offset: usize,
+}
+
+impl IovCursor {
+    fn new(iovs: Vec<IoVec>) -> Self {
+        Self {
+            iovs,
+            idx: 0,
+            offset: 0,
+        }
+    }
+
+    /// Bytes left in the buffers.
+    fn remaining(&self) -> usize {
+        self.iovs[self.idx.min(self.iovs.len())..]
+            .iter()
+            .map(|iov| iov.len)
+            .sum::<usize>()
+            - self.offset
+    }
+
+    /// Take the next `len` bytes of the buffers, calling `f` with the address
+    /// of each piece and its offset in them.
+    fn advance(
+        &mut self,
+        len: usize,
+        mut f: impl FnMut(usize, usize, usize) -> SysResult<()>,
+    ) -> SysResult<()> {
+        let mut done = 0;
+        while done < len {
+            let iov = self.iovs[self.idx];
+            let n = cmp::min(iov.len - self.offset, len - done);
+            if n > 0 {
+                f(iov.base + self.offset, done, n)?;
+            }
+            done += n;
+            self.offset += n;
+            if self.offset == iov.len {
+                self.idx += 1;
+                self.offset = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the next `data.len()` bytes of the buffers into `data`.
+    fn copy_in(&mut self, task: &Arc<Task>, data: &mut [u8]) -> SysResult<()> {
+        self.advance(data.len(), |base, offset, n| {
+            let slice = UserReadPtr::<u8>::from(base).into_slice(task, n)?;
+            data[offset..offset + n].copy_from_slice(&slice);
+            Ok(())
+        })
+    }
+
+    /// Copy `data` into the next `data.len()` bytes of the buffers.
+    fn copy_out(&mut self, task: &Arc<Task>, data: &[u8]) -> SysResult<()> {
+        self.advance(data.len(), |base, offset, n| {
+            let mut slice = UserWritePtr::<u8>::from(base).into_mut_slice(task, n)?;
+            slice.copy_from_slice(&data[offset..offset + n]);
+            Ok(())
+        })
+    }
 }
@@ -125,7 +125,38 @@ impl<'a> Syscall<'a> {
             MPROTECT => self.sys_mprotect(args[0].into(), args[1], args[2] as _),
             MSYNC => self.sys_do_nothing("msync"),
             MEMBARRIER => self.sys_do_nothing("membarrier"),
-            MADVISE => self.sys_do_nothing("madvise"),
+            MREMAP => self.sys_mremap(
+                args[0].into(),
+                args[1],
+                args[2],
+                args[3] as _,
+                args[4].into(),
+            ),
+            MADVISE => self.sys_madvise(args[0].into(), args[1], args[2] as _),
+            MINCORE => self.sys_mincore(args[0].into(), args[1], args[2].into()),
+            USERFAULTFD => self.sys_userfaultfd(args[0] as _),
+            PKEY_MPROTECT => {
+                self.sys_pkey_mprotect(args[0].into(), args[1], args[2] as _, args[3] as _)
+            }
+            PKEY_ALLOC => self.sys_pkey_alloc(args[0], args[1]),
+            PKEY_FREE => self.sys_pkey_free(args[0] as _),
+            PTRACE => self.sys_ptrace(args[0], args[1], args[2], args[3]),
+            PROCESS_VM_READV => self.sys_process_vm_readv(
+                args[0],
+                args[1].into(),
+                args[2],
+                args[3].into(),
+                args[4],
+                args[5],
+            ),
+            PROCESS_VM_WRITEV => self.sys_process_vm_writev(
+                args[0],
+                args[1].into(),
+                args[2],
+                args[3].into(),
+                args[4],
+                args[5],
+            ),
             // Shared Memory
             SHMGET => self.sys_shmget(args[0], args[1], args[2] as _),
             SHMAT => self.sys_shmat(args[0], args[1].into(), args[2] as _),
@@ -196,6 +227,8 @@ impl<'a> Syscall<'a> {
                 self.sys_readlinkat(args[0].into(), args[1].into(), args[2].into(), args[3])
                     .await
             }
+            FADVISE64 => self.sys_fadvise64(args[0], args[1] as _, args[2] as _, args[3] as _),
+            READAHEAD => self.sys_readahead(args[0], args[1] as _, args[2]),
             SYNC => self.sys_do_nothing("sync"),
             FSYNC => self.sys_do_nothing("fsync"),
             FTRUNCATE => self.sys_ftruncate(args[0], args[1] as _).await,
@@ -310,6 +343,12 @@ impl<'a> Syscall<'a> {
             SHUTDOWN => self.sys_shutdown(args[0], args[1]),
             SOCKETPAIR => self.sys_socketpair(args[0], args[1], args[2], args[3].into()),
             SENDMSG => self.sys_sendmsg(args[0], args[1].into(), args[2]).await,
+            RECVMSG => self.sys_recvmsg(args[0], args[1], args[2]).await,
+            SENDMMSG => self.sys_sendmmsg(args[0], args[1], args[2], args[3]).await,
+            RECVMMSG => {
+                self.sys_recvmmsg(args[0], args[1], args[2], args[3], args[4].into())
+                    .await
+            }
             // Miscellaneous
             UNAME => self.sys_uname(args[0].into()),
             SYSLOG => self.sys_syslog(args[0], args[1].into(), args[2]),
@@ -1,17 +1,24 @@
-use alloc::{sync::Arc, vec::Vec};
-use core::intrinsics::unlikely;
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    intrinsics::unlikely,
+    mem::{offset_of, size_of},
+    time::Duration,
+};
 
 use addr::SockAddr;
+use arch::time::get_time_duration;
 use log::info;
+use net::{MsgFlags, listen_table::SOMAXCONN};
 use socket::*;
 use systype::{SysError, SysResult, SyscallResult};
+use time::timespec::TimeSpec;
 use vfs::pipefs::new_pipe;
 use vfs_core::OpenFlags;
 use virtio_drivers::PAGE_SIZE;
 
 use super::{Syscall, fs::IoVec};
 use crate::{
-    mm::{UserRdWrPtr, UserReadPtr, UserWritePtr},
+    mm::{UserReadPtr, UserWritePtr},
     net::*,
     task::Task,
 };
@@ -20,7 +27,7 @@ impl Syscall<'_> {
     /// refers to that endpoint. The file descriptor returned by a successful
     /// call will be the lowest-numbered file descriptor not currently open
     /// for the process.
-    pub fn sys_socket(&self, domain: usize, types: i32, _protocal: usize) -> SyscallResult {
+    pub fn sys_socket(&self, domain: usize, types: i32, protocol: usize) -> SyscallResult {
         let domain = SaFamily::try_from(domain as u16)?;
         let mut types = types;
         let mut flags = OpenFlags::empty();
@@ -36,12 +43,12 @@ impl Syscall<'_> {
             flags |= OpenFlags::O_CLOEXEC;
         }
         let types = SocketType::try_from(types)?;
-        let socket = Socket::new(domain, types, nonblock);
+        let socket = Socket::new(domain, types, protocol, nonblock)?;
         let fd = self
             .task
             .with_mut_fd_table(|table| table.alloc(Arc::new(socket), flags))?;
         log::info!(
-            "[sys_socket] new socket {domain:?} {types:?} {flags:?} in fd {fd}, nonblock:{nonblock}"
+            "[sys_socket] new socket {domain:?} {types:?} protocol {protocol} {flags:?} in fd {fd}, nonblock:{nonblock}"
         );
         Ok(fd)
     }
@@ -67,10 +74,16 @@ impl Syscall<'_> {
 
     /// Mark the stream socket referenced by the file descriptor `sockfd` as
     /// passive. This socket will be used later to accept connections from other
-    /// (active) sockets
-    pub fn sys_listen(&self, sockfd: usize, _backlog: usize) -> SyscallResult {
+    /// (active) sockets. At most `backlog` established connections wait to be
+    /// accepted, it is capped by `SOMAXCONN`.
+    pub fn sys_listen(&self, sockfd: usize, backlog: usize) -> SyscallResult {
         let socket = self.task.sockfd_lookup(sockfd)?;
-        socket.sk.listen()?;
+        // A negative backlog means the max.
+        let backlog = match backlog as i32 {
+            backlog if backlog < 0 => SOMAXCONN,
+            backlog => backlog as usize,
+        };
+        socket.sk.listen(backlog)?;
         Ok(0)
     }
 
@@ -103,11 +116,11 @@ impl Syscall<'_> {
 
         task.set_interruptable();
         task.set_wake_up_signal(!*task.sig_mask_ref());
-        let new_sk = socket.sk.accept().await?;
+        let new_sk = socket.accept().await?;
         task.set_running();
 
         let peer_addr = new_sk.peer_addr()?;
-        let peer_addr = SockAddr::from_endpoint(peer_addr);
+        let peer_addr = SockAddr::from_endpoint(peer_addr).to_family(socket.domain);
         log::info!("[sys_accept] peer addr: {peer_addr}");
         task.write_sockaddr(addr, addrlen, peer_addr)?;
         let new_socket = Arc::new(Socket::from_another(&socket, Sock::Tcp(new_sk)));
@@ -122,7 +135,7 @@ impl Syscall<'_> {
     pub fn sys_getsockname(&self, sockfd: usize, addr: usize, addrlen: usize) -> SyscallResult {
         let task = self.task;
         let socket = task.sockfd_lookup(sockfd)?;
-        let local_addr = socket.sk.local_addr()?;
+        let local_addr = socket.sk.local_addr()?.to_family(socket.domain);
         log::info!("[sys_getsockname] local addr: {local_addr}");
         task.write_sockaddr(addr, addrlen, local_addr)?;
         Ok(0)
@@ -132,7 +145,7 @@ impl Syscall<'_> {
     pub fn sys_getpeername(&self, sockfd: usize, addr: usize, addrlen: usize) -> SyscallResult {
         let task = self.task;
         let socket = task.sockfd_lookup(sockfd)?;
-        let peer_addr = socket.sk.peer_addr()?;
+        let peer_addr = socket.sk.peer_addr()?.to_family(socket.domain);
         log::info!("[sys_getpeername] peer addr: {peer_addr}");
         task.write_sockaddr(addr, addrlen, peer_addr)?;
         Ok(0)
@@ -151,7 +164,7 @@ impl Syscall<'_> {
         dest_addr: usize,
         addrlen: usize,
     ) -> SyscallResult {
-        debug_assert!(flags == 0, "unsupported flags");
+        let flags = MsgFlags::from_bits_truncate(flags as u32);
         let task = self.task;
         let buf = buf.into_slice(&task, len)?;
         let socket = task.sockfd_lookup(sockfd)?;
@@ -161,15 +174,15 @@ impl Syscall<'_> {
                 if dest_addr != 0 {
                     return Err(SysError::EISCONN);
                 }
-                socket.sk.sendto(&buf, None).await?
+                socket.sendto(&buf, None, flags).await?
             }
-            SocketType::DGRAM => {
+            SocketType::DGRAM | SocketType::RAW => {
                 let sockaddr = if dest_addr != 0 {
                     Some(task.read_sockaddr(dest_addr, addrlen)?)
                 } else {
                     None
                 };
-                socket.sk.sendto(&buf, sockaddr).await?
+                socket.sendto(&buf, sockaddr, flags).await?
             }
             _ => unimplemented!(),
         };
@@ -181,7 +194,7 @@ impl Syscall<'_> {
     /// - `buf`: A pointer to a buffer used to store received data.
     /// - `len`: The length of the buffer, which is the maximum number of data
     ///   bytes received.
-    /// - `flags`: Currently ignored
+    /// - `flags`: `MSG_*` flags, see [`MsgFlags`]
     /// - `src_addr`: A pointer to the sockaddr structure used to store the
     ///   sender's address information. Can be `NULL`, if the sender address is
     ///   notrequired.
@@ -190,7 +203,8 @@ impl Syscall<'_> {
     ///   src_addr structure, which will include the actual address size after
     ///   the call. Can be `NULL`, if src_addr is `NULL`.
     ///
-    /// Return the number of bytes received
+    /// Return the number of bytes received, or the real length of the datagram
+    /// with `MSG_TRUNC`
     pub async fn sys_recvfrom(
         &self,
         sockfd: usize,
@@ -200,29 +214,29 @@ impl Syscall<'_> {
         src_addr: usize,
         addrlen: usize,
     ) -> SyscallResult {
-        debug_assert!(flags == 0, "unsupported flags");
+        let flags = MsgFlags::from_bits_truncate(flags as u32);
         let task = self.task;
         let socket = task.sockfd_lookup(sockfd)?;
         info!(
-            "[sys_recvfrom]: local_addr: {:?} is trying to recvfrom remote {:?}, ",
+            "[sys_recvfrom]: local_addr: {:?} is trying to recvfrom remote {:?}, flags {flags:?}",
             socket.sk.local_addr(),
             socket.sk.peer_addr(),
         );
-        let mut temp = Vec::with_capacity(len);
-        unsafe { temp.set_len(len) };
+        let mut temp = vec![0; len];
         task.set_interruptable();
-        // TODO: not sure if `len` is enough when call `socket.recvfrom`
-        let (bytes, remote_addr) = socket.sk.recvfrom(&mut temp).await?;
+        let (bytes, remote_addr) = socket.recvfrom(&mut temp, flags).await?;
         task.set_running();
-        let mut buf = buf.into_mut_slice(&task, bytes)?;
-        buf[..bytes].copy_from_slice(&temp[..bytes]);
-        task.write_sockaddr(src_addr, addrlen, remote_addr)?;
-        Ok(bytes)
+        let (copied, ret) = recv_result(&socket, flags, bytes, len);
+        if copied > 0 {
+            let mut buf = buf.into_mut_slice(&task, copied)?;
+            buf.copy_from_slice(&temp[..copied]);
+        }
+        task.write_sockaddr(src_addr, addrlen, remote_addr.to_family(socket.domain))?;
+        Ok(ret)
     }
 
-    /// Allow users to configure sockets
-    /// But since these configurations are too detailed, they are currently not
-    /// supported
+    /// Set option `optname` at protocol `level` of the socket `sockfd` to the
+    /// `optlen` bytes at `optval`.
     pub fn sys_setsockopt(
         &self,
         sockfd: usize,
@@ -231,17 +245,18 @@ impl Syscall<'_> {
         optval: usize,
         optlen: usize,
     ) -> SyscallResult {
-        // let task = self.task;
-        // let socket = task.sockfd_lookup(sockfd)?;
-        log::info!(
-            "[sys_setsockopt] fd{sockfd} {:?} {:?} optval:{} optlen:{optlen}",
-            SocketLevel::try_from(level)?,
-            SocketOpt::try_from(optname)?,
-            UserReadPtr::<usize>::from(optval).read(self.task)?
-        );
+        let task = self.task;
+        let socket = task.sockfd_lookup(sockfd)?;
+        let level = SocketLevel::try_from(level)?;
+        let optval = UserReadPtr::<u8>::from(optval).into_slice(&task, optlen)?;
+        log::info!("[sys_setsockopt] fd{sockfd} {level:?} optname:{optname} optlen:{optlen}");
+        socket.setsockopt(level, optname, &optval)?;
         Ok(0)
     }
 
+    /// Get option `optname` at protocol `level` of the socket `sockfd`. The
+    /// value is truncated to the buffer size given by `optlen`, which is set to
+    /// the size written on return.
     pub fn sys_getsockopt(
         &self,
         sockfd: usize,
@@ -250,58 +265,19 @@ impl Syscall<'_> {
         optval: usize,
         optlen: usize,
     ) -> SyscallResult {
-        use core::mem::size_of;
         let task = self.task;
-        // task.sockfd_lookup(sockfd)?;
-        match SocketLevel::try_from(level)? {
-            SocketLevel::SOL_SOCKET => {
-                const SEND_BUFFER_SIZE: usize = 64 * 1024;
-                const RECV_BUFFER_SIZE: usize = 64 * 1024;
-                match SocketOpt::try_from(optname)? {
-                    SocketOpt::RCVBUF => {
-                        UserWritePtr::<u32>::from(optval).write(&task, RECV_BUFFER_SIZE as u32)?;
-                        UserWritePtr::<u32>::from(optlen).write(&task, size_of::<u32>() as u32)?
-                    }
-                    SocketOpt::SNDBUF => {
-                        UserWritePtr::<u32>::from(optval).write(&task, SEND_BUFFER_SIZE as u32)?;
-                        UserWritePtr::<u32>::from(optlen).write(&task, size_of::<u32>() as u32)?
-                    }
-                    SocketOpt::ERROR => {
-                        UserWritePtr::<u32>::from(optval).write(&task, 0)?;
-                        UserWritePtr::<u32>::from(optlen).write(&task, size_of::<u32>() as u32)?
-                    }
-                    opt => {
-                        log::error!(
-                            "[sys_getsockopt] unsupported SOL_SOCKET opt {opt:?} optlen:{optlen}"
-                        )
-                    }
-                };
-            }
-            SocketLevel::IPPROTO_IP | SocketLevel::IPPROTO_TCP => {
-                const MAX_SEGMENT_SIZE: usize = 1460;
-                match TcpSocketOpt::try_from(optname)? {
-                    TcpSocketOpt::MAXSEG => {
-                        UserWritePtr::<u32>::from(optval).write(&task, MAX_SEGMENT_SIZE as u32)?;
-                        UserWritePtr::<u32>::from(optlen).write(&task, size_of::<u32>() as u32)?
-                    }
-                    TcpSocketOpt::NODELAY => {
-                        UserWritePtr::<u32>::from(optval).write(&task, 0)?;
-                        UserWritePtr::<u32>::from(optlen).write(&task, size_of::<u32>() as u32)?
-                    }
-                    TcpSocketOpt::INFO => {}
-                    TcpSocketOpt::CONGESTION => {
-                        UserWritePtr::from(optval).write_cstr(&task, "reno")?;
-                        UserWritePtr::<u32>::from(optlen).write(&task, 4)?
-                    } /* opt => {
-                       * log::error!(
-                       * "[sys_getsockopt] unsupported IPPROTO_TCP opt {opt:?} optlen:{optlen}"
-                       * )
-                       * }
-                       */
-                };
-            }
-            SocketLevel::IPPROTO_IPV6 => todo!(),
+        let socket = task.sockfd_lookup(sockfd)?;
+        let level = SocketLevel::try_from(level)?;
+        let len = UserReadPtr::<u32>::from(optlen).read(&task)? as i32;
+        if len < 0 {
+            return Err(SysError::EINVAL);
         }
+        let value = socket.getsockopt(level, optname)?;
+        let len = value.len().min(len as usize);
+        log::info!("[sys_getsockopt] fd{sockfd} {level:?} optname:{optname} optlen:{len}");
+        let mut buf = UserWritePtr::<u8>::from(optval).into_mut_slice(&task, len)?;
+        buf.copy_from_slice(&value[..len]);
+        UserWritePtr::<u32>::from(optlen).write(&task, len as u32)?;
         Ok(0)
     }
 
@@ -380,75 +356,223 @@ pub struct CMsgHdr {
     type_: i32,
 }
 
+/// ```c
+/// struct mmsghdr {
+///     struct msghdr msg_hdr;  /* Message header */
+///     unsigned int  msg_len;  /* Number of bytes transmitted */
+/// };
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MMsgHdr {
+    pub hdr: MsgHdr,
+    pub len: u32,
+}
+
+/// Max number of iovecs in a message, and of messages in `sendmmsg` and
+/// `recvmmsg`.
+pub(super) const UIO_MAXIOV: usize = 1024;
+
 impl Syscall<'_> {
+    /// Send the message `msg`. The data of all its iovecs is sent at once, i.e.
+    /// as a single datagram on datagram sockets.
     pub async fn sys_sendmsg(
         &self,
         sockfd: usize,
         msg: UserReadPtr<MsgHdr>,
         flags: usize,
     ) -> SyscallResult {
-        if flags != 0 {
-            log::error!("[sys_sendmsg] unsupported flags {flags}");
-        }
-        // TODO: support flags
+        let flags = MsgFlags::from_bits_truncate(flags as u32);
         let task = self.task;
         let socket = task.sockfd_lookup(sockfd)?;
         let message = msg.read(&task)?;
-        if message.controllen != 0 {
+        task.set_interruptable();
+        let bytes = self.sendmsg_impl(&socket, &message, flags).await?;
+        task.set_running();
+        Ok(bytes)
+    }
+
+    /// Receive a message into the iovecs of `msg`, which are filled in order.
+    /// The source address is written to `msg_name` if it is not `NULL`, and
+    /// `msg_flags` is set to `MSG_TRUNC` if a datagram was truncated. No
+    /// ancillary data is ever returned.
+    pub async fn sys_recvmsg(&self, sockfd: usize, msg: usize, flags: usize) -> SyscallResult {
+        let flags = MsgFlags::from_bits_truncate(flags as u32);
+        let task = self.task;
+        let socket = task.sockfd_lookup(sockfd)?;
+        task.set_interruptable();
+        let bytes = self.recvmsg_impl(&socket, msg, flags).await?;
+        task.set_running();
+        Ok(bytes)
+    }
+
+    /// Send up to `vlen` messages of the array `msgvec` of `struct mmsghdr`,
+    /// `msg_len` of each is set to the bytes sent.
+    ///
+    /// Returns the number of messages sent. An error is only returned if the
+    /// first message fails.
+    pub async fn sys_sendmmsg(
+        &self,
+        sockfd: usize,
+        msgvec: usize,
+        vlen: usize,
+        flags: usize,
+    ) -> SyscallResult {
+        let flags = MsgFlags::from_bits_truncate(flags as u32);
+        let task = self.task;
+        let socket = task.sockfd_lookup(sockfd)?;
+        task.set_interruptable();
+        let mut sent = 0;
+        for i in 0..vlen.min(UIO_MAXIOV) {
+            let entry = msgvec + i * size_of::<MMsgHdr>();
+            let message = UserReadPtr::<MsgHdr>::from(entry).read(&task)?;
+            match self.sendmsg_impl(&socket, &message, flags).await {
+                Ok(bytes) => {
+                    UserWritePtr::<u32>::from(entry + offset_of!(MMsgHdr, len))
+                        .write(&task, bytes as u32)?;
+                    sent += 1;
+                }
+                Err(e) if sent == 0 => return Err(e),
+                Err(e) => {
+                    log::warn!("[sys_sendmmsg] message #{i} failed: {e:?}");
+                    break;
+                }
+            }
+        }
+        task.set_running();
+        Ok(sent)
+    }
+
+    /// Receive up to `vlen` messages into the array `msgvec` of `struct
+    /// mmsghdr`, see [`sys_recvmsg`](Self::sys_recvmsg). With
+    /// `MSG_WAITFORONE` only the first receive blocks.
+    ///
+    /// Like Linux, `timeout` is only checked after each message is received,
+    /// so it does not bound a receive that blocks.
+    pub async fn sys_recvmmsg(
+        &self,
+        sockfd: usize,
+        msgvec: usize,
+        vlen: usize,
+        flags: usize,
+        timeout: UserReadPtr<TimeSpec>,
+    ) -> SyscallResult {
+        let mut flags = MsgFlags::from_bits_truncate(flags as u32);
+        let task = self.task;
+        let socket = task.sockfd_lookup(sockfd)?;
+        let deadline = if timeout.is_null() {
+            None
+        } else {
+            let timeout = timeout.read(&task)?;
+            if !timeout.is_valid() {
+                return Err(SysError::EINVAL);
+            }
+            Some(get_time_duration() + Duration::from(timeout))
+        };
+        task.set_interruptable();
+        let mut received = 0;
+        for i in 0..vlen.min(UIO_MAXIOV) {
+            let entry = msgvec + i * size_of::<MMsgHdr>();
+            match self.recvmsg_impl(&socket, entry, flags).await {
+                Ok(bytes) => {
+                    UserWritePtr::<u32>::from(entry + offset_of!(MMsgHdr, len))
+                        .write(&task, bytes as u32)?;
+                    received += 1;
+                }
+                Err(e) if received == 0 => return Err(e),
+                Err(_) => break,
+            }
+            if flags.contains(MsgFlags::WAITFORONE) {
+                flags |= MsgFlags::DONTWAIT;
+            }
+            if deadline.is_some_and(|deadline| get_time_duration() >= deadline) {
+                break;
+            }
+        }
+        task.set_running();
+        Ok(received)
+    }
+
+    /// Gather the iovecs of `msg` and send them.
+    async fn sendmsg_impl(&self, socket: &Socket, msg: &MsgHdr, flags: MsgFlags) -> SyscallResult {
+        let task = self.task;
+        if msg.controllen != 0 {
             log::warn!("[sys_sendmsg] unsupport msg control");
         }
-        let addr = task.read_sockaddr(message.name, message.namelen as _)?;
-        let iovs = UserReadPtr::<IoVec>::from(message.iov).read_array(&task, message.iovlen)?;
-        let mut total_len = 0;
+        let addr = if msg.name != 0 {
+            Some(task.read_sockaddr(msg.name, msg.namelen as _)?)
+        } else {
+            None
+        };
+        let iovs = read_iovs(task, msg)?;
+        let mut buf = Vec::with_capacity(iovs.iter().map(|iov| iov.len).sum());
+        for iov in iovs.iter().filter(|iov| iov.len != 0) {
+            let data = UserReadPtr::<u8>::from(iov.base).into_slice(&task, iov.len)?;
+            buf.extend_from_slice(&data);
+        }
+        socket.sendto(&buf, addr, flags).await
+    }
+
+    /// Receive into the iovecs of the `struct msghdr` at user address `msg`,
+    /// and fill in its `msg_namelen`, `msg_controllen` and `msg_flags`.
+    async fn recvmsg_impl(&self, socket: &Socket, msg: usize, flags: MsgFlags) -> SyscallResult {
+        let task = self.task;
+        let message = UserReadPtr::<MsgHdr>::from(msg).read(&task)?;
+        let iovs = read_iovs(task, &message)?;
+        let total = iovs.iter().map(|iov| iov.len).sum();
+        let mut buf = vec![0; total];
+        let (bytes, remote_addr) = socket.recvfrom(&mut buf, flags).await?;
+        let (copied, ret) = recv_result(socket, flags, bytes, total);
+        let mut data = &buf[..copied];
         for (i, iov) in iovs.iter().enumerate() {
-            if unlikely(iov.len == 0) {
+            if data.is_empty() {
+                break;
+            }
+            let len = iov.len.min(data.len());
+            if unlikely(len == 0) {
                 continue;
             }
-            let ptr = UserWritePtr::<u8>::from(iov.base);
-            log::info!("[sys_sendmsg] iov #{i}, ptr: {ptr}, len: {}", iov.len);
-            let buf = ptr.into_mut_slice(&task, iov.len)?;
-            let send_len = socket.sk.sendto(&buf, Some(addr)).await?;
-            total_len += send_len;
+            log::info!("[sys_recvmsg] iov #{i}, ptr: {:#x}, len: {len}", iov.base);
+            let mut dst = UserWritePtr::<u8>::from(iov.base).into_mut_slice(&task, len)?;
+            dst.copy_from_slice(&data[..len]);
+            data = &data[len..];
         }
-        Ok(total_len)
+        let mut msg_flags = MsgFlags::empty();
+        if socket.types != SocketType::STREAM && bytes > total {
+            msg_flags |= MsgFlags::TRUNC;
+        }
+        task.write_sockaddr(
+            message.name,
+            msg + offset_of!(MsgHdr, namelen),
+            remote_addr.to_family(socket.domain),
+        )?;
+        UserWritePtr::<usize>::from(msg + offset_of!(MsgHdr, controllen)).write(&task, 0)?;
+        UserWritePtr::<i32>::from(msg + offset_of!(MsgHdr, flags))
+            .write(&task, msg_flags.bits() as i32)?;
+        Ok(ret)
     }
+}
 
-    /// 目前的实现是，如果Udp Socket收到多个不同Ip地址的数据报如ip1, ip1, ip2,
-    /// ip3, ip1，recvmsg
-    // pub async fn sys_recvmsg(
-    //     &self,
-    //     sockfd: usize,
-    //     msg: UserRdWrPtr<MsgHdr>,
-    //     flags: usize,
-    // ) -> SyscallResult {
-    //     if flags != 0 {
-    //         log::error!("[sys_sendmsg] unsupported flags {flags}");
-    //     }
-    //     // TODO: support flags
-    //     let task = self.task;
-    //     let socket = task.sockfd_lookup(sockfd)?;
-    //     let msg = msg.read(&task)?;
-    //     if msg.controllen != 0 {
-    //         log::warn!("[sys_sendmsg] unsupport msg control");
-    //     }
-    //     let addr = task.read_sockaddr(msg.name, msg.namelen as _)?;
-    //     let iovs = UserReadPtr::<IoVec>::from(msg.iov).read_array(&task,
-    // msg.iovlen)?;     let mut total_len = 0;
-    //     for (i, iov) in iovs.iter().enumerate() {
-    //         if unlikely(iov.len == 0) {
-    //             continue;
-    //         }
-    //         let ptr = UserWritePtr::<u8>::from(iov.base);
-    //         log::info!("[sys_recvmsg] iov #{i}, ptr: {ptr}, len: {}", iov.len);
-    //         let buf = ptr.into_mut_slice(&task, iov.len)?;
-    //         let send_len = socket.sk.sendto(&buf, Some(addr)).await?;
-    //         total_len += send_len;
-    //     }
-    //     Ok(total_len)
-    // }
+fn read_iovs(task: &Arc<Task>, msg: &MsgHdr) -> SysResult<Vec<IoVec>> {
+    if msg.iovlen > UIO_MAXIOV {
+        return Err(SysError::EMSGSIZE);
+    }
+    if msg.iovlen == 0 {
+        return Ok(Vec::new());
+    }
+    UserReadPtr::<IoVec>::from(msg.iov).read_array(task, msg.iovlen)
+}
 
-    pub fn sys_sendmmsg(&self, sockfd: usize) -> SyscallResult {
-        Ok(0)
+/// Returns how many of the `len` bytes received into a buffer of `cap` bytes
+/// are copied to the user, and the return value of the syscall. `MSG_TRUNC`
+/// returns the real length of a datagram, and discards the data of a stream.
+fn recv_result(socket: &Socket, flags: MsgFlags, len: usize, cap: usize) -> (usize, usize) {
+    if !flags.contains(MsgFlags::TRUNC) {
+        return (len.min(cap), len.min(cap));
+    }
+    match socket.types {
+        SocketType::STREAM => (0, len),
+        _ => (len.min(cap), len),
     }
 }
 
@@ -2,18 +2,29 @@
 
 use alloc::{
     string::{String, ToString},
+    sync::Arc,
     vec::Vec,
 };
+use core::cmp;
 
 use async_utils::{suspend_now, yield_now};
 use memory::VirtAddr;
-use signal::sigset::SigSet;
+use signal::{
+    siginfo::{SigDetails, SigInfo},
+    sigset::{NSIG, Sig, SigSet},
+};
+use strum::FromRepr;
 use systype::{SysError, SysResult, SyscallResult};
 
-use super::Syscall;
+use super::{Syscall, fs::IoVec};
 use crate::{
     mm::{UserReadPtr, UserWritePtr},
-    task::{PGid, PROCESS_GROUP_MANAGER, Pid, TASK_MANAGER, spawn_user_task},
+    task::{
+        PGid, PROCESS_GROUP_MANAGER, Pid, TASK_MANAGER, Task, Tid,
+        ptrace::{self, PtraceOptions, Resume, UserRegs},
+        signal::LinuxSigInfo,
+        spawn_user_task,
+    },
 };
 
 bitflags! {
@@ -78,6 +89,37 @@ bitflags! {
     }
 }
 
+/// Requests of `ptrace`, defined in <sys/ptrace.h>.
+#[derive(FromRepr, Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(usize)]
+pub enum PtraceRequest {
+    TRACEME = 0,
+    PEEKTEXT = 1,
+    PEEKDATA = 2,
+    PEEKUSER = 3,
+    POKETEXT = 4,
+    POKEDATA = 5,
+    POKEUSER = 6,
+    CONT = 7,
+    KILL = 8,
+    SINGLESTEP = 9,
+    GETREGS = 12,
+    SETREGS = 13,
+    ATTACH = 16,
+    DETACH = 17,
+    SYSCALL = 24,
+    SETOPTIONS = 0x4200,
+    GETEVENTMSG = 0x4201,
+    GETSIGINFO = 0x4202,
+    GETREGSET = 0x4204,
+    SETREGSET = 0x4205,
+    SEIZE = 0x4206,
+    INTERRUPT = 0x4207,
+}
+
+/// Register set of `PTRACE_GETREGSET` with the general registers.
+const NT_PRSTATUS: usize = 1;
+
 impl Syscall<'_> {
     /// _exit() system call terminates only the calling thread, and actions such
     /// as reparenting child processes or sending SIGCHLD to the parent
@@ -156,10 +198,19 @@ impl Syscall<'_> {
         };
         log::info!("[sys_wait4] target: {target:?}, option: {option:?}");
 
+        // Stops and exits of tracees are reported whether they are children or not.
+        let tracee = match target {
+            WaitFor::Pid(pid) => Some(pid),
+            _ => None,
+        };
+        if let Some((tid, status)) = ptrace::wait_tracee(task, tracee) {
+            return report_tracee(task, wstatus, tid, status);
+        }
+
         let res_task = {
             // 首先检查一遍等待的进程是否已经是zombie了
             let children = task.children();
-            if children.is_empty() {
+            if children.is_empty() && !ptrace::traces(task, None) {
                 log::info!("[sys_wait4] fail: no child");
                 return Err(SysError::ECHILD);
             }
@@ -175,6 +226,8 @@ impl Syscall<'_> {
                         } else {
                             None
                         }
+                    } else if ptrace::traces(task, Some(pid)) {
+                        None
                     } else {
                         log::info!("[sys_wait4] fail: no child with pid {pid}");
                         return Err(SysError::ECHILD);
@@ -214,13 +267,22 @@ impl Syscall<'_> {
                 task.set_running();
                 let si = task.with_mut_sig_pending(|pending| pending.get_expect(SigSet::SIGCHLD));
                 if let Some(_info) = si {
+                    if let Some((tid, status)) = ptrace::wait_tracee(task, tracee) {
+                        return report_tracee(task, wstatus, tid, status);
+                    }
                     let children = task.children();
                     let child = match target {
                         WaitFor::AnyChild => children
                             .values()
                             .find(|c| c.is_zombie() && c.with_thread_group(|tg| tg.len() == 1)),
                         WaitFor::Pid(pid) => {
-                            let child = children.get(&pid).unwrap();
+                            let Some(child) = children.get(&pid) else {
+                                // A tracee that is not a child, which may have been reported.
+                                if ptrace::traces(task, Some(pid)) {
+                                    continue;
+                                }
+                                return Err(SysError::ECHILD);
+                            };
                             if child.is_zombie() && child.with_thread_group(|tg| tg.len() == 1) {
                                 Some(child)
                             } else {
@@ -230,6 +292,9 @@ impl Syscall<'_> {
                         WaitFor::PGid(_) => unimplemented!(),
                         WaitFor::AnyChildInGroup => unimplemented!(),
                     };
+                    if child.is_none() && children.is_empty() && !ptrace::traces(task, None) {
+                        return Err(SysError::ECHILD);
+                    }
                     if let Some(child) = child {
                         break (
                             child.pid(),
@@ -306,6 +371,7 @@ impl Syscall<'_> {
         let file = task.resolve_path(&path)?.open()?;
         let elf_data = file.read_all().await?;
         task.do_execve(file, &elf_data, argv, envp);
+        ptrace::exec_stop(task).await;
         Ok(0)
     }
 
@@ -325,7 +391,7 @@ impl Syscall<'_> {
             "[sys_clone] flags:{flags:?}, stack:{stack:#x}, tls:{tls:?}, parent_tid:{parent_tid:?}, child_tid:{child_tid:?}"
         );
         let task = self.task;
-        let new_task = task.do_clone(flags);
+        let new_task = task.do_clone(flags)?;
         new_task.trap_context_mut().set_user_a0(0);
         let new_tid = new_task.tid();
         log::info!("[sys_clone] clone a new thread, tid {new_tid}, clone flags {flags:?}",);
@@ -432,4 +498,165 @@ impl Syscall<'_> {
         let task = self.task;
         Ok(task.pid())
     }
+
+    /// ptrace() lets a tracer process observe and control the execution of
+    /// the tracee task `pid`, or with `PTRACE_TRACEME` makes the parent trace
+    /// the calling task.
+    ///
+    /// Except for attaching, `pid` must be a tracee of the calling process,
+    /// stopped unless the request is `PTRACE_KILL` or `PTRACE_INTERRUPT`,
+    /// or else `ESRCH` is returned. As with the system call of Linux, rather
+    /// than its C library wrapper, peeked words are written at `data`.
+    pub fn sys_ptrace(
+        &self,
+        request: usize,
+        pid: usize,
+        addr: usize,
+        data: usize,
+    ) -> SyscallResult {
+        use PtraceRequest::*;
+        let task = self.task;
+        let request = PtraceRequest::from_repr(request).ok_or(SysError::EIO)?;
+        log::info!("[sys_ptrace] {request:?} pid:{pid}, addr:{addr:#x}, data:{data:#x}");
+        match request {
+            TRACEME => {
+                let parent = task
+                    .parent()
+                    .and_then(|parent| parent.upgrade())
+                    .ok_or(SysError::EPERM)?;
+                ptrace::attach(&parent, task, PtraceOptions::empty(), false)?;
+            }
+            ATTACH => {
+                let tracee = TASK_MANAGER.get(pid).ok_or(SysError::ESRCH)?;
+                ptrace::attach(task, &tracee, PtraceOptions::empty(), false)?;
+                // Directed to the tracee, which reports the stop.
+                tracee.receive_siginfo(
+                    SigInfo {
+                        sig: Sig::SIGSTOP,
+                        code: SigInfo::USER,
+                        details: SigDetails::Kill { pid: task.pid() },
+                    },
+                    true,
+                );
+            }
+            SEIZE => {
+                if addr != 0 {
+                    return Err(SysError::EIO);
+                }
+                let options = PtraceOptions::from_bits(data).ok_or(SysError::EINVAL)?;
+                let tracee = TASK_MANAGER.get(pid).ok_or(SysError::ESRCH)?;
+                ptrace::attach(task, &tracee, options, true)?;
+            }
+            KILL => {
+                let tracee = ptrace::tracee(task, pid, false)?;
+                tracee.receive_siginfo(
+                    SigInfo {
+                        sig: Sig::SIGKILL,
+                        code: SigInfo::USER,
+                        details: SigDetails::Kill { pid: task.pid() },
+                    },
+                    true,
+                );
+            }
+            INTERRUPT => ptrace::interrupt(&ptrace::tracee(task, pid, false)?)?,
+            PEEKUSER | POKEUSER => return Err(SysError::EIO),
+            _ => {
+                let tracee = ptrace::tracee(task, pid, true)?;
+                self.ptrace_stopped(&tracee, request, addr, data)?;
+            }
+        }
+        Ok(0)
+    }
+
+    /// Requests of `ptrace` on a stopped `tracee`.
+    fn ptrace_stopped(
+        &self,
+        tracee: &Arc<Task>,
+        request: PtraceRequest,
+        addr: usize,
+        data: usize,
+    ) -> SysResult<()> {
+        use PtraceRequest::*;
+        let task = self.task;
+        match request {
+            PEEKTEXT | PEEKDATA => {
+                let word = ptrace::peek(tracee, VirtAddr::from(addr))?;
+                UserWritePtr::<usize>::from(data).write(task, word)?;
+            }
+            POKETEXT | POKEDATA => ptrace::poke(tracee, VirtAddr::from(addr), data)?,
+            CONT => ptrace::resume(tracee, Resume::Cont, resume_signal(data)?),
+            SYSCALL => ptrace::resume(tracee, Resume::Syscall, resume_signal(data)?),
+            SINGLESTEP => ptrace::resume(tracee, Resume::SingleStep, resume_signal(data)?),
+            DETACH => ptrace::detach(tracee, resume_signal(data)?),
+            GETREGS => {
+                UserWritePtr::<UserRegs>::from(data).write(task, ptrace::get_regs(tracee))?
+            }
+            SETREGS => {
+                let regs = UserReadPtr::<UserRegs>::from(data).read(task)?;
+                ptrace::set_regs(tracee, &regs);
+            }
+            GETREGSET | SETREGSET => {
+                if addr != NT_PRSTATUS {
+                    return Err(SysError::EINVAL);
+                }
+                let iov = UserReadPtr::<IoVec>::from(data).read(task)?;
+                let len = cmp::min(iov.len, size_of::<UserRegs>());
+                let mut regs = ptrace::get_regs(tracee);
+                let mut bytes: Vec<u8> = regs.iter().flat_map(|reg| reg.to_ne_bytes()).collect();
+                if request == GETREGSET {
+                    UserWritePtr::<u8>::from(iov.base).write_array(task, &bytes[..len])?;
+                } else {
+                    let new = UserReadPtr::<u8>::from(iov.base).read_array(task, len)?;
+                    bytes[..len].copy_from_slice(&new);
+                    for (reg, reg_bytes) in
+                        regs.iter_mut().zip(bytes.chunks_exact(size_of::<usize>()))
+                    {
+                        *reg = usize::from_ne_bytes(reg_bytes.try_into().unwrap());
+                    }
+                    ptrace::set_regs(tracee, &regs);
+                }
+                UserWritePtr::<IoVec>::from(data).write(task, IoVec {
+                    base: iov.base,
+                    len,
+                })?;
+            }
+            SETOPTIONS => {
+                let options = PtraceOptions::from_bits(data).ok_or(SysError::EINVAL)?;
+                ptrace::set_options(tracee, options);
+            }
+            GETEVENTMSG => {
+                UserWritePtr::<usize>::from(data).write(task, ptrace::event_msg(tracee))?
+            }
+            GETSIGINFO => {
+                let si = ptrace::siginfo(tracee).ok_or(SysError::EINVAL)?;
+                UserWritePtr::<LinuxSigInfo>::from(data).write(task, si.into())?;
+            }
+            TRACEME | ATTACH | SEIZE | KILL | INTERRUPT | PEEKUSER | POKEUSER => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// Signal `data` a tracee is resumed with, 0 for none.
+fn resume_signal(data: usize) -> SysResult<Option<Sig>> {
+    match data {
+        0 => Ok(None),
+        sig if sig <= NSIG => Ok(Some(Sig::from_i32(sig as _))),
+        _ => Err(SysError::EIO),
+    }
+}
+
+/// Report the stop or exit of tracee `tid` with wait status `status` from
+/// `wait4`.
+fn report_tracee(
+    task: &Arc<Task>,
+    wstatus: UserWritePtr<i32>,
+    tid: Tid,
+    status: i32,
+) -> SyscallResult {
+    log::debug!("[sys_wait4] tracee {tid}, wstatus: {status:#x}");
+    if wstatus.not_null() {
+        wstatus.write(task, status)?;
+    }
+    Ok(tid)
 }
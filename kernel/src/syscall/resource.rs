@@ -1,4 +1,4 @@
-use config::{board::MAX_HARTS, process::USER_STACK_SIZE};
+use config::board::MAX_HARTS;
 use strum::FromRepr;
 use systype::{RLimit, Rusage, SysError, SyscallResult};
 
@@ -118,11 +118,10 @@ impl Syscall<'_> {
         let resource = Resource::from_repr(resource).ok_or(SysError::EINVAL)?;
         if old_limit.not_null() {
             let limit = match resource {
-                STACK => RLimit {
-                    rlim_cur: USER_STACK_SIZE,
-                    rlim_max: USER_STACK_SIZE,
-                },
+                STACK => task.with_memory_space(|m| m.rlimit_stack()),
                 NOFILE => task.with_fd_table(|table| table.rlimit()),
+                AS => task.with_memory_space(|m| m.rlimit_as()),
+                DATA => task.with_memory_space(|m| m.rlimit_data()),
                 r => {
                     log::warn!("[sys_prlimit64] get old_limit : unimplemented {r:?}");
                     RLimit {
@@ -136,10 +135,16 @@ impl Syscall<'_> {
         if new_limit.not_null() {
             let limit = new_limit.read(&task)?;
             log::info!("[sys_prlimit64] new_limit: {limit:?}");
+            if limit.rlim_cur > limit.rlim_max {
+                return Err(SysError::EINVAL);
+            }
             match resource {
                 NOFILE => {
                     task.with_mut_fd_table(|table| table.set_rlimit(limit));
                 }
+                AS => task.with_mut_memory_space(|m| m.set_rlimit_as(limit)),
+                DATA => task.with_mut_memory_space(|m| m.set_rlimit_data(limit)),
+                STACK => task.with_mut_memory_space(|m| m.set_rlimit_stack(limit)),
                 r => {
                     log::warn!("[sys_prlimit64] set new_limit : unimplemented {r:?}");
                 }
@@ -1,5 +1,7 @@
 pub mod aux;
 mod manager;
+pub mod procfs;
+pub mod ptrace;
 pub mod resource;
 mod schedule;
 pub mod signal;
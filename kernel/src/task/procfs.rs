@@ -0,0 +1,306 @@
+//! Contents of the files of `/proc/<pid>` about the memory and the state of a
+//! process, in the formats of Linux.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use config::mm::PAGE_SIZE;
+use signal::{
+    action::ActionType,
+    sigset::{NSIG, Sig, SigSet},
+};
+
+use super::{Task, ptrace, task::TaskState};
+use crate::{
+    mm::memory_space::{
+        MemorySpace,
+        vm_area::{MapPerm, VmArea, VmAreaType},
+    },
+    syscall::MmapFlags,
+};
+
+/// Column the path of an area starts at in `maps`.
+const MAPS_PATH_COLUMN: usize = 73;
+
+const PAGE_KB: usize = PAGE_SIZE / 1024;
+
+/// Path shown for an area, the backing file or the kind of the area.
+fn area_path(vma: &VmArea) -> String {
+    if let Some(file) = vma.backed_file.as_ref() {
+        return file.dentry().path();
+    }
+    match vma.vma_type {
+        VmAreaType::Heap => "[heap]".to_string(),
+        VmAreaType::Stack => "[stack]".to_string(),
+        VmAreaType::Shm => "/SYSV00000000 (deleted)".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// The line of `vma` in `maps`, which is also the header of it in `smaps`.
+fn write_maps_line(out: &mut String, vma: &VmArea) {
+    let perm = vma.perm();
+    let flag = |p: MapPerm, c: char| if perm.contains(p) { c } else { '-' };
+    let ino = vma
+        .backed_file
+        .as_ref()
+        .map_or(0, |file| file.inode().meta().ino);
+    let line = format!(
+        "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 {ino}",
+        vma.start_va().bits(),
+        vma.end_va().bits(),
+        flag(MapPerm::R, 'r'),
+        flag(MapPerm::W, 'w'),
+        flag(MapPerm::X, 'x'),
+        if vma.is_shared() { 's' } else { 'p' },
+        vma.offset,
+    );
+    let path = area_path(vma);
+    if path.is_empty() {
+        let _ = writeln!(out, "{line}");
+    } else {
+        let _ = writeln!(out, "{line:<width$}{path}", width = MAPS_PATH_COLUMN - 1);
+    }
+}
+
+/// Flags of `vma` in the `VmFlags` of `smaps`.
+fn vm_flags(vma: &VmArea) -> String {
+    let perm = vma.perm();
+    let flags = [
+        (perm.contains(MapPerm::R), "rd"),
+        (perm.contains(MapPerm::W), "wr"),
+        (perm.contains(MapPerm::X), "ex"),
+        (vma.is_shared(), "sh"),
+        (vma.can_grow_down(), "gd"),
+        (vma.mmap_flags.contains(MmapFlags::MAP_NORESERVE), "nr"),
+        (vma.mmap_flags.contains(MmapFlags::MAP_HUGETLB), "ht"),
+        (vma.userfaultfd().is_some(), "um"),
+    ];
+    flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The entry of `vma` in `smaps`.
+///
+/// A page mapped by some other area or held by the page cache is shared, and
+/// it is clean if it is a page of the backing file rather than a copy.
+fn write_smaps_entry(out: &mut String, vma: &VmArea) {
+    write_maps_line(out, vma);
+    let (mut shared_clean, mut shared_dirty, mut private_clean, mut private_dirty) = (0, 0, 0, 0);
+    let mut pss = 0;
+    for page in vma.pages.values() {
+        let count = Arc::strong_count(page);
+        pss += PAGE_SIZE / count;
+        let clean = vma.backed_file.is_some() && count > 1;
+        match (count > 1, clean) {
+            (true, true) => shared_clean += PAGE_KB,
+            (true, false) => shared_dirty += PAGE_KB,
+            (false, true) => private_clean += PAGE_KB,
+            (false, false) => private_dirty += PAGE_KB,
+        }
+    }
+    let rss = vma.pages.len() * PAGE_KB;
+    let anonymous = if vma.is_private_anonymous() { rss } else { 0 };
+    let fields = [
+        ("Size:", (vma.end_vpn() - vma.start_vpn()) * PAGE_KB),
+        ("KernelPageSize:", PAGE_KB),
+        ("MMUPageSize:", PAGE_KB),
+        ("Rss:", rss),
+        ("Pss:", pss / 1024),
+        ("Shared_Clean:", shared_clean),
+        ("Shared_Dirty:", shared_dirty),
+        ("Private_Clean:", private_clean),
+        ("Private_Dirty:", private_dirty),
+        ("Referenced:", rss),
+        ("Anonymous:", anonymous),
+        ("Swap:", 0),
+        ("Locked:", 0),
+    ];
+    for (name, kb) in fields {
+        let _ = writeln!(out, "{name:<16}{kb:>8} kB");
+    }
+    let _ = writeln!(out, "VmFlags: {}", vm_flags(vma));
+}
+
+/// `/proc/<pid>/maps` of the process led by `task`.
+pub fn show_maps(task: &Arc<Task>) -> String {
+    task.with_memory_space(|m| {
+        let mut out = String::new();
+        for (_, vma) in m.areas().iter() {
+            write_maps_line(&mut out, vma);
+        }
+        out
+    })
+}
+
+/// `/proc/<pid>/smaps` of the process led by `task`.
+pub fn show_smaps(task: &Arc<Task>) -> String {
+    task.with_memory_space(|m| {
+        let mut out = String::new();
+        for (_, vma) in m.areas().iter() {
+            write_smaps_entry(&mut out, vma);
+        }
+        out
+    })
+}
+
+/// Sizes of a memory space in pages, as counted by `status` and `statm`.
+struct MemoryUsage {
+    size: usize,
+    resident: usize,
+    /// Resident pages of file mappings.
+    file: usize,
+    /// Resident pages of shared memory.
+    shmem: usize,
+    /// Executable pages of the program.
+    text: usize,
+    /// Executable pages of other files, i.e. libraries.
+    lib: usize,
+    data: usize,
+    stack: usize,
+}
+
+impl MemoryUsage {
+    fn of(m: &MemorySpace) -> Self {
+        let mut usage = Self {
+            size: m.total_pages(),
+            resident: m.resident_pages(),
+            file: 0,
+            shmem: 0,
+            text: 0,
+            lib: 0,
+            data: m.data_pages(),
+            stack: 0,
+        };
+        for (_, vma) in m.areas().iter() {
+            let pages = vma.end_vpn() - vma.start_vpn();
+            match vma.vma_type {
+                VmAreaType::Shm => usage.shmem += vma.pages.len(),
+                VmAreaType::Stack => usage.stack += pages,
+                _ if vma.backed_file.is_some() => usage.file += vma.pages.len(),
+                _ => {}
+            }
+            if vma.perm().contains(MapPerm::X) {
+                match vma.vma_type {
+                    VmAreaType::Elf => usage.text += pages,
+                    _ => usage.lib += pages,
+                }
+            }
+        }
+        usage
+    }
+
+    fn anon(&self) -> usize {
+        self.resident - self.file - self.shmem
+    }
+}
+
+fn state_name(state: TaskState) -> &'static str {
+    match state {
+        TaskState::Running => "R (running)",
+        TaskState::Interruptable => "S (sleeping)",
+        TaskState::UnInterruptable => "D (disk sleep)",
+        TaskState::Stopped => "T (stopped)",
+        TaskState::Terminated | TaskState::Zombie => "Z (zombie)",
+    }
+}
+
+/// Signals set to be ignored and the ones caught by a handler.
+fn sig_ign_and_cgt(task: &Arc<Task>) -> (SigSet, SigSet) {
+    task.with_sig_handlers(|handlers| {
+        let (mut ignored, mut caught) = (SigSet::empty(), SigSet::empty());
+        for signo in 1..=NSIG {
+            let sig = Sig::from_i32(signo as i32);
+            match handlers.get(sig).atype {
+                ActionType::Ignore if ActionType::default(sig) != ActionType::Ignore => {
+                    ignored.add_signal(sig)
+                }
+                ActionType::User { .. } => caught.add_signal(sig),
+                _ => {}
+            }
+        }
+        (ignored, caught)
+    })
+}
+
+/// `/proc/<pid>/status` of the process led by `task`.
+///
+/// `SigPnd` holds the signals pending for the leader, and `ShdPnd` the ones
+/// pending for any thread of the process.
+pub fn show_status(task: &Arc<Task>) -> String {
+    let path = task.elf_ref().dentry().path();
+    // Truncated like the `comm` of Linux.
+    let name: String = path
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take(15)
+        .collect();
+    let usage = task.with_memory_space(MemoryUsage::of);
+    let sig_pending = task.with_sig_pending(|p| p.bitmap);
+    let threads = task.with_thread_group(|tg| tg.iter().collect::<Vec<_>>());
+    let shared_pending = threads.iter().fold(SigSet::empty(), |set, t| {
+        set | t.with_sig_pending(|p| p.bitmap)
+    });
+    let (ignored, caught) = sig_ign_and_cgt(task);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "Name:\t{name}");
+    let _ = writeln!(out, "State:\t{}", state_name(task.state()));
+    let _ = writeln!(out, "Tgid:\t{}", task.pid());
+    let _ = writeln!(out, "Pid:\t{}", task.tid());
+    // Init has no parent.
+    let ppid = task
+        .parent()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.pid());
+    let _ = writeln!(out, "PPid:\t{ppid}");
+    let tracer_pid = ptrace::tracer(task).map_or(0, |tracer| tracer.pid());
+    let _ = writeln!(out, "TracerPid:\t{tracer_pid}");
+    let _ = writeln!(out, "Uid:\t0\t0\t0\t0");
+    let _ = writeln!(out, "Gid:\t0\t0\t0\t0");
+    let kb_fields = [
+        ("VmSize:", usage.size),
+        ("VmRSS:", usage.resident),
+        ("RssAnon:", usage.anon()),
+        ("RssFile:", usage.file),
+        ("RssShmem:", usage.shmem),
+        ("VmData:", usage.data),
+        ("VmStk:", usage.stack),
+        ("VmExe:", usage.text),
+        ("VmLib:", usage.lib),
+    ];
+    for (name, pages) in kb_fields {
+        let _ = writeln!(out, "{name}\t{:>8} kB", pages * PAGE_KB);
+    }
+    let _ = writeln!(out, "Threads:\t{}", threads.len());
+    let _ = writeln!(out, "SigPnd:\t{:016x}", sig_pending.bits());
+    let _ = writeln!(out, "ShdPnd:\t{:016x}", shared_pending.bits());
+    let _ = writeln!(out, "SigBlk:\t{:016x}", task.sig_mask_ref().bits());
+    let _ = writeln!(out, "SigIgn:\t{:016x}", ignored.bits());
+    let _ = writeln!(out, "SigCgt:\t{:016x}", caught.bits());
+    out
+}
+
+/// `/proc/<pid>/statm` of the process led by `task`, sizes in pages.
+pub fn show_statm(task: &Arc<Task>) -> String {
+    let usage = task.with_memory_space(MemoryUsage::of);
+    format!(
+        "{} {} {} {} 0 {} 0\n",
+        usage.size,
+        usage.resident,
+        usage.file + usage.shmem,
+        usage.text,
+        usage.data + usage.stack,
+    )
+}
@@ -0,0 +1,747 @@
+//! Tracing of a task by another process with `ptrace`, and access to a
+//! process by another one, for `process_vm_readv`, `process_vm_writev` and
+//! `/proc/<pid>/mem`.
+//!
+//! A tracee stops for its tracer before a signal is delivered to it, at the
+//! entry and exit of system calls when resumed by `PTRACE_SYSCALL`, at
+//! `execve`, and after a single step. The tracer is notified by `SIGCHLD` and
+//! gets the stop from `wait4`, while the tracee sleeps until it is resumed.
+//! RISC-V has no single step mode, so a step ends at breakpoints set at the
+//! instructions that may follow the current one.
+
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{arch::asm, mem};
+
+use async_utils::suspend_now;
+use bitflags::bitflags;
+use memory::VirtAddr;
+use signal::{
+    siginfo::{SigDetails, SigInfo},
+    sigset::{Sig, SigSet},
+};
+use systype::{SysError, SysResult};
+
+use super::{TASK_MANAGER, Task, Tid};
+use crate::trap::TrapContext;
+
+/// Event of the stops of `PTRACE_O_TRACEEXEC`.
+const PTRACE_EVENT_EXEC: i32 = 4;
+/// Event of the stops of `PTRACE_INTERRUPT`.
+const PTRACE_EVENT_STOP: i32 = 128;
+
+/// `c.ebreak`, set at the end of a single step.
+const C_EBREAK: [u8; 2] = 0x9002u16.to_le_bytes();
+
+bitflags! {
+    /// Options of a tracee, set by `PTRACE_SETOPTIONS` or `PTRACE_SEIZE`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct PtraceOptions: usize {
+        /// Report syscall stops with `SIGTRAP | 0x80`.
+        const TRACESYSGOOD = 1 << 0;
+        /// Stop at `execve` with `PTRACE_EVENT_EXEC`.
+        const TRACEEXEC = 1 << 4;
+        /// Kill the tracee when the tracer exits.
+        const EXITKILL = 1 << 20;
+    }
+}
+
+/// How a tracee runs after its tracer resumes it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Resume {
+    /// Until the next signal, by `PTRACE_CONT`.
+    #[default]
+    Cont,
+    /// Stopping at the entry and exit of system calls too, by
+    /// `PTRACE_SYSCALL`.
+    Syscall,
+    /// For one instruction, by `PTRACE_SINGLESTEP`.
+    SingleStep,
+}
+
+/// `user_regs_struct` of RISC-V, `pc` followed by `x1` to `x31`.
+pub type UserRegs = [usize; 32];
+
+/// Tracing state of a task.
+#[derive(Default)]
+pub struct Ptrace {
+    /// Leader of the tracing process, `None` if the task is not traced.
+    tracer: Option<Weak<Task>>,
+    /// Tasks traced by the process, kept by its leader.
+    tracees: Vec<Weak<Task>>,
+    /// Exits of tracees that are not children of the process, with their
+    /// wait status, until reported by `wait4`. Kept by its leader.
+    exited: VecDeque<(Tid, i32)>,
+    /// Attached by `PTRACE_SEIZE`, rather than by `PTRACE_ATTACH` or
+    /// `PTRACE_TRACEME`.
+    seized: bool,
+    options: PtraceOptions,
+    resume: Resume,
+    /// In a ptrace-stop, waiting for the tracer.
+    stopped: bool,
+    /// Wait status of the stop, until reported by `wait4`.
+    unreported: Option<i32>,
+    /// Signal of the stop, for `PTRACE_GETSIGINFO`.
+    siginfo: Option<SigInfo>,
+    /// Message of the last event, for `PTRACE_GETEVENTMSG`.
+    event_msg: usize,
+    /// Signal the tracer resumes the task with.
+    resume_sig: Option<Sig>,
+    /// Signals let through by the tracer, delivered without another stop.
+    approved: VecDeque<SigInfo>,
+    /// A stop is asked by `PTRACE_INTERRUPT`.
+    interrupt: bool,
+    /// Breakpoints of a single step, with the bytes they replace.
+    step_breakpoints: Vec<(usize, [u8; 2])>,
+}
+
+/// Check that `tracer` may access the memory of `target`, like
+/// `ptrace_may_access` of Linux.
+///
+/// Every task runs as root, which may access any process, so only a process
+/// that has exited, whose memory is released, is refused.
+pub fn may_access(tracer: &Arc<Task>, target: &Arc<Task>) -> SysResult<()> {
+    if tracer.pid() == target.pid() {
+        return Ok(());
+    }
+    if target.leader().is_zombie() {
+        return Err(SysError::ESRCH);
+    }
+    Ok(())
+}
+
+/// Read `buf.len()` bytes at `va` of the memory space of `target`, and return
+/// the bytes read. With `force`, unreadable areas are read too.
+pub fn read_process_vm(
+    target: &Arc<Task>,
+    va: VirtAddr,
+    buf: &mut [u8],
+    force: bool,
+) -> SysResult<usize> {
+    target.with_mut_memory_space(|m| {
+        m.access_remote(va, buf.len(), false, force, |offset, bytes| {
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes)
+        })
+    })
+}
+
+/// Write `buf` at `va` of the memory space of `target`, and return the bytes
+/// written. With `force`, read-only private areas are written too, e.g. to
+/// set breakpoints in the text.
+pub fn write_process_vm(
+    target: &Arc<Task>,
+    va: VirtAddr,
+    buf: &[u8],
+    force: bool,
+) -> SysResult<usize> {
+    target.with_mut_memory_space(|m| {
+        m.access_remote(va, buf.len(), true, force, |offset, bytes| {
+            bytes.copy_from_slice(&buf[offset..offset + bytes.len()])
+        })
+    })
+}
+
+/// The process tracing `task`, by its leader.
+pub fn tracer(task: &Arc<Task>) -> Option<Arc<Task>> {
+    task.with_ptrace(|p| p.tracer.as_ref().and_then(Weak::upgrade))
+}
+
+fn is_traced(task: &Arc<Task>) -> bool {
+    task.with_ptrace(|p| p.tracer.is_some())
+}
+
+/// Make the process of `tracer` trace `tracee`, which must not be traced
+/// already, nor be a thread of the tracer.
+pub fn attach(
+    tracer: &Arc<Task>,
+    tracee: &Arc<Task>,
+    options: PtraceOptions,
+    seized: bool,
+) -> SysResult<()> {
+    if tracer.pid() == tracee.pid() {
+        return Err(SysError::EPERM);
+    }
+    may_access(tracer, tracee)?;
+    let leader = tracer.leader();
+    tracee.with_mut_ptrace(|p| {
+        if p.tracer.is_some() {
+            return Err(SysError::EPERM);
+        }
+        *p = Ptrace {
+            tracer: Some(Arc::downgrade(&leader)),
+            tracees: mem::take(&mut p.tracees),
+            exited: mem::take(&mut p.exited),
+            seized,
+            options,
+            ..Default::default()
+        };
+        Ok(())
+    })?;
+    leader.with_mut_ptrace(|p| p.tracees.push(Arc::downgrade(tracee)));
+    log::info!("[ptrace] {} traces {}", leader.pid(), tracee.tid());
+    Ok(())
+}
+
+/// Tracee `tid` of the process of `tracer`, which must be in a ptrace-stop
+/// if `need_stop`.
+pub fn tracee(tracer: &Arc<Task>, tid: Tid, need_stop: bool) -> SysResult<Arc<Task>> {
+    let tracee = TASK_MANAGER.get(tid).ok_or(SysError::ESRCH)?;
+    let leader = tracer.leader();
+    let valid = tracee.with_ptrace(|p| {
+        p.tracer
+            .as_ref()
+            .is_some_and(|t| t.as_ptr() == Arc::as_ptr(&leader))
+            && (p.stopped || !need_stop)
+    });
+    if !valid {
+        return Err(SysError::ESRCH);
+    }
+    Ok(tracee)
+}
+
+/// Detach `tracee` from its tracer, and resume it with `sig` if it is
+/// stopped.
+pub fn detach(tracee: &Arc<Task>, sig: Option<Sig>) {
+    remove_step_breakpoints(tracee);
+    let tracer = tracee.with_mut_ptrace(|p| {
+        p.seized = false;
+        p.options = PtraceOptions::empty();
+        p.unreported = None;
+        p.interrupt = false;
+        p.tracer.take()
+    });
+    if let Some(tracer) = tracer.as_ref().and_then(Weak::upgrade) {
+        tracer.with_mut_ptrace(|p| p.tracees.retain(|t| t.as_ptr() != Arc::as_ptr(tracee)));
+    }
+    resume(tracee, Resume::Cont, sig);
+}
+
+/// Resume `tracee` from its ptrace-stop in `mode`. `sig` is delivered to it
+/// if this is a signal-delivery-stop.
+pub fn resume(tracee: &Arc<Task>, mode: Resume, sig: Option<Sig>) {
+    let stopped = tracee.with_mut_ptrace(|p| {
+        p.resume = mode;
+        p.resume_sig = sig;
+        mem::replace(&mut p.stopped, false)
+    });
+    if stopped && tracee.is_interruptable() {
+        tracee.wake();
+    }
+}
+
+/// Ask `tracee`, attached by `PTRACE_SEIZE`, to stop with
+/// `PTRACE_EVENT_STOP`. It stops when it next returns to user mode.
+pub fn interrupt(tracee: &Arc<Task>) -> SysResult<()> {
+    tracee.with_mut_ptrace(|p| {
+        if !p.seized {
+            return Err(SysError::EIO);
+        }
+        if !p.stopped {
+            p.interrupt = true;
+        }
+        Ok(())
+    })
+}
+
+pub fn set_options(tracee: &Arc<Task>, options: PtraceOptions) {
+    tracee.with_mut_ptrace(|p| p.options = options)
+}
+
+/// Signal of the ptrace-stop of `tracee`.
+pub fn siginfo(tracee: &Arc<Task>) -> Option<SigInfo> {
+    tracee.with_ptrace(|p| p.siginfo)
+}
+
+pub fn event_msg(tracee: &Arc<Task>) -> usize {
+    tracee.with_ptrace(|p| p.event_msg)
+}
+
+pub fn get_regs(tracee: &Arc<Task>) -> UserRegs {
+    let cx = tracee.trap_context_mut();
+    let mut regs = cx.user_x;
+    regs[0] = cx.sepc;
+    regs
+}
+
+pub fn set_regs(tracee: &Arc<Task>, regs: &UserRegs) {
+    let cx = tracee.trap_context_mut();
+    cx.sepc = regs[0];
+    cx.user_x[1..].copy_from_slice(&regs[1..]);
+}
+
+/// Read a word at `va` of `tracee`, for `PTRACE_PEEKDATA`.
+pub fn peek(tracee: &Arc<Task>, va: VirtAddr) -> SysResult<usize> {
+    let mut word = [0; size_of::<usize>()];
+    match read_process_vm(tracee, va, &mut word, true) {
+        Ok(n) if n == word.len() => Ok(usize::from_ne_bytes(word)),
+        _ => Err(SysError::EIO),
+    }
+}
+
+/// Write the word `data` at `va` of `tracee`, for `PTRACE_POKEDATA`.
+pub fn poke(tracee: &Arc<Task>, va: VirtAddr, data: usize) -> SysResult<()> {
+    let word = data.to_ne_bytes();
+    match write_process_vm(tracee, va, &word, true) {
+        Ok(n) if n == word.len() => Ok(()),
+        _ => Err(SysError::EIO),
+    }
+}
+
+/// Whether the process of `tracer` traces task `tid`, or any task if `None`,
+/// or has an exit of it to report.
+pub fn traces(tracer: &Arc<Task>, tid: Option<Tid>) -> bool {
+    tracer.leader().with_ptrace(|p| {
+        p.tracees
+            .iter()
+            .filter_map(Weak::upgrade)
+            .any(|t| tid.is_none_or(|tid| t.tid() == tid))
+            || p.exited
+                .iter()
+                .any(|(t, _)| tid.is_none_or(|tid| *t == tid))
+    })
+}
+
+/// Take the stop or exit not reported yet of a tracee of the process of
+/// `tracer`, task `tid` or any if `None`, with the id of the tracee.
+pub fn wait_tracee(tracer: &Arc<Task>, tid: Option<Tid>) -> Option<(Tid, i32)> {
+    let leader = tracer.leader();
+    let (tracees, exited) = leader.with_mut_ptrace(|p| {
+        let exited = p
+            .exited
+            .iter()
+            .position(|(t, _)| tid.is_none_or(|tid| *t == tid))
+            .and_then(|i| p.exited.remove(i));
+        (p.tracees.clone(), exited)
+    });
+    if exited.is_some() {
+        return exited;
+    }
+    tracees
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|t| tid.is_none_or(|tid| t.tid() == tid))
+        .find_map(|t| {
+            t.with_mut_ptrace(|p| p.unreported.take())
+                .map(|status| (t.tid(), status))
+        })
+}
+
+/// Unlink `task` from its tracer when it exits, and report the exit to the
+/// tracer, unless the tracer is the parent, which reaps it as any child.
+pub fn exit_tracee(task: &Arc<Task>) {
+    let tracer = task.with_mut_ptrace(|p| p.tracer.take());
+    let Some(tracer) = tracer.as_ref().and_then(Weak::upgrade) else {
+        return;
+    };
+    let reaped_by_tracer = task.is_leader()
+        && task
+            .parent()
+            .and_then(|parent| parent.upgrade())
+            .is_some_and(|parent| parent.pid() == tracer.pid());
+    let status = task.exit_code();
+    tracer.with_mut_ptrace(|p| {
+        p.tracees.retain(|t| t.as_ptr() != Arc::as_ptr(task));
+        if !reaped_by_tracer {
+            p.exited.push_back((task.tid(), status));
+        }
+    });
+    if !reaped_by_tracer {
+        let code = if status & 0x7f == 0 {
+            SigInfo::CLD_EXITED
+        } else {
+            SigInfo::CLD_KILLED
+        };
+        tracer.receive_siginfo(
+            SigInfo {
+                sig: Sig::SIGCHLD,
+                code,
+                details: SigDetails::None,
+            },
+            false,
+        );
+    }
+}
+
+/// Detach the tracees of the process led by `leader` when it exits, or kill
+/// the ones with `PTRACE_O_EXITKILL`.
+pub fn exit_tracer(leader: &Arc<Task>) {
+    let tracees = leader.with_mut_ptrace(|p| mem::take(&mut p.tracees));
+    for tracee in tracees.iter().filter_map(Weak::upgrade) {
+        if tracee.with_ptrace(|p| p.options.contains(PtraceOptions::EXITKILL)) {
+            tracee.receive_siginfo(
+                SigInfo {
+                    sig: Sig::SIGKILL,
+                    code: SigInfo::KERNEL,
+                    details: SigDetails::None,
+                },
+                true,
+            );
+        }
+        detach(&tracee, None);
+    }
+}
+
+/// Make instructions written to memory visible to the instruction fetch of
+/// this hart.
+fn sync_icache() {
+    unsafe { asm!("fence.i") };
+}
+
+/// Stop `task` for its tracer, with `sig` as bits 8 to 15 of the wait status
+/// and `si` for `PTRACE_GETSIGINFO`, and sleep until the tracer resumes it or
+/// it is killed. Return the signal the tracer resumes it with.
+///
+/// A task that is not traced any more does not stop, and the signal of `si`
+/// is returned.
+async fn ptrace_stop(task: &Arc<Task>, sig: i32, si: SigInfo) -> Option<Sig> {
+    // E.g. at the exit stop of `exit_group`.
+    if task.is_terminated() {
+        return None;
+    }
+    remove_step_breakpoints(task);
+    let tracer = task.with_mut_ptrace(|p| {
+        let tracer = p.tracer.as_ref()?.upgrade()?;
+        p.stopped = true;
+        p.unreported = Some((sig << 8) | 0x7f);
+        p.siginfo = Some(si);
+        p.resume_sig = None;
+        Some(tracer)
+    });
+    let Some(tracer) = tracer else {
+        return Some(si.sig);
+    };
+    log::info!("[ptrace_stop] tid {} stops with {sig:#x}", task.tid());
+    tracer.receive_siginfo(
+        SigInfo {
+            sig: Sig::SIGCHLD,
+            code: SigInfo::CLD_TRAPPED,
+            details: SigDetails::None,
+        },
+        false,
+    );
+    loop {
+        task.set_interruptable();
+        task.set_wake_up_signal(SigSet::SIGKILL);
+        let killed = task.with_sig_pending(|pending| pending.bitmap.contain_signal(Sig::SIGKILL));
+        if killed || !task.with_ptrace(|p| p.stopped) {
+            break;
+        }
+        suspend_now().await;
+    }
+    // Not when it was terminated by another thread meanwhile.
+    if task.is_interruptable() {
+        task.set_running();
+    }
+    // The tracer may have written the text from another hart.
+    sync_icache();
+    task.with_mut_ptrace(|p| {
+        p.stopped = false;
+        p.unreported = None;
+        p.resume_sig.take()
+    })
+}
+
+/// Syscall-enter-stop or syscall-exit-stop of `task`, if its tracer resumed
+/// it with `PTRACE_SYSCALL`.
+pub async fn syscall_stop(task: &Arc<Task>) {
+    let sig = task.with_ptrace(|p| {
+        (p.tracer.is_some() && p.resume == Resume::Syscall).then(|| {
+            let sig = Sig::SIGTRAP.raw() as i32;
+            if p.options.contains(PtraceOptions::TRACESYSGOOD) {
+                sig | 0x80
+            } else {
+                sig
+            }
+        })
+    });
+    if let Some(sig) = sig {
+        let si = SigInfo {
+            sig: Sig::SIGTRAP,
+            code: sig,
+            details: SigDetails::None,
+        };
+        ptrace_stop(task, sig, si).await;
+    }
+}
+
+/// Report a successful `execve` of `task` to its tracer, by a stop with
+/// `PTRACE_O_TRACEEXEC`, or else by a `SIGTRAP` unless it was seized.
+pub async fn exec_stop(task: &Arc<Task>) {
+    let (traced, options, seized) = task.with_mut_ptrace(|p| {
+        // They were set in the old memory space.
+        p.step_breakpoints.clear();
+        p.event_msg = task.tid();
+        (p.tracer.is_some(), p.options, p.seized)
+    });
+    if !traced {
+        return;
+    }
+    if options.contains(PtraceOptions::TRACEEXEC) {
+        let sig = Sig::SIGTRAP.raw() as i32 | (PTRACE_EVENT_EXEC << 8);
+        let si = SigInfo {
+            sig: Sig::SIGTRAP,
+            code: sig,
+            details: SigDetails::None,
+        };
+        ptrace_stop(task, sig, si).await;
+    } else if !seized {
+        task.receive_siginfo(
+            SigInfo {
+                sig: Sig::SIGTRAP,
+                code: SigInfo::USER,
+                details: SigDetails::None,
+            },
+            true,
+        );
+    }
+}
+
+/// Signal-delivery-stops of a traced `task` for its unblocked pending
+/// signals, and the stop asked by `PTRACE_INTERRUPT`.
+///
+/// A signal the tracer resumes the task with is delivered by `do_signal`
+/// then, other ones are discarded. `SIGKILL` does not stop.
+pub async fn signal_delivery_stops(task: &Arc<Task>) {
+    let mask = *task.sig_mask_ref();
+    while is_traced(task) {
+        if task.with_mut_ptrace(|p| mem::take(&mut p.interrupt)) {
+            let sig = Sig::SIGTRAP.raw() as i32 | (PTRACE_EVENT_STOP << 8);
+            let si = SigInfo {
+                sig: Sig::SIGTRAP,
+                code: sig,
+                details: SigDetails::None,
+            };
+            ptrace_stop(task, sig, si).await;
+            continue;
+        }
+        let si = task.with_mut_sig_pending(|pending| {
+            if pending.bitmap.contain_signal(Sig::SIGKILL) {
+                None
+            } else {
+                pending.dequeue_signal(&mask)
+            }
+        });
+        let Some(si) = si else {
+            break;
+        };
+        if let Some(sig) = ptrace_stop(task, si.sig.raw() as i32, si).await {
+            let si = if sig == si.sig {
+                si
+            } else {
+                SigInfo {
+                    sig,
+                    code: SigInfo::USER,
+                    details: SigDetails::None,
+                }
+            };
+            task.with_mut_ptrace(|p| p.approved.push_back(si));
+        }
+    }
+}
+
+/// Next signal for `do_signal` to deliver to `task`. A traced task only gets
+/// the signals let through by its tracer and `SIGKILL`, the others stop it
+/// first.
+pub fn next_signal(task: &Arc<Task>, mask: &SigSet) -> Option<SigInfo> {
+    let (approved, traced) = task.with_mut_ptrace(|p| (p.approved.pop_front(), p.tracer.is_some()));
+    if approved.is_some() {
+        return approved;
+    }
+    task.with_mut_sig_pending(|pending| {
+        if traced {
+            pending.dequeue_expect(SigSet::SIGKILL)
+        } else {
+            pending.dequeue_signal(mask)
+        }
+    })
+}
+
+/// Origin of a breakpoint a task trapped at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The end of the single step of the task, which is over then.
+    Step,
+    /// A breakpoint of the single step of another task sharing the memory
+    /// space, removed so that the instruction is run again.
+    OtherStep,
+    /// One in the program, e.g. set by a debugger.
+    Program,
+}
+
+/// Find out what the breakpoint at `pc` that `task` trapped at is.
+///
+/// The breakpoint of another task is removed from its step, which may then
+/// run further than one instruction, rather than the task getting `SIGTRAP`.
+pub fn hit_breakpoint(task: &Arc<Task>, pc: usize) -> Breakpoint {
+    let hit =
+        |t: &Arc<Task>| t.with_ptrace(|p| p.step_breakpoints.iter().any(|(addr, _)| *addr == pc));
+    if hit(task) {
+        remove_step_breakpoints(task);
+        return Breakpoint::Step;
+    }
+    let mm = task.raw_mm_pointer();
+    let owner = TASK_MANAGER
+        .tasks()
+        .into_iter()
+        .find(|t| t.tid() != task.tid() && t.raw_mm_pointer() == mm && hit(t));
+    let Some(owner) = owner else {
+        return Breakpoint::Program;
+    };
+    let breakpoint = owner.with_mut_ptrace(|p| {
+        let i = p
+            .step_breakpoints
+            .iter()
+            .position(|(addr, _)| *addr == pc)?;
+        Some(p.step_breakpoints.remove(i))
+    });
+    if let Some((addr, insn)) = breakpoint {
+        let _ = write_process_vm(task, VirtAddr::from(addr), &insn, true);
+        sync_icache();
+    }
+    Breakpoint::OtherStep
+}
+
+/// Put back in the memory space of `child`, forked from `parent`, the bytes
+/// replaced by the breakpoints of the steps of tasks sharing the memory space
+/// of `parent`, so that the child, which is not traced, never runs into them.
+pub fn fork_step_breakpoints(parent: &Arc<Task>, child: &Arc<Task>) {
+    let mm = parent.raw_mm_pointer();
+    let breakpoints: Vec<(usize, [u8; 2])> = TASK_MANAGER
+        .tasks()
+        .iter()
+        .filter(|t| t.raw_mm_pointer() == mm)
+        .flat_map(|t| t.with_ptrace(|p| p.step_breakpoints.clone()))
+        .collect();
+    if breakpoints.is_empty() {
+        return;
+    }
+    for (addr, insn) in breakpoints {
+        let _ = write_process_vm(child, VirtAddr::from(addr), &insn, true);
+    }
+    sync_icache();
+}
+
+fn remove_step_breakpoints(task: &Arc<Task>) {
+    let breakpoints = task.with_mut_ptrace(|p| mem::take(&mut p.step_breakpoints));
+    if breakpoints.is_empty() {
+        return;
+    }
+    for (addr, insn) in breakpoints {
+        let _ = write_process_vm(task, VirtAddr::from(addr), &insn, true);
+    }
+    sync_icache();
+}
+
+/// Set the breakpoints of the single step of `task` before it returns to
+/// user mode, if its tracer resumed it with `PTRACE_SINGLESTEP`.
+///
+/// The breakpoints are in the memory space, so they are removed from the
+/// memory of a child forked during the step, and other tasks sharing the
+/// memory space run through them, see [`hit_breakpoint`].
+pub fn arm_single_step(task: &Arc<Task>) {
+    let armed = task.with_ptrace(|p| {
+        p.tracer.is_none() || p.resume != Resume::SingleStep || !p.step_breakpoints.is_empty()
+    });
+    if armed {
+        return;
+    }
+    let cx = task.trap_context_mut();
+    let mut insn = [0; 4];
+    let len = read_process_vm(task, VirtAddr::from(cx.sepc), &mut insn, true).unwrap_or(0);
+    // The fault at the instruction ends the step otherwise.
+    if len < 2 || (len < 4 && insn[0] & 0b11 == 0b11) {
+        return;
+    }
+    let mut breakpoints: Vec<(usize, [u8; 2])> = Vec::new();
+    for target in step_targets(cx, u32::from_le_bytes(insn)) {
+        if breakpoints.iter().any(|(addr, _)| *addr == target) {
+            continue;
+        }
+        let mut orig = [0; 2];
+        let va = VirtAddr::from(target);
+        if matches!(read_process_vm(task, va, &mut orig, true), Ok(2))
+            && matches!(write_process_vm(task, va, &C_EBREAK, true), Ok(2))
+        {
+            breakpoints.push((target, orig));
+        }
+    }
+    task.with_mut_ptrace(|p| p.step_breakpoints = breakpoints);
+    sync_icache();
+}
+
+/// Bits `lo..lo + len` of `insn`.
+fn field(insn: u32, lo: u32, len: u32) -> u32 {
+    (insn >> lo) & ((1 << len) - 1)
+}
+
+/// `value` of `bits` bits, sign-extended.
+fn sign_extend(value: u32, bits: u32) -> usize {
+    (((value as i64) << (64 - bits)) >> (64 - bits)) as usize
+}
+
+/// Addresses of the instructions that may follow `insn` at `cx.sepc`.
+fn step_targets(cx: &TrapContext, insn: u32) -> Vec<usize> {
+    let pc = cx.sepc;
+    let reg = |r: u32| cx.user_x[r as usize];
+    if insn & 0b11 != 0b11 {
+        let next = pc + 2;
+        match (insn & 0b11, field(insn, 13, 3)) {
+            // c.j
+            (0b01, 0b101) => {
+                let offset = field(insn, 12, 1) << 11
+                    | field(insn, 11, 1) << 4
+                    | field(insn, 9, 2) << 8
+                    | field(insn, 8, 1) << 10
+                    | field(insn, 7, 1) << 6
+                    | field(insn, 6, 1) << 7
+                    | field(insn, 3, 3) << 1
+                    | field(insn, 2, 1) << 5;
+                vec![pc.wrapping_add(sign_extend(offset, 12))]
+            }
+            // c.beqz and c.bnez
+            (0b01, 0b110 | 0b111) => {
+                let offset = field(insn, 12, 1) << 8
+                    | field(insn, 10, 2) << 3
+                    | field(insn, 5, 2) << 6
+                    | field(insn, 3, 2) << 1
+                    | field(insn, 2, 1) << 5;
+                vec![next, pc.wrapping_add(sign_extend(offset, 9))]
+            }
+            // c.jr and c.jalr
+            (0b10, 0b100) if field(insn, 7, 5) != 0 && field(insn, 2, 5) == 0 => {
+                vec![reg(field(insn, 7, 5)) & !1]
+            }
+            _ => vec![next],
+        }
+    } else {
+        let next = pc + 4;
+        match field(insn, 0, 7) {
+            // jal
+            0b1101111 => {
+                let offset = field(insn, 31, 1) << 20
+                    | field(insn, 21, 10) << 1
+                    | field(insn, 20, 1) << 11
+                    | field(insn, 12, 8) << 12;
+                vec![pc.wrapping_add(sign_extend(offset, 21))]
+            }
+            // jalr
+            0b1100111 => {
+                let offset = sign_extend(field(insn, 20, 12), 12);
+                vec![reg(field(insn, 15, 5)).wrapping_add(offset) & !1]
+            }
+            // Branches.
+            0b1100011 => {
+                let offset = field(insn, 31, 1) << 12
+                    | field(insn, 25, 6) << 5
+                    | field(insn, 8, 4) << 1
+                    | field(insn, 7, 1) << 11;
+                vec![next, pc.wrapping_add(sign_extend(offset, 13))]
+            }
+            _ => vec![next],
+        }
+    }
+}
@@ -13,7 +13,7 @@ use timer::{TIMER_MANAGER, Timer};
 use super::Task;
 use crate::{
     processor::{env::EnvContext, hart},
-    task::{signal::*, task::TaskState::*},
+    task::{ptrace, signal::*, task::TaskState::*},
     trap,
 };
 
@@ -102,7 +102,9 @@ pub async fn task_loop(task: Arc<Task>) {
             Stopped => suspend_now().await,
             _ => {}
         }
+        ptrace::signal_delivery_stops(&task).await;
         do_signal(&task, intr).expect("do signal error");
+        ptrace::arm_single_step(&task);
     }
 
     log::debug!("thread {} terminated", task.tid());
@@ -16,7 +16,7 @@ use signal::*;
 use systype::SysResult;
 use timer::{Timer, TimerEvent};
 
-use super::Task;
+use super::{Task, ptrace};
 use crate::mm::UserWritePtr;
 
 #[derive(Clone, Copy, Default)]
@@ -38,6 +38,32 @@ pub struct SigAction {
 pub const SIG_DFL: usize = 0;
 pub const SIG_IGN: usize = 1;
 
+/// `siginfo_t` of Linux, as seen by user handlers and `PTRACE_GETSIGINFO`.
+#[derive(Default, Copy, Clone)]
+#[repr(C)]
+pub struct LinuxSigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad0: i32,
+    /// `si_addr` of the `_sigfault` in the union of details
+    pub si_addr: usize,
+    pub _pad: [i32; 26],
+    _align: [u64; 0],
+}
+
+impl From<SigInfo> for LinuxSigInfo {
+    fn from(si: SigInfo) -> Self {
+        let mut siginfo = Self::default();
+        siginfo.si_signo = si.sig.raw() as _;
+        siginfo.si_code = si.code;
+        if let SigDetails::Fault { addr } = si.details {
+            siginfo.si_addr = addr;
+        }
+        siginfo
+    }
+}
+
 impl From<Action> for SigAction {
     fn from(action: Action) -> Self {
         let sa_handler = match action.atype {
@@ -146,7 +172,7 @@ pub fn do_signal(task: &Arc<Task>, mut intr: bool) -> SysResult<()> {
     let old_mask = *task.sig_mask();
     let cx = task.trap_context_mut();
 
-    while let Some(si) = task.with_mut_sig_pending(|pending| pending.dequeue_signal(&old_mask)) {
+    while let Some(si) = ptrace::next_signal(task, &old_mask) {
         let action = task.with_sig_handlers(|handlers| handlers.get(si.sig));
         log::info!("[do signal] Handling signal: {:?} {:?}", si, action);
         if intr && action.flags.contains(SigActionFlag::SA_RESTART) {
@@ -218,18 +244,7 @@ pub fn do_signal(task: &Arc<Task>, mut intr: bool) -> SysResult<()> {
                     // log::error!("[SA_SIGINFO] set ucontext {ucontext:?}");
                     // a2
                     cx.user_x[12] = new_sp;
-                    #[derive(Default, Copy, Clone)]
-                    #[repr(C)]
-                    pub struct LinuxSigInfo {
-                        pub si_signo: i32,
-                        pub si_errno: i32,
-                        pub si_code: i32,
-                        pub _pad: [i32; 29],
-                        _align: [u64; 0],
-                    }
-                    let mut siginfo_v = LinuxSigInfo::default();
-                    siginfo_v.si_signo = si.sig.raw() as _;
-                    siginfo_v.si_code = si.code;
+                    let siginfo_v = LinuxSigInfo::from(si);
                     new_sp -= size_of::<LinuxSigInfo>();
                     let siginfo_ptr: UserWritePtr<LinuxSigInfo> = new_sp.into();
                     siginfo_ptr.write(&task, siginfo_v)?;
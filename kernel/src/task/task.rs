@@ -52,6 +52,7 @@ use crate::{
     task::{
         aux::{AT_BASE, AuxHeader},
         manager::TASK_MANAGER,
+        ptrace::{self, Ptrace},
         tid::{TidAddress, alloc_tid},
     },
     trap::TrapContext,
@@ -130,6 +131,11 @@ pub struct Task {
     elf: SyncUnsafeCell<Arc<dyn File>>,
     /// Command-line arguments for the task.
     args: SyncUnsafeCell<Vec<String>>,
+    /// Adjustment of the badness of the process for the OOM killer, in
+    /// `-1000..=1000`.
+    oom_score_adj: Shared<i32>,
+    /// Tracing of the task, and the tracees of the process if it is a leader.
+    ptrace: SpinNoIrqLock<Ptrace>,
 }
 
 impl core::fmt::Debug for Task {
@@ -206,7 +212,8 @@ impl Task {
         sig_handlers: SigHandlers,
         state: TaskState,
         shm_ids: BTreeMap<VirtAddr, usize>,
-        itimers: [ITimer;3]
+        itimers: [ITimer;3],
+        ptrace: Ptrace
     );
 
     pub fn new_init(
@@ -245,6 +252,8 @@ impl Task {
             pgid: new_shared(pgid),
             elf: SyncUnsafeCell::new(elf_file),
             args: SyncUnsafeCell::new(args),
+            oom_score_adj: new_shared(0),
+            ptrace: SpinNoIrqLock::new(Ptrace::default()),
         });
 
         task.thread_group.lock().push(task.clone());
@@ -312,6 +321,14 @@ impl Task {
         *self.pgid.lock() = pgid
     }
 
+    pub fn oom_score_adj(&self) -> i32 {
+        *self.oom_score_adj.lock()
+    }
+
+    pub fn set_oom_score_adj(&self, adj: i32) {
+        *self.oom_score_adj.lock() = adj
+    }
+
     pub fn ppid(&self) -> Pid {
         self.parent()
             .expect("Call ppid without a parent")
@@ -347,7 +364,17 @@ impl Task {
         Arc::as_ptr(&self.memory_space) as usize
     }
 
-    pub fn do_clone(self: &Arc<Self>, flags: CloneFlags) -> Arc<Self> {
+    pub fn do_clone(self: &Arc<Self>, flags: CloneFlags) -> SysResult<Arc<Self>> {
+        let memory_space;
+        if flags.contains(CloneFlags::VM) {
+            memory_space = self.memory_space.clone();
+        } else {
+            memory_space =
+                new_shared(self.with_mut_memory_space(|m| MemorySpace::from_user_lazily(m))?);
+            // TODO: avoid flushing global entries like kernel mappings
+            unsafe { sfence_vma_all() };
+        }
+
         let tid = alloc_tid();
         let trap_context = SyncUnsafeCell::new(*self.trap_context_mut());
         let state = SpinNoIrqLock::new(self.state());
@@ -362,6 +389,7 @@ impl Task {
         let robust;
         let shm_ids;
         let pgid;
+        let oom_score_adj;
         let sig_handlers = if flags.contains(CloneFlags::SIGHAND) {
             self.sig_handlers.clone()
         } else {
@@ -378,6 +406,7 @@ impl Task {
             robust = self.robust.clone();
             shm_ids = self.shm_ids.clone();
             pgid = self.pgid.clone();
+            oom_score_adj = self.oom_score_adj.clone();
         } else {
             is_leader = true;
             leader = None;
@@ -392,16 +421,7 @@ impl Task {
                 SHARED_MEMORY_MANAGER.attach(*shm_id, tid.0);
             }
             pgid = new_shared(self.pgid());
-        }
-
-        let memory_space;
-        if flags.contains(CloneFlags::VM) {
-            memory_space = self.memory_space.clone();
-        } else {
-            memory_space =
-                new_shared(self.with_mut_memory_space(|m| MemorySpace::from_user_lazily(m)));
-            // TODO: avoid flushing global entries like kernel mappings
-            unsafe { sfence_vma_all() };
+            oom_score_adj = new_shared(self.oom_score_adj());
         }
 
         let fd_table = if flags.contains(CloneFlags::FILES) {
@@ -440,11 +460,17 @@ impl Task {
             pgid,
             elf: SyncUnsafeCell::new(self.elf_ref().clone()),
             args: SyncUnsafeCell::new(self.args_ref().clone()),
+            oom_score_adj,
+            // A child is not traced, as PTRACE_O_TRACEFORK is not supported.
+            ptrace: SpinNoIrqLock::new(Ptrace::default()),
         });
 
         if !flags.contains(CloneFlags::THREAD) {
             self.add_child(new.clone());
         }
+        if !flags.contains(CloneFlags::VM) {
+            ptrace::fork_step_breakpoints(self, &new);
+        }
         new.with_mut_thread_group(|tg| tg.push(new.clone()));
 
         if new.is_leader() {
@@ -452,7 +478,7 @@ impl Task {
         }
 
         TASK_MANAGER.add(&new);
-        new
+        Ok(new)
     }
 
     pub fn do_execve(
@@ -464,6 +490,12 @@ impl Task {
     ) {
         log::debug!("[Task::do_execve] parsing elf");
         let mut memory_space = MemorySpace::new_user();
+        // Resource limits are preserved across execve.
+        self.with_memory_space(|m| {
+            memory_space.set_rlimit_as(m.rlimit_as());
+            memory_space.set_rlimit_data(m.rlimit_data());
+            memory_space.set_rlimit_stack(m.rlimit_stack());
+        });
         let (mut entry, mut auxv) = memory_space.parse_and_map_elf(elf_file.clone(), elf_data);
 
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
@@ -561,6 +593,8 @@ impl Task {
             let _ = futex_manager().wake(&key, 1);
         }
 
+        ptrace::exit_tracee(self);
+
         let mut tg = self.thread_group.lock();
 
         if (!self.leader().is_terminated())
@@ -587,6 +621,8 @@ impl Task {
         // exit the process, e.g. reparent all children, and send SIGCHLD to parent
         log::info!("[Task::do_exit] exit the whole process");
 
+        ptrace::exit_tracer(&self.leader());
+
         log::debug!("[Task::do_exit] reparent children to init");
         debug_assert_ne!(self.tid(), INIT_PROC_PID);
         self.with_mut_children(|children| {
@@ -645,6 +681,17 @@ impl Task {
         // called
         self.with_mut_fd_table(|table| table.clear());
 
+        // Release the memory now rather than when the parent reaps the process,
+        // which the OOM killer waits for. A space shared with another process,
+        // e.g. by vfork, is left to it.
+        let shared_mm = TASK_MANAGER
+            .tasks()
+            .iter()
+            .any(|t| t.raw_mm_pointer() == self.raw_mm_pointer() && t.pid() != self.pid());
+        if !shared_mm {
+            self.with_mut_memory_space(|m| m.release());
+        }
+
         if self.is_leader() {
             self.set_zombie();
         } else {
@@ -98,8 +98,9 @@ pub fn kernel_trap_handler() {
                     current_task_ref().receive_siginfo(
                         SigInfo {
                             sig: Sig::SIGSEGV,
-                            code: SigInfo::KERNEL,
-                            details: SigDetails::None,
+                            code: current_task_ref()
+                                .with_memory_space(|m| m.segv_code(VirtAddr::from(stval))),
+                            details: SigDetails::Fault { addr: stval },
                         },
                         false,
                     );
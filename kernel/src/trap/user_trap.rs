@@ -17,7 +17,15 @@ use systype::SysError;
 use timer::TIMER_MANAGER;
 
 use super::{TrapContext, set_kernel_trap};
-use crate::{mm::PageFaultAccessType, syscall::Syscall, task::Task, trap::set_user_trap};
+use crate::{
+    mm::{PageFaultAccessType, oom, userfaultfd},
+    syscall::Syscall,
+    task::{
+        Task,
+        ptrace::{self, Breakpoint},
+    },
+    trap::set_user_trap,
+};
 
 /// handle an interrupt, exception, or system call from user space
 /// return if it is syscall and has been interrupted
@@ -43,14 +51,18 @@ pub async fn trap_handler(task: &Arc<Task>) -> bool {
         Ok(Trap::Exception(e)) => {
             match e {
                 Exception::UserEnvCall => {
-                    let syscall_no = cx.syscall_no();
                     cx.set_user_pc_to_next();
+                    // The tracer may change the syscall and its arguments at the entry stop,
+                    // and its return value at the exit stop.
+                    ptrace::syscall_stop(task).await;
+                    let syscall_no = cx.syscall_no();
                     // get system call return value
                     let ret = Syscall::new(task)
                         .syscall(syscall_no, cx.syscall_args())
                         .await;
                     cx.save_last_user_a0();
                     cx.set_user_a0(ret);
+                    ptrace::syscall_stop(task).await;
                     if ret == -(SysError::EINTR as isize) as usize {
                         return true;
                     }
@@ -75,11 +87,21 @@ pub async fn trap_handler(task: &Arc<Task>) -> bool {
                     // 5. execve elf file
                     // 6. dynamic link
                     // 7. illegal page fault
+                    // 8. userfaultfd area, where user space handles the fault
 
+                    if userfaultfd::handle_userfault(task, VirtAddr::from(stval), access_type).await
+                    {
+                        return false;
+                    }
                     let result = task.with_mut_memory_space(|m| {
                         m.handle_page_fault(VirtAddr::from(stval), access_type)
                     });
-                    if let Err(_e) = result {
+                    if let Err(SysError::ENOMEM) = result {
+                        // The instruction faults again when the task returns to
+                        // user mode, after the memory of the victim is back.
+                        log::warn!("[trap_handler] out of memory at {stval:#x}");
+                        oom::out_of_memory(task).await;
+                    } else if let Err(_e) = result {
                         log::warn!(
                             "[trap_handler] encounter page fault, addr {stval:#x}, instruction {sepc:#x} scause {cause:?}",
                         );
@@ -90,8 +112,9 @@ pub async fn trap_handler(task: &Arc<Task>) -> bool {
                         task.receive_siginfo(
                             SigInfo {
                                 sig: Sig::SIGSEGV,
-                                code: SigInfo::KERNEL,
-                                details: SigDetails::None,
+                                code: task
+                                    .with_memory_space(|m| m.segv_code(VirtAddr::from(stval))),
+                                details: SigDetails::Fault { addr: stval },
                             },
                             false,
                         );
@@ -103,6 +126,23 @@ pub async fn trap_handler(task: &Arc<Task>) -> bool {
                     );
                     task.set_terminated();
                 }
+                Exception::Breakpoint => {
+                    // The pc is left at the breakpoint, which is the next instruction to run
+                    // when it ends a single step.
+                    let code = match ptrace::hit_breakpoint(task, sepc) {
+                        Breakpoint::Step => SigInfo::TRAP_TRACE,
+                        Breakpoint::OtherStep => return false,
+                        Breakpoint::Program => SigInfo::TRAP_BRKPT,
+                    };
+                    task.receive_siginfo(
+                        SigInfo {
+                            sig: Sig::SIGTRAP,
+                            code,
+                            details: SigDetails::Fault { addr: sepc },
+                        },
+                        true,
+                    );
+                }
                 e => {
                     log::warn!("Unknown user exception: {:?}", e);
                 }
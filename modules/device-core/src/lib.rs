@@ -10,7 +10,7 @@ use core::any::Any;
 use async_trait::async_trait;
 use downcast_rs::{DowncastSync, impl_downcast};
 use error::DevResult;
-pub use smoltcp::phy::{DeviceCapabilities, Loopback, Medium};
+pub use smoltcp::phy::{Checksum, DeviceCapabilities, Loopback, Medium};
 
 /// General Device Operations
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -126,6 +126,14 @@ pub trait BlockDevice: Device {
     /// Read data form block to buffer
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
 
+    /// Read data from consecutive blocks to buffer, whose length should be a
+    /// multiple of block size
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        for (i, block_buf) in buf.chunks_mut(self.block_size()).enumerate() {
+            self.read_block(block_id + i, block_buf);
+        }
+    }
+
     /// Write data from buffer to block
     fn write_block(&self, block_id: usize, buf: &[u8]);
 }
@@ -44,14 +44,17 @@ impl Disk {
         self.offset = pos as usize % BLOCK_SIZE;
     }
 
-    /// Read within one block, returns the number of bytes read.
+    /// Read within one block, or as many whole blocks as `buf` can hold when
+    /// the cursor is block aligned, returns the number of bytes read.
     pub fn read_one(&mut self, buf: &mut [u8]) -> SysResult<usize> {
         // trace!("block id: {}", self.block_id);
         let read_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            self.dev.read_block(self.block_id, &mut buf[0..BLOCK_SIZE]);
-            self.block_id += 1;
-            BLOCK_SIZE
+            // whole blocks, issued as one batch so that adjacent blocks can be merged
+            let nblocks = buf.len() / BLOCK_SIZE;
+            self.dev
+                .read_blocks(self.block_id, &mut buf[0..nblocks * BLOCK_SIZE]);
+            self.block_id += nblocks;
+            nblocks * BLOCK_SIZE
         } else {
             // partial block
             let mut data = [0u8; BLOCK_SIZE];
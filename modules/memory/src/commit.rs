@@ -0,0 +1,95 @@
+//! Commit charge of user memory and the overcommit policy.
+//!
+//! Every private writable or shared anonymous mapping charges its size when
+//! it is created, whether its pages are faulted in or not. Whether a new
+//! charge is allowed depends on the mode of `/proc/sys/vm/overcommit_memory`,
+//! the same as in Linux:
+//! - [`OvercommitMode::Guess`] refuses only a charge larger than the memory,
+//! - [`OvercommitMode::Always`] never refuses,
+//! - [`OvercommitMode::Never`] refuses to go over [`commit_limit`], which is
+//!   `overcommit_ratio` percent of the memory.
+//!
+//! There is no swap, so the memory is the frames of the frame allocator.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use crate::total_frames;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OvercommitMode {
+    Guess = 0,
+    Always = 1,
+    Never = 2,
+}
+
+impl OvercommitMode {
+    pub fn from_repr(repr: u8) -> Option<Self> {
+        match repr {
+            0 => Some(Self::Guess),
+            1 => Some(Self::Always),
+            2 => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+static OVERCOMMIT_MODE: AtomicU8 = AtomicU8::new(OvercommitMode::Guess as u8);
+static OVERCOMMIT_RATIO: AtomicUsize = AtomicUsize::new(50);
+/// Pages charged by all the user address spaces.
+static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn overcommit_mode() -> OvercommitMode {
+    OvercommitMode::from_repr(OVERCOMMIT_MODE.load(Ordering::Relaxed)).unwrap()
+}
+
+pub fn set_overcommit_mode(mode: OvercommitMode) {
+    OVERCOMMIT_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn overcommit_ratio() -> usize {
+    OVERCOMMIT_RATIO.load(Ordering::Relaxed)
+}
+
+pub fn set_overcommit_ratio(ratio: usize) {
+    OVERCOMMIT_RATIO.store(ratio, Ordering::Relaxed);
+}
+
+/// Pages that can be charged in [`OvercommitMode::Never`].
+pub fn commit_limit() -> usize {
+    total_frames() * overcommit_ratio() / 100
+}
+
+/// Pages charged now.
+pub fn committed_pages() -> usize {
+    COMMITTED.load(Ordering::Relaxed)
+}
+
+/// Charge `pages` if the overcommit policy allows it, return whether it did.
+pub fn vm_commit(pages: usize) -> bool {
+    let mode = overcommit_mode();
+    let limit = commit_limit();
+    COMMITTED
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |committed| {
+            let allowed = match mode {
+                OvercommitMode::Always => true,
+                OvercommitMode::Guess => pages <= total_frames(),
+                OvercommitMode::Never => committed
+                    .checked_add(pages)
+                    .is_some_and(|total| total <= limit),
+            };
+            allowed.then_some(committed.saturating_add(pages))
+        })
+        .is_ok()
+}
+
+/// Charge `pages` whatever the overcommit policy says.
+pub fn vm_commit_force(pages: usize) {
+    COMMITTED.fetch_add(pages, Ordering::AcqRel);
+}
+
+/// Give back the charge of `pages`.
+pub fn vm_uncommit(pages: usize) {
+    let prev = COMMITTED.fetch_sub(pages, Ordering::AcqRel);
+    debug_assert!(prev >= pages);
+}
@@ -6,9 +6,11 @@ use core::{
     cell::SyncUnsafeCell,
     fmt::{self, Debug, Formatter},
     ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use bitmap_allocator::BitAlloc;
+use config::mm::HUGE_PAGE_PAGES;
 use crate_interface::call_interface;
 use sync::mutex::SpinNoIrqLock;
 
@@ -49,6 +51,8 @@ impl Drop for FrameTracker {
 struct FrameAllocator {
     range_ppn: SyncUnsafeCell<Range<PhysPageNum>>,
     allocator: SpinNoIrqLock<bitmap_allocator::BitAlloc16M>,
+    /// Number of frames allocated.
+    allocated: AtomicUsize,
 }
 
 impl FrameAllocator {
@@ -59,20 +63,29 @@ impl FrameAllocator {
     fn range_ppn(&self) -> Range<PhysPageNum> {
         unsafe { &*self.range_ppn.get() }.clone()
     }
+
+    /// Frame of index 0 in the bitmap, aligned down to a huge page so that
+    /// aligned indices are aligned frames.
+    fn base_ppn(&self) -> PhysPageNum {
+        let start = self.range_ppn().start;
+        PhysPageNum(start.0 / HUGE_PAGE_PAGES * HUGE_PAGE_PAGES)
+    }
 }
 
 static FRAME_ALLOCATOR: FrameAllocator = FrameAllocator {
     range_ppn: SyncUnsafeCell::new(PhysPageNum::ZERO..PhysPageNum::ZERO),
     allocator: SpinNoIrqLock::new(bitmap_allocator::BitAlloc16M::DEFAULT),
+    allocated: AtomicUsize::new(0),
 };
 
 /// Initiate the frame allocator, using `VPNRange`
 pub fn init_frame_allocator(start: PhysPageNum, end: PhysPageNum) {
+    FRAME_ALLOCATOR.init(start..end);
+    let base = FRAME_ALLOCATOR.base_ppn();
     FRAME_ALLOCATOR
         .allocator
         .lock()
-        .insert(0..(end.0 - start.0));
-    FRAME_ALLOCATOR.init(start..end);
+        .insert((start - base)..(end - base));
 
     log::info!(
         "frame allocator init finshed, start {:#x}, end {:#x}",
@@ -83,66 +96,86 @@ pub fn init_frame_allocator(start: PhysPageNum, end: PhysPageNum) {
 
 /// Allocate a frame
 pub fn alloc_frame_tracker() -> FrameTracker {
-    let ret = FRAME_ALLOCATOR
-        .allocator
-        .lock()
-        .alloc()
-        .map(|u| FrameTracker::new(FRAME_ALLOCATOR.range_ppn().start + u));
-    if let Some(ret) = ret {
-        ret
-    } else {
+    try_alloc_frame_tracker().expect("frame space not enough")
+}
+
+/// Allocate a frame, `None` if there is no frame left even after releasing
+/// caches.
+pub fn try_alloc_frame_tracker() -> Option<FrameTracker> {
+    let alloc = || {
+        let frame = FRAME_ALLOCATOR.allocator.lock().alloc()?;
+        FRAME_ALLOCATOR.allocated.fetch_add(1, Ordering::Relaxed);
+        Some(FrameTracker::new(FRAME_ALLOCATOR.base_ppn() + frame))
+    };
+    alloc().or_else(|| {
         call_interface!(FrameReleaseIf::release_frames());
-        FRAME_ALLOCATOR
-            .allocator
-            .lock()
-            .alloc()
-            .map(|u| FrameTracker::new(FRAME_ALLOCATOR.range_ppn().start + u))
-            .expect("frame space not enough")
-    }
+        alloc()
+    })
 }
 
-/// Allocate contiguous frames
-pub fn alloc_frame_trackers(size: usize) -> Vec<FrameTracker> {
-    if let Some(first_frame) = FRAME_ALLOCATOR
+/// Allocate `count` contiguous frames, the first one aligned to `count`
+/// frames up to a huge page, without releasing caches. `count` must be a power
+/// of two.
+///
+/// It does not allocate from the heap, so the heap can grow from it.
+pub fn try_alloc_contiguous_frames(count: usize) -> Option<PhysPageNum> {
+    debug_assert!(count.is_power_of_two());
+    let align_log2 = count.min(HUGE_PAGE_PAGES).trailing_zeros() as usize;
+    let first_frame = FRAME_ALLOCATOR
         .allocator
         .lock()
-        .alloc_contiguous(None, size, 0)
-    {
-        (first_frame..first_frame + size)
-            .map(|u| FrameTracker::new(FRAME_ALLOCATOR.range_ppn().start + u))
-            .collect()
-    } else {
-        call_interface!(FrameReleaseIf::release_frames());
+        .alloc_contiguous(None, count, align_log2)?;
+    FRAME_ALLOCATOR
+        .allocated
+        .fetch_add(count, Ordering::Relaxed);
+    Some(FRAME_ALLOCATOR.base_ppn() + first_frame)
+}
+
+/// Allocate `size` contiguous frames, releasing caches if there are none.
+fn alloc_contiguous_frames(size: usize) -> PhysPageNum {
+    let alloc = || {
         let first_frame = FRAME_ALLOCATOR
             .allocator
             .lock()
-            .alloc_contiguous(None, size, 0)
-            .unwrap();
-        (first_frame..first_frame + size)
-            .map(|u| FrameTracker::new(FRAME_ALLOCATOR.range_ppn().start + u))
-            .collect()
-    }
+            .alloc_contiguous(None, size, 0)?;
+        FRAME_ALLOCATOR.allocated.fetch_add(size, Ordering::Relaxed);
+        Some(FRAME_ALLOCATOR.base_ppn() + first_frame)
+    };
+    alloc()
+        .or_else(|| {
+            call_interface!(FrameReleaseIf::release_frames());
+            alloc()
+        })
+        .expect("frame space not enough")
+}
+
+/// Allocate contiguous frames
+pub fn alloc_frame_trackers(size: usize) -> Vec<FrameTracker> {
+    let first_ppn = alloc_contiguous_frames(size);
+    (0..size)
+        .map(|i| FrameTracker::new(first_ppn + i))
+        .collect()
+}
+
+/// Allocate `count` contiguous frames, the first one aligned to `count`
+/// frames, which must be a power of two. Return `None` if there is no such
+/// run of free frames.
+///
+/// Used for huge pages, which are optional, so caches are not released for
+/// them.
+pub fn try_alloc_aligned_frame_trackers(count: usize) -> Option<Vec<FrameTracker>> {
+    debug_assert!(count <= HUGE_PAGE_PAGES);
+    let first_ppn = try_alloc_contiguous_frames(count)?;
+    Some(
+        (0..count)
+            .map(|i| FrameTracker::new(first_ppn + i))
+            .collect(),
+    )
 }
 
 /// Allocate contiguous frames
 pub fn alloc_frames(size: usize) -> PhysAddr {
-    if let Some(first_frame) = FRAME_ALLOCATOR
-        .allocator
-        .lock()
-        .alloc_contiguous(None, size, 0)
-    {
-        let ppn = FRAME_ALLOCATOR.range_ppn().start + first_frame;
-        ppn.to_paddr()
-    } else {
-        call_interface!(FrameReleaseIf::release_frames());
-        let ppn = FRAME_ALLOCATOR.range_ppn().start
-            + FRAME_ALLOCATOR
-                .allocator
-                .lock()
-                .alloc_contiguous(None, size, 0)
-                .unwrap();
-        ppn.to_paddr()
-    }
+    alloc_contiguous_frames(size).to_paddr()
 }
 
 /// Deallocate a frame
@@ -150,7 +183,19 @@ pub fn dealloc_frame(ppn: PhysPageNum) {
     FRAME_ALLOCATOR
         .allocator
         .lock()
-        .dealloc(ppn - FRAME_ALLOCATOR.range_ppn().start);
+        .dealloc(ppn - FRAME_ALLOCATOR.base_ppn());
+    FRAME_ALLOCATOR.allocated.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Number of frames managed by the frame allocator.
+pub fn total_frames() -> usize {
+    let range_ppn = FRAME_ALLOCATOR.range_ppn();
+    range_ppn.end - range_ppn.start
+}
+
+/// Number of frames not allocated.
+pub fn free_frames() -> usize {
+    total_frames().saturating_sub(FRAME_ALLOCATOR.allocated.load(Ordering::Relaxed))
 }
 
 #[crate_interface::def_interface]
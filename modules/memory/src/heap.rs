@@ -1,17 +1,28 @@
 //! The global allocator
+//!
+//! With the buddy allocator, the heap starts with a small space in the kernel
+//! image and grows by taking contiguous frames from the frame allocator,
+//! which it keeps for good. Small allocations go through the slab caches in
+//! [`crate::slab`] first.
 use core::{
     self,
     alloc::{GlobalAlloc, Layout},
     ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use buddy_system_allocator::Heap as BuddyHeap;
+#[cfg(all(feature = "linked", not(feature = "buddy")))]
 use config::mm::KERNEL_HEAP_SIZE;
+use config::mm::{HUGE_PAGE_SIZE, KERNEL_HEAP_INIT_SIZE, PAGE_SIZE};
 #[cfg(all(feature = "linked", not(feature = "buddy")))]
 use linked_list_allocator::Heap as LinkedHeap;
 use sbi_print::sbi_println;
 use sync::mutex::SpinNoIrqLock;
 
+#[cfg(all(feature = "buddy", not(feature = "linked")))]
+use crate::{slab, try_alloc_contiguous_frames};
+
 #[cfg(all(feature = "buddy", not(feature = "linked")))]
 type GlobalHeap = LockedBuddyHeap;
 #[cfg(all(feature = "linked", not(feature = "buddy")))]
@@ -21,9 +32,20 @@ type GlobalHeap = LockedLinkedHeap;
 #[global_allocator]
 static HEAP_ALLOCATOR: GlobalHeap = GlobalHeap::empty();
 
+#[cfg(all(feature = "buddy", not(feature = "linked")))]
+const HEAP_SPACE_SIZE: usize = KERNEL_HEAP_INIT_SIZE;
+#[cfg(all(feature = "linked", not(feature = "buddy")))]
+const HEAP_SPACE_SIZE: usize = KERNEL_HEAP_SIZE;
+
 /// heap space
 #[unsafe(link_section = ".bss.heap")]
-static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
+static mut HEAP_SPACE: [u8; HEAP_SPACE_SIZE] = [0; HEAP_SPACE_SIZE];
+
+/// Bytes of the heap taken from the frame allocator.
+static HEAP_GROWN: AtomicUsize = AtomicUsize::new(0);
+
+/// Least size the heap grows by.
+const HEAP_GROW_SIZE: usize = HUGE_PAGE_SIZE;
 
 /// Get the heap space start address safely
 #[allow(unused)]
@@ -31,6 +53,16 @@ fn get_heap_space_addr() -> usize {
     core::ptr::addr_of_mut!(HEAP_SPACE) as usize
 }
 
+/// Size of the heap and bytes allocated from it, in bytes.
+pub fn heap_usage() -> (usize, usize) {
+    HEAP_ALLOCATOR.usage()
+}
+
+/// Bytes of the heap taken from the frame allocator.
+pub fn heap_grown() -> usize {
+    HEAP_GROWN.load(Ordering::Relaxed)
+}
+
 /// Panic when heap allocation error occurs.
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -62,21 +94,53 @@ impl LockedBuddyHeap {
     unsafe fn init(&self, start: usize, size: usize) {
         unsafe { self.0.lock().init(start, size) }
     }
-}
 
-unsafe impl GlobalAlloc for LockedBuddyHeap {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0
-            .lock()
-            .alloc(layout)
-            .ok()
+    fn usage(&self) -> (usize, usize) {
+        let inner = self.0.lock();
+        (inner.stats_total_bytes(), inner.stats_alloc_actual())
+    }
+
+    /// Allocate from the buddy heap, growing it if there is no room.
+    fn alloc_block(&self, layout: Layout) -> *mut u8 {
+        let alloc = || self.0.lock().alloc(layout).ok();
+        alloc()
+            .or_else(|| self.grow(layout).then(alloc).flatten())
             .map_or(core::ptr::null_mut::<u8>(), |allocation| {
                 allocation.as_ptr()
             })
     }
 
+    /// Take frames from the frame allocator so that `layout` fits, return
+    /// whether it did.
+    fn grow(&self, layout: Layout) -> bool {
+        let block = layout.size().max(layout.align()).next_power_of_two();
+        // Blocks of the buddy heap are aligned to their size, while frames are
+        // aligned up to a huge page, so a larger block needs twice its size.
+        let size = if block <= HEAP_GROW_SIZE {
+            HEAP_GROW_SIZE
+        } else {
+            block * 2
+        };
+        let Some(ppn) = try_alloc_contiguous_frames(size / PAGE_SIZE) else {
+            return false;
+        };
+        let start = ppn.to_paddr().to_vaddr().bits();
+        unsafe { self.0.lock().add_to_heap(start, start + size) };
+        HEAP_GROWN.fetch_add(size, Ordering::Relaxed);
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for LockedBuddyHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        slab::alloc(layout, |slab_layout| self.alloc_block(slab_layout))
+            .unwrap_or_else(|| self.alloc_block(layout))
+    }
+
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        unsafe { self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout) }
+        if !slab::dealloc(ptr, layout) {
+            unsafe { self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout) }
+        }
     }
 }
 
@@ -92,6 +156,11 @@ impl LockedLinkedHeap {
     unsafe fn init(&self, start: usize, size: usize) {
         self.0.lock().init(start as *mut u8, size)
     }
+
+    fn usage(&self) -> (usize, usize) {
+        let inner = self.0.lock();
+        (inner.size(), inner.used())
+    }
 }
 
 #[cfg(all(feature = "linked", not(feature = "buddy")))]
@@ -119,11 +188,11 @@ pub fn init_heap_allocator() {
     let heap_addr = core::ptr::addr_of_mut!(HEAP_SPACE) as usize;
 
     unsafe {
-        HEAP_ALLOCATOR.init(heap_addr, KERNEL_HEAP_SIZE);
+        HEAP_ALLOCATOR.init(heap_addr, HEAP_SPACE_SIZE);
         log::info!(
             "[kernel] heap start {:#x}, end {:#x}",
             heap_addr,
-            heap_addr + KERNEL_HEAP_SIZE
+            heap_addr + HEAP_SPACE_SIZE
         );
     }
 }
@@ -10,12 +10,14 @@
 extern crate alloc;
 
 pub mod address;
+pub mod commit;
 pub mod frame;
 pub mod heap;
 pub mod page_table;
 pub mod pte;
+pub mod slab;
 
 pub use address::*;
 pub use frame::*;
-pub use page_table::PageTable;
+pub use page_table::{PageLevel, PageTable};
 pub use pte::PageTableEntry;
@@ -1,10 +1,10 @@
 //! Implementation of [`PageTable`].
 
 use alloc::{vec, vec::Vec};
-use core::{iter::zip, ops::Range};
+use core::{cmp, iter::zip, ops::Range};
 
 use arch::satp;
-use config::mm::{PAGE_SIZE, VIRT_RAM_OFFSET};
+use config::mm::{PAGE_SIZE, PAGE_TABLE_LEVEL_NUM, PTES_PER_PAGE, VIRT_RAM_OFFSET};
 
 use crate::{
     PageTableEntry, PhysAddr,
@@ -21,6 +21,30 @@ pub unsafe fn switch_page_table(page_table_token: usize) {
     }
 }
 
+/// Level of a leaf pte in sv39, which decides the size of the page it maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageLevel {
+    /// 1GiB page, mapped in the root page table.
+    Giga = 0,
+    /// 2MiB page.
+    Mega = 1,
+    /// 4KiB page, mapped in the last level page table.
+    Kilo = 2,
+}
+
+impl PageLevel {
+    const ALL: [Self; PAGE_TABLE_LEVEL_NUM] = [Self::Giga, Self::Mega, Self::Kilo];
+
+    /// Number of 4KiB pages in a page of this level.
+    pub const fn pages(self) -> usize {
+        1 << (9 * (PAGE_TABLE_LEVEL_NUM - 1 - self as usize))
+    }
+
+    pub fn is_huge(self) -> bool {
+        self != Self::Kilo
+    }
+}
+
 /// # Safety
 ///
 /// Must be dropped after switching to new page table, otherwise, there will be
@@ -73,8 +97,10 @@ impl PageTable {
     }
 
     pub fn vaddr_to_paddr(&self, vaddr: VirtAddr) -> PhysAddr {
-        let leaf_pte = self.find_leaf_pte(vaddr.floor()).unwrap();
-        let paddr = leaf_pte.ppn().to_paddr() + vaddr.page_offset();
+        let vpn = vaddr.floor();
+        let (leaf_pte, level) = self.find_leaf(vpn).unwrap();
+        let page_index = vpn.0 % level.pages();
+        let paddr = leaf_pte.ppn().to_paddr() + page_index * PAGE_SIZE + vaddr.page_offset();
         paddr
     }
 
@@ -85,44 +111,64 @@ impl PageTable {
         }
     }
 
-    /// Find the leaf pte and will create page table in need.
-    fn find_leaf_pte_create(&mut self, vpn: VirtPageNum) -> &mut PageTableEntry {
+    /// Find the pte at `level` for `vpn` and will create page table in need.
+    ///
+    /// Return `None` if `vpn` is in a huge page above `level`.
+    fn find_pte_create(
+        &mut self,
+        vpn: VirtPageNum,
+        level: PageLevel,
+    ) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indices();
         let mut ppn = self.root_ppn;
-        for &idx in &idxs[..2] {
+        for &idx in &idxs[..level as usize] {
             let pte = ppn.pte(idx);
             if !pte.is_valid() {
                 let frame = alloc_frame_tracker();
                 frame.fill_zero();
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
+            } else if pte.is_leaf() {
+                return None;
             }
             ppn = pte.ppn();
         }
-        // log::error!("{idxs:?}");
-        // log::error!("{:?}", ppn.pte_array());
-        return ppn.pte(idxs[2]);
+        Some(ppn.pte(idxs[level as usize]))
     }
 
-    /// Find the leaf pte.
+    /// Find the leaf pte and will create page table in need.
+    fn find_leaf_pte_create(&mut self, vpn: VirtPageNum) -> &mut PageTableEntry {
+        self.find_pte_create(vpn, PageLevel::Kilo)
+            .unwrap_or_else(|| panic!("vpn {vpn:?} is in a huge page"))
+    }
+
+    /// Find the leaf pte mapping `vpn` and its level, which is a huge page
+    /// if it is above the last level.
     ///
     /// Return `None` if the leaf pte is not valid.
-    pub fn find_leaf_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    pub fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, PageLevel)> {
         let idxs = vpn.indices();
         let mut ppn = self.root_ppn;
-        for (i, idx) in idxs.into_iter().enumerate() {
+        for (level, idx) in zip(PageLevel::ALL, idxs) {
             let pte = ppn.pte(idx);
             if !pte.is_valid() {
                 return None;
             }
-            if i == 2 {
-                return Some(pte);
+            if level == PageLevel::Kilo || pte.is_leaf() {
+                return Some((pte, level));
             }
             ppn = pte.ppn();
         }
         return None;
     }
 
+    /// Find the leaf pte, which may map a huge page containing `vpn`.
+    ///
+    /// Return `None` if the leaf pte is not valid.
+    pub fn find_leaf_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_leaf(vpn).map(|(pte, _)| pte)
+    }
+
     /// Map `VirtPageNum` to `PhysPageNum` with `PTEFlags`.
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
         let pte = self.find_leaf_pte_create(vpn);
@@ -140,18 +186,111 @@ impl PageTable {
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V | PTEFlags::D | PTEFlags::A);
     }
 
+    /// Map a huge page of `level` from `vpn` to `ppn`, both aligned to it.
+    ///
+    /// Return `false` without mapping if part of it is mapped already.
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        level: PageLevel,
+        flags: PTEFlags,
+    ) -> bool {
+        debug_assert!(vpn.0 % level.pages() == 0 && ppn.0 % level.pages() == 0);
+        match self.find_pte_create(vpn, level) {
+            Some(pte) if !pte.is_valid() => {
+                *pte = PageTableEntry::new(ppn, flags | PTEFlags::V | PTEFlags::D | PTEFlags::A);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Split the huge page containing `vpn` into 4KiB pages with the same
+    /// flags. Nothing is done if `vpn` is mapped by a 4KiB page or not mapped.
+    pub fn split(&mut self, vpn: VirtPageNum) {
+        while let Some((pte, level)) = self.find_leaf(vpn) {
+            if !level.is_huge() {
+                return;
+            }
+            let sub_pages = level.pages() / PTES_PER_PAGE;
+            let (ppn, flags) = (pte.ppn(), pte.flags());
+            let frame = alloc_frame_tracker();
+            for (i, sub_pte) in frame.ppn.pte_array().iter_mut().enumerate() {
+                *sub_pte = PageTableEntry::new(ppn + i * sub_pages, flags);
+            }
+            *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+            self.frames.push(frame);
+        }
+    }
+
+    /// Split the huge page containing `vpn` unless `vpn` is its first page,
+    /// so that a range starting at `vpn` has no huge page crossing its start.
+    pub fn split_at(&mut self, vpn: VirtPageNum) {
+        if let Some((_, level)) = self.find_leaf(vpn) {
+            if vpn.0 % level.pages() != 0 {
+                self.split(vpn);
+            }
+        }
+    }
+
     /// Unmap a `VirtPageNum`.
+    ///
+    /// A huge page containing it is split first.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
+        self.split(vpn);
         let pte = self.find_leaf_pte(vpn).expect("leaf pte is not valid");
         debug_assert!(pte.is_valid(), "vpn {vpn:?} is invalid before unmapping",);
         *pte = PageTableEntry::empty();
     }
 
+    /// Unmap the page containing `vpn` if it is mapped, a huge page as a
+    /// whole, and return whether it was mapped.
+    pub fn unmap_leaf(&mut self, vpn: VirtPageNum) -> bool {
+        match self.find_leaf_pte(vpn) {
+            Some(pte) => {
+                *pte = PageTableEntry::empty();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Map `count` pages from `vpn` to `ppn`, with the largest pages both of
+    /// them are aligned to. Pages mapped already are left alone, since a
+    /// device may be mapped more than once.
+    fn map_region(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, count: usize, flags: PTEFlags) {
+        let end = vpn + count;
+        let (mut vpn, mut ppn) = (vpn, ppn);
+        while vpn < end {
+            let huge_level = [PageLevel::Giga, PageLevel::Mega]
+                .into_iter()
+                .filter(|level| {
+                    let pages = level.pages();
+                    vpn.0 % pages == 0 && ppn.0 % pages == 0 && end - vpn >= pages
+                })
+                .find(|&level| self.map_huge(vpn, ppn, level, flags));
+            let level = match huge_level {
+                Some(level) => level,
+                None => {
+                    if self.find_leaf_pte(vpn).is_none() {
+                        self.map(vpn, ppn, flags);
+                    }
+                    PageLevel::Kilo
+                }
+            };
+            vpn += level.pages();
+            ppn += level.pages();
+        }
+    }
+
     pub fn map_kernel_region(&mut self, range_va: Range<VirtAddr>, flags: PTEFlags) {
         let range_vpn = range_va.start.floor()..range_va.end.floor();
-        for vpn in range_vpn {
-            self.map(vpn, vpn.to_ppn(), flags);
+        if range_vpn.is_empty() {
+            return;
         }
+        let count = range_vpn.end - range_vpn.start;
+        self.map_region(range_vpn.start, range_vpn.start.to_ppn(), count, flags);
     }
 
     pub fn map_kernel_region_offset(
@@ -162,9 +301,11 @@ impl PageTable {
     ) {
         let range_vpn = range_va.start.floor()..range_va.end.ceil();
         let range_ppn = range_pa.start.floor()..range_pa.end.ceil();
-        for (vpn, ppn) in zip(range_vpn, range_ppn) {
-            self.map(vpn, ppn, flags);
-        }
+        let count = cmp::min(
+            range_vpn.end.0.saturating_sub(range_vpn.start.0),
+            range_ppn.end.0.saturating_sub(range_ppn.start.0),
+        );
+        self.map_region(range_vpn.start, range_ppn.start, count, flags);
     }
 
     /// Map the physical addresses of I/O memory resources to core virtual
@@ -172,25 +313,23 @@ impl PageTable {
     ///
     /// Linux also has this function
     pub fn ioremap(&mut self, paddr: usize, size: usize, flags: PTEFlags) {
-        let mut vpn = VirtAddr::from(paddr + VIRT_RAM_OFFSET).floor();
-        let mut ppn = vpn.to_ppn();
-        let mut size = size as isize;
-        while size > 0 {
-            self.map(vpn, ppn, flags);
-            vpn += 1;
-            ppn += 1;
-            size -= PAGE_SIZE as isize;
-        }
+        let vpn = VirtAddr::from(paddr + VIRT_RAM_OFFSET).floor();
+        let ppn = vpn.to_ppn();
+        let count = (paddr % PAGE_SIZE + size).div_ceil(PAGE_SIZE);
+        self.map_region(vpn, ppn, count, flags);
     }
 
     /// Cancel the mapping made by ioremap()
     pub fn iounmap(&mut self, vaddr: usize, size: usize) {
-        let mut vpn = VirtAddr::from(vaddr).floor();
-        let mut size = size as isize;
-        while size > 0 {
-            self.unmap(vpn);
-            vpn += 1;
-            size -= PAGE_SIZE as isize;
+        let vpn = VirtAddr::from(vaddr).floor();
+        let end = vpn + (vaddr % PAGE_SIZE + size).div_ceil(PAGE_SIZE);
+        self.split_at(vpn);
+        self.split_at(end);
+        let mut vpn = vpn;
+        while vpn < end {
+            let pages = self.find_leaf(vpn).map_or(1, |(_, level)| level.pages());
+            self.unmap_leaf(vpn);
+            vpn += pages;
         }
     }
 
@@ -0,0 +1,250 @@
+//! Slab caches in front of the heap.
+//!
+//! Small objects are allocated from caches of fixed-size objects, which take
+//! blocks (slabs) from the heap and carve them into objects. Freed objects go
+//! back to the free list of their cache, so allocating them again does not go
+//! through the heap. Slabs are kept by their cache for good.
+//!
+//! Allocations are routed by their layout: a named cache serves the
+//! allocations of exactly its layout, e.g. the `Arc`s of a kernel object, and
+//! the `kmalloc-*` caches serve other small allocations by size.
+
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use config::mm::PAGE_SIZE;
+use sync::mutex::SpinNoIrqLock;
+
+/// Most caches there can be, including the `kmalloc-*` ones.
+const MAX_SLAB_CACHES: usize = 16;
+/// Least number of objects in a slab.
+const MIN_OBJS_PER_SLAB: usize = 8;
+
+const KMALLOC_NAMES: [&str; 8] = [
+    "kmalloc-16",
+    "kmalloc-32",
+    "kmalloc-64",
+    "kmalloc-128",
+    "kmalloc-256",
+    "kmalloc-512",
+    "kmalloc-1k",
+    "kmalloc-2k",
+];
+const KMALLOC_MIN_SIZE: usize = 16;
+const KMALLOC_MAX_SIZE: usize = KMALLOC_MIN_SIZE << (KMALLOC_NAMES.len() - 1);
+
+/// A cache of objects of the same size.
+#[derive(Clone, Copy)]
+pub struct SlabCache {
+    name: &'static str,
+    /// Layout of the allocations served by a named cache, `None` for a
+    /// `kmalloc-*` one.
+    layout: Option<Layout>,
+    /// Size of the objects, a multiple of their alignment.
+    object_size: usize,
+    /// Size of the slabs, a power of two they are aligned to.
+    slab_size: usize,
+    /// First free object, each of which links to the next by its first word.
+    free: usize,
+    /// Objects allocated.
+    active_objs: usize,
+    /// Objects carved from the slabs, allocated or free.
+    num_objs: usize,
+    /// Slabs taken from the heap.
+    num_slabs: usize,
+}
+
+impl SlabCache {
+    const fn new(name: &'static str, layout: Option<Layout>, object_size: usize) -> Self {
+        let slab_size = if object_size * MIN_OBJS_PER_SLAB > PAGE_SIZE {
+            (object_size * MIN_OBJS_PER_SLAB).next_power_of_two()
+        } else {
+            PAGE_SIZE
+        };
+        Self {
+            name,
+            layout,
+            object_size,
+            slab_size,
+            free: 0,
+            active_objs: 0,
+            num_objs: 0,
+            num_slabs: 0,
+        }
+    }
+
+    /// Whether this cache serves allocations of `layout`.
+    fn serves(&self, layout: Layout) -> bool {
+        match self.layout {
+            Some(cache_layout) => cache_layout == layout,
+            // Objects are aligned to their size, which is a power of two.
+            None => layout.size() <= self.object_size && layout.align() <= self.object_size,
+        }
+    }
+
+    /// Carve a new slab at `slab` into free objects.
+    fn add_slab(&mut self, slab: usize) {
+        let objs = self.slab_size / self.object_size;
+        for i in (0..objs).rev() {
+            let obj = slab + i * self.object_size;
+            unsafe { (obj as *mut usize).write(self.free) };
+            self.free = obj;
+        }
+        self.num_objs += objs;
+        self.num_slabs += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.free == 0 {
+            return None;
+        }
+        let obj = self.free;
+        self.free = unsafe { (obj as *const usize).read() };
+        self.active_objs += 1;
+        Some(obj)
+    }
+
+    fn push(&mut self, obj: usize) {
+        unsafe { (obj as *mut usize).write(self.free) };
+        self.free = obj;
+        self.active_objs = self.active_objs.saturating_sub(1);
+    }
+}
+
+/// Statistics of a slab cache, as in `/proc/slabinfo`.
+#[derive(Debug, Clone)]
+pub struct SlabInfo {
+    pub name: &'static str,
+    pub active_objs: usize,
+    pub num_objs: usize,
+    pub object_size: usize,
+    pub objs_per_slab: usize,
+    pub pages_per_slab: usize,
+    pub num_slabs: usize,
+}
+
+const fn kmalloc_caches() -> [Option<SlabCache>; MAX_SLAB_CACHES] {
+    let mut caches = [None; MAX_SLAB_CACHES];
+    let mut i = 0;
+    while i < KMALLOC_NAMES.len() {
+        caches[i] = Some(SlabCache::new(
+            KMALLOC_NAMES[i],
+            None,
+            KMALLOC_MIN_SIZE << i,
+        ));
+        i += 1;
+    }
+    caches
+}
+
+/// All the caches, the `kmalloc-*` ones first.
+static SLAB_CACHES: SpinNoIrqLock<[Option<SlabCache>; MAX_SLAB_CACHES]> =
+    SpinNoIrqLock::new(kmalloc_caches());
+
+/// Bytes of the slabs of all caches.
+static SLAB_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Create a cache for allocations of `layout`, named `name`.
+///
+/// It should be created before such allocations are made, since objects
+/// freed to it are counted as its own.
+pub fn create_slab_cache(name: &'static str, layout: Layout) {
+    let layout = layout.pad_to_align();
+    let object_size = layout.size().max(size_of::<usize>());
+    // Logged after unlocking, since logging may allocate.
+    let result = {
+        let mut caches = SLAB_CACHES.lock();
+        if caches.iter().flatten().any(|c| c.layout == Some(layout)) {
+            Err("another cache has its layout")
+        } else if let Some(slot) = caches.iter_mut().find(|c| c.is_none()) {
+            *slot = Some(SlabCache::new(name, Some(layout), object_size));
+            Ok(())
+        } else {
+            Err("too many caches")
+        }
+    };
+    if let Err(reason) = result {
+        log::warn!("[create_slab_cache] can not create {name}: {reason}");
+    }
+}
+
+/// Index of the cache serving `layout`, a named one if there is.
+fn cache_index(caches: &[Option<SlabCache>], layout: Layout) -> Option<usize> {
+    let named = caches
+        .iter()
+        .position(|c| c.is_some_and(|c| c.layout.is_some() && c.serves(layout)));
+    if named.is_some() {
+        return named;
+    }
+    if layout.size() > KMALLOC_MAX_SIZE || layout.align() > KMALLOC_MAX_SIZE {
+        return None;
+    }
+    caches
+        .iter()
+        .position(|c| c.is_some_and(|c| c.serves(layout)))
+}
+
+/// Allocate for `layout` from its cache, taking a slab with `alloc_slab` if
+/// the cache is empty. Return `None` if no cache serves `layout`, or a null
+/// pointer if there is no memory.
+pub(crate) fn alloc(layout: Layout, alloc_slab: impl FnOnce(Layout) -> *mut u8) -> Option<*mut u8> {
+    let mut caches = SLAB_CACHES.lock();
+    let index = cache_index(caches.as_slice(), layout)?;
+    let cache = caches[index].as_mut().unwrap();
+    if cache.free == 0 {
+        let slab_layout = Layout::from_size_align(cache.slab_size, cache.slab_size).unwrap();
+        let slab = alloc_slab(slab_layout);
+        if slab.is_null() {
+            return Some(ptr::null_mut());
+        }
+        cache.add_slab(slab as usize);
+        SLAB_BYTES.fetch_add(cache.slab_size, Ordering::Relaxed);
+    }
+    cache.pop().map(|obj| obj as *mut u8)
+}
+
+/// Free `ptr` allocated for `layout` to its cache, return whether a cache
+/// serves `layout`.
+pub(crate) fn dealloc(ptr: *mut u8, layout: Layout) -> bool {
+    let mut caches = SLAB_CACHES.lock();
+    let Some(index) = cache_index(caches.as_slice(), layout) else {
+        return false;
+    };
+    caches[index].as_mut().unwrap().push(ptr as usize);
+    true
+}
+
+/// Bytes of the slabs of all caches.
+pub fn slab_bytes() -> usize {
+    SLAB_BYTES.load(Ordering::Relaxed)
+}
+
+/// Statistics of all the caches.
+pub fn slab_info() -> Vec<SlabInfo> {
+    // Copied out, since making the `Vec` allocates.
+    let caches = *SLAB_CACHES.lock();
+    caches
+        .iter()
+        .flatten()
+        .map(|c| SlabInfo {
+            name: c.name,
+            active_objs: c.active_objs,
+            num_objs: c.num_objs,
+            object_size: c.object_size,
+            objs_per_slab: c.slab_size / c.object_size,
+            pages_per_slab: c.slab_size / PAGE_SIZE,
+            num_slabs: c.num_slabs,
+        })
+        .collect()
+}
+
+/// Layout of the allocation of an `Arc<T>`, which holds the two reference
+/// counts before the value, for creating a cache of `Arc<T>`.
+pub fn arc_layout<T>() -> Layout {
+    let counts = Layout::new::<[AtomicUsize; 2]>();
+    counts.extend(Layout::new::<T>()).unwrap().0.pad_to_align()
+}
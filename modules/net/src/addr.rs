@@ -1,7 +1,30 @@
-use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv6Address};
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv6Address};
 
 pub fn is_unspecified(ip: IpAddress) -> bool {
-    ip.as_bytes() == [0, 0, 0, 0] || ip.as_bytes() == [0, 0, 0, 0, 0, 0]
+    ip.is_unspecified()
+}
+
+/// IPv4-mapped IPv6 address `::ffff:a.b.c.d` of `v4`.
+pub fn to_ipv4_mapped(v4: Ipv4Address) -> Ipv6Address {
+    let mut bytes = [0; 16];
+    bytes[10..12].copy_from_slice(&[0xff, 0xff]);
+    bytes[12..].copy_from_slice(v4.as_bytes());
+    Ipv6Address(bytes)
+}
+
+/// The IPv4 address of an IPv4-mapped IPv6 address.
+pub fn from_ipv4_mapped(v6: Ipv6Address) -> Option<Ipv4Address> {
+    v6.is_ipv4_mapped()
+        .then(|| Ipv4Address::from_bytes(&v6.as_bytes()[12..]))
+}
+
+/// Turn an IPv4-mapped address into a plain IPv4 one, which is what the
+/// protocol stack sends and receives.
+pub fn unmap_ipv4(ip: IpAddress) -> IpAddress {
+    match ip {
+        IpAddress::Ipv6(v6) => from_ipv4_mapped(v6).map_or(ip, IpAddress::Ipv4),
+        IpAddress::Ipv4(_) => ip,
+    }
 }
 
 pub fn to_endpoint(listen_endpoint: IpListenEndpoint) -> IpEndpoint {
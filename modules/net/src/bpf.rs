@@ -0,0 +1,258 @@
+//! Classic BPF, the socket filters attached by `SO_ATTACH_FILTER`.
+//!
+//! A program is checked once when it is attached, so running it only has to
+//! guard against loads beyond the packet and division by a zero `X`, both of
+//! which drop the packet like Linux does.
+
+use alloc::vec::Vec;
+
+use systype::{SysError, SysResult};
+
+/// Max number of instructions of a program, `BPF_MAXINSNS` in Linux.
+pub const BPF_MAXINSNS: usize = 4096;
+/// Number of scratch memory words.
+const BPF_MEMWORDS: usize = 16;
+
+// Instruction classes
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+
+// Load sizes
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+
+// Load modes
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+const BPF_MSH: u16 = 0xa0;
+
+// ALU operations
+const BPF_ADD: u16 = 0x00;
+const BPF_SUB: u16 = 0x10;
+const BPF_MUL: u16 = 0x20;
+const BPF_DIV: u16 = 0x30;
+const BPF_OR: u16 = 0x40;
+const BPF_AND: u16 = 0x50;
+const BPF_LSH: u16 = 0x60;
+const BPF_RSH: u16 = 0x70;
+const BPF_NEG: u16 = 0x80;
+const BPF_MOD: u16 = 0x90;
+const BPF_XOR: u16 = 0xa0;
+
+// Jump conditions
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+
+// Operand sources
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+/// Return value taken from `A`.
+const BPF_A: u16 = 0x10;
+
+// Register transfers of `BPF_MISC`
+const BPF_TAX: u16 = 0x00;
+const BPF_TXA: u16 = 0x80;
+
+/// ```c
+/// struct sock_filter {
+///     __u16 code; /* Actual filter code */
+///     __u8  jt;   /* Jump true */
+///     __u8  jf;   /* Jump false */
+///     __u32 k;    /* Generic multiuse field */
+/// };
+/// ```
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// A checked classic BPF program.
+#[derive(Debug)]
+pub struct BpfProgram {
+    insns: Vec<SockFilter>,
+}
+
+impl BpfProgram {
+    /// Check `insns` as `bpf_check_classic` does in Linux: every opcode is
+    /// known, jumps stay in the program, constant divisors are not zero,
+    /// scratch memory indexes are in range and the last instruction returns.
+    pub fn new(insns: Vec<SockFilter>) -> SysResult<Self> {
+        if insns.is_empty() || insns.len() > BPF_MAXINSNS {
+            return Err(SysError::EINVAL);
+        }
+        let len = insns.len();
+        for (pc, insn) in insns.iter().enumerate() {
+            let code = insn.code;
+            let valid = match code & 0x07 {
+                BPF_LD => match code & 0xe0 {
+                    BPF_ABS | BPF_IND => matches!(code & 0x18, BPF_W | BPF_H | BPF_B),
+                    BPF_IMM | BPF_LEN => code & 0x18 == BPF_W,
+                    BPF_MEM => code & 0x18 == BPF_W && (insn.k as usize) < BPF_MEMWORDS,
+                    _ => false,
+                },
+                BPF_LDX => match code & 0xe0 {
+                    BPF_IMM | BPF_LEN => code & 0x18 == BPF_W,
+                    BPF_MEM => code & 0x18 == BPF_W && (insn.k as usize) < BPF_MEMWORDS,
+                    BPF_MSH => code & 0x18 == BPF_B,
+                    _ => false,
+                },
+                BPF_ST | BPF_STX => code & 0xf8 == 0 && (insn.k as usize) < BPF_MEMWORDS,
+                BPF_ALU => match code & 0xf0 {
+                    BPF_NEG => code & 0x08 == BPF_K,
+                    BPF_DIV | BPF_MOD => code & 0x08 == BPF_X || insn.k != 0,
+                    BPF_ADD | BPF_SUB | BPF_MUL | BPF_OR | BPF_AND | BPF_LSH | BPF_RSH
+                    | BPF_XOR => true,
+                    _ => false,
+                },
+                BPF_JMP => match code & 0xf0 {
+                    BPF_JA => code & 0x08 == BPF_K && pc + 1 + (insn.k as usize) < len,
+                    BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                        pc + 1 + (insn.jt as usize) < len && pc + 1 + (insn.jf as usize) < len
+                    }
+                    _ => false,
+                },
+                BPF_RET => matches!(code & 0x18, BPF_K | BPF_X | BPF_A),
+                BPF_MISC => matches!(code & 0xf8, BPF_TAX | BPF_TXA),
+                _ => false,
+            };
+            if !valid {
+                log::warn!("[BpfProgram::new] invalid instruction {pc}: {insn:?}");
+                return Err(SysError::EINVAL);
+            }
+        }
+        if insns[len - 1].code & 0x07 != BPF_RET {
+            return Err(SysError::EINVAL);
+        }
+        Ok(Self { insns })
+    }
+
+    /// Run the program on `packet`. Returns how many bytes of the packet to
+    /// accept, 0 drops it.
+    pub fn run(&self, packet: &[u8]) -> u32 {
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0u32; BPF_MEMWORDS];
+        let mut pc = 0;
+        loop {
+            let insn = self.insns[pc];
+            let k = insn.k;
+            pc += 1;
+            match insn.code & 0x07 {
+                BPF_LD => {
+                    a = match insn.code & 0xe0 {
+                        BPF_IMM => k,
+                        BPF_LEN => packet.len() as u32,
+                        BPF_MEM => mem[k as usize],
+                        mode => {
+                            let offset = if mode == BPF_IND {
+                                x.wrapping_add(k)
+                            } else {
+                                k
+                            };
+                            match load(packet, offset, insn.code & 0x18) {
+                                Some(value) => value,
+                                None => return 0,
+                            }
+                        }
+                    }
+                }
+                BPF_LDX => {
+                    x = match insn.code & 0xe0 {
+                        BPF_IMM => k,
+                        BPF_LEN => packet.len() as u32,
+                        BPF_MEM => mem[k as usize],
+                        // BPF_MSH, the IP header length.
+                        _ => match packet.get(k as usize) {
+                            Some(byte) => ((byte & 0xf) as u32) << 2,
+                            None => return 0,
+                        },
+                    }
+                }
+                BPF_ST => mem[k as usize] = a,
+                BPF_STX => mem[k as usize] = x,
+                BPF_ALU => {
+                    let operand = if insn.code & 0x08 == BPF_X { x } else { k };
+                    a = match insn.code & 0xf0 {
+                        BPF_ADD => a.wrapping_add(operand),
+                        BPF_SUB => a.wrapping_sub(operand),
+                        BPF_MUL => a.wrapping_mul(operand),
+                        BPF_DIV | BPF_MOD if operand == 0 => return 0,
+                        BPF_DIV => a / operand,
+                        BPF_MOD => a % operand,
+                        BPF_OR => a | operand,
+                        BPF_AND => a & operand,
+                        BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                        BPF_XOR => a ^ operand,
+                        // BPF_NEG
+                        _ => a.wrapping_neg(),
+                    }
+                }
+                BPF_JMP => {
+                    let operand = if insn.code & 0x08 == BPF_X { x } else { k };
+                    let taken = match insn.code & 0xf0 {
+                        BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+                        BPF_JEQ => a == operand,
+                        BPF_JGT => a > operand,
+                        BPF_JGE => a >= operand,
+                        // BPF_JSET
+                        _ => a & operand != 0,
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+                BPF_RET => {
+                    return match insn.code & 0x18 {
+                        BPF_A => a,
+                        BPF_X => x,
+                        _ => k,
+                    };
+                }
+                // BPF_MISC
+                _ => {
+                    if insn.code & 0xf8 == BPF_TAX {
+                        x = a;
+                    } else {
+                        a = x;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Load a big endian word, half word or byte at `offset` of `packet`.
+/// Negative offsets of Linux ancillary data are not supported.
+fn load(packet: &[u8], offset: u32, size: u16) -> Option<u32> {
+    let offset = offset as usize;
+    let len = match size {
+        BPF_W => 4,
+        BPF_H => 2,
+        _ => 1,
+    };
+    let bytes = packet.get(offset..offset.checked_add(len)?)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as u32),
+    )
+}
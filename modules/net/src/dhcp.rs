@@ -0,0 +1,149 @@
+//! DHCPv4 client.
+//!
+//! The client is a smoltcp dhcpv4 socket added as a link socket of an ethernet
+//! interface, and a kernel task that sleeps until the socket has an event. The
+//! network softirq polls the socket with the interface, when its deadline is
+//! due or a packet arrives, and smoltcp sends the requests for renewing and
+//! rebinding the lease by itself. The task applies every new configuration to
+//! the interface, the routing table and the DNS servers of the interface, and
+//! removes them when the lease is lost. DNS servers written to `/proc/net/dns`
+//! are left alone.
+
+use alloc::vec::Vec;
+use core::{future::poll_fn, task::Poll};
+
+use log::{info, warn};
+use smoltcp::{
+    phy::Medium,
+    socket::dhcpv4::{self, Event},
+    wire::{
+        DHCP_CLIENT_PORT, EthernetFrame, EthernetProtocol, IpAddress, IpCidr, IpProtocol,
+        Ipv4Address, Ipv4Cidr, Ipv4Packet, UdpPacket,
+    },
+};
+
+use crate::{
+    DHCP, InterfaceWrapper, iface,
+    route::{self, ROUTE_TABLE, RouteEntry},
+    set_dhcp_dns_servers, softirq,
+};
+
+/// Owned copy of `dhcpv4::Config`.
+struct DhcpConfig {
+    address: Ipv4Cidr,
+    router: Option<Ipv4Address>,
+    dns_servers: Vec<Ipv4Address>,
+}
+
+pub fn dhcp_enabled() -> bool {
+    DHCP == "y"
+}
+
+/// Whether `buf` is an IPv4 UDP packet to the DHCP client port.
+pub(crate) fn is_link_packet(buf: &[u8], is_ethernet: bool) -> bool {
+    let ipv4_packet = if is_ethernet {
+        let Ok(ether_frame) = EthernetFrame::new_checked(buf) else {
+            return false;
+        };
+        if ether_frame.ethertype() != EthernetProtocol::Ipv4 {
+            return false;
+        }
+        Ipv4Packet::new_checked(ether_frame.payload())
+    } else {
+        Ipv4Packet::new_checked(buf)
+    };
+    let Ok(ipv4_packet) = ipv4_packet else {
+        return false;
+    };
+    ipv4_packet.next_header() == IpProtocol::Udp
+        && UdpPacket::new_checked(ipv4_packet.payload())
+            .is_ok_and(|udp_packet| udp_packet.dst_port() == DHCP_CLIENT_PORT)
+}
+
+/// Run a DHCP client on interface `name` forever.
+pub async fn dhcp_client(name: &'static str) {
+    let Some(iface) = iface::get_by_name(name) else {
+        warn!("[dhcp_client] no interface {name}");
+        return;
+    };
+    if iface.medium() != Medium::Ethernet {
+        warn!("[dhcp_client] {name} is not an ethernet interface");
+        return;
+    }
+    info!("[dhcp_client] start on {name}");
+    let handle = iface.add_link_socket(dhcpv4::Socket::new());
+    // The first discover is sent when the interface is polled.
+    softirq::raise();
+    loop {
+        let config = poll_fn(|cx| {
+            iface.with_link_socket_mut::<dhcpv4::Socket, _, _>(handle, |socket| {
+                match socket.poll() {
+                    Some(Event::Configured(config)) => Poll::Ready(Some(DhcpConfig {
+                        address: config.address,
+                        router: config.router,
+                        dns_servers: config.dns_servers.iter().copied().collect(),
+                    })),
+                    Some(Event::Deconfigured) => Poll::Ready(None),
+                    None => {
+                        socket.register_waker(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await;
+        match config {
+            Some(config) => configure(&iface, config),
+            None => deconfigure(&iface),
+        }
+    }
+}
+
+fn configure(iface: &InterfaceWrapper, config: DhcpConfig) {
+    info!(
+        "[dhcp_client] {}: address {}, router {:?}, dns {:?}",
+        iface.name(),
+        config.address,
+        config.router,
+        config.dns_servers
+    );
+    let mut addrs = without_ipv4(iface);
+    addrs.insert(0, IpCidr::Ipv4(config.address));
+    iface::set_ip_addrs(iface, addrs);
+
+    ROUTE_TABLE.lock().remove_default_ipv4(iface.index);
+    if let Some(router) = config.router {
+        let route = RouteEntry {
+            dest: IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0),
+            gateway: Some(router.into()),
+            ifindex: iface.index,
+            metric: 0,
+        };
+        if let Err(e) = route::add_route(route) {
+            warn!("[dhcp_client] failed to add default route: {e:?}");
+        }
+    } else {
+        iface::sync_routes(iface.index);
+    }
+
+    let servers: Vec<IpAddress> = config.dns_servers.into_iter().map(Into::into).collect();
+    set_dhcp_dns_servers(iface.index, &servers);
+}
+
+fn deconfigure(iface: &InterfaceWrapper) {
+    info!("[dhcp_client] {}: lease lost", iface.name());
+    iface::set_ip_addrs(iface, without_ipv4(iface));
+    ROUTE_TABLE.lock().remove_default_ipv4(iface.index);
+    iface::sync_routes(iface.index);
+    set_dhcp_dns_servers(iface.index, &[]);
+}
+
+fn without_ipv4(iface: &InterfaceWrapper) -> Vec<IpCidr> {
+    iface
+        .addrs
+        .lock()
+        .iter()
+        .filter(|cidr| !matches!(cidr, IpCidr::Ipv4(_)))
+        .copied()
+        .collect()
+}
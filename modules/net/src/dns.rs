@@ -1,75 +1,111 @@
-use alloc::vec::Vec;
-use axerrno::{ax_err_type, AxError, AxResult};
-use core::net::IpAddr;
+//! DNS resolution, and a stub resolver for userspace.
+//!
+//! Queries are sent to the servers of [`dns_servers`], given by DHCP or
+//! written to `/proc/net/dns`. The stub resolver listens on 127.0.0.1:53,
+//! where the resolvers of userspace go without an `/etc/resolv.conf`, and
+//! answers A and AAAA queries by running them with [`dns_query`]. Names that
+//! do not resolve get an empty answer rather than NXDOMAIN, since smoltcp does
+//! not tell a missing name from a name without records of the asked type.
 
-use smoltcp::iface::SocketHandle;
-use smoltcp::socket::dns::{self, GetQueryResultError, StartQueryError};
-use smoltcp::wire::DnsQueryType;
+use alloc::{string::String, vec, vec::Vec};
 
-use super::addr::into_core_ipaddr;
-use super::{SocketSetWrapper, ETH0, SOCKET_SET};
+use async_utils::{get_waker, suspend_now};
+use log::{info, warn};
+use smoltcp::{
+    iface::SocketHandle,
+    socket::{
+        dns::{self, GetQueryResultError, StartQueryError},
+        udp,
+    },
+    wire::{DnsQueryType, IpAddress, IpEndpoint},
+};
+use systype::{SysError, SysResult};
 
-/// A DNS socket.
+use crate::{
+    SOCKET_SET, SocketSetWrapper, addr::LOCAL_IPV4, dns_servers, iface, iface::LOOPBACK_IFINDEX,
+    softirq,
+};
+
+/// Port of the stub resolver.
+const DNS_PORT: u16 = 53;
+/// Largest datagram taken, the usual EDNS limit of DNS over UDP.
+const MAX_QUERY_LEN: usize = 1232;
+const DNS_HEADER_LEN: usize = 12;
+/// TTL of the records of the stub resolver, which does not know the real
+/// ones.
+const STUB_TTL: u32 = 60;
+
+const FLAG_QR: u16 = 0x8000;
+const FLAG_RD: u16 = 0x0100;
+const FLAG_RA: u16 = 0x0080;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_FORMERR: u16 = 1;
+const RCODE_NOTIMP: u16 = 4;
+const CLASS_IN: u16 = 1;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// A smoltcp DNS socket in `SOCKET_SET`, removed when dropped.
 struct DnsSocket {
-    handle: Option<SocketHandle>,
+    handle: SocketHandle,
 }
 
 impl DnsSocket {
-    #[allow(clippy::new_without_default)]
-    /// Creates a new DNS socket.
-    pub fn new() -> Self {
+    fn new() -> Self {
         let socket = SocketSetWrapper::new_dns_socket();
-        let handle = Some(SOCKET_SET.add(socket));
-        Self { handle }
+        Self {
+            handle: SOCKET_SET.add(socket),
+        }
     }
 
-    #[allow(dead_code)]
-    /// Update the list of DNS servers, will replace all existing servers.
-    pub fn update_servers(self, servers: &[smoltcp::wire::IpAddress]) {
-        SOCKET_SET.with_socket_mut::<dns::Socket, _, _>(self.handle.unwrap(), |socket| {
+    /// Replace the servers queried, queries already started keep theirs.
+    fn update_servers(&self, servers: &[IpAddress]) {
+        SOCKET_SET.with_socket_mut::<dns::Socket, _, _>(self.handle, |socket| {
             socket.update_servers(servers)
         });
     }
 
-    /// Query a address with given DNS query type.
-    pub fn query(&self, name: &str, query_type: DnsQueryType) -> AxResult<Vec<IpAddr>> {
-        // let local_addr = self.local_addr.unwrap_or_else(f);
-        let handle = self.handle.ok_or_else(|| ax_err_type!(InvalidInput))?;
-        let iface = &ETH0.iface;
-        let query_handle = SOCKET_SET
-            .with_socket_mut::<dns::Socket, _, _>(handle, |socket| {
-                socket.start_query(iface.lock().context(), name, query_type)
+    async fn query(&self, name: &str, query_type: DnsQueryType) -> SysResult<Vec<IpAddress>> {
+        let servers = dns_servers();
+        self.update_servers(&servers);
+        // The interface only gives the random numbers of the query, it is sent
+        // on the one the route to the server goes through.
+        let iface = servers
+            .first()
+            .and_then(|&server| iface::route_iface(server))
+            .or_else(|| iface::get(LOOPBACK_IFINDEX))
+            .ok_or(SysError::ENETUNREACH)?;
+        let query = {
+            let mut iface = iface.iface.lock();
+            SOCKET_SET.with_socket_mut::<dns::Socket, _, _>(self.handle, |socket| {
+                socket.start_query(iface.context(), name, query_type)
             })
-            .map_err(|e| match e {
-                StartQueryError::NoFreeSlot => {
-                    ax_err_type!(ResourceBusy, "socket query() failed: no free slot")
-                }
-                StartQueryError::InvalidName => {
-                    ax_err_type!(InvalidInput, "socket query() failed: invalid name")
-                }
-                StartQueryError::NameTooLong => {
-                    ax_err_type!(InvalidInput, "socket query() failed: too long name")
-                }
-            })?;
+        }
+        .map_err(|e| {
+            warn!("[DnsSocket::query] {name}: {e:?}");
+            match e {
+                StartQueryError::NoFreeSlot => SysError::EBUSY,
+                StartQueryError::InvalidName | StartQueryError::NameTooLong => SysError::EINVAL,
+            }
+        })?;
+        let waker = get_waker().await;
         loop {
-            SOCKET_SET.poll_interfaces();
-            match SOCKET_SET.with_socket_mut::<dns::Socket, _, _>(handle, |socket| {
-                socket.get_query_result(query_handle).map_err(|e| match e {
-                    GetQueryResultError::Pending => AxError::WouldBlock,
-                    GetQueryResultError::Failed => {
-                        ax_err_type!(ConnectionRefused, "socket query() failed")
-                    }
-                })
-            }) {
-                Ok(n) => {
-                    let mut res = Vec::with_capacity(n.capacity());
-                    for ip in n {
-                        res.push(into_core_ipaddr(ip))
+            let timestamp = SOCKET_SET.poll_interfaces();
+            let ret =
+                SOCKET_SET.with_socket_mut::<dns::Socket, _, _>(self.handle, |socket| match socket
+                    .get_query_result(query)
+                {
+                    Ok(addrs) => Ok(addrs.into_iter().collect()),
+                    Err(GetQueryResultError::Pending) => {
+                        socket.register_query_waker(query, &waker);
+                        Err(SysError::EAGAIN)
                     }
-                    return Ok(res);
-                }
-                Err(AxError::WouldBlock) => axtask::yield_now(),
-                Err(e) => return Err(e),
+                    Err(GetQueryResultError::Failed) => Err(SysError::ENOENT),
+                });
+            SOCKET_SET.check_poll(timestamp);
+            match ret {
+                Err(SysError::EAGAIN) => suspend_now().await,
+                ret => return ret,
             }
         }
     }
@@ -77,14 +113,153 @@ impl DnsSocket {
 
 impl Drop for DnsSocket {
     fn drop(&mut self) {
-        if let Some(handle) = self.handle {
-            SOCKET_SET.remove(handle);
-        }
+        SOCKET_SET.remove(self.handle);
     }
 }
 
-/// Public function for DNS query.
-pub fn dns_query(name: &str) -> AxResult<alloc::vec::Vec<IpAddr>> {
-    let socket = DnsSocket::new();
-    socket.query(name, DnsQueryType::A)
+/// Resolve `name` to addresses of `query_type`, `ENOENT` if it does not
+/// resolve.
+pub async fn dns_query(name: &str, query_type: DnsQueryType) -> SysResult<Vec<IpAddress>> {
+    DnsSocket::new().query(name, query_type).await
+}
+
+/// Run the stub resolver forever. Queries are answered one at a time.
+pub async fn stub_resolver() {
+    let mut socket = SocketSetWrapper::new_udp_socket();
+    if let Err(e) = socket.bind(IpEndpoint::new(LOCAL_IPV4, DNS_PORT)) {
+        warn!("[stub_resolver] failed to bind: {e:?}");
+        return;
+    }
+    let handle = SOCKET_SET.add(socket);
+    info!("[stub_resolver] listening on {LOCAL_IPV4}:{DNS_PORT}");
+    let waker = get_waker().await;
+    let mut buf = vec![0; MAX_QUERY_LEN];
+    loop {
+        let received = SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(handle, |socket| {
+            if !socket.can_recv() {
+                socket.register_recv_waker(&waker);
+                return None;
+            }
+            // A truncated datagram is dropped, it is no valid query anyway.
+            Some(
+                socket
+                    .recv_slice(&mut buf)
+                    .ok()
+                    .map(|(len, meta)| (len, meta.endpoint)),
+            )
+        });
+        let (len, client) = match received {
+            Some(Some(query)) => query,
+            Some(None) => continue,
+            None => {
+                suspend_now().await;
+                continue;
+            }
+        };
+        let Some(reply) = answer(&buf[..len]).await else {
+            continue;
+        };
+        SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(handle, |socket| {
+            if let Err(e) = socket.send_slice(&reply, client) {
+                warn!("[stub_resolver] failed to reply to {client}: {e:?}");
+            }
+        });
+        softirq::raise();
+    }
+}
+
+/// The reply to `query`, `None` if it is not a query at all.
+async fn answer(query: &[u8]) -> Option<Vec<u8>> {
+    if query.len() < DNS_HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    if flags & FLAG_QR != 0 {
+        return None;
+    }
+    if flags & OPCODE_MASK != 0 {
+        return Some(reply_header(query, RCODE_NOTIMP, 0, 0));
+    }
+    let question_count = u16::from_be_bytes([query[4], query[5]]);
+    let Some((name, question_end)) = parse_question(query).filter(|_| question_count == 1) else {
+        return Some(reply_header(query, RCODE_FORMERR, 0, 0));
+    };
+    let qtype = u16::from_be_bytes([query[question_end - 4], query[question_end - 3]]);
+    let qclass = u16::from_be_bytes([query[question_end - 2], query[question_end - 1]]);
+    let query_type = match (qtype, qclass) {
+        (TYPE_A, CLASS_IN) => Some(DnsQueryType::A),
+        (TYPE_AAAA, CLASS_IN) => Some(DnsQueryType::Aaaa),
+        _ => None,
+    };
+    let addrs = match query_type {
+        Some(query_type) => dns_query(&name, query_type).await.unwrap_or_else(|e| {
+            info!("[stub_resolver] {name} {query_type:?}: {e:?}");
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    let rdatas: Vec<Vec<u8>> = addrs
+        .into_iter()
+        .filter_map(|addr| match (addr, qtype) {
+            (IpAddress::Ipv4(addr), TYPE_A) => Some(addr.as_bytes().to_vec()),
+            (IpAddress::Ipv6(addr), TYPE_AAAA) => Some(addr.as_bytes().to_vec()),
+            _ => None,
+        })
+        .collect();
+
+    let mut reply = reply_header(query, 0, 1, rdatas.len() as u16);
+    reply.extend_from_slice(&query[DNS_HEADER_LEN..question_end]);
+    for rdata in rdatas {
+        // The name is a pointer to the one of the question.
+        reply.extend_from_slice(&[0xc0, DNS_HEADER_LEN as u8]);
+        reply.extend_from_slice(&qtype.to_be_bytes());
+        reply.extend_from_slice(&CLASS_IN.to_be_bytes());
+        reply.extend_from_slice(&STUB_TTL.to_be_bytes());
+        reply.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        reply.extend_from_slice(&rdata);
+    }
+    Some(reply)
+}
+
+/// Header of the reply to `query`, followed by nothing.
+fn reply_header(query: &[u8], rcode: u16, question_count: u16, answer_count: u16) -> Vec<u8> {
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let flags = FLAG_QR | (flags & (OPCODE_MASK | FLAG_RD)) | FLAG_RA | rcode;
+    let mut reply = Vec::with_capacity(MAX_QUERY_LEN);
+    reply.extend_from_slice(&query[..2]);
+    reply.extend_from_slice(&flags.to_be_bytes());
+    reply.extend_from_slice(&question_count.to_be_bytes());
+    reply.extend_from_slice(&answer_count.to_be_bytes());
+    // No authority nor additional records.
+    reply.extend_from_slice(&[0; 4]);
+    reply
+}
+
+/// The name of the first question of `query` in dotted form, and where the
+/// question ends.
+fn parse_question(query: &[u8]) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut pos = DNS_HEADER_LEN;
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compressed names are not expected in a question.
+        if len > 63 {
+            return None;
+        }
+        let label = core::str::from_utf8(query.get(pos..pos + len)?).ok()?;
+        if label.contains('.') {
+            return None;
+        }
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(label);
+        pos += len;
+    }
+    // The type and the class follow the name.
+    (!name.is_empty() && query.len() >= pos + 4).then_some((name, pos + 4))
 }
@@ -0,0 +1,301 @@
+//! ICMP echo sockets, i.e. `socket(AF_INET, SOCK_DGRAM, IPPROTO_ICMP)`.
+//!
+//! A ping socket sends echo requests and only receives the echo replies with
+//! its own identifier, which is the "port" the socket is bound to. Messages
+//! sent and received are ICMP headers with payload, without the IP header.
+//! Echo requests to this host are answered by smoltcp itself.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use async_utils::{get_waker, suspend_now, yield_now};
+use log::{info, warn};
+use smoltcp::{
+    iface::SocketHandle,
+    socket::icmp::{self, BindError, Endpoint},
+    wire::{IpAddress, IpEndpoint},
+};
+use spin::RwLock;
+use systype::{SysError, SysResult};
+
+use crate::{
+    DEFAULT_HOP_LIMIT, MsgFlags, NetPollState, SOCKET_SET, SocketSetWrapper,
+    addr::UNSPECIFIED_IPV4, has_signal, iface, raw::fill_icmp_checksum,
+};
+
+// Message types, defined in <netinet/ip_icmp.h> and <netinet/icmp6.h>
+const ICMP_ECHO: u8 = 8;
+const ICMP6_ECHO_REQUEST: u8 = 128;
+
+/// Length of the ICMP echo header.
+const ECHO_HEADER_LEN: usize = 8;
+
+/// An ICMP echo socket that provides POSIX-like APIs.
+pub struct PingSocket {
+    /// Handle obtained after adding the newly created socket to SOCKET_SET.
+    handle: SocketHandle,
+    /// Whether this is an `AF_INET6` socket.
+    ipv6: bool,
+    /// Echo identifier, set when the socket is bound.
+    ident: RwLock<Option<u16>>,
+    /// Default destination given by `connect`, also used to filter received
+    /// replies.
+    peer_addr: RwLock<Option<IpAddress>>,
+    /// Indicates if the socket is in nonblocking mode.
+    nonblock: AtomicBool,
+}
+
+impl PingSocket {
+    /// Creates a new ping socket.
+    pub fn new(ipv6: bool) -> Self {
+        let socket = SocketSetWrapper::new_icmp_socket();
+        let handle = SOCKET_SET.add(socket);
+        Self {
+            handle,
+            ipv6,
+            ident: RwLock::new(None),
+            peer_addr: RwLock::new(None),
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the identifier as the port.
+    pub fn local_addr(&self) -> SysResult<IpEndpoint> {
+        let ident = (*self.ident.read()).unwrap_or(0);
+        Ok(IpEndpoint::new(UNSPECIFIED_IPV4, ident))
+    }
+
+    /// Returns the connected address, the port is always 0.
+    pub fn peer_addr(&self) -> SysResult<IpEndpoint> {
+        let addr = (*self.peer_addr.read()).ok_or(SysError::ENOTCONN)?;
+        Ok(IpEndpoint::new(addr, 0))
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this socket into or out of nonblocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Bind the socket to echo identifier `ident`, 0 picks a free one.
+    pub fn bind(&self, ident: u16) -> SysResult<()> {
+        let mut self_ident = self.ident.write();
+        if self_ident.is_some() {
+            return Err(SysError::EINVAL);
+        }
+        let ident = if ident == 0 {
+            get_ephemeral_ident()
+        } else {
+            ident
+        };
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            socket.bind(Endpoint::Ident(ident)).map_err(|e| match e {
+                BindError::InvalidState => SysError::EINVAL,
+                BindError::Unaddressable => SysError::EADDRNOTAVAIL,
+            })
+        })?;
+        info!("[PingSocket::bind] handle {} ident {ident}", self.handle);
+        *self_ident = Some(ident);
+        Ok(())
+    }
+
+    /// Sets the hop limit of echo requests sent, `None` for the default.
+    pub fn set_hop_limit(&self, hop_limit: Option<u8>) {
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            socket.set_hop_limit(hop_limit)
+        });
+    }
+
+    /// Returns the hop limit of echo requests sent.
+    pub fn hop_limit(&self) -> u8 {
+        SOCKET_SET
+            .with_socket::<icmp::Socket, _, _>(self.handle, |socket| socket.hop_limit())
+            .unwrap_or(DEFAULT_HOP_LIMIT)
+    }
+
+    /// Set the default destination, only replies from `addr` are received
+    /// afterwards.
+    pub fn connect(&self, addr: IpAddress) -> SysResult<()> {
+        self.check_family(addr)?;
+        *self.peer_addr.write() = Some(addr);
+        Ok(())
+    }
+
+    /// Sends the echo request `buf` to `dst`. The identifier and the checksum
+    /// in `buf` are replaced.
+    pub async fn send_to(&self, buf: &[u8], dst: IpAddress, flags: MsgFlags) -> SysResult<usize> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        self.check_family(dst)?;
+        let echo_type = if self.ipv6 {
+            ICMP6_ECHO_REQUEST
+        } else {
+            ICMP_ECHO
+        };
+        if buf.len() < ECHO_HEADER_LEN || buf[0] != echo_type || buf[1] != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let src = iface::source_addr(dst).ok_or_else(|| {
+            warn!("[PingSocket::send_to] no route to {dst}");
+            SysError::ENETUNREACH
+        })?;
+        if self.ident.read().is_none() {
+            self.bind(0)?;
+        }
+        let ident = self.ident.read().unwrap();
+        let mut msg: Vec<u8> = buf.to_vec();
+        msg[4..6].copy_from_slice(&ident.to_be_bytes());
+        fill_icmp_checksum(src, dst, &mut msg);
+
+        let waker = get_waker().await;
+        self.block_on(self.nonblock(flags), || {
+            SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+                if !socket.can_send() {
+                    socket.register_send_waker(&waker);
+                    return Err(SysError::EAGAIN);
+                }
+                socket.send_slice(&msg, dst).map_err(|e| {
+                    warn!("[PingSocket::send_to] failed: {e:?}");
+                    SysError::EMSGSIZE
+                })
+            })
+        })
+        .await?;
+        SOCKET_SET.poll_interfaces();
+        yield_now().await;
+        Ok(buf.len())
+    }
+
+    /// Sends the echo request `buf` to the connected address.
+    pub async fn send(&self, buf: &[u8], flags: MsgFlags) -> SysResult<usize> {
+        let dst = (*self.peer_addr.read()).ok_or(SysError::EDESTADDRREQ)?;
+        self.send_to(buf, dst, flags).await
+    }
+
+    /// Receives an echo reply. Returns the length of the reply, which is
+    /// larger than `buf` if it was truncated. smoltcp cannot peek at ICMP
+    /// sockets, so `MSG_PEEK` is not supported.
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+        flags: MsgFlags,
+    ) -> SysResult<(usize, IpEndpoint)> {
+        if flags.intersects(MsgFlags::OOB | MsgFlags::PEEK) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        if self.ident.read().is_none() {
+            return Err(SysError::ENOTCONN);
+        }
+        let peer = *self.peer_addr.read();
+        let waker = get_waker().await;
+        let ret = self
+            .block_on(self.nonblock(flags), || {
+                SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+                    loop {
+                        let Ok((msg, src)) = socket.recv() else {
+                            socket.register_recv_waker(&waker);
+                            return Err(SysError::EAGAIN);
+                        };
+                        if peer.is_some_and(|peer| peer != src) {
+                            continue;
+                        }
+                        let len = msg.len().min(buf.len());
+                        buf[..len].copy_from_slice(&msg[..len]);
+                        return Ok((msg.len(), IpEndpoint::new(src, 0)));
+                    }
+                })
+            })
+            .await;
+        yield_now().await;
+        ret
+    }
+
+    /// Whether the socket is readable or writable.
+    pub async fn poll(&self) -> NetPollState {
+        let waker = get_waker().await;
+        SOCKET_SET.with_socket_mut::<icmp::Socket, _, _>(self.handle, |socket| {
+            let readable = socket.can_recv();
+            let writable = socket.can_send();
+            if !readable {
+                socket.register_recv_waker(&waker);
+            }
+            if !writable {
+                socket.register_send_waker(&waker);
+            }
+            NetPollState {
+                readable,
+                writable,
+                hangup: false,
+            }
+        })
+    }
+
+    /// Close the socket.
+    pub fn shutdown(&self) -> SysResult<()> {
+        info!("[PingSocket::shutdown] handle {}", self.handle);
+        Ok(())
+    }
+}
+
+/// Private methods
+impl PingSocket {
+    /// Whether this call does not block, i.e. the socket is nonblocking or
+    /// `MSG_DONTWAIT` is given.
+    #[inline]
+    fn nonblock(&self, flags: MsgFlags) -> bool {
+        self.is_nonblocking() || flags.contains(MsgFlags::DONTWAIT)
+    }
+
+    fn check_family(&self, addr: IpAddress) -> SysResult<()> {
+        match (addr, self.ipv6) {
+            (IpAddress::Ipv4(_), false) | (IpAddress::Ipv6(_), true) => Ok(()),
+            _ => Err(SysError::EAFNOSUPPORT),
+        }
+    }
+
+    async fn block_on<F, T>(&self, nonblock: bool, mut f: F) -> SysResult<T>
+    where
+        F: FnMut() -> SysResult<T>,
+    {
+        if nonblock {
+            f()
+        } else {
+            loop {
+                let timestamp = SOCKET_SET.poll_interfaces();
+                let ret = f();
+                SOCKET_SET.check_poll(timestamp);
+                match ret {
+                    Ok(t) => return Ok(t),
+                    Err(SysError::EAGAIN) => {
+                        suspend_now().await;
+                        if has_signal() {
+                            warn!("[PingSocket::block_on] has signal");
+                            return Err(SysError::EINTR);
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PingSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+fn get_ephemeral_ident() -> u16 {
+    const IDENT_START: u16 = 0xc000;
+    static CURR: AtomicU16 = AtomicU16::new(IDENT_START);
+    let ident = CURR.fetch_add(1, Ordering::Relaxed);
+    if ident == 0 { IDENT_START } else { ident }
+}
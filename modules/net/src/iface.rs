@@ -0,0 +1,387 @@
+//! Network interface list.
+//!
+//! The loopback interface is always registered first with index
+//! [`LOOPBACK_IFINDEX`], other devices follow in probing order. Each interface
+//! owns a list of addresses, its directly connected networks are added to the
+//! routing table whenever the list changes.
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use device_core::{Medium, NetDevice};
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
+use spin::Lazy;
+use systype::{SysError, SysResult};
+
+use crate::{
+    InterfaceWrapper, Mutex,
+    route::{self, ROUTE_TABLE, RouteEntry},
+    slaac,
+};
+
+// Interface flags, defined in <net/if.h>
+pub const IFF_UP: u32 = 0x1;
+pub const IFF_BROADCAST: u32 = 0x2;
+pub const IFF_LOOPBACK: u32 = 0x8;
+pub const IFF_POINTOPOINT: u32 = 0x10;
+pub const IFF_RUNNING: u32 = 0x40;
+pub const IFF_NOARP: u32 = 0x80;
+pub const IFF_MULTICAST: u32 = 0x1000;
+
+/// Index of the loopback interface.
+pub const LOOPBACK_IFINDEX: usize = 1;
+
+static INTERFACES: Lazy<Mutex<Vec<Arc<InterfaceWrapper>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static NEXT_IFINDEX: AtomicUsize = AtomicUsize::new(LOOPBACK_IFINDEX);
+
+/// Packet counters of a network interface.
+#[derive(Default)]
+pub struct NetStats {
+    pub rx_packets: AtomicUsize,
+    pub rx_bytes: AtomicUsize,
+    pub rx_errors: AtomicUsize,
+    pub rx_dropped: AtomicUsize,
+    pub tx_packets: AtomicUsize,
+    pub tx_bytes: AtomicUsize,
+    pub tx_errors: AtomicUsize,
+    pub tx_dropped: AtomicUsize,
+}
+
+impl NetStats {
+    pub(crate) fn on_receive(&self, len: usize) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_transmit(&self, len: usize) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(len, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of an interface, as reported to user space.
+#[derive(Clone)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub index: usize,
+    pub flags: u32,
+    pub mtu: usize,
+    /// `ARPHRD_*` type of the hardware address.
+    pub hatype: u16,
+    pub ether_addr: [u8; 6],
+    pub ip_addrs: Vec<IpCidr>,
+    pub stats: Arc<NetStats>,
+}
+
+impl InterfaceInfo {
+    /// First IPv4 address of the interface.
+    pub fn ipv4_addr(&self) -> Option<Ipv4Cidr> {
+        self.ip_addrs.iter().find_map(|cidr| match cidr {
+            IpCidr::Ipv4(v4) => Some(*v4),
+            _ => None,
+        })
+    }
+}
+
+pub(crate) fn register(name: &str, dev: Box<dyn NetDevice>, flags: u32) -> Arc<InterfaceWrapper> {
+    let index = NEXT_IFINDEX.fetch_add(1, Ordering::Relaxed);
+    let iface = Arc::new(InterfaceWrapper::new(name.to_string(), index, dev, flags));
+    INTERFACES.lock().push(iface.clone());
+    iface
+}
+
+pub(crate) fn all() -> Vec<Arc<InterfaceWrapper>> {
+    INTERFACES.lock().clone()
+}
+
+pub(crate) fn get(ifindex: usize) -> Option<Arc<InterfaceWrapper>> {
+    INTERFACES
+        .lock()
+        .iter()
+        .find(|iface| iface.index == ifindex)
+        .cloned()
+}
+
+pub(crate) fn get_by_name(name: &str) -> Option<Arc<InterfaceWrapper>> {
+    INTERFACES
+        .lock()
+        .iter()
+        .find(|iface| iface.name == name)
+        .cloned()
+}
+
+/// Interface the packet to `dst` should be sent through.
+pub(crate) fn route_iface(dst: IpAddress) -> Option<Arc<InterfaceWrapper>> {
+    route::lookup(dst).and_then(|route| get(route.ifindex))
+}
+
+/// Source address of packets sent to `dst`, i.e. `dst` itself for a local
+/// destination, or an address of the output interface of the same family,
+/// link-local only if `dst` is.
+pub(crate) fn source_addr(dst: IpAddress) -> Option<IpAddress> {
+    let route = route::lookup(dst)?;
+    if route.ifindex == LOOPBACK_IFINDEX {
+        return Some(dst);
+    }
+    let iface = get(route.ifindex)?;
+    let addrs = iface.addrs.lock();
+    let same_family = addrs
+        .iter()
+        .map(|cidr| cidr.address())
+        .filter(|addr| addr.version() == dst.version());
+    same_family
+        .clone()
+        .find(|addr| match (addr, dst) {
+            (IpAddress::Ipv6(addr), IpAddress::Ipv6(dst)) => {
+                addr.is_link_local() == dst.is_link_local()
+            }
+            _ => true,
+        })
+        .or_else(|| same_family.clone().next())
+}
+
+/// Interface to join multicast group `group` on: interface `ifindex` if not
+/// 0, else the one owning `local` if given, else the one `group` is routed
+/// to, falling back to the first interface other than loopback.
+fn multicast_iface(
+    group: IpAddress,
+    local: Option<IpAddress>,
+    ifindex: usize,
+) -> SysResult<Arc<InterfaceWrapper>> {
+    if ifindex != 0 {
+        return get(ifindex).ok_or(SysError::ENODEV);
+    }
+    if let Some(local) = local.filter(|local| !local.is_unspecified()) {
+        return all()
+            .into_iter()
+            .find(|iface| {
+                iface
+                    .addrs
+                    .lock()
+                    .iter()
+                    .any(|cidr| cidr.address() == local)
+            })
+            .ok_or(SysError::EADDRNOTAVAIL);
+    }
+    route_iface(group)
+        .or_else(|| {
+            all()
+                .into_iter()
+                .find(|iface| iface.index != LOOPBACK_IFINDEX)
+        })
+        .ok_or(SysError::ENODEV)
+}
+
+/// Join multicast group `group`, see [`multicast_iface`] for how the
+/// interface is chosen. Returns the index of the interface.
+pub fn join_multicast_group(
+    group: IpAddress,
+    local: Option<IpAddress>,
+    ifindex: usize,
+) -> SysResult<usize> {
+    if !group.is_multicast() {
+        return Err(SysError::EINVAL);
+    }
+    let iface = multicast_iface(group, local, ifindex)?;
+    iface.join_multicast_group(group)?;
+    Ok(iface.index)
+}
+
+/// Leave multicast group `group` joined on interface `ifindex`.
+pub fn leave_multicast_group(group: IpAddress, ifindex: usize) -> SysResult<()> {
+    get(ifindex)
+        .ok_or(SysError::ENODEV)?
+        .leave_multicast_group(group)
+}
+
+/// Whether `addr` is the limited broadcast address, or the broadcast address
+/// of a network an interface is attached to.
+pub fn is_broadcast_addr(addr: IpAddress) -> bool {
+    let IpAddress::Ipv4(v4) = addr else {
+        return false;
+    };
+    v4.is_broadcast()
+        || INTERFACES.lock().iter().any(|iface| {
+            iface.addrs.lock().iter().any(|cidr| match cidr {
+                IpCidr::Ipv4(cidr) => cidr.broadcast() == Some(v4),
+                _ => false,
+            })
+        })
+}
+
+/// Whether `addr` is owned by one of the interfaces.
+pub fn is_local_addr(addr: IpAddress) -> bool {
+    INTERFACES
+        .lock()
+        .iter()
+        .any(|iface| iface.addrs.lock().iter().any(|cidr| cidr.address() == addr))
+}
+
+/// Program the routes of interface `ifindex` into smoltcp.
+pub(crate) fn sync_routes(ifindex: usize) {
+    let Some(iface) = get(ifindex) else {
+        return;
+    };
+    let routes = ROUTE_TABLE.lock().smoltcp_routes(ifindex);
+    iface.iface.lock().routes_mut().update(|storage| {
+        storage.clear();
+        for route in routes {
+            if storage.push(route).is_err() {
+                log::warn!("[sync_routes] {}: too many routes", iface.name);
+                break;
+            }
+        }
+    });
+}
+
+/// Replace the address list of `iface`.
+pub(crate) fn set_ip_addrs(iface: &InterfaceWrapper, addrs: Vec<IpCidr>) {
+    *iface.addrs.lock() = addrs.clone();
+    {
+        let mut table = ROUTE_TABLE.lock();
+        table.remove_connected(iface.index);
+        if iface.index != LOOPBACK_IFINDEX {
+            for cidr in addrs {
+                let _ = table.add(RouteEntry {
+                    dest: cidr,
+                    gateway: None,
+                    ifindex: iface.index,
+                    metric: 0,
+                });
+            }
+        }
+    }
+    sync_ip_addrs();
+}
+
+/// Push address lists into smoltcp. Addresses of other interfaces are also
+/// given to the loopback interface, so that packets sent to them are accepted
+/// there.
+fn sync_ip_addrs() {
+    let ifaces = all();
+    let local: Vec<IpCidr> = ifaces
+        .iter()
+        .filter(|iface| iface.index != LOOPBACK_IFINDEX)
+        .flat_map(|iface| iface.addrs.lock().clone())
+        .map(|cidr| match cidr {
+            IpCidr::Ipv4(v4) => IpCidr::new(v4.address().into(), 32),
+            IpCidr::Ipv6(v6) => IpCidr::new(v6.address().into(), 128),
+        })
+        .collect();
+    for iface in ifaces {
+        let mut addrs = iface.addrs.lock().clone();
+        if iface.index == LOOPBACK_IFINDEX {
+            addrs.extend(local.iter().copied());
+        }
+        iface.iface.lock().update_ip_addrs(|ip_addrs| {
+            ip_addrs.clear();
+            for cidr in addrs {
+                if ip_addrs.push(cidr).is_err() {
+                    log::warn!("[sync_ip_addrs] {}: too many addresses", iface.name);
+                    break;
+                }
+            }
+        });
+    }
+}
+
+pub fn interfaces() -> Vec<InterfaceInfo> {
+    all().iter().map(|iface| iface.info()).collect()
+}
+
+/// Register a virtual device created at run time, e.g. a TUN/TAP device.
+/// Ethernet devices get an IPv6 link-local address. Returns the index of the
+/// new interface.
+pub fn register_interface(name: &str, dev: Box<dyn NetDevice>, flags: u32) -> SysResult<usize> {
+    if get_by_name(name).is_some() {
+        return Err(SysError::EEXIST);
+    }
+    let iface = register(name, dev, flags);
+    if iface.medium() == Medium::Ethernet {
+        iface.setup_ip_addr(vec![slaac::link_local_cidr(iface.ethernet_address())]);
+    }
+    log::info!("[register_interface] {name} index {}", iface.index);
+    Ok(iface.index)
+}
+
+/// Remove interface `ifindex` with its addresses and routes.
+pub fn unregister_interface(ifindex: usize) -> SysResult<()> {
+    if ifindex == LOOPBACK_IFINDEX {
+        return Err(SysError::EPERM);
+    }
+    let mut interfaces = INTERFACES.lock();
+    let pos = interfaces
+        .iter()
+        .position(|iface| iface.index == ifindex)
+        .ok_or(SysError::ENODEV)?;
+    let iface = interfaces.remove(pos);
+    drop(interfaces);
+    ROUTE_TABLE.lock().remove_interface(ifindex);
+    sync_ip_addrs();
+    log::info!("[unregister_interface] {} index {ifindex}", iface.name);
+    Ok(())
+}
+
+pub fn interface_by_name(name: &str) -> Option<InterfaceInfo> {
+    get_by_name(name).map(|iface| iface.info())
+}
+
+pub fn interface_by_index(ifindex: usize) -> Option<InterfaceInfo> {
+    get(ifindex).map(|iface| iface.info())
+}
+
+pub fn set_interface_flags(name: &str, flags: u32) -> SysResult<()> {
+    let iface = get_by_name(name).ok_or(SysError::ENODEV)?;
+    // Only IFF_UP can be changed, the rest are decided by the device.
+    let old = iface.flags();
+    iface.set_flags((old & !IFF_UP) | (flags & IFF_UP));
+    Ok(())
+}
+
+/// Set the primary IPv4 address of interface `name`, keeping its prefix.
+pub fn set_interface_ipv4_addr(name: &str, addr: Ipv4Address) -> SysResult<()> {
+    let iface = get_by_name(name).ok_or(SysError::ENODEV)?;
+    let mut addrs = iface.addrs.lock().clone();
+    match addrs
+        .iter_mut()
+        .find(|cidr| matches!(cidr, IpCidr::Ipv4(_)))
+    {
+        Some(IpCidr::Ipv4(v4)) => *v4 = Ipv4Cidr::new(addr, v4.prefix_len()),
+        _ => addrs.insert(
+            0,
+            IpCidr::Ipv4(Ipv4Cidr::new(addr, default_prefix_len(addr))),
+        ),
+    }
+    set_ip_addrs(&iface, addrs);
+    Ok(())
+}
+
+/// Set the netmask of the primary IPv4 address of interface `name`.
+pub fn set_interface_ipv4_netmask(name: &str, netmask: Ipv4Address) -> SysResult<()> {
+    let iface = get_by_name(name).ok_or(SysError::ENODEV)?;
+    let mut addrs = iface.addrs.lock().clone();
+    let Some(IpCidr::Ipv4(v4)) = addrs
+        .iter_mut()
+        .find(|cidr| matches!(cidr, IpCidr::Ipv4(_)))
+    else {
+        return Err(SysError::EADDRNOTAVAIL);
+    };
+    *v4 = Ipv4Cidr::from_netmask(v4.address(), netmask).map_err(|_| SysError::EINVAL)?;
+    set_ip_addrs(&iface, addrs);
+    Ok(())
+}
+
+/// Classful prefix length, used when an address is given without a netmask.
+fn default_prefix_len(addr: Ipv4Address) -> u8 {
+    match addr.0[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        _ => 24,
+    }
+}
@@ -4,29 +4,63 @@
 #![no_main]
 
 extern crate alloc;
-use alloc::{boxed::Box, vec, vec::Vec};
-use core::{cell::RefCell, future::Future, ops::DerefMut, panic, time::Duration};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    cell::{Cell, RefCell},
+    future::Future,
+    ops::DerefMut,
+    panic,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
 
-use arch::time::{get_time_duration, get_time_us};
+use arch::time::get_time_us;
 use crate_interface::call_interface;
 use device_core::{NetBufPtrOps, NetDevice, error::DevError};
+use iface::{
+    IFF_BROADCAST, IFF_LOOPBACK, IFF_MULTICAST, IFF_RUNNING, IFF_UP, InterfaceInfo,
+    LOOPBACK_IFINDEX, NetStats,
+};
 use listen_table::*;
 use log::*;
-pub use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv6Address};
+use route::RouteEntry;
+pub use smoltcp::wire::{
+    IpAddress, IpCidr, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv4Cidr, Ipv6Address,
+};
 use smoltcp::{
-    iface::{Config, Interface, SocketHandle, SocketSet},
+    iface::{Config, Interface, MulticastError, SocketHandle, SocketSet},
     phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken},
     socket::{self, AnySocket},
     time::{Duration as SmolDuration, Instant as SmolInstant},
-    wire::{EthernetAddress, HardwareAddress, IpCidr},
+    wire::{ETHERNET_HEADER_LEN, EthernetAddress, HardwareAddress, IpProtocol, IpVersion},
 };
-use spin::{Lazy, Once};
+use spin::Lazy;
 use sync::mutex::SpinNoIrqLock;
-use timer::{TIMER_MANAGER, Timer, TimerEvent};
+use systype::{SysError, SysResult};
 pub mod addr;
 pub mod bench;
+pub mod bpf;
+pub mod dhcp;
+pub mod dns;
+pub mod icmp;
+pub mod iface;
 pub mod listen_table;
+pub mod netfilter;
+pub mod netstat;
+pub mod packet;
+pub mod pcap;
 pub mod portmap;
+pub mod raw;
+pub mod route;
+mod slaac;
+pub mod softirq;
 pub mod tcp;
 pub mod udp;
 
@@ -45,6 +79,8 @@ macro_rules! env_or_default {
 const IP: &str = env_or_default!("Phoenix_IP");
 const GATEWAY: &str = env_or_default!("Phoenix_GW");
 const DNS_SEVER: &str = "8.8.8.8";
+/// Configure eth0 by DHCP instead of `Phoenix_IP`/`Phoenix_GW` if set to `y`.
+const DHCP: &str = env_or_default!("Phoenix_DHCP");
 const IP_PREFIX: u8 = 24;
 
 const STANDARD_MTU: usize = 1500;
@@ -53,13 +89,25 @@ const RANDOM_SEED: u64 = 0xA2CE_05A2_CE05_A2CE;
 
 pub const TCP_RX_BUF_LEN: usize = 64 * 1024;
 pub const TCP_TX_BUF_LEN: usize = 64 * 1024;
-const UDP_RX_BUF_LEN: usize = 64 * 1024;
-const UDP_TX_BUF_LEN: usize = 64 * 1024;
-const LISTEN_QUEUE_SIZE: usize = 512;
+pub const UDP_RX_BUF_LEN: usize = 64 * 1024;
+pub const UDP_TX_BUF_LEN: usize = 64 * 1024;
+const RAW_RX_BUF_LEN: usize = 64 * 1024;
+const RAW_TX_BUF_LEN: usize = 64 * 1024;
+const ICMP_RX_BUF_LEN: usize = 16 * 1024;
+const ICMP_TX_BUF_LEN: usize = 16 * 1024;
+/// Hop limit of packets sent when a socket does not set one.
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+/// Bounds of socket buffer sizes set by `SO_RCVBUF` and `SO_SNDBUF`.
+pub const SOCK_BUF_MIN: usize = 4 * 1024;
+pub const SOCK_BUF_MAX: usize = 4 * 1024 * 1024;
 
 static LISTEN_TABLE: Lazy<ListenTable> = Lazy::new(ListenTable::new);
 static SOCKET_SET: Lazy<SocketSetWrapper> = Lazy::new(SocketSetWrapper::new);
-static ETH0: Once<InterfaceWrapper> = Once::new();
+/// DNS servers written to `/proc/net/dns`, which take precedence over the ones
+/// given by DHCP.
+static DNS_SERVERS: Mutex<Vec<IpAddress>> = Mutex::new(Vec::new());
+/// DNS servers given by the DHCP lease of each interface, by index.
+static DHCP_DNS_SERVERS: Mutex<BTreeMap<usize, Vec<IpAddress>>> = Mutex::new(BTreeMap::new());
 
 /// SocketSet is a collection of sockets that contain multiple different types
 /// of sockets (such as TCP, UDP, ICMP, etc.). It provides a mechanism to manage
@@ -73,20 +121,50 @@ struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 struct DeviceWrapper {
     /// The inner network device wrapped in a `RefCell` for interior mutability.
     inner: RefCell<Box<dyn NetDevice>>,
+    /// Index of the owning interface.
+    ifindex: usize,
+    /// Packet counters shared with the owning interface.
+    stats: Arc<NetStats>,
+    /// Whether packets for link sockets should be held back from the shared
+    /// socket set.
+    divert_link: bool,
+    /// Received packets held back for link sockets.
+    link_rx: VecDeque<Box<dyn NetBufPtrOps>>,
+    /// Frames received as if from the device, e.g. the netfilter replies to
+    /// packets of local sockets.
+    local_rx: VecDeque<Vec<u8>>,
+    /// Set while the interface is polled with its link sockets, only packets
+    /// in `link_rx` are received then.
+    link_mode: bool,
 }
 
 /// A wrapper for network interfaces, containing device and interface details
 /// and providing thread-safe access via `Mutex`.
-struct InterfaceWrapper {
+pub(crate) struct InterfaceWrapper {
     /// The name of the network interface.
-    name: &'static str,
+    name: String,
+    /// The interface index, starting from 1.
+    index: usize,
     /// The Ethernet address of the network interface.
     ether_addr: EthernetAddress,
+    /// Max payload size of a link layer frame.
+    mtu: usize,
+    /// `IFF_*` flags of the interface.
+    flags: AtomicU32,
+    /// Addresses assigned to this interface.
+    addrs: Mutex<Vec<IpCidr>>,
+    stats: Arc<NetStats>,
     /// The device wrapper protected by a `Mutex` to ensure thread-safe access.
     dev: Mutex<DeviceWrapper>,
     /// The network interface protected by a `Mutex` to ensure thread-safe
     /// access.
     iface: Mutex<Interface>,
+    /// Sockets bound to this interface only, e.g. the DHCP client. They are
+    /// kept out of `SOCKET_SET` so that no other interface dispatches them.
+    link_sockets: Mutex<SocketSet<'static>>,
+    /// Multicast groups joined on this interface, with the number of sockets
+    /// in each group.
+    multicast_groups: Mutex<Vec<(IpAddress, usize)>>,
 }
 
 impl<'a> SocketSetWrapper<'a> {
@@ -96,28 +174,69 @@ impl<'a> SocketSetWrapper<'a> {
 
     /// return a `tcp::Socket` defined in `smoltcp`
     pub fn new_tcp_socket() -> socket::tcp::Socket<'a> {
-        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
-        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
+        Self::new_tcp_socket_with_buf(TCP_RX_BUF_LEN, TCP_TX_BUF_LEN)
+    }
+
+    /// return a `tcp::Socket` with buffers of the given sizes
+    pub fn new_tcp_socket_with_buf(rx_len: usize, tx_len: usize) -> socket::tcp::Socket<'a> {
+        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; rx_len]);
+        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; tx_len]);
         socket::tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
     }
 
     /// return a `udp::Socket` defined in `smoltcp`
     pub fn new_udp_socket() -> socket::udp::Socket<'a> {
+        Self::new_udp_socket_with_buf(UDP_RX_BUF_LEN, UDP_TX_BUF_LEN)
+    }
+
+    /// return a `udp::Socket` with buffers of the given sizes, the number of
+    /// datagrams they hold grows with the size
+    pub fn new_udp_socket_with_buf(rx_len: usize, tx_len: usize) -> socket::udp::Socket<'a> {
+        let metadata_count = |len: usize| (len * 8 / UDP_RX_BUF_LEN).max(8);
         let udp_rx_buffer = socket::udp::PacketBuffer::new(
-            vec![socket::udp::PacketMetadata::EMPTY; 8],
-            vec![0; UDP_RX_BUF_LEN],
+            vec![socket::udp::PacketMetadata::EMPTY; metadata_count(rx_len)],
+            vec![0; rx_len],
         );
         let udp_tx_buffer = socket::udp::PacketBuffer::new(
-            vec![socket::udp::PacketMetadata::EMPTY; 8],
-            vec![0; UDP_TX_BUF_LEN],
+            vec![socket::udp::PacketMetadata::EMPTY; metadata_count(tx_len)],
+            vec![0; tx_len],
         );
         socket::udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
     }
 
-    #[allow(dead_code)]
+    /// return a `raw::Socket` defined in `smoltcp`, which receives every
+    /// packet of `ip_protocol`
+    pub fn new_raw_socket(
+        ip_version: IpVersion,
+        ip_protocol: IpProtocol,
+    ) -> socket::raw::Socket<'a> {
+        let raw_rx_buffer = socket::raw::PacketBuffer::new(
+            vec![socket::raw::PacketMetadata::EMPTY; 16],
+            vec![0; RAW_RX_BUF_LEN],
+        );
+        let raw_tx_buffer = socket::raw::PacketBuffer::new(
+            vec![socket::raw::PacketMetadata::EMPTY; 16],
+            vec![0; RAW_TX_BUF_LEN],
+        );
+        socket::raw::Socket::new(ip_version, ip_protocol, raw_rx_buffer, raw_tx_buffer)
+    }
+
+    /// return an `icmp::Socket` defined in `smoltcp`
+    pub fn new_icmp_socket() -> socket::icmp::Socket<'a> {
+        let icmp_rx_buffer = socket::icmp::PacketBuffer::new(
+            vec![socket::icmp::PacketMetadata::EMPTY; 16],
+            vec![0; ICMP_RX_BUF_LEN],
+        );
+        let icmp_tx_buffer = socket::icmp::PacketBuffer::new(
+            vec![socket::icmp::PacketMetadata::EMPTY; 16],
+            vec![0; ICMP_TX_BUF_LEN],
+        );
+        socket::icmp::Socket::new(icmp_rx_buffer, icmp_tx_buffer)
+    }
+
+    /// A DNS socket without servers, they are given by `update_servers`.
     pub fn new_dns_socket() -> socket::dns::Socket<'a> {
-        let server_addr = DNS_SEVER.parse().expect("invalid DNS server address");
-        socket::dns::Socket::new(&[server_addr], vec![])
+        socket::dns::Socket::new(&[], vec![])
     }
 
     /// return `SocketHandle`, which is Similar to file descriptors in the
@@ -176,12 +295,43 @@ impl<'a> SocketSetWrapper<'a> {
         f(socket)
     }
 
+    /// Poll interfaces in index order. The loopback interface comes first, so
+    /// local traffic never leaks to a device through its default route.
     pub fn poll_interfaces(&self) -> smoltcp::time::Instant {
-        ETH0.get().unwrap().poll(&self.0)
+        for iface in iface::all().iter().filter(|iface| iface.is_up()) {
+            iface.poll(&self.0);
+        }
+        slaac::handle_router_adverts();
+        netfilter::flush();
+        InterfaceWrapper::current_time()
     }
 
+    /// Schedule the next poll of the softirq, for the earliest deadline of
+    /// the interfaces.
     pub fn check_poll(&self, timestamp: SmolInstant) {
-        ETH0.get().unwrap().check_poll(timestamp, &self.0)
+        let delay = iface::all()
+            .iter()
+            .filter(|iface| iface.is_up())
+            .filter_map(|iface| iface.poll_delay(timestamp, &self.0))
+            .min();
+        match delay {
+            Some(SmolDuration::ZERO) => softirq::raise(),
+            Some(delay) => softirq::arm(
+                InterfaceWrapper::ins_to_duration(timestamp)
+                    + InterfaceWrapper::dur_to_duration(delay),
+            ),
+            None => {}
+        }
+    }
+
+    /// Whether a TCP connection still uses local port `port`.
+    pub fn has_tcp_connection(&self, port: u16) -> bool {
+        self.0.lock().iter().any(|(_, socket)| {
+            socket::tcp::Socket::downcast(socket).is_some_and(|tcp| {
+                tcp.state() != socket::tcp::State::Listen
+                    && tcp.local_endpoint().is_some_and(|local| local.port == port)
+            })
+        })
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -191,7 +341,13 @@ impl<'a> SocketSetWrapper<'a> {
 }
 
 impl InterfaceWrapper {
-    fn new(name: &'static str, dev: Box<dyn NetDevice>, ether_addr: EthernetAddress) -> Self {
+    fn new(name: String, index: usize, dev: Box<dyn NetDevice>, flags: u32) -> Self {
+        let ether_addr = EthernetAddress(dev.mac_address().0);
+        let caps = dev.capabilities();
+        let mtu = match caps.medium {
+            Medium::Ethernet => caps.max_transmission_unit - ETHERNET_HEADER_LEN,
+            Medium::Ip => caps.max_transmission_unit,
+        };
         // let mut config = Config::new(HardwareAddress::Ethernet(ether_addr));
         // let mut config = if ether_addr == EthernetAddress([0, 0, 0, 0, 0, 0]) {
         //     log::error!("[InterfaceWrapper] use HardwareAddress::Ip");
@@ -205,13 +361,21 @@ impl InterfaceWrapper {
         };
         config.random_seed = RANDOM_SEED;
 
-        let mut dev = DeviceWrapper::new(dev);
+        let stats = Arc::new(NetStats::default());
+        let mut dev = DeviceWrapper::new(dev, index, stats.clone());
         let iface = Mutex::new(Interface::new(config, &mut dev, Self::current_time()));
         Self {
             name,
+            index,
             ether_addr,
+            mtu,
+            flags: AtomicU32::new(flags),
+            addrs: Mutex::new(Vec::new()),
+            stats,
             dev: Mutex::new(dev),
             iface,
+            link_sockets: Mutex::new(SocketSet::new(vec![])),
+            multicast_groups: Mutex::new(Vec::new()),
         }
     }
 
@@ -228,24 +392,58 @@ impl InterfaceWrapper {
     }
 
     pub fn name(&self) -> &str {
-        self.name
+        &self.name
     }
 
     pub fn ethernet_address(&self) -> EthernetAddress {
         self.ether_addr
     }
 
+    pub fn flags(&self) -> u32 {
+        self.flags.load(Ordering::Relaxed)
+    }
+
+    pub fn set_flags(&self, flags: u32) {
+        self.flags.store(flags, Ordering::Relaxed);
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.flags() & IFF_UP != 0
+    }
+
+    pub fn info(&self) -> InterfaceInfo {
+        InterfaceInfo {
+            name: self.name.clone(),
+            index: self.index,
+            flags: self.flags(),
+            mtu: self.mtu,
+            hatype: packet::LinkType::of(self.index, self.medium()).hatype(),
+            ether_addr: self.ether_addr.0,
+            ip_addrs: self.addrs.lock().clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
     pub fn setup_ip_addr(&self, ips: Vec<IpCidr>) {
-        let mut iface = self.iface.lock();
-        iface.update_ip_addrs(|ip_addrs| ip_addrs.extend(ips));
+        let mut addrs = self.addrs.lock().clone();
+        addrs.extend(ips);
+        iface::set_ip_addrs(self, addrs);
     }
 
     pub fn setup_gateway(&self, gateway: IpAddress) {
-        let mut iface = self.iface.lock();
-        match gateway {
-            IpAddress::Ipv4(v4) => iface.routes_mut().add_default_ipv4_route(v4).unwrap(),
-            IpAddress::Ipv6(_) => unimplemented!(),
+        let dest = match gateway {
+            IpAddress::Ipv4(_) => IpCidr::new(IpAddress::v4(0, 0, 0, 0), 0),
+            IpAddress::Ipv6(_) => IpCidr::new(IpAddress::Ipv6(Ipv6Address::UNSPECIFIED), 0),
+        };
+        let route = RouteEntry {
+            dest,
+            gateway: Some(gateway),
+            ifindex: self.index,
+            metric: 0,
         };
+        if let Err(e) = route::add_route(route) {
+            warn!("[InterfaceWrapper::setup_gateway] {}: {e:?}", self.name);
+        }
     }
 
     /// handling the sending and receiving of network packets and updating the
@@ -255,90 +453,115 @@ impl InterfaceWrapper {
     pub fn poll(&self, sockets: &Mutex<SocketSet>) -> SmolInstant {
         let mut dev = self.dev.lock();
         let mut iface = self.iface.lock();
-        let mut sockets = sockets.lock();
         let timestamp = Self::current_time();
-        let result = iface.poll(timestamp, dev.deref_mut(), &mut sockets);
+        let result = iface.poll(timestamp, dev.deref_mut(), &mut sockets.lock());
         log::warn!("[net::InterfaceWrapper::poll] does something have been changed? {result:?}");
+        if dev.divert_link {
+            dev.link_mode = true;
+            iface.poll(timestamp, dev.deref_mut(), &mut self.link_sockets.lock());
+            dev.link_mode = false;
+        }
         timestamp
     }
 
-    // pub fn poll_at(&self, sockets: &Mutex<SocketSet>) {
-    //     let mut iface = self.iface.lock();
-    //     let mut sockets = sockets.lock();
-    //     let timestamp = Self::current_time();
-    //     if let Some(next_poll_time) = iface
-    //         .poll_at(timestamp, &mut sockets)
-    //         .map(Self::to_duration)
-    //     {
-    //         if next_poll_time.is_zero() || next_poll_time <= get_time_duration()
-    // {             iface.poll(
-    //                 Self::current_time(),
-    //                 self.dev.lock().deref_mut(),
-    //                 &mut sockets,
-    //             );
-    //             error!("poll");
-    //         } else {
-    //             error!(
-    //                 "add timer expired {}, now {}",
-    //                 next_poll_time.as_micros(),
-    //                 get_time_duration().as_micros(),
-    //             );
-    //             let timer = Timer::new(next_poll_time, Box::new(PollTimer {}));
-    //             TIMER_MANAGER.add_timer(timer);
-    //         }
-    //     } else {
-    //         error!("don't need to poll");
-    //     }
-    // }
-
-    pub fn check_poll(&self, timestamp: SmolInstant, sockets: &Mutex<SocketSet>) {
-        let mut iface = self.iface.lock();
-        let mut sockets = sockets.lock();
-        match iface
-            .poll_delay(timestamp, &mut sockets)
-            .map(Self::dur_to_duration)
-        {
-            Some(Duration::ZERO) => {
-                iface.poll(
-                    Self::current_time(),
-                    self.dev.lock().deref_mut(),
-                    &mut sockets,
-                );
-            }
-            Some(delay) => {
-                let next_poll = delay + Self::ins_to_duration(timestamp);
-                let current = get_time_duration();
-                if next_poll < current {
-                    iface.poll(
-                        Self::current_time(),
-                        self.dev.lock().deref_mut(),
-                        &mut sockets,
-                    );
-                } else {
-                    let timer = Timer::new(next_poll, Box::new(PollTimer {}));
-                    TIMER_MANAGER.add_timer(timer);
+    /// Send `frame` through the device as is, bypassing smoltcp.
+    pub(crate) fn transmit_frame(&self, frame: &[u8]) -> SysResult<()> {
+        let mut dev = self.dev.lock();
+        let mut tx =
+            Device::transmit(dev.deref_mut(), Self::current_time()).ok_or(SysError::ENOBUFS)?;
+        tx.3 = false;
+        tx.consume(frame.len(), |buf| buf.copy_from_slice(frame));
+        // No interrupt tells that the loopback device has a packet to receive.
+        if self.index == LOOPBACK_IFINDEX {
+            softirq::raise();
+        }
+        Ok(())
+    }
+
+    /// Queue `frame` as if the device received it, it is handled at the next
+    /// poll.
+    pub(crate) fn receive_frame(&self, frame: Vec<u8>) {
+        self.dev.lock().local_rx.push_back(frame);
+        softirq::raise();
+    }
+
+    /// Add a socket only dispatched on this interface. Received packets that
+    /// `is_link_packet` accepts are delivered to link sockets only.
+    pub(crate) fn add_link_socket<T: AnySocket<'static>>(&self, socket: T) -> SocketHandle {
+        let handle = self.link_sockets.lock().add(socket);
+        self.dev.lock().divert_link = true;
+        handle
+    }
+
+    pub(crate) fn with_link_socket_mut<T: AnySocket<'static>, R, F>(
+        &self,
+        handle: SocketHandle,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        f(self.link_sockets.lock().get_mut(handle))
+    }
+
+    /// Join multicast group `addr` on behalf of a socket. The group is left
+    /// when the last socket leaves it.
+    pub(crate) fn join_multicast_group(&self, addr: IpAddress) -> SysResult<()> {
+        let mut groups = self.multicast_groups.lock();
+        if let Some((_, count)) = groups.iter_mut().find(|(group, _)| *group == addr) {
+            *count += 1;
+            return Ok(());
+        }
+        let mut dev = self.dev.lock();
+        self.iface
+            .lock()
+            .join_multicast_group(dev.deref_mut(), addr, Self::current_time())
+            .map_err(|e| {
+                warn!("[join_multicast_group] {}: {addr} {e:?}", self.name);
+                match e {
+                    MulticastError::GroupTableFull => SysError::ENOMEM,
+                    _ => SysError::EINVAL,
                 }
-            }
-            None => {
-                let timer = Timer::new(
-                    get_time_duration() + Duration::from_millis(2),
-                    Box::new(PollTimer {}),
-                );
-                TIMER_MANAGER.add_timer(timer);
-            }
+            })?;
+        groups.push((addr, 1));
+        Ok(())
+    }
+
+    pub(crate) fn leave_multicast_group(&self, addr: IpAddress) -> SysResult<()> {
+        let mut groups = self.multicast_groups.lock();
+        let idx = groups
+            .iter()
+            .position(|(group, _)| *group == addr)
+            .ok_or(SysError::EADDRNOTAVAIL)?;
+        groups[idx].1 -= 1;
+        if groups[idx].1 == 0 {
+            groups.swap_remove(idx);
+            let mut dev = self.dev.lock();
+            self.iface
+                .lock()
+                .leave_multicast_group(dev.deref_mut(), addr, Self::current_time())
+                .map_err(|_| SysError::EINVAL)?;
         }
+        Ok(())
     }
 
-    // pub fn auto_poll(&self, sockets: &Mutex<SocketSet>) {
-    //     if let Some(next_poll) = self.poll_at(sockets) {
-    //         log::debug!(
-    //             "current time is {:?}, we should poll next time is {}",
-    //             get_time_duration().as_micros(),
-    //             next_poll.as_micros()
-    //         );
+    pub(crate) fn medium(&self) -> Medium {
+        self.dev.lock().capabilities().medium
+    }
 
-    //     }
-    // }
+    /// How long until the interface needs to be polled again, `None` if it
+    /// waits for an incoming packet only.
+    pub fn poll_delay(
+        &self,
+        timestamp: SmolInstant,
+        sockets: &Mutex<SocketSet>,
+    ) -> Option<SmolDuration> {
+        let mut iface = self.iface.lock();
+        let delay = iface.poll_delay(timestamp, &mut sockets.lock());
+        // Link sockets have deadlines too, e.g. DHCP retransmissions.
+        let link_delay = iface.poll_delay(timestamp, &mut self.link_sockets.lock());
+        delay.into_iter().chain(link_delay).min()
+    }
 }
 
 pub fn check_poll(timestamp: SmolInstant) {
@@ -353,23 +576,20 @@ pub fn poll_interfaces() -> smoltcp::time::Instant {
     SOCKET_SET.poll_interfaces()
 }
 
-// pub fn auto_poll_interfaces() {
-//     SOCKET_SET.auto_poll_interfaces()
-// }
-
-struct PollTimer;
-
-impl TimerEvent for PollTimer {
-    fn callback(self: Box<Self>) -> Option<Timer> {
-        poll_interfaces();
-        None
-    }
-}
+// SAFETY: the held back rx buffers belong to the device, and are only accessed
+// with the device, under the lock of the owning interface.
+unsafe impl Send for DeviceWrapper {}
 
 impl DeviceWrapper {
-    fn new(inner: Box<dyn NetDevice>) -> Self {
+    fn new(inner: Box<dyn NetDevice>, ifindex: usize, stats: Arc<NetStats>) -> Self {
         Self {
             inner: RefCell::new(inner),
+            ifindex,
+            stats,
+            divert_link: false,
+            link_rx: VecDeque::new(),
+            local_rx: VecDeque::new(),
+            link_mode: false,
         }
     }
 }
@@ -397,16 +617,58 @@ impl Device for DeviceWrapper {
         if !dev.can_transmit() {
             return None;
         }
-        let rx_buf = match dev.receive() {
-            Ok(buf) => buf,
-            Err(err) => {
-                if !matches!(err, DevError::Again) {
-                    warn!("receive failed: {:?}", err);
+        if self.link_mode {
+            let rx_buf = self.link_rx.pop_front()?;
+            return Some((
+                NetRxToken(
+                    &self.inner,
+                    RxBuf::Device(rx_buf),
+                    &self.stats,
+                    self.ifindex,
+                    Cell::new(false),
+                ),
+                NetTxToken(&self.inner, &self.stats, self.ifindex, true),
+            ));
+        }
+        if let Some(frame) = self.local_rx.pop_front() {
+            return Some((
+                NetRxToken(
+                    &self.inner,
+                    RxBuf::Local(frame),
+                    &self.stats,
+                    self.ifindex,
+                    Cell::new(false),
+                ),
+                NetTxToken(&self.inner, &self.stats, self.ifindex, true),
+            ));
+        }
+        let is_ethernet = dev.capabilities().medium == Medium::Ethernet;
+        let rx_buf = loop {
+            let rx_buf = match dev.receive() {
+                Ok(buf) => buf,
+                Err(err) => {
+                    if !matches!(err, DevError::Again) {
+                        warn!("receive failed: {:?}", err);
+                    }
+                    return None;
                 }
-                return None;
+            };
+            if self.divert_link && dhcp::is_link_packet(rx_buf.packet(), is_ethernet) {
+                self.link_rx.push_back(rx_buf);
+                continue;
             }
+            break rx_buf;
         };
-        Some((NetRxToken(&self.inner, rx_buf), NetTxToken(&self.inner)))
+        Some((
+            NetRxToken(
+                &self.inner,
+                RxBuf::Device(rx_buf),
+                &self.stats,
+                self.ifindex,
+                Cell::new(false),
+            ),
+            NetTxToken(&self.inner, &self.stats, self.ifindex, true),
+        ))
     }
 
     fn transmit(&mut self, _timestamp: smoltcp::time::Instant) -> Option<Self::TxToken<'_>> {
@@ -416,7 +678,7 @@ impl Device for DeviceWrapper {
             return None;
         }
         if dev.can_transmit() {
-            Some(NetTxToken(&self.inner))
+            Some(NetTxToken(&self.inner, &self.stats, self.ifindex, true))
         } else {
             None
         }
@@ -427,14 +689,48 @@ impl Device for DeviceWrapper {
     }
 }
 
-struct NetRxToken<'a>(&'a RefCell<Box<dyn NetDevice>>, Box<dyn NetBufPtrOps>);
-struct NetTxToken<'a>(&'a RefCell<Box<dyn NetDevice>>);
+/// The last field is set by `preprocess` if the packet should be dropped
+/// without smoltcp seeing it.
+struct NetRxToken<'a>(
+    &'a RefCell<Box<dyn NetDevice>>,
+    RxBuf,
+    &'a NetStats,
+    usize,
+    Cell<bool>,
+);
+/// A received frame, in a buffer of the device or looped back by the stack.
+enum RxBuf {
+    Device(Box<dyn NetBufPtrOps>),
+    Local(Vec<u8>),
+}
+
+impl RxBuf {
+    fn packet(&self) -> &[u8] {
+        match self {
+            Self::Device(buf) => buf.packet(),
+            Self::Local(buf) => buf,
+        }
+    }
+
+    fn packet_mut(&mut self) -> &mut [u8] {
+        match self {
+            Self::Device(buf) => buf.packet_mut(),
+            Self::Local(buf) => buf,
+        }
+    }
+}
+
+/// The third field is the index of the owning interface, the last one whether
+/// the output hook of the netfilter runs on the frame.
+struct NetTxToken<'a>(&'a RefCell<Box<dyn NetDevice>>, &'a NetStats, usize, bool);
 
 impl<'a> RxToken for NetRxToken<'a> {
     fn preprocess(&self, sockets: &mut SocketSet<'_>) {
         let medium = self.0.borrow().capabilities().medium;
         let is_ethernet = medium == Medium::Ethernet;
-        snoop_tcp_packet(self.1.packet(), sockets, is_ethernet).ok();
+        let drop = netfilter::receive(self.1.packet(), medium, self.3)
+            || snoop_tcp_packet(self.1.packet(), sockets, is_ethernet, self.3).unwrap_or(false);
+        self.4.set(drop);
     }
 
     /// 此方法接收数据包，然后以原始数据包字节作为参数调用给定的闭包f。
@@ -443,13 +739,25 @@ impl<'a> RxToken for NetRxToken<'a> {
         F: FnOnce(&mut [u8]) -> R,
     {
         let mut rx_buf = self.1;
+        packet::tap(&**self.0.borrow(), self.3, rx_buf.packet(), false);
         warn!(
             "[RxToken::consume] RECV {} bytes",
-            rx_buf.packet_len(),
+            rx_buf.packet().len(),
             // rx_buf.packet()
         );
-        let result = f(rx_buf.packet_mut());
-        self.0.borrow_mut().recycle_rx_buffer(rx_buf).unwrap();
+        let result = if self.4.get() {
+            self.2.rx_dropped.fetch_add(1, Ordering::Relaxed);
+            // An empty frame is discarded by smoltcp without any response.
+            f(&mut [])
+        } else {
+            self.2.on_receive(rx_buf.packet().len());
+            let medium = self.0.borrow().capabilities().medium;
+            netstat::count_frame(rx_buf.packet(), medium, false);
+            f(rx_buf.packet_mut())
+        };
+        if let RxBuf::Device(rx_buf) = rx_buf {
+            self.0.borrow_mut().recycle_rx_buffer(rx_buf).unwrap();
+        }
         result
     }
 }
@@ -464,46 +772,100 @@ impl<'a> TxToken for NetTxToken<'a> {
         F: FnOnce(&mut [u8]) -> R,
     {
         let mut dev = self.0.borrow_mut();
-        let mut tx_buf = dev.alloc_tx_buffer(len).unwrap();
-        let ret = f(tx_buf.packet_mut());
+        let medium = dev.capabilities().medium;
+        let (tx_buf, ret) = if self.3 && netfilter::has_output_rules() {
+            // Build the frame aside, so that no device buffer is taken by a
+            // dropped one.
+            let mut frame = vec![0; len];
+            let ret = f(&mut frame);
+            if !netfilter::transmit(&frame, medium, self.2) {
+                self.1.tx_dropped.fetch_add(1, Ordering::Relaxed);
+                return ret;
+            }
+            let mut tx_buf = dev.alloc_tx_buffer(len).unwrap();
+            tx_buf.packet_mut().copy_from_slice(&frame);
+            (tx_buf, ret)
+        } else {
+            let mut tx_buf = dev.alloc_tx_buffer(len).unwrap();
+            let ret = f(tx_buf.packet_mut());
+            (tx_buf, ret)
+        };
+        packet::tap(&**dev, self.2, tx_buf.packet(), true);
+        netstat::count_frame(tx_buf.packet(), medium, true);
         warn!(
             "[TxToken::consume] SEND {} bytes",
             len,
             // tx_buf.packet()
         );
         dev.transmit(tx_buf).unwrap();
+        self.1.on_transmit(len);
         ret
     }
 }
 
+/// Look into a received packet before smoltcp handles it: TCP packets to a
+/// listening port go to the listen table, and IPv6 router advertisements
+/// received on interface `ifindex` are recorded for SLAAC.
+///
+/// Returns `true` if the packet should be dropped.
 fn snoop_tcp_packet(
     buf: &[u8],
     sockets: &mut SocketSet<'_>,
     is_ethernet: bool,
-) -> Result<(), smoltcp::wire::Error> {
-    use smoltcp::wire::{EthernetFrame, IpProtocol, Ipv4Packet, TcpPacket};
+    ifindex: usize,
+) -> Result<bool, smoltcp::wire::Error> {
+    use smoltcp::wire::{EthernetFrame, EthernetProtocol, Ipv4Packet, Ipv6Packet, TcpPacket};
 
-    // let ether_frame = EthernetFrame::new_checked(buf)?;
-    // let ipv4_packet = Ipv4Packet::new_checked(ether_frame.payload())?;
-    let ipv4_packet = if is_ethernet {
+    let ip_buf = if is_ethernet {
         let ether_frame = EthernetFrame::new_checked(buf)?;
-        Ipv4Packet::new_checked(ether_frame.payload())?
+        if !matches!(
+            ether_frame.ethertype(),
+            EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6
+        ) {
+            return Ok(false);
+        }
+        &buf[ETHERNET_HEADER_LEN..]
     } else {
-        Ipv4Packet::new_checked(buf)?
+        buf
     };
-    if ipv4_packet.next_header() == IpProtocol::Tcp {
-        let tcp_packet = TcpPacket::new_checked(ipv4_packet.payload())?;
-        let src_addr = (ipv4_packet.src_addr(), tcp_packet.src_port()).into();
-        let dst_addr = (ipv4_packet.dst_addr(), tcp_packet.dst_port()).into();
-        let is_first = tcp_packet.syn() && !tcp_packet.ack();
-        if is_first {
-            // create a socket for the first incoming TCP packet, as the later accept()
-            // returns.
-            info!("[snoop_tcp_packet] receive TCP");
-            LISTEN_TABLE.incoming_tcp_packet(src_addr, dst_addr, sockets);
+    let (src_addr, dst_addr, next_header, payload): (IpAddress, IpAddress, _, _) =
+        match ip_buf.first().map(|byte| byte >> 4) {
+            Some(4) => {
+                let ipv4_packet = Ipv4Packet::new_checked(ip_buf)?;
+                (
+                    ipv4_packet.src_addr().into(),
+                    ipv4_packet.dst_addr().into(),
+                    ipv4_packet.next_header(),
+                    ipv4_packet.payload(),
+                )
+            }
+            Some(6) => {
+                let ipv6_packet = Ipv6Packet::new_checked(ip_buf)?;
+                (
+                    ipv6_packet.src_addr().into(),
+                    ipv6_packet.dst_addr().into(),
+                    ipv6_packet.next_header(),
+                    ipv6_packet.payload(),
+                )
+            }
+            _ => return Ok(false),
+        };
+    match (next_header, src_addr) {
+        (IpProtocol::Tcp, _) => {
+            let tcp_packet = TcpPacket::new_checked(payload)?;
+            let src_addr = (src_addr, tcp_packet.src_port()).into();
+            let dst_addr = (dst_addr, tcp_packet.dst_port()).into();
+            // A socket is created for the first incoming TCP packet, as the
+            // later accept() returns.
+            let is_first = tcp_packet.syn() && !tcp_packet.ack();
+            return Ok(LISTEN_TABLE.incoming_tcp_packet(src_addr, dst_addr, is_first, sockets));
         }
+        (IpProtocol::Icmpv6, IpAddress::Ipv6(src_addr)) if is_ethernet => {
+            slaac::snoop_router_advert(ifindex, src_addr, payload);
+        }
+        _ => {}
     }
-    Ok(())
+    Ok(false)
 }
 
 /// net poll results.
@@ -516,14 +878,54 @@ pub struct NetPollState {
     pub hangup: bool,
 }
 
+bitflags::bitflags! {
+    /// Flags of `send` and `recv`, defined in <sys/socket.h>.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct MsgFlags: u32 {
+        /// Out-of-band data.
+        const OOB = 0x1;
+        /// Receive data without removing it from the queue.
+        const PEEK = 0x2;
+        const DONTROUTE = 0x4;
+        /// Ancillary data was discarded, only set by `recvmsg`.
+        const CTRUNC = 0x8;
+        /// Return the real length of a datagram even if it was truncated. Set
+        /// by `recvmsg` when a datagram was truncated.
+        const TRUNC = 0x20;
+        /// Nonblocking for this call only.
+        const DONTWAIT = 0x40;
+        const EOR = 0x80;
+        /// Block until the whole buffer is filled.
+        const WAITALL = 0x100;
+        /// Do not raise `SIGPIPE` when the peer closed a stream.
+        const NOSIGNAL = 0x4000;
+        const MORE = 0x8000;
+        /// `recvmmsg` only blocks for the first message.
+        const WAITFORONE = 0x10000;
+        const CMSG_CLOEXEC = 0x40000000;
+    }
+}
+
+/// The interface to run benchmarks on, i.e. the first device other than
+/// loopback if there is one.
+fn bench_iface() -> Arc<InterfaceWrapper> {
+    let ifaces = iface::all();
+    ifaces
+        .iter()
+        .find(|iface| iface.index != LOOPBACK_IFINDEX)
+        .or(ifaces.first())
+        .cloned()
+        .expect("network is not initialized")
+}
+
 /// Benchmark raw socket transmit bandwidth.
 pub fn bench_transmit() {
-    ETH0.get().unwrap().dev.lock().bench_transmit_bandwidth();
+    bench_iface().dev.lock().bench_transmit_bandwidth();
 }
 
 /// Benchmark raw socket receive bandwidth.
 pub fn bench_receive() {
-    ETH0.get().unwrap().dev.lock().bench_receive_bandwidth();
+    bench_iface().dev.lock().bench_receive_bandwidth();
 }
 
 #[crate_interface::def_interface]
@@ -531,6 +933,42 @@ pub trait HasSignalIf: Send + Sync {
     fn has_signal() -> bool;
 }
 
+/// Current DNS servers: the ones written to `/proc/net/dns`, else the ones
+/// given by DHCP, else `DNS_SEVER`.
+pub fn dns_servers() -> Vec<IpAddress> {
+    let servers = DNS_SERVERS.lock().clone();
+    if !servers.is_empty() {
+        return servers;
+    }
+    let mut servers: Vec<IpAddress> = Vec::new();
+    for &server in DHCP_DNS_SERVERS.lock().values().flatten() {
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+    if servers.is_empty() {
+        servers.push(DNS_SEVER.parse().expect("invalid DNS server address"));
+    }
+    servers
+}
+
+/// Replace the DNS servers written to `/proc/net/dns`, none leaves the ones
+/// given by DHCP in use. Queries started afterwards go to them.
+pub fn set_dns_servers(servers: &[IpAddress]) {
+    *DNS_SERVERS.lock() = servers.to_vec();
+}
+
+/// Replace the DNS servers given by the DHCP lease of interface `ifindex`,
+/// none when the lease is lost.
+pub(crate) fn set_dhcp_dns_servers(ifindex: usize, servers: &[IpAddress]) {
+    let mut dhcp_servers = DHCP_DNS_SERVERS.lock();
+    if servers.is_empty() {
+        dhcp_servers.remove(&ifindex);
+    } else {
+        dhcp_servers.insert(ifindex, servers.to_vec());
+    }
+}
+
 pub(crate) fn has_signal() -> bool {
     call_interface!(HasSignalIf::has_signal())
 }
@@ -547,33 +985,43 @@ pub const SEND_SHUTDOWN: u8 = 2;
 /// 表示读和写方向都已关闭（相当于SHUT_RDWR）
 pub const SHUTDOWN_MASK: u8 = 3;
 
-pub fn init_network(net_dev: Box<dyn NetDevice>, is_loopback: bool) {
+/// Set up the loopback interface `lo`, and an ethernet interface `ethN` for
+/// each of `net_devs`.
+pub fn init_network(lo_dev: Box<dyn NetDevice>, net_devs: Vec<Box<dyn NetDevice>>) {
     info!("Initialize network subsystem...");
-    let ether_addr = EthernetAddress(net_dev.mac_address().0);
-    let eth0 = InterfaceWrapper::new("eth0", net_dev, ether_addr);
-
-    // let ip = IP.parse().expect("invalid IP address");
 
-    // let gateway = GATEWAY.parse().expect("invalid gateway IP address");
-    let gateway = GATEWAY.parse().unwrap();
-    let ip;
-    let ip_addrs = if is_loopback {
-        ip = "127.0.0.1".parse().unwrap();
-        vec![IpCidr::new(ip, 8)]
-    } else {
-        ip = IP.parse().expect("invalid IP address");
-        vec![
-            IpCidr::new(IP.parse().unwrap(), 8),
-            IpCidr::new(ip, IP_PREFIX),
-        ]
-    };
-    eth0.setup_ip_addr(ip_addrs);
-    eth0.setup_gateway(gateway);
-
-    ETH0.call_once(|| eth0);
-
-    info!("created net interface {:?}:", ETH0.get().unwrap().name());
-    info!("  ether:    {}", ETH0.get().unwrap().ethernet_address());
-    info!("  ip:       {}/{}", ip, IP_PREFIX);
-    info!("  gateway:  {}", gateway);
+    let lo = iface::register("lo", lo_dev, IFF_UP | IFF_RUNNING | IFF_LOOPBACK);
+    lo.setup_ip_addr(vec![
+        IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8),
+        IpCidr::new(IpAddress::Ipv6(Ipv6Address::LOOPBACK), 128),
+    ]);
+    info!("created net interface {:?}", lo.name());
+
+    let gateway = GATEWAY.parse::<IpAddress>().ok();
+    for (i, net_dev) in net_devs.into_iter().enumerate() {
+        let name = alloc::format!("eth{i}");
+        let eth = iface::register(
+            &name,
+            net_dev,
+            IFF_UP | IFF_RUNNING | IFF_BROADCAST | IFF_MULTICAST,
+        );
+        // Only the first device gets the address given at build time, unless it
+        // is configured by DHCP later.
+        if i == 0 && !dhcp::dhcp_enabled() {
+            let ip = IP.parse().expect("invalid IP address");
+            eth.setup_ip_addr(vec![IpCidr::new(ip, IP_PREFIX)]);
+            if let Some(gateway) = gateway {
+                eth.setup_gateway(gateway);
+            }
+            info!("  ip:       {}/{}", ip, IP_PREFIX);
+            info!("  gateway:  {:?}", gateway);
+        }
+        if eth.medium() == Medium::Ethernet {
+            let link_local = slaac::link_local_cidr(eth.ethernet_address());
+            eth.setup_ip_addr(vec![link_local]);
+            info!("  ipv6:     {}", link_local);
+        }
+        info!("created net interface {:?}:", eth.name());
+        info!("  ether:    {}", eth.ethernet_address());
+    }
 }
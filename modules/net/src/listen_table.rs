@@ -1,6 +1,22 @@
-use alloc::{boxed::Box, collections::VecDeque};
+//! TCP listen table.
+//!
+//! A socket is created in `SOCKET_SET` for every SYN that a listener takes,
+//! and kept in the SYN queue of the listener until the handshake completes.
+//! Established connections then move to the accept queue. The length of both
+//! queues is bounded by the backlog given to `listen`. A SYN that finds the
+//! queues full is dropped before smoltcp sees it, so the peer retries later
+//! instead of getting a reset.
+//!
+//! Several listeners may share a port if they listen on different addresses,
+//! or if all of them set `SO_REUSEPORT`, in which case connections are spread
+//! among them by the hash of the remote endpoint.
+//!
+//! Lock order: `SOCKET_SET`, then the port slot of this table.
+
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
 use core::{
-    ops::{Deref, DerefMut},
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
     task::Waker,
 };
 
@@ -12,32 +28,59 @@ use smoltcp::{
 };
 use systype::{SysError, SysResult};
 
-use super::{LISTEN_QUEUE_SIZE, SOCKET_SET, SocketSetWrapper};
-use crate::Mutex;
+use super::SOCKET_SET;
+use crate::{Mutex, addr::from_ipv4_mapped, netstat::SNMP, tcp::TcpOptions};
 
 const PORT_NUM: usize = 65536;
 
+/// Upper bound of the backlog, like `net.core.somaxconn` in Linux.
+pub const SOMAXCONN: usize = 4096;
+
+/// Options of a listening socket.
+#[derive(Debug, Clone, Copy)]
+pub struct ListenOptions {
+    /// Whether the listening socket is an `AF_INET6` socket.
+    pub ipv6: bool,
+    /// `IPV6_V6ONLY` of the listening socket, IPv4 connections are refused
+    /// when set.
+    pub v6only: bool,
+    /// `SO_REUSEPORT`, the port may be shared with other listeners that set it.
+    pub reuse_port: bool,
+    /// Max number of connections in the SYN queue, and of established
+    /// connections waiting to be accepted.
+    pub backlog: usize,
+    /// Options of the sockets created for incoming connections.
+    pub socket_opts: TcpOptions,
+}
+
 /// An entry in the listen table, representing a specific listening endpoint.
 ///
 /// This struct holds the information related to a specific listening IP address
-/// and port. It also manages the SYN queue and the waker for handling incoming
-/// TCP connections.
+/// and port. It also manages the SYN queue, the accept queue and the wakers for
+/// handling incoming TCP connections.
 struct ListenTableEntry {
+    /// Identifies the listener among the ones sharing the port.
+    id: usize,
     /// The IP address and port being listened on.
     listen_endpoint: IpListenEndpoint,
-    /// The SYN queue holding incoming TCP connection handles.
+    opts: ListenOptions,
+    /// Connections in the middle of the three-way handshake.
     syn_queue: VecDeque<SocketHandle>,
-    /// The waker used to wake up the listening socket when a new connection
-    /// arrives.
-    waker: Waker,
+    /// Established connections waiting to be accepted.
+    accept_queue: VecDeque<SocketHandle>,
+    /// Tasks waiting for a connection to accept.
+    wakers: Vec<Waker>,
 }
 
 impl ListenTableEntry {
-    pub fn new(listen_endpoint: IpListenEndpoint, waker: &Waker) -> Self {
+    fn new(id: usize, listen_endpoint: IpListenEndpoint, opts: ListenOptions) -> Self {
         Self {
+            id,
             listen_endpoint,
-            syn_queue: VecDeque::with_capacity(LISTEN_QUEUE_SIZE),
-            waker: waker.clone(),
+            opts,
+            syn_queue: VecDeque::new(),
+            accept_queue: VecDeque::new(),
+            wakers: Vec::new(),
         }
     }
 
@@ -46,39 +89,93 @@ impl ListenTableEntry {
     /// addresses，允许IPv6套接字接收IPv4连接
     ///
     /// 1. 当IPv6套接字绑定到::（全0地址）时，
-    ///    内核会允许该套接字接受任何传入的连接，无论其是IPv4还是IPv6地址。
+    ///    内核会允许该套接字接受任何传入的连接，无论其是IPv4还是IPv6地址，
+    ///    除非设置了 `IPV6_V6ONLY`。
     /// 2. 对于从IPv4地址到来的连接，内核会将其转换为IPv4-mapped
     ///    IPv6地址，即::ffff:a.b.c.d格式，其中a.b.c.d是IPv4地址。
+    /// 3. IPv4套接字只接受IPv4连接。
     fn can_accept(&self, dst: IpAddress) -> bool {
-        match self.listen_endpoint.addr {
-            Some(addr) => {
-                if addr == dst {
-                    return true;
+        match (self.listen_endpoint.addr, dst) {
+            (Some(addr), _) if addr == dst => true,
+            (Some(IpAddress::Ipv6(v6)), IpAddress::Ipv4(v4)) => {
+                !self.opts.v6only && from_ipv4_mapped(v6) == Some(v4)
+            }
+            (Some(_), _) => false,
+            (None, IpAddress::Ipv4(_)) => self.accepts_ipv4(),
+            (None, IpAddress::Ipv6(_)) => self.opts.ipv6,
+        }
+    }
+
+    fn accepts_ipv4(&self) -> bool {
+        !(self.opts.ipv6 && self.opts.v6only)
+    }
+
+    /// Whether some destination is accepted by both this listener and another
+    /// one on `endpoint` with `opts`.
+    fn overlaps(&self, endpoint: IpListenEndpoint, opts: ListenOptions) -> bool {
+        let other = ListenTableEntry::new(0, endpoint, opts);
+        match (self.listen_endpoint.addr, endpoint.addr) {
+            (Some(addr), _) => other.can_accept(addr),
+            (None, Some(addr)) => self.can_accept(addr),
+            (None, None) => {
+                (self.accepts_ipv4() && other.accepts_ipv4()) || (self.opts.ipv6 && opts.ipv6)
+            }
+        }
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    fn wake(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn accept_queue_full(&self) -> bool {
+        self.accept_queue.len() > self.opts.backlog
+    }
+
+    /// A backlog of 0 still lets one handshake through, like the accept queue.
+    fn syn_queue_full(&self) -> bool {
+        self.syn_queue.len() >= self.opts.backlog.max(1)
+    }
+
+    /// Move established connections from the SYN queue to the accept queue,
+    /// and release the ones reset during the handshake.
+    fn promote(&mut self, sockets: &mut SocketSet<'_>) {
+        let mut i = 0;
+        while i < self.syn_queue.len() {
+            let handle = self.syn_queue[i];
+            match sockets.get::<tcp::Socket>(handle).state() {
+                State::Listen | State::SynReceived => i += 1,
+                State::Closed | State::TimeWait => {
+                    self.syn_queue.remove(i);
+                    sockets.remove(handle);
                 }
-                if let IpAddress::Ipv6(v6) = addr {
-                    if v6.is_unspecified()
-                        || (dst.as_bytes().len() == 4
-                            && v6.is_ipv4_mapped()
-                            && v6.as_bytes()[12..] == dst.as_bytes()[..])
-                    {
-                        return true;
-                    }
+                _ => {
+                    self.syn_queue.remove(i);
+                    self.accept_queue.push_back(handle);
                 }
-                false
             }
-            None => true,
         }
     }
 
-    pub fn wake(self) {
-        self.waker.wake_by_ref()
+    /// Whether a connection from `src` is already queued.
+    fn has_connection(&self, src: IpEndpoint, sockets: &SocketSet<'_>) -> bool {
+        self.syn_queue
+            .iter()
+            .chain(self.accept_queue.iter())
+            .any(|&handle| sockets.get::<tcp::Socket>(handle).remote_endpoint() == Some(src))
     }
-}
 
-impl Drop for ListenTableEntry {
-    fn drop(&mut self) {
-        for &handle in &self.syn_queue {
-            SOCKET_SET.remove(handle);
+    /// Release all queued connections.
+    fn close(self, sockets: &mut SocketSet<'_>) {
+        for handle in self.syn_queue.into_iter().chain(self.accept_queue) {
+            sockets.remove(handle);
         }
     }
 }
@@ -86,14 +183,14 @@ impl Drop for ListenTableEntry {
 /// A table for managing TCP listen ports.
 /// Each index corresponds to a specific port number.
 ///
-/// Using an array allows direct access to the corresponding listen entry
+/// Using an array allows direct access to the listen entries of a port
 /// through the port number, improving lookup efficiency.
 /// A Mutex ensures thread safety, as multiple threads may access and modify
 /// the state of the listening ports in a multithreaded environment.
 pub struct ListenTable {
-    /// An array of Mutexes, each protecting an optional ListenTableEntry for a
-    /// specific port.
-    tcp: Box<[Mutex<Option<Box<ListenTableEntry>>>]>,
+    /// An array of Mutexes, each protecting the listeners of a specific port.
+    tcp: Box<[Mutex<Vec<ListenTableEntry>>]>,
+    next_id: AtomicUsize,
 }
 
 impl ListenTable {
@@ -101,133 +198,259 @@ impl ListenTable {
         let tcp = unsafe {
             let mut buf = Box::new_uninit_slice(PORT_NUM);
             for i in 0..PORT_NUM {
-                buf[i].write(Mutex::new(None));
+                buf[i].write(Mutex::new(Vec::new()));
             }
             buf.assume_init()
         };
-        Self { tcp }
+        Self {
+            tcp,
+            next_id: AtomicUsize::new(1),
+        }
     }
 
     pub fn can_listen(&self, port: u16) -> bool {
-        self.tcp[port as usize].lock().is_none()
+        self.tcp[port as usize].lock().is_empty()
+    }
+
+    /// Whether a listener on `endpoint` with `opts` would conflict with an
+    /// existing one, i.e. they accept a common destination and not both of
+    /// them set `SO_REUSEPORT`.
+    pub fn is_in_use(&self, endpoint: IpListenEndpoint, opts: ListenOptions) -> bool {
+        self.tcp[endpoint.port as usize].lock().iter().any(|entry| {
+            entry.overlaps(endpoint, opts) && !(entry.opts.reuse_port && opts.reuse_port)
+        })
     }
 
-    pub fn listen(&self, listen_endpoint: IpListenEndpoint, waker: &Waker) -> SysResult<()> {
+    /// Listen on `listen_endpoint`, returns the id of the listener.
+    pub fn listen(
+        &self,
+        listen_endpoint: IpListenEndpoint,
+        mut opts: ListenOptions,
+        waker: &Waker,
+    ) -> SysResult<usize> {
         let port = listen_endpoint.port;
         assert_ne!(port, 0);
-        let mut entry = self.tcp[port as usize].lock();
-        if entry.is_none() {
-            *entry = Some(Box::new(ListenTableEntry::new(listen_endpoint, waker)));
-            Ok(())
-        } else {
-            warn!("socket listen() failed");
-            Err(SysError::EADDRINUSE)
+        if self.is_in_use(listen_endpoint, opts) {
+            warn!("socket listen() failed: {listen_endpoint} in use");
+            return Err(SysError::EADDRINUSE);
+        }
+        opts.backlog = opts.backlog.min(SOMAXCONN);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut entry = ListenTableEntry::new(id, listen_endpoint, opts);
+        entry.register_waker(waker);
+        self.tcp[port as usize].lock().push(entry);
+        Ok(id)
+    }
+
+    /// Change the backlog of a listener, as `listen` on a listening socket
+    /// does.
+    pub fn set_backlog(&self, port: u16, id: usize, backlog: usize) {
+        if let Some(entry) = self.tcp[port as usize]
+            .lock()
+            .iter_mut()
+            .find(|entry| entry.id == id)
+        {
+            entry.opts.backlog = backlog.min(SOMAXCONN);
         }
     }
 
-    pub fn unlisten(&self, port: u16) {
+    /// Change the options of sockets created for listener `id` afterwards.
+    pub fn set_socket_options(&self, port: u16, id: usize, socket_opts: TcpOptions) {
+        if let Some(entry) = self.tcp[port as usize]
+            .lock()
+            .iter_mut()
+            .find(|entry| entry.id == id)
+        {
+            entry.opts.socket_opts = socket_opts;
+        }
+    }
+
+    /// Endpoint, whether it is `AF_INET6` and number of connections waiting
+    /// to be accepted of every listener.
+    pub(crate) fn listeners(&self) -> Vec<(IpListenEndpoint, bool, usize)> {
+        self.tcp
+            .iter()
+            .flat_map(|entries| {
+                entries
+                    .lock()
+                    .iter()
+                    .map(|entry| {
+                        (
+                            entry.listen_endpoint,
+                            entry.opts.ipv6,
+                            entry.accept_queue.len(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    pub fn unlisten(&self, port: u16, id: usize) {
         info!("TCP socket unlisten on {}", port);
-        if let Some(entry) = self.tcp[port as usize].lock().take() {
-            entry.wake()
+        let mut sockets = SOCKET_SET.0.lock();
+        let mut entries = self.tcp[port as usize].lock();
+        if let Some(idx) = entries.iter().position(|entry| entry.id == id) {
+            let mut entry = entries.swap_remove(idx);
+            entry.wake();
+            entry.close(&mut sockets);
         }
-        // *self.tcp[port as usize].lock() = None;
     }
 
-    pub fn can_accept(&self, port: u16) -> bool {
-        if let Some(entry) = self.tcp[port as usize].lock().deref() {
-            entry.syn_queue.iter().any(|&handle| is_connected(handle))
-        } else {
-            // 因为在listen函数调用时已经将port设为监听状态了，这里应该不会查不到？？
-            error!("socket accept() failed: not listen. I think this wouldn't happen !!!");
+    /// Whether listener `id` has a connection to accept. The waker is
+    /// registered if not.
+    pub fn can_accept(&self, port: u16, id: usize, waker: &Waker) -> bool {
+        let mut sockets = SOCKET_SET.0.lock();
+        let mut entries = self.tcp[port as usize].lock();
+        let Some(entry) = entries.iter_mut().find(|entry| entry.id == id) else {
+            warn!("socket accept() failed: not listen");
+            return false;
+        };
+        entry.promote(&mut sockets);
+        if entry.accept_queue.is_empty() {
+            entry.register_waker(waker);
             false
-            // Err(SysError::EINVAL)
-        }
-    }
-
-    /// 检查端口上的SYN队列，找到已经建立连接的句柄，并将其从队列中取出，
-    /// 返回给调用者。
-    pub fn accept(&self, port: u16) -> SysResult<(SocketHandle, (IpEndpoint, IpEndpoint))> {
-        if let Some(entry) = self.tcp[port as usize].lock().deref_mut() {
-            let syn_queue = &mut entry.syn_queue;
-            let (idx, addr_tuple) = syn_queue
-                .iter()
-                .enumerate()
-                .find_map(|(idx, &handle)| {
-                    is_connected(handle).then(|| (idx, get_addr_tuple(handle)))
-                })
-                .ok_or(SysError::EAGAIN)?; // wait for connection
-
-            // 记录慢速SYN队列遍历的警告信息是为了监控和诊断性能问题
-            // 理想情况: 如果网络连接正常，
-            // SYN队列中的连接请求应尽快完成三次握手并从队列前端被取出。因此，
-            // 最常见的情况是已连接的句柄在队列的前端，即索引为0。
-            // 异常情况: 如果队列中第一个元素（索引为0）的连接请求没有完成，
-            // 而后续的某个连接请求已经完成，这可能表明存在性能问题或异常情况,如网络延迟、
-            // 资源争用
-            if idx > 0 {
-                warn!(
-                    "slow SYN queue enumeration: index = {}, len = {}!",
-                    idx,
-                    syn_queue.len()
-                );
-            }
-            let handle = syn_queue.swap_remove_front(idx).unwrap();
-            Ok((handle, addr_tuple))
         } else {
-            warn!("socket accept() failed: not listen");
-            Err(SysError::EINVAL)
+            true
         }
     }
 
+    /// Take the oldest established connection of listener `id`, returns
+    /// `EAGAIN` after registering the waker if there is none.
+    pub fn accept(
+        &self,
+        port: u16,
+        id: usize,
+        waker: &Waker,
+    ) -> SysResult<(SocketHandle, (IpEndpoint, IpEndpoint))> {
+        let mut sockets = SOCKET_SET.0.lock();
+        let mut entries = self.tcp[port as usize].lock();
+        let Some(entry) = entries.iter_mut().find(|entry| entry.id == id) else {
+            warn!("socket accept() failed: not listen");
+            return Err(SysError::EINVAL);
+        };
+        entry.promote(&mut sockets);
+        let Some(handle) = entry.accept_queue.pop_front() else {
+            entry.register_waker(waker);
+            return Err(SysError::EAGAIN);
+        };
+        let socket = sockets.get::<tcp::Socket>(handle);
+        let addr_tuple = (
+            socket.local_endpoint().unwrap(),
+            socket.remote_endpoint().unwrap(),
+        );
+        Ok((handle, addr_tuple))
+    }
+
+    /// Handle a TCP packet from `src` to `dst` before smoltcp does. Returns
+    /// `true` if the packet should be dropped.
     pub fn incoming_tcp_packet(
         &self,
         src: IpEndpoint,
         dst: IpEndpoint,
+        is_syn: bool,
         sockets: &mut SocketSet<'_>,
-    ) {
-        if let Some(entry) = self.tcp[dst.port as usize].lock().deref_mut() {
-            if !entry.can_accept(dst.addr) {
-                // not listening on this address
-                warn!(
-                    "[ListenTable::incoming_tcp_packet] not listening on address {}",
-                    dst.addr
-                );
-                return;
-            }
-            if entry.syn_queue.len() >= LISTEN_QUEUE_SIZE {
-                // SYN queue is full, drop the packet
-                warn!("SYN queue overflow!");
-                return;
+    ) -> bool {
+        let mut entries = self.tcp[dst.port as usize].lock();
+        if entries.is_empty() {
+            return false;
+        }
+        if !is_syn {
+            // Possibly the last ACK of a handshake, let the listener check
+            // its queue after smoltcp handles it.
+            for entry in entries.iter_mut() {
+                if entry.has_connection(src, sockets) {
+                    entry.wake();
+                }
             }
-            entry.waker.wake_by_ref();
+            return false;
+        }
+        let Some(entry) = select_listener(&mut entries, src, dst.addr) else {
+            // not listening on this address
+            warn!(
+                "[ListenTable::incoming_tcp_packet] not listening on address {}",
+                dst.addr
+            );
+            return false;
+        };
+        entry.promote(sockets);
+        if entry.has_connection(src, sockets) {
+            // A retransmitted SYN, handled by the existing socket.
+            return false;
+        }
+        if entry.accept_queue_full() {
+            warn!(
+                "[ListenTable::incoming_tcp_packet] accept queue of {} is full, drop SYN from {src}",
+                entry.listen_endpoint
+            );
+            return true;
+        }
+        if entry.syn_queue_full() {
+            warn!(
+                "[ListenTable::incoming_tcp_packet] SYN queue of {} is full, drop SYN from {src}",
+                entry.listen_endpoint
+            );
+            return true;
+        }
+        // Listen on the exact destination, since a wildcard or IPv4-mapped
+        // address of an IPv6 listener does not match the packet in smoltcp.
+        let mut socket = entry.opts.socket_opts.new_socket();
+        if socket.listen(IpListenEndpoint::from(dst)).is_ok() {
+            let handle = sockets.add(socket);
             info!(
-                "[ListenTable::incoming_tcp_packet] wake the socket who listens port {}",
-                dst.port
+                "TCP socket {}: prepare for connection {} -> {}",
+                handle, src, entry.listen_endpoint
             );
-            let mut socket = SocketSetWrapper::new_tcp_socket();
-            if socket.listen(entry.listen_endpoint).is_ok() {
-                let handle = sockets.add(socket);
-                info!(
-                    "TCP socket {}: prepare for connection {} -> {}",
-                    handle, src, entry.listen_endpoint
-                );
-                entry.syn_queue.push_back(handle);
-            }
+            entry.syn_queue.push_back(handle);
+            SNMP.tcp_passive_opens.fetch_add(1, Ordering::Relaxed);
         }
+        entry.wake();
+        false
     }
 }
 
-fn is_connected(handle: SocketHandle) -> bool {
-    SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| {
-        !matches!(socket.state(), State::Listen | State::SynReceived)
-    })
+/// Choose the listener of a connection from `src` to `dst`: one on the exact
+/// address beats a wildcard one, and a group of `SO_REUSEPORT` listeners
+/// shares connections by the hash of `src`.
+fn select_listener<'a>(
+    entries: &'a mut [ListenTableEntry],
+    src: IpEndpoint,
+    dst: IpAddress,
+) -> Option<&'a mut ListenTableEntry> {
+    let specific = entries
+        .iter()
+        .any(|entry| entry.listen_endpoint.addr.is_some() && entry.can_accept(dst));
+    let mut candidates: Vec<&'a mut ListenTableEntry> = entries
+        .iter_mut()
+        .filter(|entry| entry.can_accept(dst) && entry.listen_endpoint.addr.is_some() == specific)
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    let mut hasher = FnvHasher::default();
+    src.hash(&mut hasher);
+    let idx = hasher.finish() as usize % candidates.len();
+    Some(candidates.swap_remove(idx))
 }
 
-fn get_addr_tuple(handle: SocketHandle) -> (IpEndpoint, IpEndpoint) {
-    SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| {
-        (
-            socket.local_endpoint().unwrap(),
-            socket.remote_endpoint().unwrap(),
-        )
-    })
+/// FNV-1a, only used to spread connections among `SO_REUSEPORT` listeners.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
 }
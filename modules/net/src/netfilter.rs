@@ -0,0 +1,960 @@
+//! A small netfilter: rules on the input, output and forward hooks, with
+//! `REJECT` replies and masquerading of forwarded IPv4 traffic.
+//!
+//! The input and forward hooks run in `NetRxToken::preprocess`, before smoltcp
+//! sees a packet, and the output hook when smoltcp transmits one. Frames sent
+//! by packet sockets, forwarded frames and `REJECT` replies skip the output
+//! hook. Replies and forwarded frames are queued and sent after the interfaces
+//! are polled, since the interface they go through may be the one being
+//! polled. A reply to a packet rejected on output is received on the interface
+//! the packet went through instead, for the local socket that sent it.
+//!
+//! Rules are written to `/proc/net/netfilter` in a subset of the `iptables`
+//! syntax, e.g. `-A INPUT -p tcp --dport 80 -i eth0 -j DROP`. Forwarding is
+//! enabled by `/proc/sys/net/ipv4/ip_forward`. Only IPv4 is forwarded, and the
+//! next hop on an Ethernet interface is resolved from the ARP packets seen on
+//! it: a packet to an unknown neighbor is dropped after an ARP request is sent.
+//!
+//! Masqueraded connections are tracked by their ports, or the identifier of
+//! ICMP echoes. Replies are translated back and forwarded without running the
+//! forward hook again.
+
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use smoltcp::{
+    phy::Medium,
+    wire::{
+        ArpOperation, ArpPacket, ArpRepr, ETHERNET_HEADER_LEN, EthernetAddress, EthernetFrame,
+        EthernetProtocol, Icmpv4Message, Icmpv4Packet, Icmpv6Message, Icmpv6Packet, IpAddress,
+        IpCidr, IpProtocol, Ipv4Address, Ipv4Packet, Ipv6Packet, TcpPacket, TcpSeqNumber,
+        UdpPacket,
+    },
+};
+use systype::{SysError, SysResult};
+
+use crate::{DEFAULT_HOP_LIMIT, InterfaceWrapper, Mutex, iface, route};
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const TCP_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+/// Max length of the packet quoted in an ICMP error, so that the error fits in
+/// the minimum MTU of IPv4.
+const ICMP_QUOTE_LEN: usize = 548;
+const ICMPV4_PORT_UNREACHABLE: u8 = 3;
+const ICMPV6_PORT_UNREACHABLE: u8 = 4;
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const CONNTRACK_LEN: usize = 256;
+const NEIGHBOR_CACHE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hook {
+    /// Packets to the host.
+    Input = 0,
+    /// Packets sent by the host.
+    Output = 1,
+    /// Packets routed from an interface to another one.
+    Forward = 2,
+}
+
+impl Hook {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Input => "INPUT",
+            Self::Output => "OUTPUT",
+            Self::Forward => "FORWARD",
+        }
+    }
+
+    fn from_name(name: &str) -> SysResult<Self> {
+        match name {
+            "INPUT" => Ok(Self::Input),
+            "OUTPUT" => Ok(Self::Output),
+            "FORWARD" => Ok(Self::Forward),
+            _ => Err(SysError::ENOENT),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Accept,
+    Drop,
+    /// Drop and answer with a TCP reset or an ICMP port unreachable error.
+    Reject,
+    /// Accept and rewrite the source to the address of the output interface.
+    Masquerade,
+}
+
+impl Target {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Accept => "ACCEPT",
+            Self::Drop => "DROP",
+            Self::Reject => "REJECT",
+            Self::Masquerade => "MASQUERADE",
+        }
+    }
+
+    fn from_name(name: &str) -> SysResult<Self> {
+        match name {
+            "ACCEPT" => Ok(Self::Accept),
+            "DROP" => Ok(Self::Drop),
+            "REJECT" => Ok(Self::Reject),
+            "MASQUERADE" => Ok(Self::Masquerade),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+}
+
+/// A rule matches a packet if all of its given fields do.
+#[derive(Debug, Clone)]
+struct Rule {
+    protocol: Option<IpProtocol>,
+    src: Option<IpCidr>,
+    dst: Option<IpCidr>,
+    sport: Option<u16>,
+    dport: Option<u16>,
+    in_iface: Option<String>,
+    out_iface: Option<String>,
+    target: Target,
+}
+
+impl Rule {
+    /// Parse the options of a rule of `hook`, e.g. `-p tcp --dport 80 -j DROP`.
+    fn parse<'a>(hook: Hook, mut args: impl Iterator<Item = &'a str>) -> SysResult<Self> {
+        let mut rule = Self {
+            protocol: None,
+            src: None,
+            dst: None,
+            sport: None,
+            dport: None,
+            in_iface: None,
+            out_iface: None,
+            target: Target::Accept,
+        };
+        let mut target = None;
+        while let Some(opt) = args.next() {
+            let value = args.next().ok_or(SysError::EINVAL)?;
+            match opt {
+                "-p" => rule.protocol = Some(parse_protocol(value)?),
+                "-s" => rule.src = Some(parse_cidr(value)?),
+                "-d" => rule.dst = Some(parse_cidr(value)?),
+                "--sport" => rule.sport = Some(value.parse().map_err(|_| SysError::EINVAL)?),
+                "--dport" => rule.dport = Some(value.parse().map_err(|_| SysError::EINVAL)?),
+                "-i" if hook != Hook::Output => rule.in_iface = Some(value.to_string()),
+                "-o" if hook != Hook::Input => rule.out_iface = Some(value.to_string()),
+                "-j" => target = Some(Target::from_name(value)?),
+                _ => return Err(SysError::EINVAL),
+            }
+        }
+        rule.target = target.ok_or(SysError::EINVAL)?;
+        let has_ports = matches!(rule.protocol, Some(IpProtocol::Tcp | IpProtocol::Udp));
+        if (rule.sport.is_some() || rule.dport.is_some()) && !has_ports {
+            return Err(SysError::EINVAL);
+        }
+        match (hook, rule.target) {
+            (Hook::Input | Hook::Output, Target::Masquerade) => Err(SysError::EINVAL),
+            _ => Ok(rule),
+        }
+    }
+
+    fn matches(
+        &self,
+        packet: &PacketMeta,
+        in_iface: Option<&str>,
+        out_iface: Option<&str>,
+    ) -> bool {
+        self.protocol
+            .is_none_or(|protocol| protocol == packet.protocol)
+            && self.src.is_none_or(|cidr| cidr.contains_addr(&packet.src))
+            && self.dst.is_none_or(|cidr| cidr.contains_addr(&packet.dst))
+            && self.sport.is_none_or(|port| port == packet.sport)
+            && self.dport.is_none_or(|port| port == packet.dport)
+            && self
+                .in_iface
+                .as_deref()
+                .is_none_or(|name| Some(name) == in_iface)
+            && self
+                .out_iface
+                .as_deref()
+                .is_none_or(|name| Some(name) == out_iface)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(protocol) = self.protocol {
+            write!(f, " -p {}", protocol_name(protocol))?;
+        }
+        if let Some(src) = self.src {
+            write!(f, " -s {src}")?;
+        }
+        if let Some(dst) = self.dst {
+            write!(f, " -d {dst}")?;
+        }
+        if let Some(name) = &self.in_iface {
+            write!(f, " -i {name}")?;
+        }
+        if let Some(name) = &self.out_iface {
+            write!(f, " -o {name}")?;
+        }
+        if let Some(port) = self.sport {
+            write!(f, " --sport {port}")?;
+        }
+        if let Some(port) = self.dport {
+            write!(f, " --dport {port}")?;
+        }
+        write!(f, " -j {}", self.target.name())
+    }
+}
+
+struct Chain {
+    /// Target of packets no rule matches, `ACCEPT` or `DROP`.
+    policy: Target,
+    rules: Vec<Rule>,
+}
+
+impl Chain {
+    const fn new() -> Self {
+        Self {
+            policy: Target::Accept,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Chains indexed by `Hook`.
+static CHAINS: Mutex<[Chain; 3]> = Mutex::new([Chain::new(), Chain::new(), Chain::new()]);
+/// Bits of the hooks whose chain may drop a packet, so that packets are not
+/// parsed for nothing.
+static HOOKS_IN_USE: AtomicU8 = AtomicU8::new(0);
+static IP_FORWARD: AtomicBool = AtomicBool::new(false);
+static CONNTRACK: Mutex<VecDeque<NatEntry>> = Mutex::new(VecDeque::new());
+/// IPv4 neighbors learnt from ARP packets, with the index of the interface.
+static NEIGHBORS: Mutex<VecDeque<(usize, Ipv4Address, EthernetAddress)>> =
+    Mutex::new(VecDeque::new());
+/// Frames to send after the interfaces are polled, with the index of the
+/// interface. Forwarded IPv4 packets come with their next hop instead of a link
+/// header, which needs the device of the interface, locked while it is polled.
+static PENDING: Mutex<Vec<(usize, Option<Ipv4Address>, Vec<u8>)>> = Mutex::new(Vec::new());
+/// Replies to frames rejected by the output hook, with the index of the
+/// interface they are received on after the interfaces are polled.
+static LOCAL_REPLIES: Mutex<Vec<(usize, Vec<u8>)>> = Mutex::new(Vec::new());
+
+/// A masqueraded connection from `orig_src:port` to `peer:peer_port`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NatEntry {
+    protocol: IpProtocol,
+    orig_src: Ipv4Address,
+    port: u16,
+    peer: Ipv4Address,
+    peer_port: u16,
+    /// Address of the output interface the source was rewritten to.
+    nat_addr: Ipv4Address,
+    in_ifindex: usize,
+    out_ifindex: usize,
+}
+
+/// Addresses and ports of an IP packet. Ports are 0 for other protocols than
+/// TCP and UDP.
+#[derive(Debug, Clone, Copy)]
+struct PacketMeta {
+    src: IpAddress,
+    dst: IpAddress,
+    protocol: IpProtocol,
+    sport: u16,
+    dport: u16,
+    /// Offsets of the IP header, the transport header and the end of the IP
+    /// packet in the frame.
+    ip_offset: usize,
+    l4_offset: usize,
+    ip_end: usize,
+}
+
+/// Run a command on the rules, one of:
+///
+/// - `-A CHAIN RULE` or `-I CHAIN RULE` to append or insert a rule,
+/// - `-D CHAIN N` to delete rule `N`, counted from 1,
+/// - `-F [CHAIN]` to delete all rules of a chain, or of all chains,
+/// - `-P CHAIN ACCEPT|DROP` to set the policy of a chain.
+pub fn execute(cmd: &str) -> SysResult<()> {
+    let mut args = cmd.split_whitespace();
+    let op = args.next().ok_or(SysError::EINVAL)?;
+    let hook = args.next().map(Hook::from_name).transpose()?;
+    let mut chains = CHAINS.lock();
+    match (op, hook) {
+        ("-A" | "-I", Some(hook)) => {
+            let rule = Rule::parse(hook, args)?;
+            let rules = &mut chains[hook as usize].rules;
+            if op == "-A" {
+                rules.push(rule);
+            } else {
+                rules.insert(0, rule);
+            }
+        }
+        ("-D", Some(hook)) => {
+            let n: usize = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .ok_or(SysError::EINVAL)?;
+            let rules = &mut chains[hook as usize].rules;
+            if n == 0 || n > rules.len() {
+                return Err(SysError::ENOENT);
+            }
+            rules.remove(n - 1);
+        }
+        ("-F", Some(hook)) => chains[hook as usize].rules.clear(),
+        ("-F", None) => chains.iter_mut().for_each(|chain| chain.rules.clear()),
+        ("-P", Some(hook)) => {
+            let policy = args
+                .next()
+                .map(Target::from_name)
+                .transpose()?
+                .filter(|policy| matches!(policy, Target::Accept | Target::Drop))
+                .ok_or(SysError::EINVAL)?;
+            chains[hook as usize].policy = policy;
+        }
+        _ => return Err(SysError::EINVAL),
+    }
+    let mut in_use = 0;
+    for hook in [Hook::Input, Hook::Output, Hook::Forward] {
+        let chain = &chains[hook as usize];
+        if !chain.rules.is_empty() || chain.policy != Target::Accept {
+            in_use |= hook.bit();
+        }
+    }
+    HOOKS_IN_USE.store(in_use, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The policies and rules, in the form `execute` takes.
+pub fn rules() -> String {
+    let chains = CHAINS.lock();
+    let mut res = String::new();
+    for hook in [Hook::Input, Hook::Output, Hook::Forward] {
+        let _ = writeln!(
+            res,
+            "-P {} {}",
+            hook.name(),
+            chains[hook as usize].policy.name()
+        );
+    }
+    for hook in [Hook::Input, Hook::Output, Hook::Forward] {
+        for rule in chains[hook as usize].rules.iter() {
+            let _ = writeln!(res, "-A {}{rule}", hook.name());
+        }
+    }
+    res
+}
+
+pub fn forwarding() -> bool {
+    IP_FORWARD.load(Ordering::Relaxed)
+}
+
+/// Enable or disable IPv4 forwarding. Masqueraded connections are forgotten
+/// when it is disabled.
+pub fn set_forwarding(enabled: bool) {
+    IP_FORWARD.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        CONNTRACK.lock().clear();
+    }
+}
+
+pub(crate) fn has_output_rules() -> bool {
+    HOOKS_IN_USE.load(Ordering::Relaxed) & Hook::Output.bit() != 0
+}
+
+/// Run the input or forward hook on `frame` received on interface `ifindex`.
+/// Returns `true` if smoltcp should not see the frame, i.e. it was dropped or
+/// forwarded.
+pub(crate) fn receive(frame: &[u8], medium: Medium, ifindex: usize) -> bool {
+    let forwarding = forwarding();
+    let input = HOOKS_IN_USE.load(Ordering::Relaxed) & Hook::Input.bit() != 0;
+    if !forwarding && !input {
+        return false;
+    }
+    if forwarding && medium == Medium::Ethernet {
+        learn_neighbor(frame, ifindex);
+    }
+    let Some(packet) = parse(frame, medium) else {
+        return false;
+    };
+    let local = ifindex == iface::LOOPBACK_IFINDEX
+        || packet.dst.is_multicast()
+        || packet.dst.is_unspecified()
+        || iface::is_broadcast_addr(packet.dst)
+        || iface::is_local_addr(packet.dst);
+    if !local {
+        return forwarding && forward(frame, medium, &packet, ifindex);
+    }
+    if forwarding && unmasquerade(frame, &packet, ifindex) {
+        return true;
+    }
+    if !input {
+        return false;
+    }
+    let in_iface = iface::get(ifindex).map(|iface| iface.name.clone());
+    match verdict(Hook::Input, &packet, in_iface.as_deref(), None) {
+        Target::Accept | Target::Masquerade => false,
+        Target::Drop => true,
+        Target::Reject => {
+            if let Some(reply) = reject(frame, medium, &packet, ifindex, packet.dst) {
+                PENDING.lock().push((ifindex, None, reply));
+            }
+            true
+        }
+    }
+}
+
+/// Run the output hook on `frame` sent by smoltcp on interface `ifindex`.
+/// Returns `false` if the frame should be dropped. The reply to a rejected
+/// frame is received on the same interface, for the local socket that sent it.
+pub(crate) fn transmit(frame: &[u8], medium: Medium, ifindex: usize) -> bool {
+    let Some(packet) = parse(frame, medium) else {
+        return true;
+    };
+    let out_iface = iface::get(ifindex).map(|iface| iface.name.clone());
+    match verdict(Hook::Output, &packet, None, out_iface.as_deref()) {
+        Target::Accept | Target::Masquerade => true,
+        Target::Drop => false,
+        Target::Reject => {
+            if let Some(reply) = reject(frame, medium, &packet, ifindex, packet.dst) {
+                LOCAL_REPLIES.lock().push((ifindex, reply));
+            }
+            false
+        }
+    }
+}
+
+/// Send the frames queued while the interfaces were polled.
+pub(crate) fn flush() {
+    let replies = core::mem::take(&mut *LOCAL_REPLIES.lock());
+    for (ifindex, frame) in replies {
+        if let Some(iface) = iface::get(ifindex) {
+            iface.receive_frame(frame);
+        }
+    }
+    let pending = core::mem::take(&mut *PENDING.lock());
+    for (ifindex, next_hop, buf) in pending {
+        let Some(iface) = iface::get(ifindex) else {
+            continue;
+        };
+        let frame = match next_hop {
+            Some(next_hop) => match link_frame(&iface, next_hop, &buf) {
+                Some(frame) => frame,
+                None => continue,
+            },
+            None => buf,
+        };
+        if let Err(e) = iface.transmit_frame(&frame) {
+            log::warn!("[netfilter::flush] {} drops a frame: {e:?}", iface.name);
+        }
+    }
+}
+
+fn verdict(
+    hook: Hook,
+    packet: &PacketMeta,
+    in_iface: Option<&str>,
+    out_iface: Option<&str>,
+) -> Target {
+    let chains = CHAINS.lock();
+    let chain = &chains[hook as usize];
+    chain
+        .rules
+        .iter()
+        .find(|rule| rule.matches(packet, in_iface, out_iface))
+        .map_or(chain.policy, |rule| rule.target)
+}
+
+fn parse(frame: &[u8], medium: Medium) -> Option<PacketMeta> {
+    let ip_offset = if medium == Medium::Ethernet {
+        let ether_frame = EthernetFrame::new_checked(frame).ok()?;
+        if !matches!(
+            ether_frame.ethertype(),
+            EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6
+        ) {
+            return None;
+        }
+        ETHERNET_HEADER_LEN
+    } else {
+        0
+    };
+    let ip_buf = &frame[ip_offset..];
+    let (src, dst, protocol, header_len, ip_len) = match ip_buf.first().map(|byte| byte >> 4) {
+        Some(4) => {
+            let packet = Ipv4Packet::new_checked(ip_buf).ok()?;
+            (
+                packet.src_addr().into(),
+                packet.dst_addr().into(),
+                packet.next_header(),
+                packet.header_len() as usize,
+                packet.total_len() as usize,
+            )
+        }
+        Some(6) => {
+            let packet = Ipv6Packet::new_checked(ip_buf).ok()?;
+            (
+                packet.src_addr().into(),
+                packet.dst_addr().into(),
+                packet.next_header(),
+                IPV6_HEADER_LEN,
+                IPV6_HEADER_LEN + packet.payload_len() as usize,
+            )
+        }
+        _ => return None,
+    };
+    let l4 = &ip_buf[header_len..ip_len];
+    let (sport, dport) = match protocol {
+        IpProtocol::Tcp | IpProtocol::Udp if l4.len() >= 4 => (
+            u16::from_be_bytes([l4[0], l4[1]]),
+            u16::from_be_bytes([l4[2], l4[3]]),
+        ),
+        _ => (0, 0),
+    };
+    Some(PacketMeta {
+        src,
+        dst,
+        protocol,
+        sport,
+        dport,
+        ip_offset,
+        l4_offset: ip_offset + header_len,
+        ip_end: ip_offset + ip_len,
+    })
+}
+
+/// Forward an IPv4 packet received on interface `in_ifindex`. Returns `false`
+/// if it is left to smoltcp, which drops it.
+fn forward(frame: &[u8], medium: Medium, packet: &PacketMeta, in_ifindex: usize) -> bool {
+    let IpAddress::Ipv4(dst) = packet.dst else {
+        return false;
+    };
+    let Some(route) = route::lookup(packet.dst) else {
+        return true;
+    };
+    let (Some(in_iface), Some(out_iface)) = (iface::get(in_ifindex), iface::get(route.ifindex))
+    else {
+        return true;
+    };
+    if !out_iface.is_up() {
+        return true;
+    }
+    let target = if HOOKS_IN_USE.load(Ordering::Relaxed) & Hook::Forward.bit() != 0 {
+        verdict(
+            Hook::Forward,
+            packet,
+            Some(&in_iface.name),
+            Some(&out_iface.name),
+        )
+    } else {
+        Target::Accept
+    };
+    let mut ip = frame[packet.ip_offset..packet.ip_end].to_vec();
+    match target {
+        Target::Accept => {}
+        Target::Drop => return true,
+        Target::Reject => {
+            let reply_src = ipv4_addr(&in_iface).map_or(packet.dst, IpAddress::from);
+            if let Some(reply) = reject(frame, medium, packet, in_ifindex, reply_src) {
+                PENDING.lock().push((in_ifindex, None, reply));
+            }
+            return true;
+        }
+        Target::Masquerade => {
+            let (IpAddress::Ipv4(src), Some(nat_addr)) = (packet.src, ipv4_addr(&out_iface)) else {
+                return true;
+            };
+            let l4 = &frame[packet.l4_offset..packet.ip_end];
+            let Some((port, peer_port)) = nat_ports(packet.protocol, l4, false) else {
+                return true;
+            };
+            track(NatEntry {
+                protocol: packet.protocol,
+                orig_src: src,
+                port,
+                peer: dst,
+                peer_port,
+                nat_addr,
+                in_ifindex,
+                out_ifindex: out_iface.index,
+            });
+            rewrite_ipv4(&mut ip, Some(nat_addr), None);
+        }
+    }
+    if !decrement_ttl(&mut ip) || ip.len() > out_iface.mtu {
+        return true;
+    }
+    let next_hop = match route.gateway {
+        Some(IpAddress::Ipv4(gateway)) => gateway,
+        _ => dst,
+    };
+    PENDING.lock().push((out_iface.index, Some(next_hop), ip));
+    true
+}
+
+/// Translate a reply of a masqueraded connection back and forward it. Returns
+/// `false` if the packet does not belong to one.
+fn unmasquerade(frame: &[u8], packet: &PacketMeta, ifindex: usize) -> bool {
+    let (IpAddress::Ipv4(src), IpAddress::Ipv4(dst)) = (packet.src, packet.dst) else {
+        return false;
+    };
+    let l4 = &frame[packet.l4_offset..packet.ip_end];
+    let Some((peer_port, port)) = nat_ports(packet.protocol, l4, true) else {
+        return false;
+    };
+    let entry = CONNTRACK.lock().iter().copied().find(|entry| {
+        entry.out_ifindex == ifindex
+            && entry.protocol == packet.protocol
+            && entry.peer == src
+            && entry.peer_port == peer_port
+            && entry.nat_addr == dst
+            && entry.port == port
+    });
+    let Some(entry) = entry else {
+        return false;
+    };
+    let Some(in_iface) = iface::get(entry.in_ifindex) else {
+        return true;
+    };
+    let mut ip = frame[packet.ip_offset..packet.ip_end].to_vec();
+    rewrite_ipv4(&mut ip, None, Some(entry.orig_src));
+    if decrement_ttl(&mut ip) && ip.len() <= in_iface.mtu {
+        let next_hop = match route::lookup(entry.orig_src.into()).and_then(|route| route.gateway) {
+            Some(IpAddress::Ipv4(gateway)) => gateway,
+            _ => entry.orig_src,
+        };
+        PENDING.lock().push((in_iface.index, Some(next_hop), ip));
+    }
+    true
+}
+
+/// Ports identifying a masqueraded connection: the source and destination
+/// ports of TCP and UDP, or twice the identifier of an ICMP echo request, or
+/// of an echo reply if `reply`.
+fn nat_ports(protocol: IpProtocol, l4: &[u8], reply: bool) -> Option<(u16, u16)> {
+    match protocol {
+        IpProtocol::Tcp | IpProtocol::Udp if l4.len() >= 4 => Some((
+            u16::from_be_bytes([l4[0], l4[1]]),
+            u16::from_be_bytes([l4[2], l4[3]]),
+        )),
+        IpProtocol::Icmp if l4.len() >= ICMP_HEADER_LEN => {
+            let echo = if reply {
+                ICMPV4_ECHO_REPLY
+            } else {
+                ICMPV4_ECHO_REQUEST
+            };
+            let ident = u16::from_be_bytes([l4[4], l4[5]]);
+            (l4[0] == echo).then_some((ident, ident))
+        }
+        _ => None,
+    }
+}
+
+fn track(entry: NatEntry) {
+    let mut conntrack = CONNTRACK.lock();
+    if conntrack.contains(&entry) {
+        return;
+    }
+    if conntrack.len() == CONNTRACK_LEN {
+        conntrack.pop_front();
+    }
+    conntrack.push_back(entry);
+}
+
+/// The answer to a packet rejected on interface `ifindex`, a TCP reset or an
+/// ICMP port unreachable error from `reply_src`, addressed to the link layer
+/// source of the packet. There is none for ICMP packets, resets and packets to
+/// a group address.
+fn reject(
+    frame: &[u8],
+    medium: Medium,
+    packet: &PacketMeta,
+    ifindex: usize,
+    reply_src: IpAddress,
+) -> Option<Vec<u8>> {
+    if packet.dst.is_multicast() || iface::is_broadcast_addr(packet.dst) {
+        return None;
+    }
+    let ip = &frame[packet.ip_offset..packet.ip_end];
+    let l4 = &frame[packet.l4_offset..packet.ip_end];
+    let reply = match packet.protocol {
+        IpProtocol::Tcp => tcp_reset(packet, l4),
+        IpProtocol::Icmp | IpProtocol::Icmpv6 => None,
+        _ => port_unreachable(packet, ip, reply_src),
+    };
+    let (Some(reply), Some(iface)) = (reply, iface::get(ifindex)) else {
+        return None;
+    };
+    if medium == Medium::Ethernet {
+        let src_mac = EthernetFrame::new_unchecked(frame).src_addr();
+        Some(ethernet_frame(&iface, src_mac, &reply))
+    } else {
+        Some(reply)
+    }
+}
+
+fn tcp_reset(packet: &PacketMeta, l4: &[u8]) -> Option<Vec<u8>> {
+    let tcp = TcpPacket::new_checked(l4).ok()?;
+    if tcp.rst() {
+        return None;
+    }
+    let mut segment = vec![0; TCP_HEADER_LEN];
+    let mut reset = TcpPacket::new_unchecked(&mut segment[..]);
+    reset.set_src_port(tcp.dst_port());
+    reset.set_dst_port(tcp.src_port());
+    reset.set_header_len(TCP_HEADER_LEN as u8);
+    reset.clear_flags();
+    reset.set_rst(true);
+    if tcp.ack() {
+        reset.set_seq_number(tcp.ack_number());
+    } else {
+        let len = tcp.payload().len() + tcp.syn() as usize + tcp.fin() as usize;
+        reset.set_seq_number(TcpSeqNumber(0));
+        reset.set_ack(true);
+        reset.set_ack_number(tcp.seq_number() + len);
+    }
+    reset.fill_checksum(&packet.dst, &packet.src);
+    ip_packet(packet.dst, packet.src, IpProtocol::Tcp, &segment)
+}
+
+fn port_unreachable(packet: &PacketMeta, ip: &[u8], reply_src: IpAddress) -> Option<Vec<u8>> {
+    let quoted = &ip[..ip.len().min(ICMP_QUOTE_LEN)];
+    let mut msg = vec![0; ICMP_HEADER_LEN + quoted.len()];
+    msg[ICMP_HEADER_LEN..].copy_from_slice(quoted);
+    let protocol = match packet.src {
+        IpAddress::Ipv4(_) => {
+            let mut icmp = Icmpv4Packet::new_unchecked(&mut msg[..]);
+            icmp.set_msg_type(Icmpv4Message::DstUnreachable);
+            icmp.set_msg_code(ICMPV4_PORT_UNREACHABLE);
+            icmp.fill_checksum();
+            IpProtocol::Icmp
+        }
+        IpAddress::Ipv6(_) => {
+            let mut icmp = Icmpv6Packet::new_unchecked(&mut msg[..]);
+            icmp.set_msg_type(Icmpv6Message::DstUnreachable);
+            icmp.set_msg_code(ICMPV6_PORT_UNREACHABLE);
+            icmp.fill_checksum(&reply_src, &packet.src);
+            IpProtocol::Icmpv6
+        }
+    };
+    ip_packet(reply_src, packet.src, protocol, &msg)
+}
+
+/// An IP packet carrying `payload`, `None` if the addresses are of different
+/// families.
+fn ip_packet(
+    src: IpAddress,
+    dst: IpAddress,
+    protocol: IpProtocol,
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    match (src, dst) {
+        (IpAddress::Ipv4(src), IpAddress::Ipv4(dst)) => {
+            let mut buf = vec![0; IPV4_HEADER_LEN + payload.len()];
+            let mut packet = Ipv4Packet::new_unchecked(&mut buf[..]);
+            packet.set_version(4);
+            packet.set_header_len(IPV4_HEADER_LEN as u8);
+            packet.set_total_len((IPV4_HEADER_LEN + payload.len()) as u16);
+            packet.set_hop_limit(DEFAULT_HOP_LIMIT);
+            packet.set_next_header(protocol);
+            packet.set_src_addr(src);
+            packet.set_dst_addr(dst);
+            packet.payload_mut().copy_from_slice(payload);
+            packet.fill_checksum();
+            Some(buf)
+        }
+        (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => {
+            let mut buf = vec![0; IPV6_HEADER_LEN + payload.len()];
+            let mut packet = Ipv6Packet::new_unchecked(&mut buf[..]);
+            packet.set_version(6);
+            packet.set_payload_len(payload.len() as u16);
+            packet.set_next_header(protocol);
+            packet.set_hop_limit(DEFAULT_HOP_LIMIT);
+            packet.set_src_addr(src);
+            packet.set_dst_addr(dst);
+            packet.payload_mut().copy_from_slice(payload);
+            Some(buf)
+        }
+        _ => None,
+    }
+}
+
+/// Rewrite the source or destination of an IPv4 packet, and update the
+/// checksums.
+fn rewrite_ipv4(ip: &mut [u8], src: Option<Ipv4Address>, dst: Option<Ipv4Address>) {
+    let mut packet = Ipv4Packet::new_unchecked(&mut *ip);
+    if let Some(src) = src {
+        packet.set_src_addr(src);
+    }
+    if let Some(dst) = dst {
+        packet.set_dst_addr(dst);
+    }
+    packet.fill_checksum();
+    let src = IpAddress::from(packet.src_addr());
+    let dst = IpAddress::from(packet.dst_addr());
+    let protocol = packet.next_header();
+    let header_len = packet.header_len() as usize;
+    let l4 = &mut ip[header_len..];
+    match protocol {
+        IpProtocol::Tcp => {
+            if let Ok(mut tcp) = TcpPacket::new_checked(l4) {
+                tcp.fill_checksum(&src, &dst);
+            }
+        }
+        IpProtocol::Udp => {
+            // A zero checksum means there is none.
+            if let Ok(mut udp) = UdpPacket::new_checked(l4) {
+                if udp.checksum() != 0 {
+                    udp.fill_checksum(&src, &dst);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Decrement the TTL of an IPv4 packet. Returns `false` if it expired.
+fn decrement_ttl(ip: &mut [u8]) -> bool {
+    let mut packet = Ipv4Packet::new_unchecked(ip);
+    let ttl = packet.hop_limit();
+    if ttl <= 1 {
+        return false;
+    }
+    packet.set_hop_limit(ttl - 1);
+    packet.fill_checksum();
+    true
+}
+
+/// The frame carrying an IPv4 packet to `next_hop` on `iface`, or an ARP
+/// request for it if its hardware address is not known yet, in which case the
+/// packet is dropped.
+fn link_frame(iface: &InterfaceWrapper, next_hop: Ipv4Address, ip: &[u8]) -> Option<Vec<u8>> {
+    if iface.medium() != Medium::Ethernet {
+        return Some(ip.to_vec());
+    }
+    let neighbor = NEIGHBORS
+        .lock()
+        .iter()
+        .find(|(ifindex, addr, _)| *ifindex == iface.index && *addr == next_hop)
+        .map(|(_, _, mac)| *mac);
+    if let Some(mac) = neighbor {
+        return Some(ethernet_frame(iface, mac, ip));
+    }
+    let arp = ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Request,
+        source_hardware_addr: iface.ethernet_address(),
+        source_protocol_addr: ipv4_addr(iface)?,
+        target_hardware_addr: EthernetAddress([0; 6]),
+        target_protocol_addr: next_hop,
+    };
+    let mut buf = vec![0; arp.buffer_len()];
+    arp.emit(&mut ArpPacket::new_unchecked(&mut buf[..]));
+    let mut frame = ethernet_frame(iface, EthernetAddress::BROADCAST, &buf);
+    EthernetFrame::new_unchecked(&mut frame[..]).set_ethertype(EthernetProtocol::Arp);
+    Some(frame)
+}
+
+/// Remember the sender of an ARP packet received on interface `ifindex`.
+fn learn_neighbor(frame: &[u8], ifindex: usize) {
+    let Ok(ether_frame) = EthernetFrame::new_checked(frame) else {
+        return;
+    };
+    if ether_frame.ethertype() != EthernetProtocol::Arp {
+        return;
+    }
+    let Ok(arp) = ArpPacket::new_checked(ether_frame.payload()) else {
+        return;
+    };
+    let Ok(ArpRepr::EthernetIpv4 {
+        source_hardware_addr,
+        source_protocol_addr,
+        ..
+    }) = ArpRepr::parse(&arp)
+    else {
+        return;
+    };
+    let mut neighbors = NEIGHBORS.lock();
+    neighbors.retain(|(index, addr, _)| !(*index == ifindex && *addr == source_protocol_addr));
+    if neighbors.len() == NEIGHBOR_CACHE_LEN {
+        neighbors.pop_front();
+    }
+    neighbors.push_back((ifindex, source_protocol_addr, source_hardware_addr));
+}
+
+/// An Ethernet frame from `iface` to `dst` carrying the IP packet `ip`.
+fn ethernet_frame(iface: &InterfaceWrapper, dst: EthernetAddress, ip: &[u8]) -> Vec<u8> {
+    let ethertype = match ip.first().map(|byte| byte >> 4) {
+        Some(6) => EthernetProtocol::Ipv6,
+        _ => EthernetProtocol::Ipv4,
+    };
+    let mut buf = vec![0; ETHERNET_HEADER_LEN + ip.len()];
+    let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+    frame.set_dst_addr(dst);
+    frame.set_src_addr(iface.ethernet_address());
+    frame.set_ethertype(ethertype);
+    frame.payload_mut().copy_from_slice(ip);
+    buf
+}
+
+fn ipv4_addr(iface: &InterfaceWrapper) -> Option<Ipv4Address> {
+    iface.addrs.lock().iter().find_map(|cidr| match cidr {
+        IpCidr::Ipv4(cidr) => Some(cidr.address()),
+        _ => None,
+    })
+}
+
+fn parse_protocol(name: &str) -> SysResult<IpProtocol> {
+    match name {
+        "tcp" => Ok(IpProtocol::Tcp),
+        "udp" => Ok(IpProtocol::Udp),
+        "icmp" => Ok(IpProtocol::Icmp),
+        "icmpv6" => Ok(IpProtocol::Icmpv6),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+fn protocol_name(protocol: IpProtocol) -> &'static str {
+    match protocol {
+        IpProtocol::Tcp => "tcp",
+        IpProtocol::Udp => "udp",
+        IpProtocol::Icmp => "icmp",
+        _ => "icmpv6",
+    }
+}
+
+/// An address, or a network in the CIDR notation.
+fn parse_cidr(s: &str) -> SysResult<IpCidr> {
+    let (addr, prefix_len) = match s.split_once('/') {
+        Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+        None => (s, None),
+    };
+    let addr: IpAddress = addr.parse().map_err(|_| SysError::EINVAL)?;
+    let max_prefix_len = match addr {
+        IpAddress::Ipv4(_) => 32,
+        IpAddress::Ipv6(_) => 128,
+    };
+    let prefix_len = match prefix_len {
+        Some(prefix_len) => prefix_len.parse().map_err(|_| SysError::EINVAL)?,
+        None => max_prefix_len,
+    };
+    if prefix_len > max_prefix_len {
+        return Err(SysError::EINVAL);
+    }
+    Ok(IpCidr::new(addr, prefix_len))
+}
@@ -0,0 +1,244 @@
+//! Socket tables and protocol counters, shown in `/proc/net/{tcp,udp,snmp}`.
+//!
+//! Sockets are listed from `SOCKET_SET`, except TCP listeners which only exist
+//! in `LISTEN_TABLE`. A socket goes to the IPv6 table if its local address is
+//! an IPv6 one, or if it is an `AF_INET6` socket bound to the wildcard address.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use smoltcp::{
+    phy::Medium,
+    socket::{
+        tcp::{self, State},
+        udp,
+    },
+    wire::{
+        ETHERNET_HEADER_LEN, EthernetFrame, EthernetProtocol, IpAddress, IpEndpoint, IpProtocol,
+        Ipv4Address, Ipv4Packet, Ipv6Address, Ipv6Packet, TcpPacket,
+    },
+};
+
+use crate::{LISTEN_TABLE, SOCKET_SET, udp::udp_sockets_info};
+
+// TCP states as numbered in <net/tcp_states.h>
+pub const TCP_ESTABLISHED: u8 = 1;
+pub const TCP_SYN_SENT: u8 = 2;
+pub const TCP_SYN_RECV: u8 = 3;
+pub const TCP_FIN_WAIT1: u8 = 4;
+pub const TCP_FIN_WAIT2: u8 = 5;
+pub const TCP_TIME_WAIT: u8 = 6;
+pub const TCP_CLOSE: u8 = 7;
+pub const TCP_CLOSE_WAIT: u8 = 8;
+pub const TCP_LAST_ACK: u8 = 9;
+pub const TCP_LISTEN: u8 = 10;
+pub const TCP_CLOSING: u8 = 11;
+
+/// A row of a socket table.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketStat {
+    pub local: IpEndpoint,
+    /// The unspecified address and port 0 if there is no peer.
+    pub remote: IpEndpoint,
+    /// One of the `TCP_*` states. UDP sockets are `TCP_ESTABLISHED` if
+    /// connected and `TCP_CLOSE` otherwise, as on Linux.
+    pub state: u8,
+    pub tx_queue: usize,
+    pub rx_queue: usize,
+}
+
+/// Counters of `/proc/net/snmp`. IP and ICMP ones are about IPv4 only, TCP and
+/// UDP ones cover both families, as on Linux.
+#[derive(Debug)]
+pub struct SnmpStats {
+    pub ip_in_receives: AtomicUsize,
+    pub ip_out_requests: AtomicUsize,
+    pub icmp_in_msgs: AtomicUsize,
+    pub icmp_out_msgs: AtomicUsize,
+    pub tcp_active_opens: AtomicUsize,
+    pub tcp_passive_opens: AtomicUsize,
+    pub tcp_in_segs: AtomicUsize,
+    pub tcp_out_segs: AtomicUsize,
+    pub tcp_out_rsts: AtomicUsize,
+    pub udp_in_datagrams: AtomicUsize,
+    pub udp_out_datagrams: AtomicUsize,
+}
+
+pub static SNMP: SnmpStats = SnmpStats {
+    ip_in_receives: AtomicUsize::new(0),
+    ip_out_requests: AtomicUsize::new(0),
+    icmp_in_msgs: AtomicUsize::new(0),
+    icmp_out_msgs: AtomicUsize::new(0),
+    tcp_active_opens: AtomicUsize::new(0),
+    tcp_passive_opens: AtomicUsize::new(0),
+    tcp_in_segs: AtomicUsize::new(0),
+    tcp_out_segs: AtomicUsize::new(0),
+    tcp_out_rsts: AtomicUsize::new(0),
+    udp_in_datagrams: AtomicUsize::new(0),
+    udp_out_datagrams: AtomicUsize::new(0),
+};
+
+/// Count `frame` received or transmitted by a device of `medium`.
+pub(crate) fn count_frame(frame: &[u8], medium: Medium, outgoing: bool) {
+    let ip_buf = if medium == Medium::Ethernet {
+        let Ok(ether_frame) = EthernetFrame::new_checked(frame) else {
+            return;
+        };
+        if !matches!(
+            ether_frame.ethertype(),
+            EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6
+        ) {
+            return;
+        }
+        &frame[ETHERNET_HEADER_LEN..]
+    } else {
+        frame
+    };
+    let (is_ipv4, protocol, payload) = match ip_buf.first().map(|byte| byte >> 4) {
+        Some(4) => {
+            let Ok(packet) = Ipv4Packet::new_checked(ip_buf) else {
+                return;
+            };
+            (true, packet.next_header(), packet.payload())
+        }
+        Some(6) => {
+            let Ok(packet) = Ipv6Packet::new_checked(ip_buf) else {
+                return;
+            };
+            (false, packet.next_header(), packet.payload())
+        }
+        _ => return,
+    };
+    let bump = |counter: &AtomicUsize| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    };
+    if is_ipv4 {
+        bump(if outgoing {
+            &SNMP.ip_out_requests
+        } else {
+            &SNMP.ip_in_receives
+        });
+    }
+    match protocol {
+        IpProtocol::Icmp if is_ipv4 => bump(if outgoing {
+            &SNMP.icmp_out_msgs
+        } else {
+            &SNMP.icmp_in_msgs
+        }),
+        IpProtocol::Tcp if outgoing => {
+            bump(&SNMP.tcp_out_segs);
+            if TcpPacket::new_checked(payload).is_ok_and(|tcp| tcp.rst()) {
+                bump(&SNMP.tcp_out_rsts);
+            }
+        }
+        IpProtocol::Tcp => bump(&SNMP.tcp_in_segs),
+        IpProtocol::Udp if outgoing => bump(&SNMP.udp_out_datagrams),
+        IpProtocol::Udp => bump(&SNMP.udp_in_datagrams),
+        _ => {}
+    }
+}
+
+/// Number of TCP connections in the established or close wait state, i.e.
+/// `CurrEstab` of `/proc/net/snmp`.
+pub fn tcp_curr_estab() -> usize {
+    SOCKET_SET
+        .0
+        .lock()
+        .iter()
+        .filter_map(|(_, socket)| tcp::Socket::downcast(socket))
+        .filter(|socket| matches!(socket.state(), State::Established | State::CloseWait))
+        .count()
+}
+
+/// TCP sockets of the IPv4 or IPv6 family, listeners first.
+pub fn tcp_sockets(ipv6: bool) -> Vec<SocketStat> {
+    let mut stats: Vec<SocketStat> = LISTEN_TABLE
+        .listeners()
+        .into_iter()
+        .filter(|(endpoint, is_ipv6, _)| {
+            endpoint.addr.map_or(*is_ipv6, |addr| is_ipv6_addr(addr)) == ipv6
+        })
+        .map(|(endpoint, _, accept_queue_len)| SocketStat {
+            local: IpEndpoint::new(endpoint.addr.unwrap_or(unspecified(ipv6)), endpoint.port),
+            remote: IpEndpoint::new(unspecified(ipv6), 0),
+            state: TCP_LISTEN,
+            tx_queue: 0,
+            rx_queue: accept_queue_len,
+        })
+        .collect();
+    let sockets = SOCKET_SET.0.lock();
+    for (_, socket) in sockets.iter() {
+        let Some(socket) = tcp::Socket::downcast(socket) else {
+            continue;
+        };
+        // Sockets waiting for a SYN belong to a listener shown above.
+        let state = match socket.state() {
+            State::Closed | State::Listen => continue,
+            State::SynSent => TCP_SYN_SENT,
+            State::SynReceived => TCP_SYN_RECV,
+            State::Established => TCP_ESTABLISHED,
+            State::FinWait1 => TCP_FIN_WAIT1,
+            State::FinWait2 => TCP_FIN_WAIT2,
+            State::CloseWait => TCP_CLOSE_WAIT,
+            State::Closing => TCP_CLOSING,
+            State::LastAck => TCP_LAST_ACK,
+            State::TimeWait => TCP_TIME_WAIT,
+        };
+        let (Some(local), Some(remote)) = (socket.local_endpoint(), socket.remote_endpoint())
+        else {
+            continue;
+        };
+        if is_ipv6_addr(local.addr) != ipv6 {
+            continue;
+        }
+        stats.push(SocketStat {
+            local,
+            remote,
+            state,
+            tx_queue: socket.send_queue(),
+            rx_queue: socket.recv_queue(),
+        });
+    }
+    stats
+}
+
+/// Bound UDP sockets of the IPv4 or IPv6 family.
+pub fn udp_sockets(ipv6: bool) -> Vec<SocketStat> {
+    // A socket leaves the registry before `SOCKET_SET`, so every socket in a
+    // registry snapshot taken under the lock of the set is in the set.
+    let sockets = SOCKET_SET.0.lock();
+    udp_sockets_info()
+        .into_iter()
+        .filter_map(|(handle, is_ipv6, peer)| {
+            let socket = sockets.get::<udp::Socket>(handle);
+            let endpoint = socket.endpoint();
+            if !socket.is_open() || endpoint.addr.map_or(is_ipv6, |addr| is_ipv6_addr(addr)) != ipv6
+            {
+                return None;
+            }
+            Some(SocketStat {
+                local: IpEndpoint::new(endpoint.addr.unwrap_or(unspecified(ipv6)), endpoint.port),
+                remote: peer.unwrap_or(IpEndpoint::new(unspecified(ipv6), 0)),
+                state: if peer.is_some() {
+                    TCP_ESTABLISHED
+                } else {
+                    TCP_CLOSE
+                },
+                tx_queue: socket.send_queue(),
+                rx_queue: socket.recv_queue(),
+            })
+        })
+        .collect()
+}
+
+fn is_ipv6_addr(addr: IpAddress) -> bool {
+    matches!(addr, IpAddress::Ipv6(_))
+}
+
+fn unspecified(ipv6: bool) -> IpAddress {
+    if ipv6 {
+        Ipv6Address::UNSPECIFIED.into()
+    } else {
+        Ipv4Address::UNSPECIFIED.into()
+    }
+}
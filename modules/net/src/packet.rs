@@ -0,0 +1,505 @@
+//! Packet sockets, i.e. `socket(AF_PACKET, SOCK_RAW | SOCK_DGRAM, protocol)`.
+//!
+//! Every frame an interface receives or transmits is copied to the packet
+//! sockets bound to its protocol and interface, and to the pcap ring if it is
+//! enabled. A `SOCK_RAW` socket sees the whole frame with its link layer
+//! header, a `SOCK_DGRAM` socket sees the payload only, the header is described
+//! by the address returned with it. Frames of the loopback interface get an
+//! Ethernet header of zero addresses like on Linux, frames of IP interfaces
+//! such as TUN have no link layer header at all.
+
+use alloc::{borrow::Cow, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
+
+use async_utils::{get_waker, suspend_now, yield_now};
+use device_core::{Medium, NetDevice};
+use log::{info, warn};
+use systype::{SysError, SysResult};
+
+use crate::{
+    MsgFlags, Mutex, NetPollState, SOCKET_SET,
+    bpf::BpfProgram,
+    has_signal,
+    iface::{self, LOOPBACK_IFINDEX},
+    pcap,
+};
+
+// Protocols, defined in <linux/if_ether.h>
+/// Every protocol, including outgoing frames.
+pub const ETH_P_ALL: u16 = 0x0003;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+
+// Packet types, defined in <linux/if_packet.h>
+pub const PACKET_HOST: u8 = 0;
+pub const PACKET_BROADCAST: u8 = 1;
+pub const PACKET_MULTICAST: u8 = 2;
+pub const PACKET_OTHERHOST: u8 = 3;
+pub const PACKET_OUTGOING: u8 = 4;
+
+// Hardware types, defined in <linux/if_arp.h>
+pub const ARPHRD_ETHER: u16 = 1;
+pub const ARPHRD_LOOPBACK: u16 = 772;
+pub const ARPHRD_NONE: u16 = 0xfffe;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+/// Bytes of frames a socket holds, new frames are dropped beyond this.
+const PACKET_RX_BUF_LEN: usize = 256 * 1024;
+
+/// Sockets frames are copied to.
+static PACKET_SOCKETS: Mutex<Vec<Arc<PacketSocketInner>>> = Mutex::new(Vec::new());
+
+/// Link level address of a frame, i.e. `struct sockaddr_ll` in host byte
+/// order.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkAddr {
+    pub ifindex: usize,
+    /// Ethernet type of the payload.
+    pub protocol: u16,
+    pub hatype: u16,
+    pub pkttype: u8,
+    pub halen: u8,
+    /// Source hardware address for received frames, destination for sent
+    /// ones.
+    pub addr: [u8; 8],
+}
+
+/// How the frames of an interface are presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LinkType {
+    Ethernet,
+    /// IP packets with a made up Ethernet header.
+    Loopback,
+    /// IP packets without any header.
+    RawIp,
+}
+
+impl LinkType {
+    pub(crate) fn of(ifindex: usize, medium: Medium) -> Self {
+        match medium {
+            Medium::Ethernet => Self::Ethernet,
+            Medium::Ip if ifindex == LOOPBACK_IFINDEX => Self::Loopback,
+            Medium::Ip => Self::RawIp,
+        }
+    }
+
+    pub(crate) fn hatype(self) -> u16 {
+        match self {
+            Self::Ethernet => ARPHRD_ETHER,
+            Self::Loopback => ARPHRD_LOOPBACK,
+            Self::RawIp => ARPHRD_NONE,
+        }
+    }
+
+    /// Length of the link layer header seen by user space.
+    pub(crate) fn header_len(self) -> usize {
+        match self {
+            Self::Ethernet | Self::Loopback => ETHERNET_HEADER_LEN,
+            Self::RawIp => 0,
+        }
+    }
+}
+
+/// Whether frames have to be copied at all.
+fn has_taps() -> bool {
+    pcap::is_enabled() || !PACKET_SOCKETS.lock().is_empty()
+}
+
+/// Copy `frame` received or transmitted by `dev` on interface `ifindex` to
+/// packet sockets and the pcap ring.
+pub(crate) fn tap(dev: &dyn NetDevice, ifindex: usize, frame: &[u8], outgoing: bool) {
+    if !has_taps() {
+        return;
+    }
+    let link = LinkType::of(ifindex, dev.capabilities().medium);
+    let Some((frame, addr)) = present(link, ifindex, dev.mac_address().0, frame, outgoing) else {
+        return;
+    };
+    if pcap::is_enabled() {
+        pcap::record(&frame[link.header_len()..], &addr);
+    }
+    let sockets = PACKET_SOCKETS.lock().clone();
+    for socket in sockets {
+        socket.deliver(&frame, &addr, link);
+    }
+}
+
+/// The frame as user space sees it, and its address.
+fn present(
+    link: LinkType,
+    ifindex: usize,
+    own_mac: [u8; 6],
+    frame: &[u8],
+    outgoing: bool,
+) -> Option<(Cow<'_, [u8]>, LinkAddr)> {
+    let mut addr = LinkAddr {
+        ifindex,
+        hatype: link.hatype(),
+        pkttype: if outgoing {
+            PACKET_OUTGOING
+        } else {
+            PACKET_HOST
+        },
+        ..Default::default()
+    };
+    match link {
+        LinkType::Ethernet => {
+            if frame.len() < ETHERNET_HEADER_LEN {
+                return None;
+            }
+            addr.protocol = u16::from_be_bytes([frame[12], frame[13]]);
+            addr.halen = 6;
+            addr.addr[..6].copy_from_slice(&frame[6..12]);
+            let dst = &frame[..6];
+            if !outgoing {
+                addr.pkttype = if dst == [0xff; 6] {
+                    PACKET_BROADCAST
+                } else if dst[0] & 1 != 0 {
+                    PACKET_MULTICAST
+                } else if dst == own_mac {
+                    PACKET_HOST
+                } else {
+                    PACKET_OTHERHOST
+                };
+            }
+            Some((Cow::Borrowed(frame), addr))
+        }
+        LinkType::Loopback => {
+            addr.protocol = ip_protocol(frame)?;
+            addr.halen = 6;
+            let mut with_header = Vec::with_capacity(ETHERNET_HEADER_LEN + frame.len());
+            with_header.extend_from_slice(&[0; 12]);
+            with_header.extend_from_slice(&addr.protocol.to_be_bytes());
+            with_header.extend_from_slice(frame);
+            Some((Cow::Owned(with_header), addr))
+        }
+        LinkType::RawIp => {
+            addr.protocol = ip_protocol(frame)?;
+            Some((Cow::Borrowed(frame), addr))
+        }
+    }
+}
+
+/// Ethernet type of an IP packet.
+fn ip_protocol(packet: &[u8]) -> Option<u16> {
+    match packet.first()? >> 4 {
+        4 => Some(ETH_P_IP),
+        6 => Some(ETH_P_IPV6),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Binding {
+    /// Ethernet type of frames received, in host byte order.
+    protocol: u16,
+    /// 0 for every interface.
+    ifindex: usize,
+}
+
+struct PacketQueue {
+    packets: VecDeque<(Vec<u8>, LinkAddr)>,
+    /// Total length of `packets`.
+    len: usize,
+    /// Frames queued and dropped since the statistics were last taken.
+    received: u32,
+    dropped: u32,
+    waker: Option<Waker>,
+}
+
+struct PacketSocketInner {
+    /// `SOCK_DGRAM`, the link layer header is removed.
+    cooked: bool,
+    binding: Mutex<Binding>,
+    filter: Mutex<Option<Arc<BpfProgram>>>,
+    rx: Mutex<PacketQueue>,
+}
+
+impl PacketSocketInner {
+    fn deliver(&self, frame: &[u8], addr: &LinkAddr, link: LinkType) {
+        let binding = *self.binding.lock();
+        // Only sockets of all protocols see outgoing frames, as on Linux.
+        let protocol_matches = binding.protocol == ETH_P_ALL
+            || (binding.protocol == addr.protocol && addr.pkttype != PACKET_OUTGOING);
+        if !protocol_matches || (binding.ifindex != 0 && binding.ifindex != addr.ifindex) {
+            return;
+        }
+        let data = if self.cooked {
+            &frame[link.header_len()..]
+        } else {
+            frame
+        };
+        let filter = self.filter.lock().clone();
+        let snap_len = match filter {
+            Some(filter) => filter.run(data) as usize,
+            None => data.len(),
+        };
+        if snap_len == 0 {
+            return;
+        }
+        let data = &data[..snap_len.min(data.len())];
+        let mut rx = self.rx.lock();
+        if rx.len + data.len() > PACKET_RX_BUF_LEN {
+            warn!("[PacketSocket::deliver] receive buffer full, drop a frame");
+            rx.dropped = rx.dropped.wrapping_add(1);
+            return;
+        }
+        rx.len += data.len();
+        rx.received = rx.received.wrapping_add(1);
+        rx.packets.push_back((data.to_vec(), *addr));
+        if let Some(waker) = rx.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A packet socket that provides POSIX-like APIs.
+pub struct PacketSocket {
+    inner: Arc<PacketSocketInner>,
+    /// Indicates if the socket is in nonblocking mode.
+    nonblock: AtomicBool,
+}
+
+impl PacketSocket {
+    /// Creates a packet socket receiving frames of `protocol` from every
+    /// interface. It receives nothing if `protocol` is 0 until it is bound to
+    /// a protocol.
+    pub fn new(cooked: bool, protocol: u16) -> Self {
+        let inner = Arc::new(PacketSocketInner {
+            cooked,
+            binding: Mutex::new(Binding {
+                protocol,
+                ifindex: 0,
+            }),
+            filter: Mutex::new(None),
+            rx: Mutex::new(PacketQueue {
+                packets: VecDeque::new(),
+                len: 0,
+                received: 0,
+                dropped: 0,
+                waker: None,
+            }),
+        });
+        PACKET_SOCKETS.lock().push(inner.clone());
+        Self {
+            inner,
+            nonblock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this socket into or out of nonblocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Receive frames of `protocol` from interface `ifindex` only, 0 for every
+    /// interface. The protocol is kept if `protocol` is 0.
+    pub fn bind(&self, protocol: u16, ifindex: usize) -> SysResult<()> {
+        if ifindex != 0 && iface::get(ifindex).is_none() {
+            return Err(SysError::ENODEV);
+        }
+        let mut binding = self.inner.binding.lock();
+        if protocol != 0 {
+            binding.protocol = protocol;
+        }
+        binding.ifindex = ifindex;
+        info!("[PacketSocket::bind] {binding:?}");
+        Ok(())
+    }
+
+    /// Returns the bound protocol and interface, with the hardware address of
+    /// the interface.
+    pub fn local_addr(&self) -> LinkAddr {
+        let binding = *self.inner.binding.lock();
+        let mut addr = LinkAddr {
+            ifindex: binding.ifindex,
+            protocol: binding.protocol,
+            ..Default::default()
+        };
+        if let Some(iface) = iface::get(binding.ifindex) {
+            let link = LinkType::of(iface.index, iface.medium());
+            addr.hatype = link.hatype();
+            if link != LinkType::RawIp {
+                addr.halen = 6;
+                addr.addr[..6].copy_from_slice(&iface.ethernet_address().0);
+            }
+        }
+        addr
+    }
+
+    /// Replace the filter, `None` removes it.
+    pub fn set_filter(&self, filter: Option<BpfProgram>) {
+        *self.inner.filter.lock() = filter.map(Arc::new);
+    }
+
+    /// Returns the number of frames received, including the dropped ones, and
+    /// the number of frames dropped, i.e. `PACKET_STATISTICS`. The counters
+    /// are reset.
+    pub fn take_stats(&self) -> (u32, u32) {
+        let mut rx = self.inner.rx.lock();
+        let stats = (rx.received.wrapping_add(rx.dropped), rx.dropped);
+        rx.received = 0;
+        rx.dropped = 0;
+        stats
+    }
+
+    /// Sends `buf` through interface `addr.ifindex`, or the bound interface if
+    /// `addr` is not given. A `SOCK_DGRAM` socket sends `buf` to hardware
+    /// address `addr.addr` as protocol `addr.protocol`.
+    pub async fn send_to(
+        &self,
+        buf: &[u8],
+        addr: Option<LinkAddr>,
+        flags: MsgFlags,
+    ) -> SysResult<usize> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        let binding = *self.inner.binding.lock();
+        let ifindex = addr.map_or(binding.ifindex, |addr| addr.ifindex);
+        let iface = iface::get(ifindex).ok_or(SysError::ENXIO)?;
+        if !iface.is_up() {
+            return Err(SysError::ENETDOWN);
+        }
+        let link = LinkType::of(ifindex, iface.medium());
+        let frame = match (link, self.inner.cooked) {
+            (LinkType::Ethernet, true) => {
+                let addr = addr.ok_or(SysError::EINVAL)?;
+                if addr.halen < 6 {
+                    return Err(SysError::EINVAL);
+                }
+                let protocol = if addr.protocol != 0 {
+                    addr.protocol
+                } else {
+                    binding.protocol
+                };
+                let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + buf.len());
+                frame.extend_from_slice(&addr.addr[..6]);
+                frame.extend_from_slice(&iface.ethernet_address().0);
+                frame.extend_from_slice(&protocol.to_be_bytes());
+                frame.extend_from_slice(buf);
+                frame
+            }
+            (LinkType::Ethernet, false) if buf.len() < ETHERNET_HEADER_LEN => {
+                return Err(SysError::EINVAL);
+            }
+            (LinkType::Loopback, false) => buf
+                .get(ETHERNET_HEADER_LEN..)
+                .ok_or(SysError::EINVAL)?
+                .to_vec(),
+            _ => buf.to_vec(),
+        };
+        let header_len = match link {
+            LinkType::Ethernet => ETHERNET_HEADER_LEN,
+            LinkType::Loopback | LinkType::RawIp => 0,
+        };
+        if frame.len() - header_len > iface.mtu {
+            return Err(SysError::EMSGSIZE);
+        }
+        iface.transmit_frame(&frame)?;
+        // Let the stack handle frames looped back.
+        SOCKET_SET.poll_interfaces();
+        yield_now().await;
+        Ok(buf.len())
+    }
+
+    /// Receives a frame. Returns the length of the frame, which is larger than
+    /// `buf` if it was truncated.
+    pub async fn recv_from(&self, buf: &mut [u8], flags: MsgFlags) -> SysResult<(usize, LinkAddr)> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        let waker = get_waker().await;
+        self.block_on(self.nonblock(flags), || {
+            let mut rx = self.inner.rx.lock();
+            let Some((packet, addr)) = rx.packets.front() else {
+                rx.waker = Some(waker.clone());
+                return Err(SysError::EAGAIN);
+            };
+            let len = packet.len().min(buf.len());
+            buf[..len].copy_from_slice(&packet[..len]);
+            let ret = (packet.len(), *addr);
+            if !flags.contains(MsgFlags::PEEK) {
+                let (packet, _) = rx.packets.pop_front().unwrap();
+                rx.len -= packet.len();
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    /// Whether the socket is readable or writable.
+    pub async fn poll(&self) -> NetPollState {
+        let waker = get_waker().await;
+        let mut rx = self.inner.rx.lock();
+        let readable = !rx.packets.is_empty();
+        if !readable {
+            rx.waker = Some(waker);
+        }
+        NetPollState {
+            readable,
+            writable: true,
+            hangup: false,
+        }
+    }
+
+    /// Close the socket.
+    pub fn shutdown(&self) -> SysResult<()> {
+        info!("[PacketSocket::shutdown]");
+        Ok(())
+    }
+}
+
+/// Private methods
+impl PacketSocket {
+    /// Whether this call does not block, i.e. the socket is nonblocking or
+    /// `MSG_DONTWAIT` is given.
+    #[inline]
+    fn nonblock(&self, flags: MsgFlags) -> bool {
+        self.is_nonblocking() || flags.contains(MsgFlags::DONTWAIT)
+    }
+
+    async fn block_on<F, T>(&self, nonblock: bool, mut f: F) -> SysResult<T>
+    where
+        F: FnMut() -> SysResult<T>,
+    {
+        if nonblock {
+            f()
+        } else {
+            loop {
+                let timestamp = SOCKET_SET.poll_interfaces();
+                let ret = f();
+                SOCKET_SET.check_poll(timestamp);
+                match ret {
+                    Ok(t) => return Ok(t),
+                    Err(SysError::EAGAIN) => {
+                        suspend_now().await;
+                        if has_signal() {
+                            warn!("[PacketSocket::block_on] has signal");
+                            return Err(SysError::EINTR);
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        PACKET_SOCKETS
+            .lock()
+            .retain(|socket| !Arc::ptr_eq(socket, &self.inner));
+    }
+}
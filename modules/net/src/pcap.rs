@@ -0,0 +1,90 @@
+//! A ring of the last frames seen on any interface, dumped in the pcap format
+//! through `/proc/net/pcap`.
+//!
+//! Capturing is off until it is enabled. Frames of every interface go to the
+//! same ring, so records use the Linux cooked header (`LINKTYPE_LINUX_SLL`)
+//! instead of the header of the interface, as `tcpdump -i any` does.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use arch::time::get_time_us;
+
+use crate::{Mutex, packet::LinkAddr};
+
+/// Number of frames kept.
+const PCAP_RING_LEN: usize = 256;
+/// Max length of a record, including the cooked header.
+const PCAP_SNAPLEN: usize = 2048;
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const SLL_HEADER_LEN: usize = 16;
+
+struct PcapRecord {
+    time_us: u64,
+    /// Length of the frame before it was truncated to `PCAP_SNAPLEN`.
+    orig_len: usize,
+    /// Cooked header and the frame.
+    data: Vec<u8>,
+}
+
+static PCAP_ENABLED: AtomicBool = AtomicBool::new(false);
+static PCAP_RING: Mutex<VecDeque<PcapRecord>> = Mutex::new(VecDeque::new());
+
+/// Start or stop capturing. Frames captured before are discarded when it is
+/// started.
+pub fn set_enabled(enabled: bool) {
+    if enabled {
+        PCAP_RING.lock().clear();
+    }
+    PCAP_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    PCAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record the frame whose link layer payload is `payload`.
+pub(crate) fn record(payload: &[u8], addr: &LinkAddr) {
+    // struct sll_header in <pcap/sll.h>
+    let mut data = Vec::with_capacity(SLL_HEADER_LEN + payload.len());
+    data.extend_from_slice(&(addr.pkttype as u16).to_be_bytes());
+    data.extend_from_slice(&addr.hatype.to_be_bytes());
+    data.extend_from_slice(&(addr.halen as u16).to_be_bytes());
+    data.extend_from_slice(&addr.addr);
+    data.extend_from_slice(&addr.protocol.to_be_bytes());
+    let len = payload.len().min(PCAP_SNAPLEN - SLL_HEADER_LEN);
+    data.extend_from_slice(&payload[..len]);
+
+    let mut ring = PCAP_RING.lock();
+    if ring.len() == PCAP_RING_LEN {
+        ring.pop_front();
+    }
+    ring.push_back(PcapRecord {
+        time_us: get_time_us() as u64,
+        orig_len: SLL_HEADER_LEN + payload.len(),
+        data,
+    });
+}
+
+/// The frames in the ring as a pcap file.
+pub fn dump() -> Vec<u8> {
+    let ring = PCAP_RING.lock();
+    let mut file = Vec::new();
+    // struct pcap_file_header in <pcap/pcap.h>
+    file.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+    file.extend_from_slice(&2u16.to_le_bytes());
+    file.extend_from_slice(&4u16.to_le_bytes());
+    file.extend_from_slice(&0i32.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&(PCAP_SNAPLEN as u32).to_le_bytes());
+    file.extend_from_slice(&LINKTYPE_LINUX_SLL.to_le_bytes());
+    for record in ring.iter() {
+        file.extend_from_slice(&((record.time_us / 1_000_000) as u32).to_le_bytes());
+        file.extend_from_slice(&((record.time_us % 1_000_000) as u32).to_le_bytes());
+        file.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(record.orig_len as u32).to_le_bytes());
+        file.extend_from_slice(&record.data);
+    }
+    file
+}
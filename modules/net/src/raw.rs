@@ -0,0 +1,377 @@
+//! Raw IP sockets.
+//!
+//! Like Linux, an IPv4 raw socket receives whole packets including the IP
+//! header, while an IPv6 raw socket only receives the payload. Data sent is
+//! always the payload, the IP header is built here since smoltcp expects a
+//! complete packet. The checksum of ICMPv6 messages is filled in by the kernel.
+
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use async_utils::{get_waker, suspend_now, yield_now};
+use log::{info, warn};
+use smoltcp::{
+    iface::SocketHandle,
+    phy::ChecksumCapabilities,
+    socket::raw,
+    wire::{
+        IPV4_HEADER_LEN, IPV6_HEADER_LEN, IpAddress, IpEndpoint, IpProtocol, IpVersion, Ipv4Packet,
+        Ipv4Repr, Ipv6Packet, Ipv6Repr,
+    },
+};
+use spin::RwLock;
+use systype::{SysError, SysResult};
+
+use crate::{
+    DEFAULT_HOP_LIMIT, MsgFlags, NetPollState, SOCKET_SET, SocketSetWrapper,
+    addr::UNSPECIFIED_IPV4, has_signal, iface,
+};
+
+/// A raw IP socket that provides POSIX-like APIs.
+pub struct RawSocket {
+    /// Handle obtained after adding the newly created socket to SOCKET_SET.
+    handle: SocketHandle,
+    /// Whether this is an `AF_INET6` socket.
+    ipv6: bool,
+    /// The IP protocol of packets sent and received.
+    protocol: IpProtocol,
+    /// Source address given by `bind`.
+    local_addr: RwLock<Option<IpAddress>>,
+    /// Default destination given by `connect`, also used to filter received
+    /// packets.
+    peer_addr: RwLock<Option<IpAddress>>,
+    /// Indicates if the socket is in nonblocking mode.
+    nonblock: AtomicBool,
+    /// Hop limit of packets sent.
+    hop_limit: AtomicU8,
+}
+
+impl RawSocket {
+    /// Creates a new raw socket of IP protocol number `protocol`.
+    pub fn new(ipv6: bool, protocol: u8) -> Self {
+        let protocol = IpProtocol::from(protocol);
+        let ip_version = if ipv6 {
+            IpVersion::Ipv6
+        } else {
+            IpVersion::Ipv4
+        };
+        let socket = SocketSetWrapper::new_raw_socket(ip_version, protocol);
+        let handle = SOCKET_SET.add(socket);
+        Self {
+            handle,
+            ipv6,
+            protocol,
+            local_addr: RwLock::new(None),
+            peer_addr: RwLock::new(None),
+            nonblock: AtomicBool::new(false),
+            hop_limit: AtomicU8::new(DEFAULT_HOP_LIMIT),
+        }
+    }
+
+    /// Returns the bound address, the port is always 0.
+    pub fn local_addr(&self) -> SysResult<IpEndpoint> {
+        let addr = (*self.local_addr.read()).unwrap_or(UNSPECIFIED_IPV4);
+        Ok(IpEndpoint::new(addr, 0))
+    }
+
+    /// Returns the connected address, the port is always 0.
+    pub fn peer_addr(&self) -> SysResult<IpEndpoint> {
+        let addr = (*self.peer_addr.read()).ok_or(SysError::ENOTCONN)?;
+        Ok(IpEndpoint::new(addr, 0))
+    }
+
+    /// Returns whether this socket is in nonblocking mode.
+    #[inline]
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblock.load(Ordering::Acquire)
+    }
+
+    /// Moves this socket into or out of nonblocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblock.store(nonblocking, Ordering::Release);
+    }
+
+    /// Returns the hop limit of packets sent.
+    #[inline]
+    pub fn hop_limit(&self) -> u8 {
+        self.hop_limit.load(Ordering::Acquire)
+    }
+
+    /// Sets the hop limit of packets sent, `None` for the default.
+    #[inline]
+    pub fn set_hop_limit(&self, hop_limit: Option<u8>) {
+        let hop_limit = hop_limit.unwrap_or(DEFAULT_HOP_LIMIT);
+        self.hop_limit.store(hop_limit, Ordering::Release);
+    }
+
+    /// Use `addr` as the source address of packets sent. The unspecified
+    /// address lets the kernel choose one per packet.
+    pub fn bind(&self, addr: IpAddress) -> SysResult<()> {
+        self.check_family(addr)?;
+        if !addr.is_unspecified() && !iface::is_local_addr(addr) && !addr.is_loopback() {
+            return Err(SysError::EADDRNOTAVAIL);
+        }
+        *self.local_addr.write() = (!addr.is_unspecified()).then_some(addr);
+        Ok(())
+    }
+
+    /// Set the default destination, only packets from `addr` are received
+    /// afterwards.
+    pub fn connect(&self, addr: IpAddress) -> SysResult<()> {
+        self.check_family(addr)?;
+        *self.peer_addr.write() = Some(addr);
+        Ok(())
+    }
+
+    /// Sends a packet with payload `buf` to `dst`.
+    pub async fn send_to(&self, buf: &[u8], dst: IpAddress, flags: MsgFlags) -> SysResult<usize> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        self.check_family(dst)?;
+        let src = match *self.local_addr.read() {
+            Some(src) => src,
+            None => iface::source_addr(dst).ok_or_else(|| {
+                warn!("[RawSocket::send_to] no route to {dst}");
+                SysError::ENETUNREACH
+            })?,
+        };
+        let packet = self.build_packet(src, dst, buf)?;
+        let waker = get_waker().await;
+        self.block_on(self.nonblock(flags), || {
+            SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+                if !socket.can_send() {
+                    socket.register_send_waker(&waker);
+                    return Err(SysError::EAGAIN);
+                }
+                socket.send_slice(&packet).map_err(|e| {
+                    warn!("[RawSocket::send_to] failed: {e:?}");
+                    SysError::EMSGSIZE
+                })
+            })
+        })
+        .await?;
+        SOCKET_SET.poll_interfaces();
+        yield_now().await;
+        Ok(buf.len())
+    }
+
+    /// Sends a packet to the connected address.
+    pub async fn send(&self, buf: &[u8], flags: MsgFlags) -> SysResult<usize> {
+        let dst = (*self.peer_addr.read()).ok_or(SysError::EDESTADDRREQ)?;
+        self.send_to(buf, dst, flags).await
+    }
+
+    /// Receives a packet, see the module doc for what is received. Returns the
+    /// length of the packet, which is larger than `buf` if it was truncated.
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+        flags: MsgFlags,
+    ) -> SysResult<(usize, IpEndpoint)> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        let peer = *self.peer_addr.read();
+        let waker = get_waker().await;
+        let ret = self
+            .block_on(self.nonblock(flags), || {
+                SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+                    loop {
+                        let ret = {
+                            let Ok(packet) = socket.peek() else {
+                                socket.register_recv_waker(&waker);
+                                return Err(SysError::EAGAIN);
+                            };
+                            self.parse_packet(packet, peer).map(|(src, data)| {
+                                let len = data.len().min(buf.len());
+                                buf[..len].copy_from_slice(&data[..len]);
+                                (data.len(), IpEndpoint::new(src, 0))
+                            })
+                        };
+                        // Packets filtered out are always dropped.
+                        if ret.is_none() || !flags.contains(MsgFlags::PEEK) {
+                            let _ = socket.recv();
+                        }
+                        if let Some(ret) = ret {
+                            return Ok(ret);
+                        }
+                    }
+                })
+            })
+            .await;
+        yield_now().await;
+        ret
+    }
+
+    /// Whether the socket is readable or writable.
+    pub async fn poll(&self) -> NetPollState {
+        let waker = get_waker().await;
+        SOCKET_SET.with_socket_mut::<raw::Socket, _, _>(self.handle, |socket| {
+            let readable = socket.can_recv();
+            let writable = socket.can_send();
+            if !readable {
+                socket.register_recv_waker(&waker);
+            }
+            if !writable {
+                socket.register_send_waker(&waker);
+            }
+            NetPollState {
+                readable,
+                writable,
+                hangup: false,
+            }
+        })
+    }
+
+    /// Close the socket.
+    pub fn shutdown(&self) -> SysResult<()> {
+        info!("[RawSocket::shutdown] handle {}", self.handle);
+        Ok(())
+    }
+}
+
+/// Private methods
+impl RawSocket {
+    fn check_family(&self, addr: IpAddress) -> SysResult<()> {
+        match (addr, self.ipv6) {
+            (IpAddress::Ipv4(_), false) | (IpAddress::Ipv6(_), true) => Ok(()),
+            _ => Err(SysError::EAFNOSUPPORT),
+        }
+    }
+
+    /// Whether this call does not block, i.e. the socket is nonblocking or
+    /// `MSG_DONTWAIT` is given.
+    #[inline]
+    fn nonblock(&self, flags: MsgFlags) -> bool {
+        self.is_nonblocking() || flags.contains(MsgFlags::DONTWAIT)
+    }
+
+    /// Returns the source and the data of a received packet, or `None` if it
+    /// is malformed or not from `peer`.
+    fn parse_packet<'a>(
+        &self,
+        packet: &'a [u8],
+        peer: Option<IpAddress>,
+    ) -> Option<(IpAddress, &'a [u8])> {
+        let (src, data) = if self.ipv6 {
+            let packet = Ipv6Packet::new_checked(packet).ok()?;
+            (IpAddress::Ipv6(packet.src_addr()), packet.payload())
+        } else {
+            let ipv4_packet = Ipv4Packet::new_checked(packet).ok()?;
+            (IpAddress::Ipv4(ipv4_packet.src_addr()), packet)
+        };
+        if peer.is_some_and(|peer| peer != src) {
+            return None;
+        }
+        Some((src, data))
+    }
+
+    /// Put `payload` behind an IP header from `src` to `dst`.
+    fn build_packet(&self, src: IpAddress, dst: IpAddress, payload: &[u8]) -> SysResult<Vec<u8>> {
+        match (src, dst) {
+            (IpAddress::Ipv4(src_addr), IpAddress::Ipv4(dst_addr)) => {
+                let repr = Ipv4Repr {
+                    src_addr,
+                    dst_addr,
+                    next_header: self.protocol,
+                    payload_len: payload.len(),
+                    hop_limit: self.hop_limit(),
+                };
+                let mut packet = vec![0; IPV4_HEADER_LEN + payload.len()];
+                repr.emit(
+                    &mut Ipv4Packet::new_unchecked(&mut packet),
+                    &ChecksumCapabilities::default(),
+                );
+                packet[IPV4_HEADER_LEN..].copy_from_slice(payload);
+                Ok(packet)
+            }
+            (IpAddress::Ipv6(src_addr), IpAddress::Ipv6(dst_addr)) => {
+                let repr = Ipv6Repr {
+                    src_addr,
+                    dst_addr,
+                    next_header: self.protocol,
+                    payload_len: payload.len(),
+                    hop_limit: self.hop_limit(),
+                };
+                let mut packet = vec![0; IPV6_HEADER_LEN + payload.len()];
+                repr.emit(&mut Ipv6Packet::new_unchecked(&mut packet));
+                packet[IPV6_HEADER_LEN..].copy_from_slice(payload);
+                if self.protocol == IpProtocol::Icmpv6 {
+                    fill_icmp_checksum(src, dst, &mut packet[IPV6_HEADER_LEN..]);
+                }
+                Ok(packet)
+            }
+            _ => Err(SysError::EAFNOSUPPORT),
+        }
+    }
+
+    async fn block_on<F, T>(&self, nonblock: bool, mut f: F) -> SysResult<T>
+    where
+        F: FnMut() -> SysResult<T>,
+    {
+        if nonblock {
+            f()
+        } else {
+            loop {
+                let timestamp = SOCKET_SET.poll_interfaces();
+                let ret = f();
+                SOCKET_SET.check_poll(timestamp);
+                match ret {
+                    Ok(t) => return Ok(t),
+                    Err(SysError::EAGAIN) => {
+                        suspend_now().await;
+                        if has_signal() {
+                            warn!("[RawSocket::block_on] has signal");
+                            return Err(SysError::EINTR);
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        SOCKET_SET.remove(self.handle);
+    }
+}
+
+/// Fill the checksum of ICMP message `msg` sent from `src` to `dst`. ICMPv6
+/// covers an IPv6 pseudo header, ICMPv4 only covers the message.
+pub(crate) fn fill_icmp_checksum(src: IpAddress, dst: IpAddress, msg: &mut [u8]) {
+    if msg.len() < 4 {
+        return;
+    }
+    msg[2..4].fill(0);
+    let mut sum = 0u32;
+    if let (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) = (src, dst) {
+        sum += ones_complement_sum(src.as_bytes());
+        sum += ones_complement_sum(dst.as_bytes());
+        sum += ones_complement_sum(&(msg.len() as u32).to_be_bytes());
+        sum += u8::from(IpProtocol::Icmpv6) as u32;
+    }
+    sum += ones_complement_sum(msg);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    msg[2..4].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+}
+
+fn ones_complement_sum(data: &[u8]) -> u32 {
+    let mut sum = data
+        .chunks(2)
+        .map(|word| match word {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]) as u32,
+            [hi] => (*hi as u32) << 8,
+            _ => unreachable!(),
+        })
+        .fold(0u32, |sum, word| sum + word);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum
+}
@@ -0,0 +1,165 @@
+//! Kernel routing table.
+//!
+//! Every interface only knows the routes assigned to it in this table, so
+//! smoltcp can only emit a packet on an interface that has a route for its
+//! destination. Destinations owned by the host itself are always routed
+//! through the loopback interface.
+
+use alloc::vec::Vec;
+
+use smoltcp::{
+    iface::Route,
+    wire::{IpAddress, IpCidr},
+};
+use spin::Lazy;
+use systype::{SysError, SysResult};
+
+use crate::{Mutex, iface};
+
+pub(crate) static ROUTE_TABLE: Lazy<Mutex<RouteTable>> =
+    Lazy::new(|| Mutex::new(RouteTable::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteEntry {
+    /// Destination network.
+    pub dest: IpCidr,
+    /// Next hop, `None` means the destination is directly reachable.
+    pub gateway: Option<IpAddress>,
+    /// Index of the output interface.
+    pub ifindex: usize,
+    pub metric: u32,
+}
+
+pub struct RouteTable {
+    routes: Vec<RouteEntry>,
+}
+
+impl RouteTable {
+    const fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    pub fn routes(&self) -> &[RouteEntry] {
+        &self.routes
+    }
+
+    /// Fails with `EEXIST` if the interface already has a route to the same
+    /// destination with the same metric.
+    pub fn add(&mut self, entry: RouteEntry) -> SysResult<()> {
+        if self
+            .routes
+            .iter()
+            .any(|r| r.dest == entry.dest && r.metric == entry.metric && r.ifindex == entry.ifindex)
+        {
+            return Err(SysError::EEXIST);
+        }
+        self.routes.push(entry);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, dest: IpCidr, ifindex: usize) -> SysResult<()> {
+        let len = self.routes.len();
+        self.routes
+            .retain(|r| !(r.dest == dest && r.ifindex == ifindex));
+        if self.routes.len() == len {
+            return Err(SysError::ESRCH);
+        }
+        Ok(())
+    }
+
+    /// Remove the directly connected routes of interface `ifindex`.
+    pub fn remove_connected(&mut self, ifindex: usize) {
+        self.routes
+            .retain(|r| !(r.ifindex == ifindex && r.gateway.is_none()));
+    }
+
+    /// Remove all routes through interface `ifindex`.
+    pub fn remove_interface(&mut self, ifindex: usize) {
+        self.routes.retain(|r| r.ifindex != ifindex);
+    }
+
+    /// Remove the default IPv4 routes of interface `ifindex`.
+    pub fn remove_default_ipv4(&mut self, ifindex: usize) {
+        self.routes.retain(|r| {
+            !(r.ifindex == ifindex && r.dest.prefix_len() == 0 && matches!(r.dest, IpCidr::Ipv4(_)))
+        });
+    }
+
+    /// Remove the default IPv6 routes of interface `ifindex`.
+    pub fn remove_default_ipv6(&mut self, ifindex: usize) {
+        self.routes.retain(|r| {
+            !(r.ifindex == ifindex && r.dest.prefix_len() == 0 && matches!(r.dest, IpCidr::Ipv6(_)))
+        });
+    }
+
+    /// Longest-prefix match, ties are broken by the lowest metric.
+    pub fn lookup(&self, dst: IpAddress) -> Option<RouteEntry> {
+        self.routes
+            .iter()
+            .filter(|r| r.dest.contains_addr(&dst))
+            .max_by(|a, b| {
+                a.dest
+                    .prefix_len()
+                    .cmp(&b.dest.prefix_len())
+                    .then(b.metric.cmp(&a.metric))
+            })
+            .copied()
+    }
+
+    /// Routes via a gateway on interface `ifindex`, in the form smoltcp
+    /// expects.
+    pub(crate) fn smoltcp_routes(&self, ifindex: usize) -> Vec<Route> {
+        self.routes
+            .iter()
+            .filter(|r| r.ifindex == ifindex)
+            .filter_map(|r| {
+                Some(Route {
+                    cidr: r.dest,
+                    via_router: r.gateway?,
+                    preferred_until: None,
+                    expires_at: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Find the route to `dst`. Local and loopback destinations always go
+/// through the loopback interface.
+pub fn lookup(dst: IpAddress) -> Option<RouteEntry> {
+    if dst.is_loopback() || iface::is_local_addr(dst) {
+        return Some(RouteEntry {
+            dest: IpCidr::new(dst, max_prefix_len(dst)),
+            gateway: None,
+            ifindex: iface::LOOPBACK_IFINDEX,
+            metric: 0,
+        });
+    }
+    ROUTE_TABLE.lock().lookup(dst)
+}
+
+fn max_prefix_len(addr: IpAddress) -> u8 {
+    match addr {
+        IpAddress::Ipv4(_) => 32,
+        IpAddress::Ipv6(_) => 128,
+    }
+}
+
+pub fn add_route(entry: RouteEntry) -> SysResult<()> {
+    if iface::get(entry.ifindex).is_none() {
+        return Err(SysError::ENODEV);
+    }
+    ROUTE_TABLE.lock().add(entry)?;
+    iface::sync_routes(entry.ifindex);
+    Ok(())
+}
+
+pub fn del_route(dest: IpCidr, ifindex: usize) -> SysResult<()> {
+    ROUTE_TABLE.lock().remove(dest, ifindex)?;
+    iface::sync_routes(ifindex);
+    Ok(())
+}
+
+pub fn routes() -> Vec<RouteEntry> {
+    ROUTE_TABLE.lock().routes().to_vec()
+}
@@ -0,0 +1,123 @@
+//! IPv6 stateless address autoconfiguration.
+//!
+//! Every ethernet interface gets a link-local address made from its MAC
+//! address by EUI-64. Router advertisements are snooped from received packets
+//! and applied after the interface is polled: an address is formed the same
+//! way for every autonomous /64 prefix, and the advertising router becomes the
+//! default IPv6 gateway of the interface.
+
+use alloc::vec::Vec;
+
+use log::{info, warn};
+use smoltcp::wire::{
+    EthernetAddress, Icmpv6Packet, IpAddress, IpCidr, Ipv6Address, Ipv6Cidr, NdiscPrefixInfoFlags,
+    NdiscRepr,
+};
+
+use crate::{
+    Mutex, iface,
+    route::{self, ROUTE_TABLE, RouteEntry},
+};
+
+/// Prefix length of addresses made by EUI-64.
+const SLAAC_PREFIX_LEN: u8 = 64;
+
+/// Router advertisements received but not applied yet.
+static PENDING_ADVERTS: Mutex<Vec<RouterAdvert>> = Mutex::new(Vec::new());
+
+struct RouterAdvert {
+    ifindex: usize,
+    router: Ipv6Address,
+    /// Whether the router is willing to be a default router.
+    is_default: bool,
+    /// Prefix to configure an address from.
+    prefix: Option<Ipv6Cidr>,
+}
+
+/// `prefix` followed by the EUI-64 interface identifier of `mac`.
+fn eui64_addr(prefix: Ipv6Address, mac: EthernetAddress) -> Ipv6Address {
+    let mac = mac.as_bytes();
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&prefix.as_bytes()[..8]);
+    bytes[8..11].copy_from_slice(&mac[..3]);
+    bytes[8] ^= 0x02;
+    bytes[11..13].copy_from_slice(&[0xff, 0xfe]);
+    bytes[13..].copy_from_slice(&mac[3..]);
+    Ipv6Address(bytes)
+}
+
+/// Link-local address `fe80::/64` of an interface with address `mac`.
+pub(crate) fn link_local_cidr(mac: EthernetAddress) -> IpCidr {
+    let prefix = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0);
+    IpCidr::new(eui64_addr(prefix, mac).into(), SLAAC_PREFIX_LEN)
+}
+
+/// Record the router advertisement in the ICMPv6 message `payload` from
+/// `src`, received on interface `ifindex`.
+pub(crate) fn snoop_router_advert(ifindex: usize, src: Ipv6Address, payload: &[u8]) {
+    let Ok(packet) = Icmpv6Packet::new_checked(payload) else {
+        return;
+    };
+    let Ok(NdiscRepr::RouterAdvert {
+        router_lifetime,
+        prefix_info,
+        ..
+    }) = NdiscRepr::parse(&packet)
+    else {
+        return;
+    };
+    let prefix = prefix_info
+        .filter(|info| {
+            info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+                && info.prefix_len == SLAAC_PREFIX_LEN
+                && info.valid_lifetime.total_millis() > 0
+        })
+        .map(|info| Ipv6Cidr::new(info.prefix, info.prefix_len));
+    PENDING_ADVERTS.lock().push(RouterAdvert {
+        ifindex,
+        router: src,
+        is_default: router_lifetime.total_millis() > 0,
+        prefix,
+    });
+}
+
+/// Apply router advertisements received so far. Must not be called with an
+/// interface locked.
+pub(crate) fn handle_router_adverts() {
+    let adverts = core::mem::take(&mut *PENDING_ADVERTS.lock());
+    for advert in adverts {
+        apply(advert);
+    }
+}
+
+fn apply(advert: RouterAdvert) {
+    let Some(iface) = iface::get(advert.ifindex) else {
+        return;
+    };
+    if let Some(prefix) = advert.prefix {
+        let addr = eui64_addr(prefix.address(), iface.ethernet_address());
+        let cidr = IpCidr::new(addr.into(), prefix.prefix_len());
+        if !iface.addrs.lock().contains(&cidr) {
+            info!("[slaac] {}: address {cidr}", iface.name());
+            iface.setup_ip_addr(alloc::vec![cidr]);
+        }
+    }
+
+    let default_route = RouteEntry {
+        dest: IpCidr::new(IpAddress::Ipv6(Ipv6Address::UNSPECIFIED), 0),
+        gateway: Some(advert.router.into()),
+        ifindex: advert.ifindex,
+        metric: 0,
+    };
+    let has_route = route::routes().contains(&default_route);
+    if advert.is_default && !has_route {
+        info!("[slaac] {}: default router {}", iface.name(), advert.router);
+        ROUTE_TABLE.lock().remove_default_ipv6(advert.ifindex);
+        if let Err(e) = route::add_route(default_route) {
+            warn!("[slaac] failed to add default route: {e:?}");
+        }
+    } else if !advert.is_default && has_route {
+        ROUTE_TABLE.lock().remove_default_ipv6(advert.ifindex);
+        iface::sync_routes(advert.ifindex);
+    }
+}
@@ -0,0 +1,94 @@
+//! The network softirq: a kernel task polling the interfaces when a device
+//! raises an interrupt, or when a timer of smoltcp is due.
+//!
+//! Interrupt handlers only call [`raise`], which wakes the task. After each
+//! round of polling, the task arms a single timer for the earliest deadline
+//! `poll_delay` gives. A timer armed for a later deadline is superseded
+//! instead of removed, and does nothing when it expires.
+
+use alloc::boxed::Box;
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use async_utils::yield_now;
+use timer::{TIMER_MANAGER, Timer, TimerEvent};
+
+use crate::{Mutex, SOCKET_SET};
+
+static RAISED: AtomicBool = AtomicBool::new(false);
+static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+/// Expiry in microseconds of the armed timer, 0 if there is none.
+static ARMED: AtomicU64 = AtomicU64::new(0);
+
+/// Ask the softirq task to poll the interfaces. Safe to call in interrupt
+/// context.
+pub fn raise() {
+    RAISED.store(true, Ordering::Release);
+    if let Some(waker) = WAKER.lock().take() {
+        waker.wake();
+    }
+}
+
+/// Arm the timer of the softirq for `deadline`, unless one is armed for an
+/// earlier deadline, after which the task arms it again anyway.
+pub(crate) fn arm(deadline: Duration) {
+    let expire = (deadline.as_micros() as u64).max(1);
+    let mut armed = ARMED.load(Ordering::Acquire);
+    loop {
+        if armed != 0 && armed <= expire {
+            return;
+        }
+        match ARMED.compare_exchange(armed, expire, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => break,
+            Err(current) => armed = current,
+        }
+    }
+    TIMER_MANAGER.add_timer(Timer::new(deadline, Box::new(SoftirqTimer { expire })));
+}
+
+struct SoftirqTimer {
+    expire: u64,
+}
+
+impl TimerEvent for SoftirqTimer {
+    fn callback(self: Box<Self>) -> Option<Timer> {
+        if ARMED
+            .compare_exchange(self.expire, 0, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            raise();
+        }
+        None
+    }
+}
+
+async fn wait_raised() {
+    poll_fn(|cx| {
+        if RAISED.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        *WAKER.lock() = Some(cx.waker().clone());
+        // `raise` may have run between the check and the registration.
+        if RAISED.swap(false, Ordering::AcqRel) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// The softirq task, spawned once at boot.
+pub async fn softirq_task() {
+    loop {
+        wait_raised().await;
+        let timestamp = SOCKET_SET.poll_interfaces();
+        SOCKET_SET.check_poll(timestamp);
+        // Let other tasks run, in case smoltcp wants to be polled again at once.
+        yield_now().await;
+    }
+}
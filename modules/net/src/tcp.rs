@@ -1,28 +1,33 @@
+use alloc::boxed::Box;
 use core::{
     cell::UnsafeCell,
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
     task::{Context, Poll, Waker},
+    time::Duration,
 };
 
+use arch::time::get_time_duration;
 use async_utils::{get_waker, suspend_now, yield_now};
 use log::*;
 use smoltcp::{
     iface::SocketHandle,
     socket::tcp::{self, ConnectError, State},
+    time::Duration as SmolDuration,
     wire::{IpAddress, IpEndpoint, IpListenEndpoint},
 };
 use systype::*;
-use timer::timelimited_task::ksleep_ms;
+use timer::{TIMER_MANAGER, Timer, TimerEvent, timelimited_task::ksleep_ms};
 
 use super::{
-    ETH0, LISTEN_TABLE, SOCKET_SET, SocketSetWrapper,
+    LISTEN_TABLE, SOCKET_SET, SocketSetWrapper,
     addr::{UNSPECIFIED_ENDPOINT_V4, is_unspecified},
 };
 use crate::{
-    Mutex, NetPollState, RCV_SHUTDOWN, SEND_SHUTDOWN, SHUT_RD, SHUT_RDWR, SHUT_WR, SHUTDOWN_MASK,
-    TCP_RX_BUF_LEN, TCP_TX_BUF_LEN, addr::UNSPECIFIED_IPV4, has_signal,
+    MsgFlags, Mutex, NetPollState, RCV_SHUTDOWN, SEND_SHUTDOWN, SHUT_RD, SHUT_RDWR, SHUT_WR,
+    SHUTDOWN_MASK, SOCK_BUF_MAX, SOCK_BUF_MIN, TCP_RX_BUF_LEN, TCP_TX_BUF_LEN, has_signal, iface,
+    listen_table::ListenOptions, netstat::SNMP,
 };
 
 // State transitions:
@@ -37,6 +42,71 @@ const STATE_CONNECTING: u8 = 2;
 const STATE_CONNECTED: u8 = 3;
 const STATE_LISTENING: u8 = 4;
 
+/// Socket options of a TCP socket. They are applied to the smoltcp socket when
+/// it is created, and inherited by connections accepted from a listener.
+#[derive(Debug, Clone, Copy)]
+pub struct TcpOptions {
+    /// `SO_RCVBUF`, size of the receive buffer.
+    pub recv_buf_size: usize,
+    /// `SO_SNDBUF`, size of the send buffer.
+    pub send_buf_size: usize,
+    /// `SO_KEEPALIVE`
+    pub keep_alive: bool,
+    /// `TCP_KEEPIDLE`, in seconds.
+    pub keep_idle: u32,
+    /// `TCP_KEEPINTVL`, in seconds.
+    pub keep_intvl: u32,
+    /// `TCP_KEEPCNT`
+    pub keep_cnt: u32,
+    /// `TCP_NODELAY`, disables the Nagle algorithm.
+    pub nodelay: bool,
+    /// `SO_LINGER`, `None` if disabled.
+    pub linger: Option<Duration>,
+    /// `IP_TTL` or `IPV6_UNICAST_HOPS`, `None` for the default.
+    pub hop_limit: Option<u8>,
+}
+
+impl TcpOptions {
+    pub const DEFAULT: Self = Self {
+        recv_buf_size: TCP_RX_BUF_LEN,
+        send_buf_size: TCP_TX_BUF_LEN,
+        keep_alive: false,
+        keep_idle: 7200,
+        keep_intvl: 75,
+        keep_cnt: 9,
+        nodelay: false,
+        linger: None,
+        hop_limit: None,
+    };
+
+    /// Create a smoltcp socket with these options.
+    pub(crate) fn new_socket<'a>(&self) -> tcp::Socket<'a> {
+        let mut socket =
+            SocketSetWrapper::new_tcp_socket_with_buf(self.recv_buf_size, self.send_buf_size);
+        self.apply(&mut socket);
+        socket
+    }
+
+    /// Apply the options that smoltcp can change on an existing socket.
+    ///
+    /// smoltcp sends a keep-alive every `interval` of idleness and gives up a
+    /// connection the peer is silent for `timeout`, so keep-alives are sent
+    /// every `TCP_KEEPINTVL`, and the connection is dropped after
+    /// `TCP_KEEPIDLE` plus `TCP_KEEPCNT` intervals without an answer.
+    pub(crate) fn apply(&self, socket: &mut tcp::Socket) {
+        socket.set_nagle_enabled(!self.nodelay);
+        socket.set_hop_limit(self.hop_limit);
+        if self.keep_alive {
+            let timeout = self.keep_idle as u64 + self.keep_intvl as u64 * self.keep_cnt as u64;
+            socket.set_keep_alive(Some(SmolDuration::from_secs(self.keep_intvl as u64)));
+            socket.set_timeout(Some(SmolDuration::from_secs(timeout)));
+        } else {
+            socket.set_keep_alive(None);
+            socket.set_timeout(None);
+        }
+    }
+}
+
 /// A TCP socket that provides POSIX-like APIs.
 ///
 /// - [`connect`] is for TCP clients.
@@ -67,6 +137,20 @@ pub struct TcpSocket {
     /// Indicates whether the socket is in non-blocking mode, using an atomic
     /// boolean for thread-safe access.
     nonblock: AtomicBool,
+    /// Whether this is an `AF_INET6` socket.
+    ipv6: bool,
+    /// `IPV6_V6ONLY`, an IPv6 socket refuses IPv4 connections if set.
+    v6only: AtomicBool,
+    /// `SO_REUSEADDR`, allows binding to a port that still has connections.
+    reuse_addr: AtomicBool,
+    /// `SO_REUSEPORT`, allows listening on a port together with other sockets
+    /// that set it.
+    reuse_port: AtomicBool,
+    /// Id of the listener in `LISTEN_TABLE` while listening.
+    listen_id: AtomicUsize,
+    opts: Mutex<TcpOptions>,
+    /// Error of an asynchronous connect, reported by `SO_ERROR`.
+    error: Mutex<Option<SysError>>,
 }
 
 unsafe impl Sync for TcpSocket {}
@@ -76,6 +160,16 @@ impl TcpSocket {
     ///
     /// 此时并没有加到SocketSet中（还没有handle），在connect/listen中才会添加
     pub const fn new_v4() -> Self {
+        Self::new(false)
+    }
+
+    /// Creates a new `AF_INET6` TCP socket, which also accepts IPv4
+    /// connections unless `IPV6_V6ONLY` is set.
+    pub const fn new_v6() -> Self {
+        Self::new(true)
+    }
+
+    const fn new(ipv6: bool) -> Self {
         Self {
             state: AtomicU8::new(STATE_CLOSED),
             shutdown: UnsafeCell::new(0),
@@ -83,6 +177,13 @@ impl TcpSocket {
             local_addr: UnsafeCell::new(UNSPECIFIED_ENDPOINT_V4),
             peer_addr: UnsafeCell::new(UNSPECIFIED_ENDPOINT_V4),
             nonblock: AtomicBool::new(false),
+            ipv6,
+            v6only: AtomicBool::new(false),
+            reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+            listen_id: AtomicUsize::new(0),
+            opts: Mutex::new(TcpOptions::DEFAULT),
+            error: Mutex::new(None),
         }
     }
 
@@ -91,6 +192,9 @@ impl TcpSocket {
         handle: SocketHandle,
         local_addr: IpEndpoint,
         peer_addr: IpEndpoint,
+        ipv6: bool,
+        v6only: bool,
+        opts: TcpOptions,
     ) -> Self {
         Self {
             state: AtomicU8::new(STATE_CONNECTED),
@@ -99,6 +203,13 @@ impl TcpSocket {
             local_addr: UnsafeCell::new(local_addr),
             peer_addr: UnsafeCell::new(peer_addr),
             nonblock: AtomicBool::new(false),
+            ipv6,
+            v6only: AtomicBool::new(v6only),
+            reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+            listen_id: AtomicUsize::new(0),
+            opts: Mutex::new(opts),
+            error: Mutex::new(None),
         }
     }
 
@@ -143,21 +254,110 @@ impl TcpSocket {
         self.nonblock.store(nonblocking, Ordering::Release);
     }
 
+    /// Returns whether `IPV6_V6ONLY` is set.
+    #[inline]
+    pub fn v6only(&self) -> bool {
+        self.v6only.load(Ordering::Acquire)
+    }
+
+    /// Sets `IPV6_V6ONLY`, only allowed on an IPv6 socket that is not
+    /// listening or connected yet.
+    pub fn set_v6only(&self, v6only: bool) -> SysResult<()> {
+        if !self.ipv6 {
+            return Err(SysError::ENOPROTOOPT);
+        }
+        if self.get_state() != STATE_CLOSED {
+            return Err(SysError::EINVAL);
+        }
+        self.v6only.store(v6only, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns whether `SO_REUSEADDR` is set.
+    #[inline]
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr.load(Ordering::Acquire)
+    }
+
+    /// Sets `SO_REUSEADDR`, which takes effect on the next `bind`.
+    #[inline]
+    pub fn set_reuse_addr(&self, reuse_addr: bool) {
+        self.reuse_addr.store(reuse_addr, Ordering::Release);
+    }
+
+    /// Returns whether `SO_REUSEPORT` is set.
+    #[inline]
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port.load(Ordering::Acquire)
+    }
+
+    /// Sets `SO_REUSEPORT`, which takes effect on the next `bind` or `listen`.
+    #[inline]
+    pub fn set_reuse_port(&self, reuse_port: bool) {
+        self.reuse_port.store(reuse_port, Ordering::Release);
+    }
+
+    /// Returns the socket options.
+    pub fn options(&self) -> TcpOptions {
+        *self.opts.lock()
+    }
+
+    /// Change the socket options with `f`. Buffer sizes are clamped to
+    /// [`SOCK_BUF_MIN`, `SOCK_BUF_MAX`], and only take effect on connections
+    /// made afterwards.
+    pub fn set_options(&self, f: impl FnOnce(&mut TcpOptions)) {
+        let opts = {
+            let mut opts = self.opts.lock();
+            f(&mut opts);
+            opts.recv_buf_size = opts.recv_buf_size.clamp(SOCK_BUF_MIN, SOCK_BUF_MAX);
+            opts.send_buf_size = opts.send_buf_size.clamp(SOCK_BUF_MIN, SOCK_BUF_MAX);
+            *opts
+        };
+        match self.get_state() {
+            STATE_LISTENING => {
+                // SAFETY: `self.local_addr` should be initialized in a listening socket.
+                let local_port = unsafe { self.local_addr.get().read().port };
+                LISTEN_TABLE.set_socket_options(local_port, self.listen_id(), opts);
+            }
+            _ => {
+                if let Some(handle) = unsafe { self.handle.get().read() } {
+                    SOCKET_SET
+                        .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| opts.apply(socket));
+                }
+            }
+        }
+    }
+
+    /// Returns and clears the pending error, i.e. `SO_ERROR`.
+    pub fn take_error(&self) -> Option<SysError> {
+        if self.is_connecting() {
+            SOCKET_SET.poll_interfaces();
+            self.check_connect(None);
+        }
+        self.error.lock().take()
+    }
+
     /// Connects to the given address and port.
     ///
     /// The local port is generated automatically.
     pub async fn connect(&self, remote_addr: IpEndpoint) -> SysResult<()> {
+        if self.v6only() && matches!(remote_addr.addr, IpAddress::Ipv4(_)) {
+            return Err(SysError::ENETUNREACH);
+        }
         yield_now().await;
         // 将STATE_CLOSED改为STATE_CONNECTING，在poll_connect的时候，
         // 会再变为STATE_CONNECTED
         self.update_state(STATE_CLOSED, STATE_CONNECTING, || {
             // SAFETY: no other threads can read or write these fields.
             let handle = unsafe { self.handle.get().read() }
-                .unwrap_or_else(|| SOCKET_SET.add(SocketSetWrapper::new_tcp_socket()));
+                .unwrap_or_else(|| SOCKET_SET.add(self.options().new_socket()));
 
-            // TODO: check remote addr unreachable
             let bound_endpoint = self.bound_endpoint()?;
-            let iface = &ETH0.get().unwrap().iface;
+            let iface = iface::route_iface(remote_addr.addr).ok_or_else(|| {
+                warn!("[TcpSocket::connect] no route to {}", remote_addr.addr);
+                SysError::ENETUNREACH
+            })?;
+            let iface = &iface.iface;
             let (local_endpoint, remote_endpoint) = SOCKET_SET
                 .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
                     socket
@@ -189,12 +389,17 @@ impl TcpSocket {
                 self.peer_addr.get().write(remote_endpoint);
                 self.handle.get().write(Some(handle));
             }
+            SNMP.tcp_active_opens.fetch_add(1, Ordering::Relaxed);
             Ok(())
         })
-        .unwrap_or_else(|_| {
+        .unwrap_or_else(|state| {
             warn!("[TcpSocket::connect] failed: already connected");
-            Err(SysError::EEXIST)
-        })?; // EISCONN
+            match state {
+                STATE_CONNECTING => Err(SysError::EALREADY),
+                STATE_CONNECTED => Err(SysError::EISCONN),
+                _ => Err(SysError::EEXIST),
+            }
+        })?;
 
         // Here our state must be `CONNECTING`, and only one thread can run here.
         if self.is_nonblocking() {
@@ -209,7 +414,7 @@ impl TcpSocket {
                     Ok(())
                 } else {
                     warn!("[TcpSocket::connect] failed, connection refused");
-                    Err(SysError::ECONNREFUSED)
+                    Err(self.take_error().unwrap_or(SysError::ECONNREFUSED))
                 }
             })
             .await
@@ -223,12 +428,16 @@ impl TcpSocket {
     /// It's must be called before [`listen`](Self::listen) and
     /// [`accept`](Self::accept).
     pub fn bind(&self, mut local_addr: IpEndpoint) -> SysResult<()> {
+        if self.v6only() && matches!(local_addr.addr, IpAddress::Ipv4(_)) {
+            return Err(SysError::EINVAL);
+        }
         self.update_state(STATE_CLOSED, STATE_CLOSED, || {
-            // TODO: check addr is available
             if local_addr.port == 0 {
                 let port = get_ephemeral_port()?;
                 local_addr.port = port;
                 info!("[TcpSocket::bind] local port is 0, use port {port}");
+            } else {
+                self.check_addr_in_use(local_addr)?;
             }
             // SAFETY: no other threads can read or write `self.local_addr` as we
             // have changed the state to `BUSY`.
@@ -238,13 +447,6 @@ impl TcpSocket {
                     warn!("socket bind() failed: {:?} already bound", local_addr);
                     return Err(SysError::EINVAL);
                 }
-                // FIXME
-                if let IpAddress::Ipv6(v6) = local_addr.addr {
-                    if v6.is_unspecified() {
-                        log::warn!("[TcpSocket::bind] Unstable: just use ipv4 instead of ipv6 when ipv6 is unspecified");
-                        local_addr.addr = UNSPECIFIED_IPV4;
-                    }
-                }
                 self.local_addr.get().write(local_addr);
             }
             Ok(())
@@ -258,18 +460,29 @@ impl TcpSocket {
     /// Starts listening on the bound address and port.
     ///
     /// It's must be called after [`bind`](Self::bind) and before
-    /// [`accept`](Self::accept).
-    pub fn listen(&self, waker: &Waker) -> SysResult<()> {
+    /// [`accept`](Self::accept). At most `backlog` established connections
+    /// wait to be accepted, calling it again on a listening socket only
+    /// changes the backlog.
+    pub fn listen(&self, waker: &Waker, backlog: usize) -> SysResult<()> {
         self.update_state(STATE_CLOSED, STATE_LISTENING, || {
             let bound_endpoint = self.bound_endpoint()?;
             unsafe {
                 (*self.local_addr.get()).port = bound_endpoint.port;
             }
-            LISTEN_TABLE.listen(bound_endpoint, waker)?;
-            info!("[TcpSocket::listen] listening on {bound_endpoint:?}");
+            let id = LISTEN_TABLE.listen(bound_endpoint, self.listen_options(backlog), waker)?;
+            self.listen_id.store(id, Ordering::Release);
+            info!("[TcpSocket::listen] listening on {bound_endpoint:?}, backlog {backlog}");
+            Ok(())
+        })
+        .unwrap_or_else(|state| {
+            if state == STATE_LISTENING {
+                // SAFETY: `self.local_addr` should be initialized in a listening socket.
+                let local_port = unsafe { self.local_addr.get().read().port };
+                LISTEN_TABLE.set_backlog(local_port, self.listen_id(), backlog);
+            }
+            // ignore simultaneous `listen`s.
             Ok(())
         })
-        .unwrap_or(Ok(())) // ignore simultaneous `listen`s.
     }
 
     /// Accepts a new connection.
@@ -287,10 +500,20 @@ impl TcpSocket {
 
         // SAFETY: `self.local_addr` should be initialized after `bind()`.
         let local_port = unsafe { self.local_addr.get().read().port };
-        self.block_on(|| {
-            let (handle, (local_addr, peer_addr)) = LISTEN_TABLE.accept(local_port)?;
+        let listen_id = self.listen_id();
+        let waker = get_waker().await;
+        self.block_on(self.is_nonblocking(), || {
+            let (handle, (local_addr, peer_addr)) =
+                LISTEN_TABLE.accept(local_port, listen_id, &waker)?;
             info!("TCP socket accepted a new connection {}", peer_addr);
-            Ok(TcpSocket::new_connected(handle, local_addr, peer_addr))
+            Ok(TcpSocket::new_connected(
+                handle,
+                local_addr,
+                peer_addr,
+                self.ipv6,
+                self.v6only(),
+                self.options(),
+            ))
         })
         .await
     }
@@ -338,7 +561,7 @@ impl TcpSocket {
             // and no other threads can read or write it.
             let local_port = unsafe { self.local_addr.get().read().port };
             unsafe { self.local_addr.get().write(UNSPECIFIED_ENDPOINT_V4) }; // clear bound address
-            LISTEN_TABLE.unlisten(local_port);
+            LISTEN_TABLE.unlisten(local_port, self.listen_id());
             let timestamp = SOCKET_SET.poll_interfaces();
             SOCKET_SET.check_poll(timestamp);
             Ok(())
@@ -349,58 +572,42 @@ impl TcpSocket {
     }
 
     /// Receives data from the socket, stores it in the given buffer.
-    pub async fn recv(&self, buf: &mut [u8]) -> SysResult<usize> {
-        let shutdown = unsafe { *self.shutdown.get() };
-        if shutdown & RCV_SHUTDOWN != 0 {
-            log::warn!("[TcpSocket::recv] shutdown closed read, recv return 0");
-            return Ok(0);
+    ///
+    /// With `MSG_WAITALL` it blocks until `buf` is full, the peer closes the
+    /// connection or a signal arrives, whatever has been received is returned
+    /// in the latter two cases. Urgent data is always received inline, so
+    /// `MSG_OOB` fails like on Linux with `SO_OOBINLINE`.
+    pub async fn recv(&self, buf: &mut [u8], flags: MsgFlags) -> SysResult<usize> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EINVAL);
         }
-        if self.is_connecting() {
-            // TODO: 这里是否要加上 waker
-            return Err(SysError::EAGAIN);
-        } else if !self.is_connected() && shutdown == 0 {
-            warn!("socket recv() failed");
-            return Err(SysError::ENOTCONN);
+        if !flags.contains(MsgFlags::WAITALL)
+            || flags.contains(MsgFlags::PEEK)
+            || self.nonblock(flags)
+        {
+            return self.recv_once(buf, flags).await;
         }
-
-        // SAFETY: `self.handle` should be initialized in a connected socket.
-        let handle = unsafe { self.handle.get().read().unwrap() };
-        let waker = get_waker().await;
-        self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                log::info!("[TcpSocket::recv] handle{handle} state {} is trying to recv", socket.state());
-                if !socket.is_active() {
-                    // not open
-                    warn!("[TcpSocket::recv] socket recv() failed because handle{handle} is not active");
-                    Err(SysError::ECONNREFUSED)
-                } else if !socket.may_recv() {
-                    // connection closed
-                    Ok(0)
-                } else if socket.recv_queue() > 0 {
-                    // data available
-                    // TODO: use socket.recv(|buf| {...})
-                    let len = socket.recv_slice(buf).map_err(|_| {
-                        warn!("socket recv() failed, badstate");
-                        SysError::EBADF
-                    })?;
-                    Ok(len)
-                } else {
-                    // no more data
-                    log::info!("[TcpSocket::recv] handle{handle} has no data to recv, register waker and suspend");
-                    socket.register_recv_waker(&waker);
-                    Err(SysError::EAGAIN)
-                }
-            })
-        })
-        .await
+        let mut total = 0;
+        while total < buf.len() {
+            match self.recv_once(&mut buf[total..], flags).await {
+                Ok(0) => break,
+                Ok(len) => total += len,
+                Err(_) if total > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(total)
     }
 
     /// Transmits data in the given buffer.
-    pub async fn send(&self, buf: &[u8]) -> SysResult<usize> {
+    ///
+    /// Fails with `EPIPE` if the connection was shut down for writing. Urgent
+    /// pointers are not supported, so `MSG_OOB` data is sent inline.
+    pub async fn send(&self, buf: &[u8], flags: MsgFlags) -> SysResult<usize> {
         let shutdown = unsafe { *self.shutdown.get() };
         if shutdown & SEND_SHUTDOWN != 0 {
-            log::warn!("[TcpSocket::send] shutdown closed write, send return 0");
-            return Ok(0);
+            log::warn!("[TcpSocket::send] shutdown closed write");
+            return Err(SysError::EPIPE);
         }
         if self.is_connecting() {
             return Err(SysError::EAGAIN);
@@ -408,16 +615,23 @@ impl TcpSocket {
             warn!("socket send() failed");
             return Err(SysError::ENOTCONN);
         }
+        if flags.contains(MsgFlags::OOB) {
+            warn!("[TcpSocket::send] MSG_OOB is sent inline");
+        }
 
         // SAFETY: `self.handle` should be initialized in a connected socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
         let waker = get_waker().await;
-        let ret = self.block_on(|| {
+        let ret = self.block_on(self.nonblock(flags), || {
             SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                if !socket.is_active() || !socket.may_send() {
-                    // closed by remote
+                if !socket.is_active() {
+                    // reset by remote
                     warn!("socket send() failed, ECONNRESET");
                     Err(SysError::ECONNRESET)
+                } else if !socket.may_send() {
+                    // closed for writing
+                    warn!("socket send() failed, EPIPE");
+                    Err(SysError::EPIPE)
                 } else if socket.can_send() {
                     // connected, and the tx buffer is not full
                     // TODO: use socket.send(|buf| {...})
@@ -452,7 +666,7 @@ impl TcpSocket {
         match self.get_state() {
             STATE_CONNECTING => self.poll_connect().await,
             STATE_CONNECTED => self.poll_stream().await,
-            STATE_LISTENING => self.poll_listener(),
+            STATE_LISTENING => self.poll_listener().await,
             STATE_CLOSED => self.poll_closed(),
             _ => NetPollState {
                 readable: false,
@@ -571,6 +785,69 @@ impl TcpSocket {
         }
     }
 
+    /// Receive what is available now, or block until something is.
+    async fn recv_once(&self, buf: &mut [u8], flags: MsgFlags) -> SysResult<usize> {
+        let shutdown = unsafe { *self.shutdown.get() };
+        if shutdown & RCV_SHUTDOWN != 0 {
+            log::warn!("[TcpSocket::recv] shutdown closed read, recv return 0");
+            return Ok(0);
+        }
+        if self.is_connecting() {
+            // TODO: 这里是否要加上 waker
+            return Err(SysError::EAGAIN);
+        } else if !self.is_connected() && shutdown == 0 {
+            warn!("socket recv() failed");
+            return Err(SysError::ENOTCONN);
+        }
+
+        // SAFETY: `self.handle` should be initialized in a connected socket.
+        let handle = unsafe { self.handle.get().read().unwrap() };
+        let waker = get_waker().await;
+        self.block_on(self.nonblock(flags), || {
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                log::info!("[TcpSocket::recv] handle{handle} state {} is trying to recv", socket.state());
+                if !socket.is_active() {
+                    // not open
+                    warn!("[TcpSocket::recv] socket recv() failed because handle{handle} is not active");
+                    Err(SysError::ECONNREFUSED)
+                } else if !socket.may_recv() {
+                    // connection closed
+                    Ok(0)
+                } else if socket.recv_queue() > 0 {
+                    // data available
+                    let ret = if flags.contains(MsgFlags::PEEK) {
+                        socket.peek_slice(buf)
+                    } else if flags.contains(MsgFlags::TRUNC) {
+                        // discard the data instead of copying it
+                        socket.recv(|data| {
+                            let len = data.len().min(buf.len());
+                            (len, len)
+                        })
+                    } else {
+                        socket.recv_slice(buf)
+                    };
+                    ret.map_err(|_| {
+                        warn!("socket recv() failed, badstate");
+                        SysError::EBADF
+                    })
+                } else {
+                    // no more data
+                    log::info!("[TcpSocket::recv] handle{handle} has no data to recv, register waker and suspend");
+                    socket.register_recv_waker(&waker);
+                    Err(SysError::EAGAIN)
+                }
+            })
+        })
+        .await
+    }
+
+    /// Whether this call does not block, i.e. the socket is nonblocking or
+    /// `MSG_DONTWAIT` is given.
+    #[inline]
+    fn nonblock(&self, flags: MsgFlags) -> bool {
+        self.is_nonblocking() || flags.contains(MsgFlags::DONTWAIT)
+    }
+
     #[inline]
     fn is_connecting(&self) -> bool {
         self.get_state() == STATE_CONNECTING
@@ -586,6 +863,43 @@ impl TcpSocket {
         self.get_state() == STATE_LISTENING
     }
 
+    #[inline]
+    fn listen_id(&self) -> usize {
+        self.listen_id.load(Ordering::Acquire)
+    }
+
+    fn listen_options(&self, backlog: usize) -> ListenOptions {
+        ListenOptions {
+            ipv6: self.ipv6,
+            v6only: self.v6only(),
+            reuse_port: self.reuse_port(),
+            backlog,
+            socket_opts: self.options(),
+        }
+    }
+
+    /// Fails with `EADDRINUSE` if `local_addr` conflicts with a listener, or
+    /// if the port still has connections and `SO_REUSEADDR` is not set.
+    fn check_addr_in_use(&self, local_addr: IpEndpoint) -> SysResult<()> {
+        let addr = (!is_unspecified(local_addr.addr)).then_some(local_addr.addr);
+        let endpoint = IpListenEndpoint {
+            addr,
+            port: local_addr.port,
+        };
+        if LISTEN_TABLE.is_in_use(endpoint, self.listen_options(0)) {
+            warn!("[TcpSocket::bind] {local_addr} is in use by a listener");
+            return Err(SysError::EADDRINUSE);
+        }
+        if !self.reuse_addr() && SOCKET_SET.has_tcp_connection(local_addr.port) {
+            warn!(
+                "[TcpSocket::bind] port {} still has connections",
+                local_addr.port
+            );
+            return Err(SysError::EADDRINUSE);
+        }
+        Ok(())
+    }
+
     /// 构建并返回当前对象绑定的网络端点信息。
     /// 具体来说，它从对象的 local_addr
     /// 属性中读取IP地址和端口信息，如果端口未指定则分配一个临时端口，
@@ -613,14 +927,27 @@ impl TcpSocket {
     /// Returning `true` indicates that the socket has entered a stable
     /// state(connected or failed) and can proceed to the next step
     async fn poll_connect(&self) -> NetPollState {
-        // SAFETY: `self.handle` should be initialized above.
-        let handle = unsafe { self.handle.get().read().unwrap() };
         let waker = get_waker().await;
-        let writable = SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+        let writable = self.check_connect(Some(&waker));
+        NetPollState {
+            readable: false,
+            writable,
+            hangup: false,
+        }
+    }
+
+    /// Update the state of a connecting socket, returns `true` if the
+    /// connection is established or failed. `waker` is registered if neither.
+    fn check_connect(&self, waker: Option<&Waker>) -> bool {
+        // SAFETY: `self.handle` should be initialized in `connect`.
+        let handle = unsafe { self.handle.get().read().unwrap() };
+        SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
             match socket.state() {
                 State::SynSent => {
                     // The connection request has been sent but no response
-                    socket.register_recv_waker(&waker);
+                    if let Some(waker) = waker {
+                        socket.register_recv_waker(waker);
+                    }
                     false
                 }
                 // has been received yet
@@ -638,16 +965,12 @@ impl TcpSocket {
                         self.local_addr.get().write(UNSPECIFIED_ENDPOINT_V4);
                         self.peer_addr.get().write(UNSPECIFIED_ENDPOINT_V4);
                     }
+                    *self.error.lock() = Some(SysError::ECONNREFUSED);
                     self.set_state(STATE_CLOSED); // connection failed
                     true
                 }
             }
-        });
-        NetPollState {
-            readable: false,
-            writable,
-            hangup: false,
-        }
+        })
     }
 
     async fn poll_stream(&self) -> NetPollState {
@@ -675,10 +998,11 @@ impl TcpSocket {
         })
     }
 
-    fn poll_listener(&self) -> NetPollState {
+    async fn poll_listener(&self) -> NetPollState {
         // SAFETY: `self.local_addr` should be initialized in a listening socket.
         let local_addr = unsafe { self.local_addr.get().read() };
-        let readable = LISTEN_TABLE.can_accept(local_addr.port);
+        let waker = get_waker().await;
+        let readable = LISTEN_TABLE.can_accept(local_addr.port, self.listen_id(), &waker);
         NetPollState {
             readable,
             writable: false,
@@ -713,14 +1037,14 @@ impl TcpSocket {
 
     /// Block the current thread until the given function completes or fails.
     ///
-    /// If the socket is non-blocking, it calls the function once and returns
+    /// If `nonblock` is set, it calls the function once and returns
     /// immediately. Otherwise, it may call the function multiple times if it
     /// returns [`Err(WouldBlock)`](AxError::WouldBlock).
-    async fn block_on<F, T>(&self, mut f: F) -> SysResult<T>
+    async fn block_on<F, T>(&self, nonblock: bool, mut f: F) -> SysResult<T>
     where
         F: FnMut() -> SysResult<T>,
     {
-        if self.is_nonblocking() {
+        if nonblock {
             f()
         } else {
             loop {
@@ -773,10 +1097,59 @@ impl TcpSocket {
 impl Drop for TcpSocket {
     fn drop(&mut self) {
         log::info!("[TcpSocket::Drop] ");
-        self.shutdown(SHUT_RDWR).ok();
+        let linger = self.options().linger;
         // Safe because we have mut reference to `self`.
-        if let Some(handle) = unsafe { self.handle.get().read() } {
-            SOCKET_SET.remove(handle);
+        let handle = unsafe { self.handle.get().read() };
+        if let (Some(Duration::ZERO), Some(handle), STATE_CONNECTED) =
+            (linger, handle, self.get_state())
+        {
+            // Reset the connection instead of closing it gracefully.
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.abort());
+        }
+        self.shutdown(SHUT_RDWR).ok();
+        if let Some(handle) = handle {
+            match linger {
+                Some(linger) if !linger.is_zero() => LingerTimer::start(handle, linger),
+                _ => SOCKET_SET.remove(handle),
+            }
+        }
+    }
+}
+
+/// Keeps the smoltcp socket of a closed `SO_LINGER` socket until its
+/// connection is closed or the linger time is up, so that queued data is
+/// still sent. `close` itself does not wait.
+struct LingerTimer {
+    handle: SocketHandle,
+    deadline: Duration,
+}
+
+impl LingerTimer {
+    /// How often the connection is checked.
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    fn start(handle: SocketHandle, linger: Duration) {
+        let now = get_time_duration();
+        let timer = Self {
+            handle,
+            deadline: now + linger,
+        };
+        TIMER_MANAGER.add_timer(Timer::new(now + Self::INTERVAL, Box::new(timer)));
+    }
+}
+
+impl TimerEvent for LingerTimer {
+    fn callback(self: Box<Self>) -> Option<Timer> {
+        SOCKET_SET.poll_interfaces();
+        let closed = SOCKET_SET.with_socket::<tcp::Socket, _, _>(self.handle, |socket| {
+            matches!(socket.state(), State::Closed | State::TimeWait)
+        });
+        let now = get_time_duration();
+        if closed || now >= self.deadline {
+            SOCKET_SET.remove(self.handle);
+            None
+        } else {
+            Some(Timer::new(now + Self::INTERVAL, self))
         }
     }
 }
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use core::{
     cell::UnsafeCell,
     ops::Deref,
@@ -19,14 +20,61 @@ use super::{
     addr::{UNSPECIFIED_ENDPOINT_V4, is_unspecified},
 };
 use crate::{
-    Mutex, NetPollState,
+    MsgFlags, Mutex, NetPollState, SOCK_BUF_MAX, SOCK_BUF_MIN, UDP_RX_BUF_LEN, UDP_TX_BUF_LEN,
     addr::{
         LOCAL_ENDPOINT_V4, LOCAL_IPV4, UNSPECIFIED_IPV4, UNSPECIFIED_LISTEN_ENDPOINT, to_endpoint,
     },
-    has_signal,
+    has_signal, iface,
     portmap::PORT_MAP,
+    route,
 };
 
+/// Socket options of a UDP socket.
+///
+/// smoltcp has a single hop limit per socket, so `IP_MULTICAST_TTL` takes
+/// the place of `IP_TTL` while the socket is connected to a multicast group.
+/// Multicast datagrams are never looped back, whatever `IP_MULTICAST_LOOP`
+/// says.
+#[derive(Debug, Clone, Copy)]
+pub struct UdpOptions {
+    /// `SO_RCVBUF`, size of the receive buffer.
+    pub recv_buf_size: usize,
+    /// `SO_SNDBUF`, size of the send buffer.
+    pub send_buf_size: usize,
+    /// `SO_BROADCAST`, sending to a broadcast address is refused if unset.
+    pub broadcast: bool,
+    /// `IP_TTL` or `IPV6_UNICAST_HOPS`, `None` for the default.
+    pub hop_limit: Option<u8>,
+    /// `IP_MULTICAST_TTL` or `IPV6_MULTICAST_HOPS`.
+    pub multicast_hop_limit: u8,
+    /// `IP_MULTICAST_LOOP` or `IPV6_MULTICAST_LOOP`.
+    pub multicast_loop: bool,
+    /// `IP_MULTICAST_IF` or `IPV6_MULTICAST_IF`, index of the interface, 0 for
+    /// the default.
+    pub multicast_ifindex: usize,
+}
+
+impl UdpOptions {
+    pub const DEFAULT: Self = Self {
+        recv_buf_size: UDP_RX_BUF_LEN,
+        send_buf_size: UDP_TX_BUF_LEN,
+        broadcast: false,
+        hop_limit: None,
+        multicast_hop_limit: 1,
+        multicast_loop: true,
+        multicast_ifindex: 0,
+    };
+}
+
+/// Family and peer of every UDP socket, keyed by its handle, which smoltcp
+/// does not know. Only read by `/proc/net/udp`.
+static UDP_SOCKETS: Mutex<Vec<(SocketHandle, bool, Option<IpEndpoint>)>> = Mutex::new(Vec::new());
+
+/// Handle, whether it is `AF_INET6` and peer of every UDP socket.
+pub(crate) fn udp_sockets_info() -> Vec<(SocketHandle, bool, Option<IpEndpoint>)> {
+    UDP_SOCKETS.lock().clone()
+}
+
 /// A UDP socket that provides POSIX-like APIs.
 pub struct UdpSocket {
     /// Handle obtained after adding the newly created socket to SOCKET_SET.
@@ -38,20 +86,41 @@ pub struct UdpSocket {
     /// Indicates if the socket is in nonblocking mode. Uses AtomicBool for
     /// thread-safe access.
     nonblock: AtomicBool,
+    /// Whether this is an `AF_INET6` socket.
+    ipv6: bool,
+    /// `IPV6_V6ONLY`, datagrams from IPv4 peers are dropped if set.
+    v6only: AtomicBool,
+    opts: Mutex<UdpOptions>,
+    /// Multicast groups joined, with the index of the interface.
+    multicast_groups: Mutex<Vec<(IpAddress, usize)>>,
 }
 
 impl UdpSocket {
     /// Creates a new UDP socket.
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        Self::with_family(false)
+    }
+
+    /// Creates a new `AF_INET6` UDP socket.
+    pub fn new_v6() -> Self {
+        Self::with_family(true)
+    }
+
+    fn with_family(ipv6: bool) -> Self {
         let socket = SocketSetWrapper::new_udp_socket();
         let handle = SOCKET_SET.add(socket);
+        UDP_SOCKETS.lock().push((handle, ipv6, None));
         Self {
             handle,
             local_addr: RwLock::new(None),
             peer_addr: RwLock::new(None),
             nonblock: AtomicBool::new(false),
             // overridden: AtomicBool::new(false),
+            ipv6,
+            v6only: AtomicBool::new(false),
+            opts: Mutex::new(UdpOptions::DEFAULT),
+            multicast_groups: Mutex::new(Vec::new()),
         }
     }
 
@@ -89,6 +158,92 @@ impl UdpSocket {
         self.nonblock.store(nonblocking, Ordering::Release);
     }
 
+    /// Returns whether `IPV6_V6ONLY` is set.
+    #[inline]
+    pub fn v6only(&self) -> bool {
+        self.v6only.load(Ordering::Acquire)
+    }
+
+    /// Sets `IPV6_V6ONLY`, only allowed on an unbound IPv6 socket.
+    pub fn set_v6only(&self, v6only: bool) -> SysResult<()> {
+        if !self.ipv6 {
+            return Err(SysError::ENOPROTOOPT);
+        }
+        if self.local_addr.read().is_some() {
+            return Err(SysError::EINVAL);
+        }
+        self.v6only.store(v6only, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns the socket options.
+    pub fn options(&self) -> UdpOptions {
+        *self.opts.lock()
+    }
+
+    /// Change the socket options with `f`. Buffer sizes are clamped to
+    /// [`SOCK_BUF_MIN`, `SOCK_BUF_MAX`], and the buffers are only replaced
+    /// while they are empty.
+    pub fn set_options(&self, f: impl FnOnce(&mut UdpOptions)) {
+        let (old, new) = {
+            let mut opts = self.opts.lock();
+            let old = *opts;
+            f(&mut opts);
+            opts.recv_buf_size = opts.recv_buf_size.clamp(SOCK_BUF_MIN, SOCK_BUF_MAX);
+            opts.send_buf_size = opts.send_buf_size.clamp(SOCK_BUF_MIN, SOCK_BUF_MAX);
+            (old, *opts)
+        };
+        let resize =
+            old.recv_buf_size != new.recv_buf_size || old.send_buf_size != new.send_buf_size;
+        SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
+            if resize && socket.recv_queue() == 0 && socket.send_queue() == 0 {
+                let mut new_socket =
+                    SocketSetWrapper::new_udp_socket_with_buf(new.recv_buf_size, new.send_buf_size);
+                if socket.is_open() {
+                    new_socket.bind(socket.endpoint()).ok();
+                }
+                *socket = new_socket;
+            }
+        });
+        self.apply_hop_limit();
+    }
+
+    /// Join multicast group `group` on interface `ifindex`, or the one owning
+    /// address `local`, see [`iface::join_multicast_group`].
+    pub fn join_multicast_group(
+        &self,
+        group: IpAddress,
+        local: Option<IpAddress>,
+        ifindex: usize,
+    ) -> SysResult<()> {
+        let mut groups = self.multicast_groups.lock();
+        if groups
+            .iter()
+            .any(|&(addr, index)| addr == group && (ifindex == 0 || index == ifindex))
+        {
+            return Err(SysError::EADDRINUSE);
+        }
+        let ifindex = iface::join_multicast_group(group, local, ifindex)?;
+        info!(
+            "[UdpSocket::join_multicast_group] handle {} joined {group} on interface {ifindex}",
+            self.handle
+        );
+        groups.push((group, ifindex));
+        Ok(())
+    }
+
+    /// Leave multicast group `group` joined on interface `ifindex`, or any
+    /// interface if 0.
+    pub fn leave_multicast_group(&self, group: IpAddress, ifindex: usize) -> SysResult<()> {
+        let mut groups = self.multicast_groups.lock();
+        let idx = groups
+            .iter()
+            .position(|&(addr, index)| addr == group && (ifindex == 0 || index == ifindex))
+            .ok_or(SysError::EADDRNOTAVAIL)?;
+        let (group, ifindex) = groups.swap_remove(idx);
+        iface::leave_multicast_group(group, ifindex)
+    }
+
     pub fn check_bind(&self, fd: usize, mut bound_addr: IpListenEndpoint) -> Option<usize> {
         // 查看是否已经用过该端口和地址。可以将两个UDP套接字绑定到同一个端口，
         // 但它们需要绑定到不同的地址
@@ -164,39 +319,36 @@ impl UdpSocket {
 
     /// Sends data on the socket to the given address. On success, returns the
     /// number of bytes written.
-    pub async fn send_to(&self, buf: &[u8], remote_addr: IpEndpoint) -> SysResult<usize> {
+    pub async fn send_to(
+        &self,
+        buf: &[u8],
+        remote_addr: IpEndpoint,
+        flags: MsgFlags,
+    ) -> SysResult<usize> {
         if remote_addr.port == 0 || remote_addr.addr.is_unspecified() {
             warn!("socket send_to() failed: invalid remote address");
             return Err(SysError::EINVAL);
         }
-        self.send_impl(buf, remote_addr).await
+        if self.v6only() && matches!(remote_addr.addr, IpAddress::Ipv4(_)) {
+            return Err(SysError::ENETUNREACH);
+        }
+        if route::lookup(remote_addr.addr).is_none() && !remote_addr.addr.is_multicast() {
+            warn!("socket send_to() failed: no route to {}", remote_addr.addr);
+            return Err(SysError::ENETUNREACH);
+        }
+        self.send_impl(buf, remote_addr, flags).await
     }
 
     /// Receives a single datagram message on the socket. On success, returns
-    /// the number of bytes read and the origin.
-    pub async fn recv_from(&self, buf: &mut [u8]) -> SysResult<(usize, IpEndpoint)> {
-        self.recv_impl(|socket| match socket.recv_slice(buf) {
-            Ok((len, meta)) => Ok((len, meta.endpoint)),
-            Err(e) => {
-                warn!("[UdpSocket::recv_from] socket {} failed {e:?}", self.handle);
-                Err(SysError::EAGAIN)
-            }
-        })
-        .await
-    }
-
-    /// Receives a single datagram message on the socket, without removing it
-    /// from the queue. On success, returns the number of bytes read and the
-    /// origin.
-    pub async fn peek_from(&self, buf: &mut [u8]) -> SysResult<(usize, IpEndpoint)> {
-        self.recv_impl(|socket| match socket.peek_slice(buf) {
-            Ok((len, meta)) => Ok((len, meta.endpoint)),
-            Err(_) => {
-                warn!("socket recv_from() failed");
-                Err(SysError::EAGAIN)
-            }
-        })
-        .await
+    /// the length of the datagram and the origin. The length is larger than
+    /// `buf` if the datagram was truncated.
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+        flags: MsgFlags,
+    ) -> SysResult<(usize, IpEndpoint)> {
+        self.recv_impl(flags, |socket| self.recv_datagram(socket, buf, flags, None))
+            .await
     }
 
     /// Connects this UDP socket to a remote address, allowing the `send` and
@@ -213,8 +365,15 @@ impl UdpSocket {
             );
             self.bind(UNSPECIFIED_LISTEN_ENDPOINT)?;
         }
-        let mut self_peer_addr = self.peer_addr.write();
-        *self_peer_addr = Some(addr);
+        *self.peer_addr.write() = Some(addr);
+        if let Some(info) = UDP_SOCKETS
+            .lock()
+            .iter_mut()
+            .find(|(handle, ..)| *handle == self.handle)
+        {
+            info.2 = Some(addr);
+        }
+        self.apply_hop_limit();
         info!(
             "[UdpSocket::connect] handle {} local {} connected to remote {}",
             self.handle,
@@ -225,29 +384,21 @@ impl UdpSocket {
     }
 
     /// Sends data on the socket to the remote address to which it is connected.
-    pub async fn send(&self, buf: &[u8]) -> SysResult<usize> {
+    pub async fn send(&self, buf: &[u8], flags: MsgFlags) -> SysResult<usize> {
         let remote_endpoint = self.remote_endpoint()?;
-        self.send_impl(buf, remote_endpoint).await
+        self.send_impl(buf, remote_endpoint, flags).await
     }
 
     /// Receives a single datagram message on the socket from the remote address
-    /// to which it is connected. On success, returns the number of bytes read.
-    pub async fn recv(&self, buf: &mut [u8]) -> SysResult<usize> {
+    /// to which it is connected. On success, returns the length of the
+    /// datagram, see [`recv_from`](Self::recv_from).
+    pub async fn recv(&self, buf: &mut [u8], flags: MsgFlags) -> SysResult<usize> {
         let remote_endpoint = self.remote_endpoint()?;
-        self.recv_impl(|socket| {
-            let (len, meta) = socket.recv_slice(buf).map_err(|_| {
-                warn!("socket recv()  failed");
-                SysError::EAGAIN
-            })?;
-            if !is_unspecified(remote_endpoint.addr) && remote_endpoint.addr != meta.endpoint.addr {
-                return Err(SysError::EAGAIN);
-            }
-            if remote_endpoint.port != 0 && remote_endpoint.port != meta.endpoint.port {
-                return Err(SysError::EAGAIN);
-            }
-            Ok(len)
+        self.recv_impl(flags, |socket| {
+            self.recv_datagram(socket, buf, flags, Some(remote_endpoint))
         })
         .await
+        .map(|(len, _)| len)
     }
 
     /// Close the socket.
@@ -297,6 +448,30 @@ impl UdpSocket {
 
 /// Private methods
 impl UdpSocket {
+    /// Whether this call does not block, i.e. the socket is nonblocking or
+    /// `MSG_DONTWAIT` is given.
+    #[inline]
+    fn nonblock(&self, flags: MsgFlags) -> bool {
+        self.is_nonblocking() || flags.contains(MsgFlags::DONTWAIT)
+    }
+
+    /// Whether datagrams from `src` are dropped because of `IPV6_V6ONLY`.
+    fn is_filtered(&self, src: IpAddress) -> bool {
+        self.v6only() && matches!(src, IpAddress::Ipv4(_))
+    }
+
+    /// Set the hop limit of the smoltcp socket, see [`UdpOptions`].
+    fn apply_hop_limit(&self) {
+        let opts = self.options();
+        let hop_limit = match *self.peer_addr.read() {
+            Some(peer) if peer.addr.is_multicast() => Some(opts.multicast_hop_limit.max(1)),
+            _ => opts.hop_limit,
+        };
+        SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
+            socket.set_hop_limit(hop_limit)
+        });
+    }
+
     fn remote_endpoint(&self) -> SysResult<IpEndpoint> {
         match self.peer_addr.try_read() {
             Some(addr) => addr.ok_or(SysError::ENOTCONN),
@@ -304,7 +479,19 @@ impl UdpSocket {
         }
     }
 
-    async fn send_impl(&self, buf: &[u8], remote_endpoint: IpEndpoint) -> SysResult<usize> {
+    async fn send_impl(
+        &self,
+        buf: &[u8],
+        remote_endpoint: IpEndpoint,
+        flags: MsgFlags,
+    ) -> SysResult<usize> {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        if !self.options().broadcast && iface::is_broadcast_addr(remote_endpoint.addr) {
+            warn!("[send_impl] sending to {remote_endpoint} requires SO_BROADCAST");
+            return Err(SysError::EACCES);
+        }
         if self.local_addr.read().is_none() {
             warn!(
                 "[send_impl] UDP socket {}: not bound. Use 127.0.0.1",
@@ -314,7 +501,7 @@ impl UdpSocket {
         }
         let waker = get_waker().await;
         let bytes = self
-            .block_on(|| {
+            .block_on(self.nonblock(flags), || {
                 SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
                     if socket.can_send() {
                         socket
@@ -348,21 +535,64 @@ impl UdpSocket {
         Ok(bytes)
     }
 
-    async fn recv_impl<F, T>(&self, mut op: F) -> SysResult<T>
+    /// Take the next datagram from `socket` and copy it into `buf`, or only
+    /// copy it with `MSG_PEEK`. Datagrams not from `peer` are dropped, and
+    /// `EAGAIN` is returned for them so that [`recv_impl`](Self::recv_impl)
+    /// tries the next one.
+    fn recv_datagram(
+        &self,
+        socket: &mut udp::Socket,
+        buf: &mut [u8],
+        flags: MsgFlags,
+        peer: Option<IpEndpoint>,
+    ) -> SysResult<(usize, IpEndpoint)> {
+        let (len, src) = {
+            let (data, meta) = socket.peek().map_err(|_| SysError::EAGAIN)?;
+            let src = meta.endpoint;
+            let accepted = !self.is_filtered(src.addr)
+                && peer.is_none_or(|peer| {
+                    (is_unspecified(peer.addr) || peer.addr == src.addr)
+                        && (peer.port == 0 || peer.port == src.port)
+                });
+            if accepted {
+                let copied = data.len().min(buf.len());
+                buf[..copied].copy_from_slice(&data[..copied]);
+            }
+            (accepted.then_some(data.len()), src)
+        };
+        if len.is_none() || !flags.contains(MsgFlags::PEEK) {
+            let _ = socket.recv();
+        }
+        len.map(|len| (len, src)).ok_or(SysError::EAGAIN)
+    }
+
+    async fn recv_impl<F, T>(&self, flags: MsgFlags, mut op: F) -> SysResult<T>
     where
         F: FnMut(&mut udp::Socket) -> SysResult<T>,
     {
+        if flags.contains(MsgFlags::OOB) {
+            return Err(SysError::EOPNOTSUPP);
+        }
         if self.local_addr.read().is_none() {
             warn!("socket send() failed");
             return Err(SysError::ENOTCONN);
         }
         let waker = get_waker().await;
         let ret = self
-            .block_on(|| {
+            .block_on(self.nonblock(flags), || {
                 SOCKET_SET.with_socket_mut::<udp::Socket, _, _>(self.handle, |socket| {
                     if socket.can_recv() {
-                        // data available
-                        op(socket)
+                        // data available, datagrams dropped by `op` are skipped
+                        loop {
+                            match op(socket) {
+                                Err(SysError::EAGAIN) if socket.can_recv() => continue,
+                                Err(SysError::EAGAIN) => {
+                                    socket.register_recv_waker(&waker);
+                                    break Err(SysError::EAGAIN);
+                                }
+                                ret => break ret,
+                            }
+                        }
                     } else if !socket.is_open() {
                         // TODO: I suppose that this would't happen
                         warn!("UDP socket {}: recv() failed: not connected", self.handle);
@@ -380,11 +610,11 @@ impl UdpSocket {
         ret
     }
 
-    async fn block_on<F, T>(&self, mut f: F) -> SysResult<T>
+    async fn block_on<F, T>(&self, nonblock: bool, mut f: F) -> SysResult<T>
     where
         F: FnMut() -> SysResult<T>,
     {
-        if self.is_nonblocking() {
+        if nonblock {
             f()
         } else {
             loop {
@@ -413,6 +643,12 @@ impl Drop for UdpSocket {
         //     return;
         // }
         self.shutdown().ok();
+        for (group, ifindex) in core::mem::take(&mut *self.multicast_groups.lock()) {
+            iface::leave_multicast_group(group, ifindex).ok();
+        }
+        UDP_SOCKETS
+            .lock()
+            .retain(|(handle, ..)| *handle != self.handle);
         SOCKET_SET.remove(self.handle);
         if let Ok(addr) = self.local_addr() {
             PORT_MAP.remove(addr.port);
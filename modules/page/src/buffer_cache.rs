@@ -1,5 +1,8 @@
-use alloc::sync::{Arc, Weak};
-use core::num::NonZeroUsize;
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{num::NonZeroUsize, ops::Range};
 
 use config::{
     board::BLOCK_SIZE,
@@ -49,13 +52,30 @@ impl BufferCache {
         self.device.as_ref().unwrap().upgrade().unwrap()
     }
 
-    pub fn read_block(&mut self, block_id: usize, buf: &mut [u8]) {
-        let buffer_head = self.get_buffer_head_from_disk(block_id);
-        if buffer_head.has_cached() {
-            buffer_head.read_block(buf)
-        } else {
-            self.device().base_read_blocks(block_id, buf)
+    /// Copy the cached ones of the consecutive blocks starting from
+    /// `block_id` into `buf`, whose length should be a multiple of
+    /// `BLOCK_SIZE`. Returns the runs of blocks missing from cache, which are
+    /// left for the caller to read from the device.
+    pub fn read_cached_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> Vec<Range<usize>> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        let nblocks = buf.len() / BLOCK_SIZE;
+        let mut missing = Vec::new();
+        let mut miss_start = None;
+        for i in 0..nblocks {
+            let buffer_head = self.get_buffer_head_from_disk(block_id + i);
+            if buffer_head.has_cached() {
+                if let Some(start) = miss_start.take() {
+                    missing.push(start..block_id + i);
+                }
+                buffer_head.read_block(&mut buf[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE]);
+            } else if miss_start.is_none() {
+                miss_start = Some(block_id + i);
+            }
+        }
+        if let Some(start) = miss_start {
+            missing.push(start..block_id + nblocks);
         }
+        missing
     }
 
     pub fn write_block(&mut self, block_id: usize, buf: &[u8]) {
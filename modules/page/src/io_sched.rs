@@ -0,0 +1,195 @@
+//! A simple elevator scheduler with deadlines sitting in front of a
+//! `BlockDevice`.
+//!
+//! Reads of blocks missing from the buffer cache are queued here by every
+//! reader of the device, readahead tasks included. Whoever waits for a request
+//! while no one else is dispatching takes a batch of pending requests and
+//! issues it to the device on behalf of all of them: requests are served in
+//! ascending block order starting from the current head position (C-SCAN),
+//! and adjacent or overlapping ranges are merged so that the device sees as
+//! few transfers as possible. A request that has been passed over for more
+//! than `MAX_STARVE_ROUNDS` dispatch rounds is served first regardless of its
+//! position.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::ops::Range;
+
+use config::board::BLOCK_SIZE;
+use device_core::BlockDevice;
+use sync::mutex::SpinNoIrqLock;
+
+/// Max number of dispatch rounds a request can be skipped before it expires.
+const MAX_STARVE_ROUNDS: usize = 4;
+
+/// Max number of blocks merged into a single device transfer.
+pub const MAX_MERGE_BLOCKS: usize = 256;
+
+/// Max number of blocks issued in one dispatch round, the requests left over
+/// wait for the next one.
+const MAX_DISPATCH_BLOCKS: usize = 4 * MAX_MERGE_BLOCKS;
+
+#[derive(Debug, Clone)]
+struct BlockRequest {
+    id: usize,
+    /// Block range on the device.
+    blocks: Range<usize>,
+    /// Dispatch round after which this request must be served first.
+    deadline: usize,
+}
+
+/// One device transfer, covering the blocks of all its requests.
+struct Transfer {
+    blocks: Range<usize>,
+    reqs: Vec<BlockRequest>,
+}
+
+struct Queue {
+    /// Pending requests in submission order.
+    pending: Vec<BlockRequest>,
+    /// Data read for requests not taken by their submitter yet, by id.
+    completed: BTreeMap<usize, Vec<u8>>,
+    /// Whether some reader is issuing a batch to the device.
+    dispatching: bool,
+    /// Block after the last dispatched one, i.e. current head position.
+    head: usize,
+    /// Count of dispatch rounds.
+    round: usize,
+    next_id: usize,
+}
+
+pub struct IoScheduler {
+    queue: SpinNoIrqLock<Queue>,
+}
+
+impl IoScheduler {
+    pub const fn new() -> Self {
+        Self {
+            queue: SpinNoIrqLock::new(Queue {
+                pending: Vec::new(),
+                completed: BTreeMap::new(),
+                dispatching: false,
+                head: 0,
+                round: 0,
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Queue a read of `blocks`, returns the id to wait for it with.
+    pub fn submit(&self, blocks: Range<usize>) -> usize {
+        let mut queue = self.queue.lock();
+        let id = queue.next_id;
+        queue.next_id += 1;
+        let deadline = queue.round + MAX_STARVE_ROUNDS;
+        queue.pending.push(BlockRequest {
+            id,
+            blocks,
+            deadline,
+        });
+        id
+    }
+
+    /// Wait for request `id` to be read from `device` into `buf`, whose length
+    /// should be the one of its blocks. Batches of pending requests are
+    /// dispatched meanwhile if no one else does.
+    pub fn wait(&self, device: &dyn BlockDevice, id: usize, buf: &mut [u8]) {
+        loop {
+            let batch = {
+                let mut queue = self.queue.lock();
+                if let Some(data) = queue.completed.remove(&id) {
+                    buf.copy_from_slice(&data);
+                    return;
+                }
+                if queue.dispatching {
+                    None
+                } else {
+                    queue.dispatching = true;
+                    Some(queue.take_batch())
+                }
+            };
+            let Some(batch) = batch else {
+                core::hint::spin_loop();
+                continue;
+            };
+            let completed = issue(device, batch);
+            let mut queue = self.queue.lock();
+            queue.completed.extend(completed);
+            queue.dispatching = false;
+        }
+    }
+
+    /// Read `blocks` from `device` into `buf` through the queue.
+    pub fn read(&self, device: &dyn BlockDevice, blocks: Range<usize>, buf: &mut [u8]) {
+        let id = self.submit(blocks);
+        self.wait(device, id, buf);
+    }
+}
+
+impl Default for IoScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Queue {
+    /// Take a batch of requests in the order they should be issued to the
+    /// device. Adjacent ranges are merged into one transfer.
+    fn take_batch(&mut self) -> Vec<Transfer> {
+        self.round += 1;
+        let round = self.round;
+        let (expired, mut reqs): (Vec<_>, Vec<_>) =
+            self.pending.drain(..).partition(|req| req.deadline < round);
+
+        reqs.sort_unstable_by_key(|req| req.blocks.start);
+        let split = reqs.partition_point(|req| req.blocks.start < self.head);
+        // C-SCAN: sweep from head to the end, then wrap to the lowest block.
+        reqs.rotate_left(split);
+
+        // Expired requests are served in fifo order before the elevator sweep.
+        let mut batch: Vec<Transfer> = Vec::new();
+        let mut budget = MAX_DISPATCH_BLOCKS;
+        for req in expired.into_iter().chain(reqs) {
+            if req.blocks.len() > budget && !batch.is_empty() {
+                self.pending.push(req);
+                continue;
+            }
+            budget = budget.saturating_sub(req.blocks.len());
+            match batch.last_mut() {
+                Some(last)
+                    if req.blocks.start <= last.blocks.end
+                        && req.blocks.start >= last.blocks.start
+                        && req.blocks.end - last.blocks.start <= MAX_MERGE_BLOCKS =>
+                {
+                    last.blocks.end = last.blocks.end.max(req.blocks.end);
+                    last.reqs.push(req);
+                }
+                _ => batch.push(Transfer {
+                    blocks: req.blocks.clone(),
+                    reqs: vec![req],
+                }),
+            }
+        }
+        self.pending.sort_unstable_by_key(|req| req.id);
+
+        if let Some(last) = batch.last() {
+            self.head = last.blocks.end;
+        }
+        batch
+    }
+}
+
+/// Issue `batch` to `device`, returns the data of each request by id.
+fn issue(device: &dyn BlockDevice, batch: Vec<Transfer>) -> Vec<(usize, Vec<u8>)> {
+    let mut completed = Vec::new();
+    for transfer in batch {
+        let start = transfer.blocks.start;
+        let mut data = vec![0; transfer.blocks.len() * BLOCK_SIZE];
+        device.base_read_blocks(start, &mut data);
+        for req in transfer.reqs {
+            let range =
+                (req.blocks.start - start) * BLOCK_SIZE..(req.blocks.end - start) * BLOCK_SIZE;
+            completed.push((req.id, data[range].to_vec()));
+        }
+    }
+    completed
+}
@@ -4,9 +4,11 @@
 extern crate alloc;
 
 mod buffer_cache;
+mod io_sched;
 mod page;
 mod page_cache;
 
 pub use buffer_cache::*;
+pub use io_sched::*;
 pub use page::*;
 pub use page_cache::*;
@@ -1,4 +1,7 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{cmp, fmt, ops::Range};
 
 use config::{
@@ -8,7 +11,10 @@ use config::{
 use device_core::BlockDevice;
 use enum_as_inner::EnumAsInner;
 use intrusive_collections::LinkedList;
-use memory::{FrameTracker, PhysPageNum, alloc_frame_tracker};
+use memory::{
+    FrameTracker, PhysPageNum, alloc_frame_tracker, try_alloc_aligned_frame_trackers,
+    try_alloc_frame_tracker,
+};
 use sync::mutex::SpinNoIrqLock;
 
 use crate::{
@@ -94,6 +100,32 @@ impl Page {
         })
     }
 
+    /// Create a `Page` by allocating a frame, `None` if memory is exhausted.
+    pub fn try_new() -> Option<Arc<Self>> {
+        let frame = try_alloc_frame_tracker()?;
+        Some(Arc::new(Self {
+            frame,
+            kind: PageKind::Normal,
+        }))
+    }
+
+    /// Create `count` `Page`s of contiguous frames aligned to `count` frames,
+    /// which back a huge page. `None` if there are no such frames.
+    pub fn try_new_contiguous(count: usize) -> Option<Vec<Arc<Self>>> {
+        let frames = try_alloc_aligned_frame_trackers(count)?;
+        Some(
+            frames
+                .into_iter()
+                .map(|frame| {
+                    Arc::new(Self {
+                        frame,
+                        kind: PageKind::Normal,
+                    })
+                })
+                .collect(),
+        )
+    }
+
     pub fn new_file(block_device: &Arc<dyn BlockDevice>) -> Arc<Self> {
         let frame = alloc_frame_tracker();
         Arc::new(Self {
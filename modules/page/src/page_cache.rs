@@ -23,9 +23,16 @@ impl PageCache {
         self.pages.lock().get(&offset_aligned).cloned()
     }
 
-    pub fn insert_page(&self, offset_aligned: usize, page: Arc<Page>) {
+    /// Insert `page` at `offset_aligned` unless a page is cached there already,
+    /// e.g. one inserted and written by a concurrent writer while `page` was
+    /// read. Returns the page in the cache, which callers should use.
+    pub fn insert_page(&self, offset_aligned: usize, page: Arc<Page>) -> Arc<Page> {
         debug_assert!(is_aligned_to_page(offset_aligned));
-        self.pages.lock().insert(offset_aligned, page);
+        self.pages
+            .lock()
+            .entry(offset_aligned)
+            .or_insert(page)
+            .clone()
     }
 
     pub fn clear(&self) {
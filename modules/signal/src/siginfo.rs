@@ -16,6 +16,11 @@ pub enum SigDetails {
         /// sender's pid
         pid: usize,
     },
+    /// `SIGSEGV`, `SIGBUS`, `SIGILL` and `SIGFPE` of a faulting access
+    Fault {
+        /// faulting address
+        addr: usize,
+    },
 }
 
 #[allow(unused)]
@@ -55,4 +60,16 @@ impl SigInfo {
     /// stopped child has continued
     pub const CLD_CONTINUED: i32 = 6;
     pub const NSIGCHLD: i32 = 6;
+
+    // SIGSEGV si_codes
+    /// address not mapped to object
+    pub const SEGV_MAPERR: i32 = 1;
+    /// invalid permissions for mapped object
+    pub const SEGV_ACCERR: i32 = 2;
+
+    // SIGTRAP si_codes
+    /// process breakpoint
+    pub const TRAP_BRKPT: i32 = 1;
+    /// process trace trap
+    pub const TRAP_TRACE: i32 = 2;
 }
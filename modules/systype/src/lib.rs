@@ -102,22 +102,44 @@ pub enum SysError {
     ENOTEMPTY = 39,
     /// Too many symbolic links encountered
     ELOOP = 40,
+    /// File descriptor in bad state
+    EBADFD = 77,
     /// Socket operation on non-socket
     ENOTSOCK = 88,
+    /// Destination address required
+    EDESTADDRREQ = 89,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// Protocol not available
+    ENOPROTOOPT = 92,
+    /// Protocol not supported
+    EPROTONOSUPPORT = 93,
+    /// Socket type not supported
+    ESOCKTNOSUPPORT = 94,
     /// Unsupported
     EOPNOTSUPP = 95,
+    /// Address family not supported by protocol
+    EAFNOSUPPORT = 97,
     /// Socket address is already in use
     EADDRINUSE = 98,
     /// Address not available
     EADDRNOTAVAIL = 99,
+    /// Network is down
+    ENETDOWN = 100,
+    /// Network is unreachable
+    ENETUNREACH = 101,
     /// Connection reset
     ECONNRESET = 104,
+    /// No buffer space available
+    ENOBUFS = 105,
     /// Transport endpoint is already connected
     EISCONN = 106,
     /// The socket is not connected
     ENOTCONN = 107,
     /// Connection refused
     ECONNREFUSED = 111,
+    /// Operation already in progress
+    EALREADY = 114,
     /// The socket is nonblocking and the connection cannot be completed
     /// immediately.(connect.2)
     EINPROGRESS = 115,
@@ -168,14 +190,25 @@ impl SysError {
             ENOSYS => "Invalid system call number",
             ENOTEMPTY => "Directory not empty",
             ELOOP => "Too many symbolic links encountered",
+            EBADFD => "File descriptor in bad state",
             ENOTSOCK => "Socket operation on non-socket",
+            EDESTADDRREQ => "Destination address required",
+            EMSGSIZE => "Message too long",
+            ENOPROTOOPT => "Protocol not available",
+            EPROTONOSUPPORT => "Protocol not supported",
+            ESOCKTNOSUPPORT => "Socket type not supported",
             ENOTCONN => "Transport endpoint is not connected",
             EOPNOTSUPP => "Unsupported Error",
             EADDRNOTAVAIL => "Address not available",
+            EAFNOSUPPORT => "Address family not supported by protocol",
             EADDRINUSE => "Address already in use",
+            ENETDOWN => "Network is down",
+            ENETUNREACH => "Network is unreachable",
             EISCONN => "Transport endpoint is already connected",
             ECONNRESET => "Connection reset",
+            ENOBUFS => "No buffer space available",
             ECONNREFUSED => "Connection refused",
+            EALREADY => "Operation already in progress",
             EINPROGRESS => "Operation now in progress",
         }
     }
@@ -20,8 +20,8 @@ use spin::Mutex;
 use systype::{SysError, SysResult, SyscallResult};
 
 use crate::{
-    Dentry, DirEntry, Inode, InodeState, InodeType, OpenFlags, PollEvents, SeekFrom, SuperBlock,
-    inode,
+    Dentry, DirEntry, Inode, InodeState, InodeType, OpenFlags, PollEvents, ReadaheadState,
+    SeekFrom, SuperBlock, inode,
 };
 
 pub struct FileMeta {
@@ -33,6 +33,8 @@ pub struct FileMeta {
     /// WARN: may cause trouble if this is not locked with other things.
     pub pos: AtomicUsize,
     pub flags: Mutex<OpenFlags>,
+    /// Readahead window of this opened file.
+    pub ra: Mutex<ReadaheadState>,
 }
 
 impl FileMeta {
@@ -42,6 +44,7 @@ impl FileMeta {
             inode,
             pos: 0.into(),
             flags: Mutex::new(OpenFlags::empty()),
+            ra: Mutex::new(ReadaheadState::new()),
         }
     }
 }
@@ -108,7 +111,7 @@ pub trait File: Send + Sync + DowncastSync {
         //     page.insert_buffer_head(buffer_head);
         // }
 
-        page_cache.insert_page(offset_aligned, page.clone());
+        let page = page_cache.insert_page(offset_aligned, page);
 
         Ok(Some(page))
     }
@@ -132,6 +135,9 @@ pub trait File: Send + Sync + DowncastSync {
             return Ok(count);
         };
 
+        self.meta()
+            .readahead_on_read(offset, buf.len().min(self.size().saturating_sub(offset)));
+
         let mut buf_it = buf;
         let mut offset_it = offset;
 
@@ -211,9 +217,7 @@ pub trait File: Send + Sync + DowncastSync {
                 page
             } else {
                 log::info!("[File::write_at] create new page");
-                let page = Page::new_file(&device);
-                page_cache.insert_page(offset_aligned, page.clone());
-                page
+                page_cache.insert_page(offset_aligned, Page::new_file(&device))
             };
             let len = (buf_it.len()).min(PAGE_SIZE - offset_in_page);
             page.bytes_array_range(offset_in_page..offset_in_page + len)
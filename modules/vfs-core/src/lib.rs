@@ -7,6 +7,7 @@ mod file;
 mod file_system_type;
 mod inode;
 mod path;
+mod readahead;
 mod super_block;
 mod utils;
 
@@ -35,5 +36,6 @@ pub use file::*;
 pub use file_system_type::*;
 pub use inode::*;
 pub use path::*;
+pub use readahead::*;
 pub use super_block::*;
 pub use utils::*;
@@ -0,0 +1,149 @@
+//! Per-file readahead.
+//!
+//! Every opened file keeps a readahead window. When reads look sequential, the
+//! window ahead of the reader is prefetched into the page cache by a background
+//! kernel task, and its size doubles each time the reader catches up with it,
+//! until it reaches the max size.
+
+use alloc::boxed::Box;
+use core::{future::Future, ops::Range, pin::Pin};
+
+use config::mm::{PAGE_SIZE, round_down_to_page, round_up_to_page};
+use crate_interface::call_interface;
+
+use crate::FileMeta;
+
+/// Size of the first readahead window in pages.
+pub const RA_INIT_PAGES: usize = 4;
+/// Default max size of the readahead window in pages.
+pub const RA_MAX_PAGES: usize = 32;
+
+/// Access pattern advice given by posix_fadvise(2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileAdvice {
+    #[default]
+    Normal,
+    Sequential,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReadaheadState {
+    /// Page index following the last page read.
+    prev_end: usize,
+    /// First page index of the current readahead window.
+    start: usize,
+    /// Size of the current readahead window in pages.
+    size: usize,
+    /// Max size of the readahead window in pages, zero means disabled.
+    max: usize,
+}
+
+impl ReadaheadState {
+    pub const fn new() -> Self {
+        Self {
+            prev_end: 0,
+            start: 0,
+            size: 0,
+            max: RA_MAX_PAGES,
+        }
+    }
+
+    pub fn set_advice(&mut self, advice: FileAdvice) {
+        self.max = match advice {
+            FileAdvice::Normal => RA_MAX_PAGES,
+            FileAdvice::Sequential => 2 * RA_MAX_PAGES,
+            FileAdvice::Random => 0,
+        };
+        self.size = self.size.min(self.max);
+    }
+
+    /// Record a read of pages in `pages`, returns the page range that should be
+    /// prefetched, if any.
+    pub fn on_read(&mut self, pages: Range<usize>) -> Option<Range<usize>> {
+        let sequential = pages.start == self.prev_end
+            || pages.start + 1 == self.prev_end
+            || (pages.start == 0 && self.prev_end == 0);
+        self.prev_end = pages.end;
+
+        if self.max == 0 {
+            return None;
+        }
+        if !sequential {
+            self.start = 0;
+            self.size = 0;
+            return None;
+        }
+        // Reader has not reached the window prefetched last time.
+        if self.size != 0 && pages.end <= self.start {
+            return None;
+        }
+
+        let start = pages.end.max(self.start + self.size);
+        self.size = if self.size == 0 {
+            RA_INIT_PAGES
+        } else {
+            (self.size * 2).min(self.max)
+        };
+        self.start = start;
+        Some(start..start + self.size)
+    }
+}
+
+#[crate_interface::def_interface]
+pub trait ReadaheadIf {
+    /// Run `future` as a background kernel task.
+    fn spawn_readahead(future: Pin<Box<dyn Future<Output = ()> + Send>>);
+}
+
+impl FileMeta {
+    /// Update readahead state with a read of `len` bytes at `offset`, and
+    /// prefetch the next window if the access is sequential.
+    pub fn readahead_on_read(&self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let pages = offset / PAGE_SIZE..round_up_to_page(offset + len) / PAGE_SIZE;
+        let window = self.ra.lock().on_read(pages);
+        if let Some(window) = window {
+            self.readahead(window.start * PAGE_SIZE, window.len() * PAGE_SIZE);
+        }
+    }
+
+    /// Prefetch pages covering `[offset, offset + len)` into page cache in the
+    /// background.
+    pub fn readahead(&self, offset: usize, len: usize) {
+        let Some(page_cache) = self.inode.page_cache() else {
+            return;
+        };
+        let size = self.inode.size();
+        if offset >= size || len == 0 {
+            return;
+        }
+        let start = round_down_to_page(offset);
+        let end = round_up_to_page(offset.saturating_add(len).min(size));
+        if (start..end)
+            .step_by(PAGE_SIZE)
+            .all(|offset_aligned| page_cache.get_page(offset_aligned).is_some())
+        {
+            return;
+        }
+
+        log::debug!(
+            "[FileMeta::readahead] file {}, range [{start:#x}, {end:#x})",
+            self.dentry.path()
+        );
+        let dentry = self.dentry.clone();
+        call_interface!(ReadaheadIf::spawn_readahead(Box::pin(async move {
+            let Ok(file) = dentry.open() else {
+                return;
+            };
+            for offset_aligned in (start..end).step_by(PAGE_SIZE) {
+                match file.get_page_at(offset_aligned).await {
+                    Ok(Some(_)) => {}
+                    _ => break,
+                }
+            }
+        })));
+    }
+}
@@ -9,6 +9,7 @@ use self::{
     null::{NullDentry, NullInode},
     rtc::{RtcDentry, RtcInode},
     tty::{TTY, TtyDentry, TtyFile, TtyInode},
+    tun::{TunDentry, TunInode},
     urandom::{UrandomDentry, UrandomInode},
     zero::{ZeroDentry, ZeroInode},
 };
@@ -18,6 +19,7 @@ mod cpu_dma_latency;
 mod null;
 mod rtc;
 pub mod tty;
+mod tun;
 pub mod urandom;
 mod zero;
 
@@ -59,6 +61,15 @@ pub fn init_devfs(root_dentry: Arc<dyn Dentry>) -> SysResult<()> {
     let tty_file = TtyFile::new(tty_dentry.clone(), tty_dentry.inode()?);
     TTY.call_once(|| tty_file);
 
+    let net_dentry = SimpleDentry::new("net", sb.clone(), Some(root_dentry.clone()));
+    root_dentry.insert(net_dentry.clone());
+    let net_inode = SimpleDirInode::new(InodeMode::DIR, sb.clone(), 0);
+    net_dentry.set_inode(net_inode);
+    let tun_dentry = TunDentry::new("tun", sb.clone(), Some(net_dentry.clone()));
+    net_dentry.insert(tun_dentry.clone());
+    let tun_inode = TunInode::new(sb.clone());
+    tun_dentry.set_inode(tun_inode);
+
     // TODO: POSIX shm operations are not implemented yet. The code below is work
     // around to pass libc test pthread_cancel_points.
     let shm_dentry = SimpleDentry::new("shm", sb.clone(), Some(root_dentry.clone()));
@@ -0,0 +1,560 @@
+//! `/dev/net/tun`, the TUN/TAP driver.
+//!
+//! An open file is attached to a new virtual interface by `TUNSETIFF`. Packets
+//! the network stack sends through the interface are read from the file, and
+//! packets written to the file are received by the stack, as if they came from
+//! a wire. A TUN interface carries IP packets, a TAP interface carries
+//! Ethernet frames. The interface is removed when the file is closed.
+
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use async_trait::async_trait;
+use async_utils::get_waker;
+use device_core::{
+    DeviceCapabilities, EthernetAddress, Medium, NetBufPtrOps, NetDevice,
+    error::{DevError, DevResult},
+};
+use net::iface::{
+    self, IFF_BROADCAST, IFF_MULTICAST, IFF_NOARP, IFF_POINTOPOINT, IFF_RUNNING, IFF_UP,
+};
+use strum::FromRepr;
+use sync::mutex::SpinNoIrqLock;
+use systype::{SysError, SysResult, SyscallResult};
+use vfs_core::{
+    Dentry, DentryMeta, DirEntry, File, FileMeta, Inode, InodeMeta, InodeMode, OpenFlags,
+    PollEvents, Stat, SuperBlock,
+};
+
+use super::urandom::RNG;
+
+type Mutex<T> = SpinNoIrqLock<T>;
+
+/// Defined in <linux/if_tun.h>
+#[derive(FromRepr, Debug)]
+#[repr(usize)]
+enum TunIoctlCmd {
+    /// Create an interface and attach it to the file.
+    TUNSETIFF = 0x400454ca,
+    /// Get the `IFF_*` flags supported by `TUNSETIFF`.
+    TUNGETFEATURES = 0x800454cf,
+    /// Get the name and flags of the attached interface.
+    TUNGETIFF = 0x800454d2,
+}
+
+// `ifr_flags` of `TUNSETIFF`, defined in <linux/if_tun.h>
+const IFF_TUN: u16 = 0x0001;
+const IFF_TAP: u16 = 0x0002;
+const IFF_ONE_QUEUE: u16 = 0x2000;
+const IFF_NO_PI: u16 = 0x1000;
+const TUN_FEATURES: u16 = IFF_TUN | IFF_TAP | IFF_NO_PI | IFF_ONE_QUEUE;
+
+/// Set in `tun_pi.flags` if the packet did not fit in the read buffer.
+const TUN_PKT_STRIP: u16 = 0x0001;
+/// Length of `struct tun_pi`, which precedes each packet unless `IFF_NO_PI`.
+const TUN_PI_LEN: usize = 4;
+
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+const ETHERNET_HEADER_LEN: usize = 14;
+
+const IFNAMSIZ: usize = 16;
+const TUN_MTU: usize = 1500;
+/// Packets not read yet are dropped beyond this, as `txqueuelen` in Linux.
+const TUN_QUEUE_LEN: usize = 500;
+
+/// Leading fields of `struct ifreq`, defined in <net/if.h>. The rest of the
+/// union is not used by the TUN ioctls.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct IfReq {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_flags: u16,
+}
+
+/// Packets in flight between an interface and its file.
+struct TunQueue {
+    /// Packets sent by the stack, waiting to be read.
+    to_user: VecDeque<Vec<u8>>,
+    /// Packets written to the file, waiting to be received by the stack.
+    to_stack: VecDeque<Vec<u8>>,
+    read_wakers: VecDeque<Waker>,
+}
+
+impl TunQueue {
+    fn new() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            to_user: VecDeque::new(),
+            to_stack: VecDeque::new(),
+            read_wakers: VecDeque::new(),
+        }))
+    }
+}
+
+/// The device behind a TUN/TAP interface.
+struct TunDevice {
+    tap: bool,
+    mac: [u8; 6],
+    queue: Arc<Mutex<TunQueue>>,
+}
+
+impl NetDevice for TunDevice {
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut cap = DeviceCapabilities::default();
+        if self.tap {
+            cap.max_transmission_unit = TUN_MTU + ETHERNET_HEADER_LEN;
+            cap.medium = Medium::Ethernet;
+        } else {
+            cap.max_transmission_unit = TUN_MTU;
+            cap.medium = Medium::Ip;
+        }
+        cap.max_burst_size = None;
+        cap
+    }
+
+    fn mac_address(&self) -> EthernetAddress {
+        EthernetAddress(self.mac)
+    }
+
+    fn can_transmit(&self) -> bool {
+        true
+    }
+
+    fn can_receive(&self) -> bool {
+        !self.queue.lock().to_stack.is_empty()
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        TUN_QUEUE_LEN
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        TUN_QUEUE_LEN
+    }
+
+    fn recycle_rx_buffer(&mut self, _rx_buf: Box<dyn NetBufPtrOps>) -> DevResult {
+        Ok(())
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: Box<dyn NetBufPtrOps>) -> DevResult {
+        let mut queue = self.queue.lock();
+        if queue.to_user.len() >= TUN_QUEUE_LEN {
+            log::warn!("[TunDevice::transmit] queue full, drop a packet");
+            return Ok(());
+        }
+        queue.to_user.push_back(tx_buf.packet().to_vec());
+        while let Some(waker) = queue.read_wakers.pop_front() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> DevResult<Box<dyn NetBufPtrOps>> {
+        match self.queue.lock().to_stack.pop_front() {
+            Some(buf) => Ok(Box::new(TunBuf(buf))),
+            None => Err(DevError::Again),
+        }
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<Box<dyn NetBufPtrOps>> {
+        Ok(Box::new(TunBuf(vec![0; size])))
+    }
+}
+
+struct TunBuf(Vec<u8>);
+
+impl NetBufPtrOps for TunBuf {
+    fn packet(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    fn packet_mut(&mut self) -> &mut [u8] {
+        self.0.as_mut_slice()
+    }
+
+    fn packet_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+pub struct TunDentry {
+    meta: DentryMeta,
+}
+
+impl TunDentry {
+    pub fn new(
+        name: &str,
+        super_block: Arc<dyn SuperBlock>,
+        parent: Option<Arc<dyn Dentry>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new(name, super_block, parent),
+        })
+    }
+}
+
+impl Dentry for TunDentry {
+    fn meta(&self) -> &DentryMeta {
+        &self.meta
+    }
+
+    fn base_open(self: Arc<Self>) -> SysResult<Arc<dyn File>> {
+        Ok(Arc::new(TunFile {
+            meta: FileMeta::new(self.clone(), self.inode()?),
+            attached: Mutex::new(None),
+        }))
+    }
+
+    fn base_lookup(self: Arc<Self>, _name: &str) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_create(self: Arc<Self>, _name: &str, _mode: InodeMode) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_unlink(self: Arc<Self>, _name: &str) -> SysResult<()> {
+        Err(SysError::ENOTDIR)
+    }
+}
+
+pub struct TunInode {
+    meta: InodeMeta,
+}
+
+impl TunInode {
+    pub fn new(super_block: Arc<dyn SuperBlock>) -> Arc<Self> {
+        Arc::new(Self {
+            meta: InodeMeta::new(InodeMode::CHAR, super_block, 0),
+        })
+    }
+}
+
+impl Inode for TunInode {
+    fn meta(&self) -> &InodeMeta {
+        &self.meta
+    }
+
+    fn get_attr(&self) -> SysResult<Stat> {
+        let inner = self.meta.inner.lock();
+        Ok(Stat {
+            st_dev: 0,
+            st_ino: self.meta.ino as u64,
+            st_mode: self.meta.mode.bits(),
+            st_nlink: 1,
+            st_uid: 0,
+            st_gid: 0,
+            st_rdev: 0,
+            __pad: 0,
+            st_size: inner.size as u64,
+            st_blksize: 0,
+            __pad2: 0,
+            st_blocks: 0,
+            st_atime: inner.atime,
+            st_mtime: inner.mtime,
+            st_ctime: inner.ctime,
+            unused: 0,
+        })
+    }
+}
+
+/// The interface a file is attached to.
+#[derive(Clone)]
+struct TunAttachment {
+    ifindex: usize,
+    name: String,
+    /// `ifr_flags` given to `TUNSETIFF`.
+    flags: u16,
+    queue: Arc<Mutex<TunQueue>>,
+}
+
+impl TunAttachment {
+    fn no_pi(&self) -> bool {
+        self.flags & IFF_NO_PI != 0
+    }
+
+    fn is_up(&self) -> bool {
+        iface::interface_by_index(self.ifindex).is_some_and(|info| info.flags & IFF_UP != 0)
+    }
+}
+
+pub struct TunFile {
+    meta: FileMeta,
+    attached: Mutex<Option<TunAttachment>>,
+}
+
+impl TunFile {
+    fn attachment(&self) -> SysResult<TunAttachment> {
+        self.attached.lock().clone().ok_or(SysError::EBADFD)
+    }
+
+    /// Create an interface as requested by `TUNSETIFF`, the name written back
+    /// to `ifr` is the one actually used.
+    fn set_iff(&self, ifr: &mut IfReq) -> SysResult<()> {
+        let mut attached = self.attached.lock();
+        if attached.is_some() {
+            return Err(SysError::EEXIST);
+        }
+        let flags = ifr.ifr_flags;
+        if flags & !TUN_FEATURES != 0 {
+            log::warn!("[TunFile::set_iff] unsupported flags {flags:#x}");
+            return Err(SysError::EINVAL);
+        }
+        let tap = match flags & (IFF_TUN | IFF_TAP) {
+            IFF_TUN => false,
+            IFF_TAP => true,
+            _ => return Err(SysError::EINVAL),
+        };
+        let name = {
+            let len = ifr
+                .ifr_name
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(SysError::EINVAL)?;
+            core::str::from_utf8(&ifr.ifr_name[..len]).map_err(|_| SysError::EINVAL)?
+        };
+        let name = match name {
+            "" if tap => alloc_name("tap%d")?,
+            "" => alloc_name("tun%d")?,
+            name if name.contains("%d") => alloc_name(name)?,
+            name => name.to_string(),
+        };
+
+        let mut mac = [0; 6];
+        if tap {
+            RNG.get_mut().fill_buf(&mut mac[1..]);
+            // Locally administered unicast address.
+            mac[0] = 0x02;
+        }
+        let queue = TunQueue::new();
+        let dev = Box::new(TunDevice {
+            tap,
+            mac,
+            queue: queue.clone(),
+        });
+        let if_flags = if tap {
+            IFF_BROADCAST | IFF_MULTICAST | IFF_RUNNING
+        } else {
+            IFF_POINTOPOINT | IFF_NOARP | IFF_MULTICAST | IFF_RUNNING
+        };
+        let ifindex = iface::register_interface(&name, dev, if_flags).map_err(|e| match e {
+            SysError::EEXIST => SysError::EBUSY,
+            e => e,
+        })?;
+        log::info!("[TunFile::set_iff] {name} index {ifindex}, flags {flags:#x}");
+
+        ifr.ifr_name = [0; IFNAMSIZ];
+        ifr.ifr_name[..name.len()].copy_from_slice(name.as_bytes());
+        *attached = Some(TunAttachment {
+            ifindex,
+            name,
+            flags,
+            queue,
+        });
+        Ok(())
+    }
+}
+
+/// Replace `%d` in `pattern` with the smallest number giving an unused name.
+fn alloc_name(pattern: &str) -> SysResult<String> {
+    (0..)
+        .map(|i| pattern.replacen("%d", &i.to_string(), 1))
+        .take_while(|name| name.len() < IFNAMSIZ)
+        .find(|name| iface::interface_by_name(name).is_none())
+        .ok_or(SysError::ENFILE)
+}
+
+/// Waits for a packet sent through the interface.
+struct TunReadFuture {
+    queue: Arc<Mutex<TunQueue>>,
+    nonblock: bool,
+}
+
+impl Future for TunReadFuture {
+    type Output = SysResult<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut queue = self.queue.lock();
+        if let Some(packet) = queue.to_user.pop_front() {
+            Poll::Ready(Ok(packet))
+        } else if self.nonblock {
+            Poll::Ready(Err(SysError::EAGAIN))
+        } else {
+            queue.read_wakers.push_back(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[async_trait]
+impl File for TunFile {
+    fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    /// Read one packet, which is truncated if `buf` is too small.
+    async fn base_read_at(&self, _offset: usize, buf: &mut [u8]) -> SyscallResult {
+        let attachment = self.attachment()?;
+        // Let the stack flush packets it has queued for the interface.
+        net::poll_interfaces();
+        let packet = TunReadFuture {
+            queue: attachment.queue.clone(),
+            nonblock: self.flags().contains(OpenFlags::O_NONBLOCK),
+        }
+        .await?;
+
+        let mut buf = buf;
+        let mut len = 0;
+        if !attachment.no_pi() {
+            if buf.len() < TUN_PI_LEN {
+                return Err(SysError::EINVAL);
+            }
+            let proto = if attachment.flags & IFF_TAP != 0 {
+                packet
+                    .get(12..14)
+                    .map_or(0, |ty| u16::from_be_bytes([ty[0], ty[1]]))
+            } else {
+                match packet.first().map(|b| b >> 4) {
+                    Some(4) => ETH_P_IP,
+                    Some(6) => ETH_P_IPV6,
+                    _ => 0,
+                }
+            };
+            let pi_flags = if packet.len() > buf.len() - TUN_PI_LEN {
+                TUN_PKT_STRIP
+            } else {
+                0
+            };
+            buf[..2].copy_from_slice(&pi_flags.to_ne_bytes());
+            buf[2..TUN_PI_LEN].copy_from_slice(&proto.to_be_bytes());
+            buf = &mut buf[TUN_PI_LEN..];
+            len = TUN_PI_LEN;
+        }
+        let count = packet.len().min(buf.len());
+        buf[..count].copy_from_slice(&packet[..count]);
+        Ok(len + count)
+    }
+
+    /// Write one packet, which is received by the stack right away.
+    async fn base_write_at(&self, _offset: usize, buf: &[u8]) -> SyscallResult {
+        let attachment = self.attachment()?;
+        if !attachment.is_up() {
+            return Err(SysError::EIO);
+        }
+        let packet = if attachment.no_pi() {
+            buf
+        } else {
+            buf.get(TUN_PI_LEN..).ok_or(SysError::EINVAL)?
+        };
+        let max_len = if attachment.flags & IFF_TAP != 0 {
+            TUN_MTU + ETHERNET_HEADER_LEN
+        } else {
+            TUN_MTU
+        };
+        if packet.is_empty() || packet.len() > max_len {
+            return Err(SysError::EINVAL);
+        }
+        {
+            let mut queue = attachment.queue.lock();
+            if queue.to_stack.len() >= TUN_QUEUE_LEN {
+                log::warn!("[TunFile::base_write_at] queue full, drop a packet");
+                return Ok(buf.len());
+            }
+            queue.to_stack.push_back(packet.to_vec());
+        }
+        net::poll_interfaces();
+        Ok(buf.len())
+    }
+
+    async fn base_poll(&self, events: PollEvents) -> PollEvents {
+        let Ok(attachment) = self.attachment() else {
+            return PollEvents::ERR;
+        };
+        let waker = get_waker().await;
+        let mut queue = attachment.queue.lock();
+        let mut res = PollEvents::empty();
+        if events.contains(PollEvents::IN) {
+            if queue.to_user.is_empty() {
+                queue.read_wakers.push_back(waker);
+            } else {
+                res |= PollEvents::IN;
+            }
+        }
+        if events.contains(PollEvents::OUT) {
+            res |= PollEvents::OUT;
+        }
+        res
+    }
+
+    /// See `Documentation/networking/tuntap.rst` in Linux.
+    fn ioctl(&self, cmd: usize, arg: usize) -> SyscallResult {
+        use TunIoctlCmd::*;
+        let Some(cmd) = TunIoctlCmd::from_repr(cmd) else {
+            log::warn!("[TunFile::ioctl] cmd {cmd:#x} not supported");
+            return Err(SysError::EINVAL);
+        };
+        log::info!("[TunFile::ioctl] cmd {:?}, value {:#x}", cmd, arg);
+        match cmd {
+            TUNSETIFF => {
+                let mut ifr = unsafe { *(arg as *const IfReq) };
+                self.set_iff(&mut ifr)?;
+                unsafe {
+                    *(arg as *mut IfReq) = ifr;
+                }
+                Ok(0)
+            }
+            TUNGETIFF => {
+                let attachment = self.attachment()?;
+                let mut ifr = IfReq {
+                    ifr_name: [0; IFNAMSIZ],
+                    ifr_flags: attachment.flags,
+                };
+                ifr.ifr_name[..attachment.name.len()].copy_from_slice(attachment.name.as_bytes());
+                unsafe {
+                    *(arg as *mut IfReq) = ifr;
+                }
+                Ok(0)
+            }
+            TUNGETFEATURES => {
+                unsafe {
+                    *(arg as *mut u32) = TUN_FEATURES as u32;
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    fn base_read_dir(&self) -> SysResult<Option<DirEntry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn flush(&self) -> SysResult<usize> {
+        todo!()
+    }
+}
+
+impl Drop for TunFile {
+    fn drop(&mut self) {
+        if let Some(attachment) = self.attached.lock().take() {
+            log::info!("[TunFile::drop] remove interface {}", attachment.name);
+            if let Err(e) = iface::unregister_interface(attachment.ifindex) {
+                log::warn!("[TunFile::drop] {}: {e:?}", attachment.name);
+            }
+        }
+    }
+}
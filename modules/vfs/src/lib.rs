@@ -15,7 +15,7 @@ extern crate alloc;
 use alloc::{collections::BTreeMap, string::String, sync::Arc};
 
 use driver::BLOCK_DEVICE;
-use memory::FrameReleaseIf;
+use memory::{FrameReleaseIf, slab};
 use procfs::init_procfs;
 use sockfs::SockFsType;
 use spin::Once;
@@ -24,6 +24,7 @@ use vfs_core::{Dentry, DentryState, FileSystemType, InodeMode, MountFlags, OpenF
 
 use crate::{
     devfs::{DevFsType, init_devfs},
+    fd_table::FdTable,
     procfs::ProcFsType,
     tmpfs::TmpFsType,
 };
@@ -37,6 +38,7 @@ static SYS_ROOT_DENTRY: Once<Arc<dyn Dentry>> = Once::new();
 
 // type DiskFsType = fat32::FatFsType;
 type DiskFsType = ext4::Ext4FsType;
+type DiskDentry = ext4::Ext4Dentry;
 
 // pub const DISK_FS_NAME: &str = "fat32";
 pub const DISK_FS_NAME: &str = "ext4";
@@ -62,6 +64,10 @@ fn register_all_fs() {
 
 /// Init the filesystem.
 pub fn init() {
+    // Dentries of the disk fs and fd tables are made for every lookup and
+    // process.
+    slab::create_slab_cache("dentry", slab::arc_layout::<DiskDentry>());
+    slab::create_slab_cache("files_cache", slab::arc_layout::<Mutex<FdTable>>());
     register_all_fs();
     let diskfs = FS_MANAGER.lock().get(DISK_FS_NAME).unwrap().clone();
     log::info!("[vfs] mounting disk fs");
@@ -115,6 +121,10 @@ pub fn init() {
         .unwrap();
     sockfs_dentry.set_state(DentryState::Sync);
 
+    // No `/etc/resolv.conf` is written to the disk: the resolvers of musl and
+    // glibc fall back to 127.0.0.1 without one, which is the stub resolver of
+    // the kernel.
+
     SYS_ROOT_DENTRY.call_once(|| diskfs_root);
 
     sys_root_dentry().open().unwrap().load_dir().unwrap();
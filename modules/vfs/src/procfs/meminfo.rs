@@ -1,27 +1,22 @@
 use alloc::{
     boxed::Box,
+    format,
     string::{String, ToString},
     sync::Arc,
 };
 use core::cmp;
 
 use async_trait::async_trait;
+use config::mm::PAGE_SIZE;
+use memory::{commit, free_frames, heap, slab, total_frames};
 use systype::{SysError, SysResult, SyscallResult};
 use vfs_core::{
     Dentry, DentryMeta, DirEntry, File, FileMeta, Inode, InodeMeta, InodeMode, Stat, SuperBlock,
 };
 
-use crate::Mutex;
-
-pub static MEM_INFO: Mutex<MemInfo> = Mutex::new(MemInfo::new());
-
-const TOTAL_MEM: usize = 16251136;
-const FREE_MEM: usize = 327680;
-const BUFFER: usize = 373336;
-const CACHED: usize = 10391984;
-const TOTAL_SWAP: usize = 4194300;
-
 /// Mapping to free output: https://access.redhat.com/solutions/406773.
+///
+/// Sizes are in KB. There is no swap, and no cache is counted as available.
 pub struct MemInfo {
     /// General memory
     pub total_mem: usize,
@@ -36,22 +31,41 @@ pub struct MemInfo {
     /// Share memory
     pub shmem: usize,
     pub slab: usize,
+    /// Commit charge of user memory, and its limit when overcommit is never
+    /// allowed
+    pub commit_limit: usize,
+    pub committed_as: usize,
+    /// Kernel heap, which grows from the frames
+    pub kernel_heap: usize,
+    pub kernel_heap_used: usize,
 }
 
 impl MemInfo {
-    pub const fn new() -> Self {
+    /// Memory usage now.
+    pub fn now() -> Self {
+        let frames_kb = |frames: usize| frames * PAGE_SIZE / 1024;
+        let (heap_total, heap_used) = heap::heap_usage();
+        // Frames taken by the heap are not free, but the heap in the kernel
+        // image is not counted in the frames.
+        let total_mem = frames_kb(total_frames()) + (heap_total - heap::heap_grown()) / 1024;
+        let free_mem = frames_kb(free_frames());
         Self {
-            total_mem: TOTAL_MEM,
-            free_mem: FREE_MEM,
-            avail_mem: TOTAL_MEM - FREE_MEM,
-            buffers: BUFFER,
-            cached: CACHED,
-            total_swap: TOTAL_SWAP,
-            free_swap: TOTAL_SWAP,
+            total_mem,
+            free_mem,
+            avail_mem: free_mem,
+            buffers: 0,
+            cached: 0,
+            total_swap: 0,
+            free_swap: 0,
             shmem: 0,
-            slab: 0,
+            slab: slab::slab_bytes() / 1024,
+            commit_limit: frames_kb(commit::commit_limit()),
+            committed_as: frames_kb(commit::committed_pages()),
+            kernel_heap: heap_total / 1024,
+            kernel_heap_used: heap_used / 1024,
         }
     }
+
     pub fn serialize(&self) -> String {
         let mut res = "".to_string();
         let end = " KB\n";
@@ -65,6 +79,15 @@ impl MemInfo {
         let free_swap = "SwapFree:\t".to_string() + self.free_swap.to_string().as_str() + end;
         let shmem = "Shmem:\t".to_string() + self.shmem.to_string().as_str() + end;
         let slab = "Slab:\t".to_string() + self.slab.to_string().as_str() + end;
+        let sreclaimable = "SReclaimable:\t".to_string() + 0.to_string().as_str() + end;
+        let sunreclaim = "SUnreclaim:\t".to_string() + self.slab.to_string().as_str() + end;
+        let commit_limit =
+            "CommitLimit:\t".to_string() + self.commit_limit.to_string().as_str() + end;
+        let committed_as =
+            "Committed_AS:\t".to_string() + self.committed_as.to_string().as_str() + end;
+        let kernel_heap = "KernelHeap:\t".to_string() + self.kernel_heap.to_string().as_str() + end;
+        let kernel_heap_used =
+            "KernelHeapUsed:\t".to_string() + self.kernel_heap_used.to_string().as_str() + end;
         res += total_mem.as_str();
         res += free_mem.as_str();
         res += avail_mem.as_str();
@@ -75,10 +98,42 @@ impl MemInfo {
         res += free_swap.as_str();
         res += shmem.as_str();
         res += slab.as_str();
+        res += sreclaimable.as_str();
+        res += sunreclaim.as_str();
+        res += commit_limit.as_str();
+        res += committed_as.as_str();
+        res += kernel_heap.as_str();
+        res += kernel_heap_used.as_str();
         res
     }
 }
 
+/// `/proc/slabinfo`, in the format of version 2.1 with the tunables all zero.
+pub fn show_slabinfo() -> String {
+    let mut res = String::from("slabinfo - version: 2.1\n");
+    res += "# name            <active_objs> <num_objs> <objsize> <objperslab> <pagesperslab>";
+    res += " : tunables <limit> <batchcount> <sharedfactor>";
+    res += " : slabdata <active_slabs> <num_slabs> <sharedavail>\n";
+    for info in slab::slab_info() {
+        res += &format!(
+            "{:<17} {:>6} {:>6} {:>6} {:>4} {:>4} : tunables {:>4} {:>4} {:>4} : slabdata {:>6} {:>6} {:>6}\n",
+            info.name,
+            info.active_objs,
+            info.num_objs,
+            info.object_size,
+            info.objs_per_slab,
+            info.pages_per_slab,
+            0,
+            0,
+            0,
+            info.num_slabs,
+            info.num_slabs,
+            0,
+        );
+    }
+    res
+}
+
 pub struct MemInfoDentry {
     meta: DentryMeta,
 }
@@ -125,7 +180,7 @@ pub struct MemInfoInode {
 
 impl MemInfoInode {
     pub fn new(super_block: Arc<dyn SuperBlock>, _size: usize) -> Arc<Self> {
-        let size = MEM_INFO.lock().serialize().len();
+        let size = MemInfo::now().serialize().len();
         Arc::new(Self {
             meta: InodeMeta::new(InodeMode::FILE, super_block, size),
         })
@@ -173,8 +228,10 @@ impl File for MemInfoFile {
     }
 
     async fn base_read_at(&self, offset: usize, buf: &mut [u8]) -> SyscallResult {
-        let meminfo = MEM_INFO.lock();
-        let info = meminfo.serialize();
+        let info = MemInfo::now().serialize();
+        if offset >= info.len() {
+            return Ok(0);
+        }
         let len = cmp::min(info.len() - offset, buf.len());
         buf[..len].copy_from_slice(&info.as_bytes()[offset..offset + len]);
         Ok(len)
@@ -1,8 +1,12 @@
 mod meminfo;
 mod mounts;
+mod net;
+mod pcap;
+mod pid;
 mod self_;
+mod vm;
 
-use alloc::sync::Arc;
+use alloc::{string::String, sync::Arc};
 
 use async_utils::block_on;
 use device_core::BlockDevice;
@@ -13,9 +17,21 @@ use vfs_core::{
 };
 
 use self::{
-    meminfo::{MemInfoDentry, MemInfoInode},
+    meminfo::{MemInfoDentry, MemInfoInode, show_slabinfo},
     mounts::{MountsDentry, MountsInode},
+    net::{
+        NetDentry, NetInode, list_net_devs, list_netfilter_rules, list_snmp, list_tcp4_sockets,
+        list_tcp6_sockets, list_udp4_sockets, list_udp6_sockets, list_unix_sockets,
+        show_dns_servers, show_ip_forward, store_dns_servers, store_ip_forward,
+        store_netfilter_rules,
+    },
+    pcap::{PcapDentry, PcapInode},
+    pid::ProcRootDentry,
     self_::{ExeDentry, ExeFile, ExeInode},
+    vm::{
+        show_overcommit_memory, show_overcommit_ratio, store_overcommit_memory,
+        store_overcommit_ratio,
+    },
 };
 use crate::simplefs::{dentry::SimpleDentry, inode::SimpleDirInode};
 
@@ -29,6 +45,15 @@ pub fn init_procfs(root_dentry: Arc<dyn Dentry>) -> SysResult<()> {
     mem_info_dentry.set_inode(mem_info_inode);
     root_dentry.insert(mem_info_dentry);
 
+    let slabinfo_dentry = NetDentry::new(
+        "slabinfo",
+        root_dentry.super_block(),
+        Some(root_dentry.clone()),
+        show_slabinfo,
+    );
+    slabinfo_dentry.set_inode(NetInode::new(root_dentry.super_block(), 0));
+    root_dentry.insert(slabinfo_dentry);
+
     let mounts_dentry = MountsDentry::new(
         "mounts",
         root_dentry.super_block(),
@@ -38,6 +63,57 @@ pub fn init_procfs(root_dentry: Arc<dyn Dentry>) -> SysResult<()> {
     mounts_dentry.set_inode(mounts_inode);
     root_dentry.insert(mounts_dentry);
 
+    let net_dentry: Arc<dyn Dentry> =
+        SimpleDentry::new("net", root_dentry.super_block(), Some(root_dentry.clone()));
+    let net_inode = SimpleDirInode::new(InodeMode::DIR, root_dentry.super_block(), 0);
+    net_dentry.set_inode(net_inode);
+    root_dentry.insert(net_dentry.clone());
+
+    let net_files: [(&str, fn() -> String); 7] = [
+        ("dev", list_net_devs),
+        ("tcp", list_tcp4_sockets),
+        ("tcp6", list_tcp6_sockets),
+        ("udp", list_udp4_sockets),
+        ("udp6", list_udp6_sockets),
+        ("unix", list_unix_sockets),
+        ("snmp", list_snmp),
+    ];
+    for (name, show) in net_files {
+        let dentry = NetDentry::new(
+            name,
+            root_dentry.super_block(),
+            Some(net_dentry.clone()),
+            show,
+        );
+        dentry.set_inode(NetInode::new(root_dentry.super_block(), 0));
+        net_dentry.insert(dentry);
+    }
+
+    let pcap_dentry = PcapDentry::new("pcap", root_dentry.super_block(), Some(net_dentry.clone()));
+    let pcap_inode = PcapInode::new(root_dentry.super_block(), 0);
+    pcap_dentry.set_inode(pcap_inode);
+    net_dentry.insert(pcap_dentry);
+
+    let netfilter_dentry = NetDentry::new_writable(
+        "netfilter",
+        root_dentry.super_block(),
+        Some(net_dentry.clone()),
+        list_netfilter_rules,
+        store_netfilter_rules,
+    );
+    netfilter_dentry.set_inode(NetInode::new(root_dentry.super_block(), 0));
+    net_dentry.insert(netfilter_dentry);
+
+    let dns_dentry = NetDentry::new_writable(
+        "dns",
+        root_dentry.super_block(),
+        Some(net_dentry.clone()),
+        show_dns_servers,
+        store_dns_servers,
+    );
+    dns_dentry.set_inode(NetInode::new(root_dentry.super_block(), 0));
+    net_dentry.insert(dns_dentry);
+
     let sys_dentry: Arc<dyn Dentry> =
         SimpleDentry::new("sys", root_dentry.super_block(), Some(root_dentry.clone()));
     let sys_inode = SimpleDirInode::new(InodeMode::DIR, root_dentry.super_block(), 0);
@@ -51,6 +127,43 @@ pub fn init_procfs(root_dentry: Arc<dyn Dentry>) -> SysResult<()> {
         let _ = pid_max_file.write("32768\0".as_bytes()).await;
     });
 
+    let sys_net_dentry = sys_dentry.create("net", InodeMode::DIR)?;
+    let ipv4_dentry = sys_net_dentry.create("ipv4", InodeMode::DIR)?;
+    let ip_forward_dentry = NetDentry::new_writable(
+        "ip_forward",
+        root_dentry.super_block(),
+        Some(ipv4_dentry.clone()),
+        show_ip_forward,
+        store_ip_forward,
+    );
+    ip_forward_dentry.set_inode(NetInode::new(root_dentry.super_block(), 0));
+    ipv4_dentry.insert(ip_forward_dentry);
+
+    let vm_dentry = sys_dentry.create("vm", InodeMode::DIR)?;
+    let vm_files: [(&str, fn() -> String, fn(&[u8]) -> SysResult<()>); 2] = [
+        (
+            "overcommit_memory",
+            show_overcommit_memory,
+            store_overcommit_memory,
+        ),
+        (
+            "overcommit_ratio",
+            show_overcommit_ratio,
+            store_overcommit_ratio,
+        ),
+    ];
+    for (name, show, store) in vm_files {
+        let dentry = NetDentry::new_writable(
+            name,
+            root_dentry.super_block(),
+            Some(vm_dentry.clone()),
+            show,
+            store,
+        );
+        dentry.set_inode(NetInode::new(root_dentry.super_block(), 0));
+        vm_dentry.insert(dentry);
+    }
+
     let self_dentry: Arc<dyn Dentry> =
         SimpleDentry::new("self", root_dentry.super_block(), Some(root_dentry.clone()));
     let self_inode = SimpleDirInode::new(InodeMode::DIR, root_dentry.super_block(), 0);
@@ -97,7 +210,7 @@ impl FileSystemType for ProcFsType {
         dev: Option<Arc<dyn BlockDevice>>,
     ) -> SysResult<Arc<dyn Dentry>> {
         let sb = ProcSuperBlock::new(dev, self.clone());
-        let mount_dentry = SimpleDentry::new(name, sb.clone(), parent.clone());
+        let mount_dentry = ProcRootDentry::new(name, sb.clone(), parent.clone());
         let mount_inode = SimpleDirInode::new(InodeMode::DIR, sb.clone(), 0);
         mount_dentry.set_inode(mount_inode.clone());
         if let Some(parent) = parent {
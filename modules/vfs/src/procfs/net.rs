@@ -0,0 +1,396 @@
+//! Files of `/proc/net`. Each one is generated from the state of the network
+//! stack when it is read.
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cmp,
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use async_trait::async_trait;
+use config::board::BLOCK_SIZE;
+use net::{
+    IpAddress, IpEndpoint,
+    iface::interfaces,
+    netfilter,
+    netstat::{self, SNMP, SocketStat},
+};
+use systype::{SysError, SysResult, SyscallResult};
+use vfs_core::{
+    Dentry, DentryMeta, DirEntry, File, FileMeta, Inode, InodeMeta, InodeMode, Stat, SuperBlock,
+};
+
+/// A file of `/proc/net` whose content is returned by `show`. Writes go to
+/// `store` if there is one, and are refused otherwise.
+pub struct NetDentry {
+    meta: DentryMeta,
+    show: fn() -> String,
+    store: Option<fn(&[u8]) -> SysResult<()>>,
+}
+
+impl NetDentry {
+    pub fn new(
+        name: &str,
+        super_block: Arc<dyn SuperBlock>,
+        parent: Option<Arc<dyn Dentry>>,
+        show: fn() -> String,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new(name, super_block, parent),
+            show,
+            store: None,
+        })
+    }
+
+    pub fn new_writable(
+        name: &str,
+        super_block: Arc<dyn SuperBlock>,
+        parent: Option<Arc<dyn Dentry>>,
+        show: fn() -> String,
+        store: fn(&[u8]) -> SysResult<()>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new(name, super_block, parent),
+            show,
+            store: Some(store),
+        })
+    }
+}
+
+impl Dentry for NetDentry {
+    fn meta(&self) -> &DentryMeta {
+        &self.meta
+    }
+
+    fn base_open(self: Arc<Self>) -> SysResult<Arc<dyn File>> {
+        Ok(Arc::new(NetFile {
+            meta: FileMeta::new(self.clone(), self.inode()?),
+            show: self.show,
+            store: self.store,
+        }))
+    }
+
+    fn base_lookup(self: Arc<Self>, _name: &str) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_create(self: Arc<Self>, _name: &str, _mode: InodeMode) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_unlink(self: Arc<Self>, _name: &str) -> SysResult<()> {
+        Err(SysError::ENOTDIR)
+    }
+}
+
+pub struct NetInode {
+    meta: InodeMeta,
+}
+
+impl NetInode {
+    pub fn new(super_block: Arc<dyn SuperBlock>, _size: usize) -> Arc<Self> {
+        let size = BLOCK_SIZE;
+        Arc::new(Self {
+            meta: InodeMeta::new(InodeMode::FILE, super_block, size),
+        })
+    }
+}
+
+impl Inode for NetInode {
+    fn meta(&self) -> &InodeMeta {
+        &self.meta
+    }
+
+    fn get_attr(&self) -> SysResult<Stat> {
+        let inner = self.meta.inner.lock();
+        let mode = self.meta.mode.bits();
+        let len = inner.size;
+        Ok(Stat {
+            st_dev: 0,
+            st_ino: self.meta.ino as u64,
+            st_mode: mode,
+            st_nlink: 1,
+            st_uid: 0,
+            st_gid: 0,
+            st_rdev: 0,
+            __pad: 0,
+            st_size: len as u64,
+            st_blksize: 512,
+            __pad2: 0,
+            st_blocks: (len / 512) as u64,
+            st_atime: inner.atime,
+            st_mtime: inner.mtime,
+            st_ctime: inner.ctime,
+            unused: 0,
+        })
+    }
+}
+
+/// Same layout as `dev_seq_show` in linux/net/core/net-procfs.c
+pub fn list_net_devs() -> String {
+    let mut res = "Inter-|   Receive                                                |  Transmit\n"
+        .to_string();
+    res += " face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n";
+    for info in interfaces() {
+        let stats = &info.stats;
+        let get = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        res += &format!(
+            "{:>6}:{:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>10} {:>9} {:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>7} {:>10}\n",
+            info.name,
+            get(&stats.rx_bytes),
+            get(&stats.rx_packets),
+            get(&stats.rx_errors),
+            get(&stats.rx_dropped),
+            0,
+            0,
+            0,
+            0,
+            get(&stats.tx_bytes),
+            get(&stats.tx_packets),
+            get(&stats.tx_errors),
+            get(&stats.tx_dropped),
+            0,
+            0,
+            0,
+            0,
+        );
+    }
+    res
+}
+
+/// Same layout as `tcp4_seq_show` and `tcp6_seq_show` in
+/// linux/net/ipv4/tcp_ipv4.c and linux/net/ipv6/tcp_ipv6.c
+fn list_tcp_sockets(ipv6: bool) -> String {
+    let mut res = if ipv6 {
+        "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n"
+    } else {
+        "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n"
+    }
+    .to_string();
+    for (i, stat) in netstat::tcp_sockets(ipv6).iter().enumerate() {
+        write_socket_line(&mut res, i, stat);
+        res += "\n";
+    }
+    res
+}
+
+/// Same layout as `udp4_format_sock` in linux/net/ipv4/udp.c
+fn list_udp_sockets(ipv6: bool) -> String {
+    let mut res = if ipv6 {
+        "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n"
+    } else {
+        "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n"
+    }
+    .to_string();
+    for (i, stat) in netstat::udp_sockets(ipv6).iter().enumerate() {
+        write_socket_line(&mut res, i, stat);
+        res += " 2 0000000000000000 0\n";
+    }
+    res
+}
+
+/// The common columns of a TCP or UDP socket. No timer runs, and sockets are
+/// owned by root and have no inode.
+fn write_socket_line(res: &mut String, i: usize, stat: &SocketStat) {
+    let _ = write!(
+        res,
+        "{i:>4}: {} {} {:02X} {:08X}:{:08X} 00:00000000 00000000 {:>5} {:>8} {}",
+        hex_endpoint(stat.local),
+        hex_endpoint(stat.remote),
+        stat.state,
+        stat.tx_queue,
+        stat.rx_queue,
+        0,
+        0,
+        0,
+    );
+}
+
+/// An endpoint as Linux prints it: the address as native endian 32 bit words
+/// of the network order bytes, and the port in hex.
+fn hex_endpoint(endpoint: IpEndpoint) -> String {
+    let bytes = match &endpoint.addr {
+        IpAddress::Ipv4(v4) => &v4.0[..],
+        IpAddress::Ipv6(v6) => &v6.0[..],
+    };
+    let mut res = String::new();
+    for word in bytes.chunks_exact(4) {
+        let _ = write!(res, "{:08X}", u32::from_ne_bytes(word.try_into().unwrap()));
+    }
+    let _ = write!(res, ":{:04X}", endpoint.port);
+    res
+}
+
+pub fn list_tcp4_sockets() -> String {
+    list_tcp_sockets(false)
+}
+
+pub fn list_tcp6_sockets() -> String {
+    list_tcp_sockets(true)
+}
+
+pub fn list_udp4_sockets() -> String {
+    list_udp_sockets(false)
+}
+
+pub fn list_udp6_sockets() -> String {
+    list_udp_sockets(true)
+}
+
+/// Unix domain sockets are not implemented, so the table is always empty.
+pub fn list_unix_sockets() -> String {
+    "Num       RefCount Protocol Flags    Type St Inode Path\n".to_string()
+}
+
+/// Same layout as `snmp_seq_show` in linux/net/ipv4/proc.c, with the counters
+/// that are not kept left out.
+pub fn list_snmp() -> String {
+    let get = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+    let mut res = String::new();
+    let mut table = |name: &str, fields: &[(&str, isize)]| {
+        let _ = write!(res, "{name}:");
+        for (field, _) in fields {
+            let _ = write!(res, " {field}");
+        }
+        let _ = write!(res, "\n{name}:");
+        for (_, value) in fields {
+            let _ = write!(res, " {value}");
+        }
+        res += "\n";
+    };
+    table("Ip", &[
+        // Not forwarding
+        ("Forwarding", 2),
+        ("DefaultTTL", net::DEFAULT_HOP_LIMIT as isize),
+        ("InReceives", get(&SNMP.ip_in_receives) as isize),
+        ("OutRequests", get(&SNMP.ip_out_requests) as isize),
+    ]);
+    table("Icmp", &[
+        ("InMsgs", get(&SNMP.icmp_in_msgs) as isize),
+        ("OutMsgs", get(&SNMP.icmp_out_msgs) as isize),
+    ]);
+    table("Tcp", &[
+        ("RtoAlgorithm", 1),
+        // Bounds of the retransmission timeout of smoltcp in milliseconds.
+        ("RtoMin", 10),
+        ("RtoMax", 10000),
+        ("MaxConn", -1),
+        ("ActiveOpens", get(&SNMP.tcp_active_opens) as isize),
+        ("PassiveOpens", get(&SNMP.tcp_passive_opens) as isize),
+        ("CurrEstab", netstat::tcp_curr_estab() as isize),
+        ("InSegs", get(&SNMP.tcp_in_segs) as isize),
+        ("OutSegs", get(&SNMP.tcp_out_segs) as isize),
+        ("OutRsts", get(&SNMP.tcp_out_rsts) as isize),
+    ]);
+    table("Udp", &[
+        ("InDatagrams", get(&SNMP.udp_in_datagrams) as isize),
+        ("OutDatagrams", get(&SNMP.udp_out_datagrams) as isize),
+    ]);
+    res
+}
+
+/// Rules of the netfilter, in the `iptables -S` format.
+pub fn list_netfilter_rules() -> String {
+    netfilter::rules()
+}
+
+/// Run the commands written, one per line. Blank lines and `#` comments are
+/// skipped.
+pub fn store_netfilter_rules(buf: &[u8]) -> SysResult<()> {
+    let cmds = core::str::from_utf8(buf).map_err(|_| SysError::EINVAL)?;
+    cmds.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .try_for_each(netfilter::execute)
+}
+
+pub fn show_ip_forward() -> String {
+    format!("{}\n", netfilter::forwarding() as u8)
+}
+
+pub fn store_ip_forward(buf: &[u8]) -> SysResult<()> {
+    match buf.trim_ascii() {
+        b"1" => netfilter::set_forwarding(true),
+        b"0" => netfilter::set_forwarding(false),
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(())
+}
+
+/// DNS servers, in the format of `/etc/resolv.conf`.
+pub fn show_dns_servers() -> String {
+    net::dns_servers()
+        .into_iter()
+        .map(|server| format!("nameserver {server}\n"))
+        .collect()
+}
+
+/// Replace DNS servers by the ones written, either bare addresses or
+/// `nameserver` lines of `/etc/resolv.conf`. Other lines of it are skipped,
+/// and nothing but blank lines restores the servers given by DHCP or the
+/// default one. Loopback
+/// addresses are refused with `EINVAL`: queries to them would reach the stub
+/// resolver, which would forward them to itself.
+pub fn store_dns_servers(buf: &[u8]) -> SysResult<()> {
+    let conf = core::str::from_utf8(buf).map_err(|_| SysError::EINVAL)?;
+    let servers = conf
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(['#', ';']))
+        .filter_map(|line| match line.split_once(char::is_whitespace) {
+            Some(("nameserver", addr)) => Some(addr.trim()),
+            Some(_) => None,
+            None => Some(line),
+        })
+        .map(|addr| match addr.parse::<IpAddress>() {
+            Ok(addr) if !addr.is_loopback() => Ok(addr),
+            _ => Err(SysError::EINVAL),
+        })
+        .collect::<SysResult<Vec<_>>>()?;
+    net::set_dns_servers(&servers);
+    Ok(())
+}
+
+pub struct NetFile {
+    meta: FileMeta,
+    show: fn() -> String,
+    store: Option<fn(&[u8]) -> SysResult<()>>,
+}
+
+#[async_trait]
+impl File for NetFile {
+    fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    async fn base_read_at(&self, offset: usize, buf: &mut [u8]) -> SyscallResult {
+        let info = (self.show)();
+        if offset >= info.len() {
+            return Ok(0);
+        }
+        let len = cmp::min(info.len() - offset, buf.len());
+        buf[..len].copy_from_slice(&info.as_bytes()[offset..offset + len]);
+        Ok(len)
+    }
+
+    async fn base_write_at(&self, _offset: usize, buf: &[u8]) -> SyscallResult {
+        let store = self.store.ok_or(SysError::EACCES)?;
+        store(buf)?;
+        Ok(buf.len())
+    }
+
+    fn base_read_dir(&self) -> SysResult<Option<DirEntry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn flush(&self) -> SysResult<usize> {
+        todo!()
+    }
+}
@@ -0,0 +1,134 @@
+//! `/proc/net/pcap`, the frames captured on all interfaces in the pcap
+//! format. Writing `1` starts capturing and `0` stops it.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::cmp;
+
+use async_trait::async_trait;
+use config::board::BLOCK_SIZE;
+use net::pcap;
+use systype::{SysError, SysResult, SyscallResult};
+use vfs_core::{
+    Dentry, DentryMeta, DirEntry, File, FileMeta, Inode, InodeMeta, InodeMode, Stat, SuperBlock,
+};
+
+pub struct PcapDentry {
+    meta: DentryMeta,
+}
+
+impl PcapDentry {
+    pub fn new(
+        name: &str,
+        super_block: Arc<dyn SuperBlock>,
+        parent: Option<Arc<dyn Dentry>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new(name, super_block, parent),
+        })
+    }
+}
+
+impl Dentry for PcapDentry {
+    fn meta(&self) -> &DentryMeta {
+        &self.meta
+    }
+
+    fn base_open(self: Arc<Self>) -> SysResult<Arc<dyn File>> {
+        Ok(Arc::new(PcapFile {
+            meta: FileMeta::new(self.clone(), self.inode()?),
+        }))
+    }
+
+    fn base_lookup(self: Arc<Self>, _name: &str) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_create(self: Arc<Self>, _name: &str, _mode: InodeMode) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_unlink(self: Arc<Self>, _name: &str) -> SysResult<()> {
+        Err(SysError::ENOTDIR)
+    }
+}
+
+pub struct PcapInode {
+    meta: InodeMeta,
+}
+
+impl PcapInode {
+    pub fn new(super_block: Arc<dyn SuperBlock>, _size: usize) -> Arc<Self> {
+        let size = BLOCK_SIZE;
+        Arc::new(Self {
+            meta: InodeMeta::new(InodeMode::FILE, super_block, size),
+        })
+    }
+}
+
+impl Inode for PcapInode {
+    fn meta(&self) -> &InodeMeta {
+        &self.meta
+    }
+
+    fn get_attr(&self) -> SysResult<Stat> {
+        let inner = self.meta.inner.lock();
+        let mode = self.meta.mode.bits();
+        let len = inner.size;
+        Ok(Stat {
+            st_dev: 0,
+            st_ino: self.meta.ino as u64,
+            st_mode: mode,
+            st_nlink: 1,
+            st_uid: 0,
+            st_gid: 0,
+            st_rdev: 0,
+            __pad: 0,
+            st_size: len as u64,
+            st_blksize: 512,
+            __pad2: 0,
+            st_blocks: (len / 512) as u64,
+            st_atime: inner.atime,
+            st_mtime: inner.mtime,
+            st_ctime: inner.ctime,
+            unused: 0,
+        })
+    }
+}
+
+pub struct PcapFile {
+    meta: FileMeta,
+}
+
+#[async_trait]
+impl File for PcapFile {
+    fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    async fn base_read_at(&self, offset: usize, buf: &mut [u8]) -> SyscallResult {
+        let dump = pcap::dump();
+        if offset >= dump.len() {
+            return Ok(0);
+        }
+        let len = cmp::min(dump.len() - offset, buf.len());
+        buf[..len].copy_from_slice(&dump[offset..offset + len]);
+        Ok(len)
+    }
+
+    async fn base_write_at(&self, _offset: usize, buf: &[u8]) -> SyscallResult {
+        match buf.trim_ascii() {
+            b"1" => pcap::set_enabled(true),
+            b"0" => pcap::set_enabled(false),
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(buf.len())
+    }
+
+    fn base_read_dir(&self) -> SysResult<Option<DirEntry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn flush(&self) -> SysResult<usize> {
+        todo!()
+    }
+}
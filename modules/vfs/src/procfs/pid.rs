@@ -0,0 +1,328 @@
+//! Directories `/proc/<pid>`, made when a live process is looked up or procfs
+//! is listed.
+//!
+//! Their files are generated from the process when they are read, and
+//! fail with `ESRCH` once it is gone.
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::cmp;
+
+use async_trait::async_trait;
+use crate_interface::call_interface;
+use systype::{SysError, SysResult, SyscallResult};
+use vfs_core::{
+    Dentry, DentryMeta, DirEntry, File, FileMeta, Inode, InodeMode, InodeType, SuperBlock,
+};
+
+use super::{KernelProcIf, net::NetInode};
+use crate::simplefs::{
+    dentry::SimpleDentry,
+    file::SimpleDirFile,
+    inode::{SimpleDirInode, SimpleFileInode},
+};
+
+type Show = fn(usize) -> SysResult<String>;
+type Store = fn(usize, &[u8]) -> SysResult<()>;
+
+/// Files of `/proc/<pid>`, with the function making their content and the
+/// one taking writes if they are writable.
+const PID_FILES: [(&str, Show, Option<Store>); 6] = [
+    ("oom_score", show_oom_score, None),
+    (
+        "oom_score_adj",
+        show_oom_score_adj,
+        Some(store_oom_score_adj),
+    ),
+    ("maps", show_maps, None),
+    ("smaps", show_smaps, None),
+    ("status", show_status, None),
+    ("statm", show_statm, None),
+];
+
+fn show_maps(pid: usize) -> SysResult<String> {
+    call_interface!(KernelProcIf::maps(pid))
+}
+
+fn show_smaps(pid: usize) -> SysResult<String> {
+    call_interface!(KernelProcIf::smaps(pid))
+}
+
+fn show_status(pid: usize) -> SysResult<String> {
+    call_interface!(KernelProcIf::status(pid))
+}
+
+fn show_statm(pid: usize) -> SysResult<String> {
+    call_interface!(KernelProcIf::statm(pid))
+}
+
+fn show_oom_score(pid: usize) -> SysResult<String> {
+    call_interface!(KernelProcIf::oom_score(pid)).map(|score| format!("{score}\n"))
+}
+
+fn show_oom_score_adj(pid: usize) -> SysResult<String> {
+    call_interface!(KernelProcIf::oom_score_adj(pid)).map(|adj| format!("{adj}\n"))
+}
+
+fn store_oom_score_adj(pid: usize, buf: &[u8]) -> SysResult<()> {
+    let adj = core::str::from_utf8(buf.trim_ascii())
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(SysError::EINVAL)?;
+    call_interface!(KernelProcIf::set_oom_score_adj(pid, adj))
+}
+
+/// Root of procfs, which makes the directory of a process when it is looked
+/// up.
+pub struct ProcRootDentry {
+    meta: DentryMeta,
+}
+
+impl ProcRootDentry {
+    pub fn new(
+        name: &str,
+        super_block: Arc<dyn SuperBlock>,
+        parent: Option<Arc<dyn Dentry>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new(name, super_block, parent),
+        })
+    }
+}
+
+impl Dentry for ProcRootDentry {
+    fn meta(&self) -> &DentryMeta {
+        &self.meta
+    }
+
+    /// List the directories of the live processes, and drop the ones of the
+    /// processes gone.
+    fn base_open(self: Arc<Self>) -> SysResult<Arc<dyn File>> {
+        let stale: Vec<String> = self
+            .children()
+            .into_keys()
+            .filter(|name| {
+                name.parse::<usize>()
+                    .is_ok_and(|pid| !call_interface!(KernelProcIf::has_process(pid)))
+            })
+            .collect();
+        for name in stale {
+            self.remove_child(&name);
+        }
+        let this: Arc<dyn Dentry> = self.clone();
+        for pid in call_interface!(KernelProcIf::pids()) {
+            this.lookup(&pid.to_string())?;
+        }
+        let inode = self.inode()?;
+        Ok(SimpleDirFile::new(self, inode))
+    }
+
+    fn base_lookup(self: Arc<Self>, name: &str) -> SysResult<Arc<dyn Dentry>> {
+        let child = self.get_child(name).ok_or(SysError::ENOENT)?;
+        let Ok(pid) = name.parse::<usize>() else {
+            return Ok(child);
+        };
+        if !call_interface!(KernelProcIf::has_process(pid)) {
+            // Not cached, so that it is looked up again once the process
+            // exists.
+            self.remove_child(name);
+            return Ok(child);
+        }
+        let sb = self.super_block();
+        child.set_inode(SimpleDirInode::new(InodeMode::DIR, sb.clone(), 0));
+        for (name, show, store) in PID_FILES {
+            let dentry = PidFileDentry::new(name, sb.clone(), child.clone(), pid, show, store);
+            dentry.set_inode(NetInode::new(sb.clone(), 0));
+            child.insert(dentry);
+        }
+        let mem_dentry = PidMemDentry::new(sb.clone(), child.clone(), pid);
+        mem_dentry.set_inode(NetInode::new(sb.clone(), 0));
+        child.insert(mem_dentry);
+        Ok(child)
+    }
+
+    fn base_create(self: Arc<Self>, name: &str, mode: InodeMode) -> SysResult<Arc<dyn Dentry>> {
+        let sb = self.super_block();
+        let this: Arc<dyn Dentry> = self;
+        let sub_dentry = this.get_child_or_create(name);
+        let sub_inode: Arc<dyn Inode> = match mode.to_type() {
+            InodeType::Dir => SimpleDirInode::new(mode, sb, 0),
+            InodeType::File => SimpleFileInode::new(mode, sb, 0),
+            _ => return Err(SysError::EPERM),
+        };
+        sub_dentry.set_inode(sub_inode);
+        Ok(sub_dentry)
+    }
+
+    fn base_unlink(self: Arc<Self>, name: &str) -> SysResult<()> {
+        self.remove_child(name).ok_or(SysError::ENOENT).map(|_| ())
+    }
+
+    fn base_new_child(self: Arc<Self>, name: &str) -> Arc<dyn Dentry> {
+        SimpleDentry::new(name, self.super_block(), Some(self))
+    }
+}
+
+/// A file of `/proc/<pid>`.
+pub struct PidFileDentry {
+    meta: DentryMeta,
+    pid: usize,
+    show: Show,
+    store: Option<Store>,
+}
+
+impl PidFileDentry {
+    pub fn new(
+        name: &str,
+        super_block: Arc<dyn SuperBlock>,
+        parent: Arc<dyn Dentry>,
+        pid: usize,
+        show: Show,
+        store: Option<Store>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new(name, super_block, Some(parent)),
+            pid,
+            show,
+            store,
+        })
+    }
+}
+
+impl Dentry for PidFileDentry {
+    fn meta(&self) -> &DentryMeta {
+        &self.meta
+    }
+
+    fn base_open(self: Arc<Self>) -> SysResult<Arc<dyn File>> {
+        Ok(Arc::new(PidFile {
+            meta: FileMeta::new(self.clone(), self.inode()?),
+            pid: self.pid,
+            show: self.show,
+            store: self.store,
+        }))
+    }
+
+    fn base_lookup(self: Arc<Self>, _name: &str) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_create(self: Arc<Self>, _name: &str, _mode: InodeMode) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_unlink(self: Arc<Self>, _name: &str) -> SysResult<()> {
+        Err(SysError::ENOTDIR)
+    }
+}
+
+pub struct PidFile {
+    meta: FileMeta,
+    pid: usize,
+    show: Show,
+    store: Option<Store>,
+}
+
+#[async_trait]
+impl File for PidFile {
+    fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    async fn base_read_at(&self, offset: usize, buf: &mut [u8]) -> SyscallResult {
+        let info = (self.show)(self.pid)?;
+        if offset >= info.len() {
+            return Ok(0);
+        }
+        let len = cmp::min(info.len() - offset, buf.len());
+        buf[..len].copy_from_slice(&info.as_bytes()[offset..offset + len]);
+        Ok(len)
+    }
+
+    async fn base_write_at(&self, _offset: usize, buf: &[u8]) -> SyscallResult {
+        let store = self.store.ok_or(SysError::EACCES)?;
+        store(self.pid, buf)?;
+        Ok(buf.len())
+    }
+
+    fn base_read_dir(&self) -> SysResult<Option<DirEntry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn flush(&self) -> SysResult<usize> {
+        todo!()
+    }
+}
+
+/// `/proc/<pid>/mem`, the memory of the process at the offsets of the file.
+pub struct PidMemDentry {
+    meta: DentryMeta,
+    pid: usize,
+}
+
+impl PidMemDentry {
+    pub fn new(super_block: Arc<dyn SuperBlock>, parent: Arc<dyn Dentry>, pid: usize) -> Arc<Self> {
+        Arc::new(Self {
+            meta: DentryMeta::new("mem", super_block, Some(parent)),
+            pid,
+        })
+    }
+}
+
+impl Dentry for PidMemDentry {
+    fn meta(&self) -> &DentryMeta {
+        &self.meta
+    }
+
+    fn base_open(self: Arc<Self>) -> SysResult<Arc<dyn File>> {
+        Ok(Arc::new(PidMemFile {
+            meta: FileMeta::new(self.clone(), self.inode()?),
+            pid: self.pid,
+        }))
+    }
+
+    fn base_lookup(self: Arc<Self>, _name: &str) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_create(self: Arc<Self>, _name: &str, _mode: InodeMode) -> SysResult<Arc<dyn Dentry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn base_unlink(self: Arc<Self>, _name: &str) -> SysResult<()> {
+        Err(SysError::ENOTDIR)
+    }
+}
+
+pub struct PidMemFile {
+    meta: FileMeta,
+    pid: usize,
+}
+
+#[async_trait]
+impl File for PidMemFile {
+    fn meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    async fn base_read_at(&self, offset: usize, buf: &mut [u8]) -> SyscallResult {
+        call_interface!(KernelProcIf::read_mem(self.pid, offset, buf))
+    }
+
+    async fn base_write_at(&self, offset: usize, buf: &[u8]) -> SyscallResult {
+        call_interface!(KernelProcIf::write_mem(self.pid, offset, buf))
+    }
+
+    fn base_read_dir(&self) -> SysResult<Option<DirEntry>> {
+        Err(SysError::ENOTDIR)
+    }
+
+    fn flush(&self) -> SysResult<usize> {
+        todo!()
+    }
+}
@@ -11,6 +11,33 @@ use vfs_core::{
 #[crate_interface::def_interface]
 pub trait KernelProcIf {
     fn exe() -> alloc::string::String;
+
+    /// Whether `pid` is a process, zombies included.
+    fn has_process(pid: usize) -> bool;
+
+    /// Pids of the processes, zombies included.
+    fn pids() -> alloc::vec::Vec<usize>;
+
+    fn maps(pid: usize) -> SysResult<alloc::string::String>;
+
+    fn smaps(pid: usize) -> SysResult<alloc::string::String>;
+
+    fn status(pid: usize) -> SysResult<alloc::string::String>;
+
+    fn statm(pid: usize) -> SysResult<alloc::string::String>;
+
+    /// Read the memory of process `pid` at `addr` for `/proc/<pid>/mem`,
+    /// which may return fewer bytes than `buf` holds.
+    fn read_mem(pid: usize, addr: usize, buf: &mut [u8]) -> SysResult<usize>;
+
+    /// Write the memory of process `pid` at `addr` for `/proc/<pid>/mem`.
+    fn write_mem(pid: usize, addr: usize, buf: &[u8]) -> SysResult<usize>;
+
+    fn oom_score(pid: usize) -> SysResult<usize>;
+
+    fn oom_score_adj(pid: usize) -> SysResult<i32>;
+
+    fn set_oom_score_adj(pid: usize, adj: i32) -> SysResult<()>;
 }
 
 pub struct ExeDentry {
@@ -0,0 +1,36 @@
+//! Files of `/proc/sys/vm`, tuning the overcommit policy of user memory.
+
+use alloc::{format, string::String};
+
+use memory::commit::{self, OvercommitMode};
+use systype::{SysError, SysResult};
+
+pub fn show_overcommit_memory() -> String {
+    format!("{}\n", commit::overcommit_mode() as u8)
+}
+
+pub fn store_overcommit_memory(buf: &[u8]) -> SysResult<()> {
+    let mode = parse_number(buf)?
+        .try_into()
+        .ok()
+        .and_then(OvercommitMode::from_repr)
+        .ok_or(SysError::EINVAL)?;
+    commit::set_overcommit_mode(mode);
+    Ok(())
+}
+
+pub fn show_overcommit_ratio() -> String {
+    format!("{}\n", commit::overcommit_ratio())
+}
+
+pub fn store_overcommit_ratio(buf: &[u8]) -> SysResult<()> {
+    commit::set_overcommit_ratio(parse_number(buf)?);
+    Ok(())
+}
+
+fn parse_number(buf: &[u8]) -> SysResult<usize> {
+    core::str::from_utf8(buf.trim_ascii())
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(SysError::EINVAL)
+}
@@ -142,9 +142,7 @@ impl File for SimpleFileFile {
                 page
             } else {
                 log::info!("[File::write_at] create new page");
-                let page = Page::new();
-                page_cache.insert_page(offset_aligned, page.clone());
-                page
+                page_cache.insert_page(offset_aligned, Page::new())
             };
             let len = (buf_it.len()).min(PAGE_SIZE - offset_in_page);
             page.bytes_array_range(offset_in_page..offset_in_page + len)
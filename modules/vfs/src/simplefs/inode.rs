@@ -59,7 +59,7 @@ impl Inode for SimpleFileInode {
             for offset_aligned in (offset_aligned_start..len).step_by(PAGE_SIZE) {
                 let page = Page::new();
                 page.fill_zero();
-                page_cache.insert_page(offset_aligned, page.clone());
+                page_cache.insert_page(offset_aligned, page);
             }
             self.set_size(len);
             Ok(())
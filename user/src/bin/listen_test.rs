@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::*;
+
+const LOCALHOST: [u8; 4] = [127, 0, 0, 1];
+
+fn tcp_socket(ty: i32) -> usize {
+    let fd = socket(AF_INET, SOCK_STREAM | ty, 0);
+    assert!(fd >= 0);
+    fd as usize
+}
+
+fn set_int(fd: usize, optname: i32, value: i32) {
+    assert_eq!(setsockopt(fd, SOL_SOCKET, optname, &value.to_ne_bytes()), 0);
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin listen test");
+    let eagain = -(SyscallErr::EAGAIN as isize);
+
+    // With a backlog of 1, two connections wait to be accepted and the SYNs of
+    // the others are dropped, so that their connect is still in progress.
+    let addr = SockAddrIn::new(LOCALHOST, 5601);
+    let listener = tcp_socket(SOCK_NONBLOCK);
+    assert_eq!(bind(listener, &addr), 0);
+    assert_eq!(listen(listener, 1), 0);
+    let clients = [0; 4].map(|_| {
+        let fd = tcp_socket(SOCK_NONBLOCK);
+        assert_eq!(connect(fd, &addr), -(SyscallErr::EINPROGRESS as isize));
+        // Let the handshake complete before the next SYN.
+        sleep(100);
+        fd
+    });
+    for &fd in &clients[2..] {
+        assert_eq!(connect(fd, &addr), -(SyscallErr::EALREADY as isize));
+    }
+    let accepted = [accept(listener), accept(listener)];
+    assert!(accepted.iter().all(|&fd| fd >= 0));
+    assert_eq!(accept(listener), eagain);
+    for fd in accepted {
+        close(fd as usize);
+    }
+    for fd in clients {
+        close(fd);
+    }
+
+    // A second bind to a listening port fails without SO_REUSEADDR.
+    let other = tcp_socket(0);
+    assert_eq!(bind(other, &addr), -(SyscallErr::EADDRINUSE as isize));
+    close(other);
+    close(listener);
+
+    // Listeners that all set SO_REUSEPORT share the port.
+    let addr = SockAddrIn::new(LOCALHOST, 5602);
+    let listeners = [0; 2].map(|_| {
+        let fd = tcp_socket(0);
+        set_int(fd, SO_REUSEPORT, 1);
+        assert_eq!(bind(fd, &addr), 0);
+        assert_eq!(listen(fd, 4), 0);
+        fd
+    });
+    for fd in listeners {
+        close(fd);
+    }
+    println!("listen pass.");
+    0
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::*;
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4;
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin mincore test");
+    let addr = mmap(
+        core::ptr::null(),
+        PAGES * PAGE_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        usize::MAX,
+        0,
+    );
+    assert!(addr > 0);
+    let base = addr as *mut u8;
+
+    // Only the pages written are resident.
+    unsafe {
+        base.write_volatile(1);
+        base.add(2 * PAGE_SIZE).write_volatile(1);
+    }
+    let mut vec = [0xffu8; PAGES];
+    assert_eq!(mincore(base, PAGES * PAGE_SIZE, &mut vec), 0);
+    println!("residency: {:?}", vec);
+    assert_eq!(vec.map(|page| page & 1), [1, 0, 1, 0]);
+
+    // A partial last page counts as a whole one.
+    let mut vec = [0xffu8; 1];
+    assert_eq!(mincore(base, 1, &mut vec), 0);
+    assert_eq!(vec[0] & 1, 1);
+
+    assert_eq!(
+        mincore(unsafe { base.add(1) }, PAGE_SIZE, &mut vec),
+        -(SyscallErr::EINVAL as isize)
+    );
+    // Ranges wrapping around or above the user space are not mapped.
+    assert_eq!(
+        mincore(base, usize::MAX - PAGE_SIZE, &mut vec),
+        -(SyscallErr::ENOMEM as isize)
+    );
+
+    // A hole in the range fails the whole call.
+    assert_eq!(munmap(unsafe { base.add(3 * PAGE_SIZE) }, PAGE_SIZE), 0);
+    let mut vec = [0xffu8; PAGES];
+    assert_eq!(
+        mincore(base, PAGES * PAGE_SIZE, &mut vec),
+        -(SyscallErr::ENOMEM as isize)
+    );
+    assert_eq!(vec, [0xff; PAGES]);
+
+    println!("mincore pass.");
+    0
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::{string::String, vec::Vec};
+use core::ptr;
+
+use user_lib::*;
+
+const PAGE_SIZE: usize = 4096;
+const OVERCOMMIT_MEMORY: &str = "/proc/sys/vm/overcommit_memory\0";
+const OVERCOMMIT_NEVER: &str = "2\n";
+
+fn read_file(path: &str) -> String {
+    let fd = openat(path, OpenFlags::O_RDONLY);
+    assert!(fd >= 0);
+    let mut content = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let n = read(fd as usize, &mut buf);
+        assert!(n >= 0);
+        if n == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..n as usize]);
+    }
+    close(fd as usize);
+    String::from_utf8(content).unwrap()
+}
+
+fn write_file(path: &str, content: &str) {
+    let fd = openat(path, OpenFlags::O_WRONLY);
+    assert!(fd >= 0);
+    assert_eq!(
+        write(fd as usize, content.as_bytes()),
+        content.len() as isize
+    );
+    close(fd as usize);
+}
+
+/// The value of `field` in `/proc/meminfo`, in KB.
+fn meminfo_kb(field: &str) -> usize {
+    read_file("/proc/meminfo\0")
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .unwrap()
+        .trim()
+        .trim_end_matches("KB")
+        .trim()
+        .parse()
+        .unwrap()
+}
+
+fn map_anonymous(len: usize) -> isize {
+    mmap(
+        ptr::null(),
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        usize::MAX,
+        0,
+    )
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin overcommit test");
+    let mode = read_file(OVERCOMMIT_MEMORY);
+
+    // A private writable mapping is charged when created, and given back on
+    // munmap, whether its pages are touched or not.
+    let len = 64 * PAGE_SIZE;
+    let committed = meminfo_kb("Committed_AS");
+    let addr = map_anonymous(len);
+    assert!(addr > 0);
+    assert_eq!(meminfo_kb("Committed_AS"), committed + len / 1024);
+    assert_eq!(munmap(addr as *const u8, len), 0);
+    assert_eq!(meminfo_kb("Committed_AS"), committed);
+
+    // In strict mode, a mapping larger than the commit limit is refused
+    // up front instead of failing on a page fault.
+    write_file(OVERCOMMIT_MEMORY, OVERCOMMIT_NEVER);
+    assert_eq!(read_file(OVERCOMMIT_MEMORY), OVERCOMMIT_NEVER);
+    let limit = meminfo_kb("CommitLimit") * 1024;
+    assert_eq!(
+        map_anonymous(limit + PAGE_SIZE),
+        -(SyscallErr::ENOMEM as isize)
+    );
+    assert_eq!(meminfo_kb("Committed_AS"), committed);
+
+    write_file(OVERCOMMIT_MEMORY, &mode);
+    println!("overcommit pass.");
+    0
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::*;
+
+const PROC_NET_DNS: &str = "/proc/net/dns\0";
+
+fn write_dns(conf: &str) -> isize {
+    let fd = openat(PROC_NET_DNS, OpenFlags::O_WRONLY);
+    assert!(fd >= 0);
+    let ret = write(fd as usize, conf.as_bytes());
+    close(fd as usize);
+    ret
+}
+
+fn read_dns(buf: &mut [u8]) -> &str {
+    let fd = openat(PROC_NET_DNS, OpenFlags::O_RDONLY);
+    assert!(fd >= 0);
+    let n = read(fd as usize, buf);
+    close(fd as usize);
+    assert!(n >= 0);
+    core::str::from_utf8(&buf[..n as usize]).unwrap()
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin /proc/net/dns test");
+    let conf = "# resolv.conf\nnameserver 1.1.1.1\nsearch example.com\n9.9.9.9\n";
+    assert_eq!(write_dns(conf), conf.len() as isize);
+    let mut buf = [0u8; 256];
+    assert_eq!(
+        read_dns(&mut buf),
+        "nameserver 1.1.1.1\nnameserver 9.9.9.9\n"
+    );
+
+    // The stub resolver on 127.0.0.1 would forward queries to itself.
+    let einval = -(SyscallErr::EINVAL as isize);
+    assert_eq!(write_dns("nameserver 127.0.0.1\n"), einval);
+    assert_eq!(write_dns("nameserver ::1\n"), einval);
+    assert_eq!(write_dns("nameserver not-an-address\n"), einval);
+    // Failed writes leave the servers alone.
+    assert_eq!(
+        read_dns(&mut buf),
+        "nameserver 1.1.1.1\nnameserver 9.9.9.9\n"
+    );
+
+    // Nothing but blank lines restores the servers given by DHCP, or the
+    // default one.
+    assert_eq!(write_dns("\n"), 1);
+    println!("{}", read_dns(&mut buf));
+    println!("/proc/net/dns pass.");
+    0
+}
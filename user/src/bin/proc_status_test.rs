@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::{format, string::String, vec::Vec};
+
+use user_lib::*;
+
+/// The value of `field` in `/proc/<pid>/status`.
+fn status_field(path: &str, field: &str) -> String {
+    let fd = openat(path, OpenFlags::O_RDONLY);
+    assert!(fd >= 0);
+    let mut content = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let n = read(fd as usize, &mut buf);
+        assert!(n >= 0);
+        if n == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..n as usize]);
+    }
+    close(fd as usize);
+    let content = core::str::from_utf8(&content).unwrap();
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .unwrap()
+        .trim()
+        .into()
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin proc status test");
+    // init has no parent.
+    assert_eq!(status_field("/proc/1/status\0", "Pid"), "1");
+    assert_eq!(status_field("/proc/1/status\0", "PPid"), "0");
+
+    let path = format!("/proc/{}/status\0", getpid());
+    assert_eq!(status_field(&path, "Pid"), format!("{}", getpid()));
+    let ppid = status_field(&path, "PPid");
+    assert_eq!(ppid.parse::<isize>().unwrap(), getppid());
+    println!("proc status pass.");
+    0
+}
@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+extern crate user_lib;
+
+use alloc::{format, vec::Vec};
+
+use user_lib::*;
+
+const PAGE_SIZE: usize = 4096;
+
+static mut VALUE: [u8; 8] = *b"parent\0\0";
+
+fn read_child(pid: usize, addr: usize, buf: &mut [u8]) -> isize {
+    let local = [IoVec {
+        base: buf.as_mut_ptr() as usize,
+        len: buf.len(),
+    }];
+    let remote = [IoVec {
+        base: addr,
+        len: buf.len(),
+    }];
+    process_vm_readv(pid, &local, &remote)
+}
+
+/// Start of the stack area of process `pid`, from `/proc/<pid>/maps`.
+fn stack_start(pid: usize) -> usize {
+    let fd = openat(&format!("/proc/{pid}/maps\0"), OpenFlags::O_RDONLY);
+    assert!(fd >= 0);
+    let mut content = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let n = read(fd as usize, &mut buf);
+        assert!(n >= 0);
+        if n == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..n as usize]);
+    }
+    close(fd as usize);
+    let line = core::str::from_utf8(&content)
+        .unwrap()
+        .lines()
+        .find(|line| line.ends_with("[stack]"))
+        .unwrap();
+    let (start, _) = line.split_once('-').unwrap();
+    usize::from_str_radix(start, 16).unwrap()
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin process_vm test");
+    // A page followed by a hole, in the child too.
+    let addr = mmap(
+        core::ptr::null(),
+        2 * PAGE_SIZE,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        usize::MAX,
+        0,
+    );
+    assert!(addr > 0);
+    let base = addr as usize;
+    assert_eq!(munmap((base + PAGE_SIZE) as *const u8, PAGE_SIZE), 0);
+    unsafe { (base as *mut u8).write_volatile(0x5a) };
+
+    let pid = fork();
+    if pid == 0 {
+        unsafe { (&raw mut VALUE).write_volatile(*b"child\0\0\0") };
+        sleep(1000);
+        exit(0);
+    }
+    assert!(pid > 0);
+    sleep(100);
+    let pid = pid as usize;
+
+    let mut buf = [0u8; 8];
+    assert_eq!(read_child(pid, &raw const VALUE as usize, &mut buf), 8);
+    assert_eq!(&buf, b"child\0\0\0");
+
+    // The read stops at the hole.
+    let mut buf = [0u8; 2];
+    assert_eq!(read_child(pid, base + PAGE_SIZE - 1, &mut buf), 1);
+    // Nothing is mapped for the hole, so nothing is read from it.
+    assert_eq!(
+        read_child(pid, base + PAGE_SIZE, &mut buf),
+        -(SyscallErr::EFAULT as isize)
+    );
+
+    // Reading below the stack does not grow it.
+    let stack = stack_start(pid);
+    assert_eq!(
+        read_child(pid, stack - PAGE_SIZE, &mut buf),
+        -(SyscallErr::EFAULT as isize)
+    );
+    assert_eq!(stack_start(pid), stack);
+
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid, &mut exit_code), pid as isize);
+    println!("process_vm pass.");
+    0
+}
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::*;
+
+const TRACEE_EXIT_CODE: i32 = 7;
+
+/// Attach to `tracee`, a sibling rather than a child, and follow it until it
+/// exits.
+fn tracer(tracee: usize) -> i32 {
+    assert_eq!(ptrace(PTRACE_ATTACH, tracee, 0, 0), 0);
+    let mut status = 0;
+    assert_eq!(waitpid(tracee, &mut status), tracee as isize);
+    // Stopped by SIGSTOP.
+    assert_eq!(status, (19 << 8) | 0x7f);
+    assert_eq!(ptrace(PTRACE_CONT, tracee, 0, 0), 0);
+
+    // The exit is reported to the tracer too.
+    assert_eq!(waitpid(tracee, &mut status), tracee as isize);
+    assert_eq!(status, TRACEE_EXIT_CODE << 8);
+    // Then it is neither a child nor a tracee.
+    assert_eq!(waitpid(tracee, &mut status), -(SyscallErr::ECHILD as isize));
+    0
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin ptrace test");
+    let tracee = fork();
+    if tracee == 0 {
+        sleep(500);
+        exit(TRACEE_EXIT_CODE);
+    }
+    assert!(tracee > 0);
+    let pid = fork();
+    if pid == 0 {
+        exit(tracer(tracee as usize));
+    }
+    assert!(pid > 0);
+
+    let mut status = 0;
+    assert_eq!(waitpid(pid as usize, &mut status), pid);
+    assert_eq!(status, 0);
+    // The parent still reaps the tracee.
+    assert_eq!(waitpid(tracee as usize, &mut status), tracee);
+    assert_eq!(status, TRACEE_EXIT_CODE << 8);
+    println!("ptrace pass.");
+    0
+}
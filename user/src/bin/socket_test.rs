@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::*;
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin socket test");
+    for (ty, protocol) in [
+        (SOCK_STREAM, 0),
+        (SOCK_STREAM, IPPROTO_TCP),
+        (SOCK_DGRAM, IPPROTO_UDP),
+    ] {
+        let fd = socket(AF_INET, ty, protocol);
+        assert!(fd >= 0);
+        close(fd as usize);
+    }
+    assert_eq!(
+        socket(AF_INET, SOCK_STREAM, IPPROTO_UDP),
+        -(SyscallErr::EPROTONOSUPPORT as isize)
+    );
+    // Known to the kernel, but not supported for inet sockets.
+    assert_eq!(
+        socket(AF_INET, SOCK_SEQPACKET, 0),
+        -(SyscallErr::ESOCKTNOSUPPORT as isize)
+    );
+    println!("socket pass.");
+    0
+}
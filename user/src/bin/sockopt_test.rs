@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use user_lib::*;
+
+fn get_int(fd: usize, level: i32, optname: i32) -> i32 {
+    let mut buf = [0u8; 4];
+    assert_eq!(getsockopt(fd, level, optname, &mut buf), 4);
+    i32::from_ne_bytes(buf)
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin sockopt test");
+    let fd = socket(AF_INET, SOCK_STREAM, 0);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    let einval = -(SyscallErr::EINVAL as isize);
+
+    // Options of SOL_SOCKET and IPPROTO_TCP take a whole int.
+    assert_eq!(setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, &[1]), einval);
+    assert_eq!(setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, &[1, 0, 0]), einval);
+    assert_eq!(
+        setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, &1i32.to_ne_bytes()),
+        0
+    );
+    assert_eq!(get_int(fd, SOL_SOCKET, SO_REUSEADDR), 1);
+    assert_eq!(setsockopt(fd, IPPROTO_TCP, TCP_NODELAY, &[1, 0]), einval);
+
+    // Those of IPPROTO_IP also take a single byte.
+    assert_eq!(setsockopt(fd, IPPROTO_IP, IP_TTL, &[32]), 0);
+    assert_eq!(get_int(fd, IPPROTO_IP, IP_TTL), 32);
+    assert_eq!(setsockopt(fd, IPPROTO_IP, IP_TTL, &[]), einval);
+
+    // struct linger { l_onoff, l_linger }
+    let mut linger = [0u8; 8];
+    linger[..4].copy_from_slice(&1i32.to_ne_bytes());
+    linger[4..].copy_from_slice(&5i32.to_ne_bytes());
+    assert_eq!(setsockopt(fd, SOL_SOCKET, SO_LINGER, &linger[..4]), einval);
+    assert_eq!(setsockopt(fd, SOL_SOCKET, SO_LINGER, &linger), 0);
+    let mut got = [0u8; 8];
+    assert_eq!(getsockopt(fd, SOL_SOCKET, SO_LINGER, &mut got), 8);
+    assert_eq!(got, linger);
+
+    close(fd);
+    println!("sockopt pass.");
+    0
+}
@@ -0,0 +1,179 @@
+#![no_std]
+#![no_main]
+
+extern crate user_lib;
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+};
+
+use user_lib::*;
+
+const PAGE_SIZE: usize = 4096;
+
+const UFFD_API: u64 = 0xaa;
+const UFFDIO_REGISTER_MODE_MISSING: u64 = 1;
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+
+const UFFDIO_API: usize = 0xc018aa3f;
+const UFFDIO_REGISTER: usize = 0xc020aa00;
+const UFFDIO_COPY: usize = 0xc028aa03;
+const UFFDIO_ZEROPAGE: usize = 0xc020aa04;
+
+#[repr(C)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+struct UffdioCopy {
+    dst: u64,
+    src: u64,
+    len: u64,
+    mode: u64,
+    copy: i64,
+}
+
+#[repr(C)]
+struct UffdioZeropage {
+    range: UffdioRange,
+    mode: u64,
+    zeropage: i64,
+}
+
+/// Addresses of the faults the handler read, and what resolving them returned.
+static FAULTS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+static COPIED: AtomicI64 = AtomicI64::new(0);
+static ZEROED: AtomicI64 = AtomicI64::new(0);
+static HANDLER_DONE: AtomicBool = AtomicBool::new(false);
+
+static mut HANDLER_STACK: [u8; 4 * PAGE_SIZE] = [0; 4 * PAGE_SIZE];
+
+fn map_anonymous(len: usize) -> *mut u8 {
+    let addr = mmap(
+        ptr::null(),
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        usize::MAX,
+        0,
+    );
+    assert!(addr > 0);
+    addr as *mut u8
+}
+
+/// Read a page fault from the userfaultfd, returns its address.
+fn read_fault(uffd: usize) -> u64 {
+    // struct uffd_msg
+    let mut msg = [0u8; 32];
+    assert_eq!(read(uffd, &mut msg), 32);
+    assert_eq!(msg[0], UFFD_EVENT_PAGEFAULT);
+    u64::from_ne_bytes(msg[16..24].try_into().unwrap())
+}
+
+/// Resolve the first fault with a copy of a page of 0x5a, and the second one
+/// with a zeroed page.
+extern "C" fn handler(uffd: usize) -> ! {
+    let src = map_anonymous(PAGE_SIZE);
+    unsafe { src.write_bytes(0x5a, PAGE_SIZE) };
+
+    let addr = read_fault(uffd);
+    FAULTS[0].store(addr, Ordering::SeqCst);
+    let mut copy = UffdioCopy {
+        dst: addr,
+        src: src as u64,
+        len: PAGE_SIZE as u64,
+        mode: 0,
+        copy: 0,
+    };
+    assert_eq!(ioctl(uffd, UFFDIO_COPY, &mut copy as *mut _ as usize), 0);
+    COPIED.store(copy.copy, Ordering::SeqCst);
+
+    let addr = read_fault(uffd);
+    FAULTS[1].store(addr, Ordering::SeqCst);
+    let mut zeropage = UffdioZeropage {
+        range: UffdioRange {
+            start: addr,
+            len: PAGE_SIZE as u64,
+        },
+        mode: 0,
+        zeropage: 0,
+    };
+    assert_eq!(
+        ioctl(uffd, UFFDIO_ZEROPAGE, &mut zeropage as *mut _ as usize),
+        0
+    );
+    ZEROED.store(zeropage.zeropage, Ordering::SeqCst);
+
+    HANDLER_DONE.store(true, Ordering::SeqCst);
+    exit(0)
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("begin userfaultfd test");
+    let uffd = userfaultfd(0);
+    assert!(uffd >= 0);
+    let uffd = uffd as usize;
+    let mut api = UffdioApi {
+        api: UFFD_API,
+        features: 0,
+        ioctls: 0,
+    };
+    assert_eq!(ioctl(uffd, UFFDIO_API, &mut api as *mut _ as usize), 0);
+
+    let area = map_anonymous(2 * PAGE_SIZE);
+    let mut reg = UffdioRegister {
+        range: UffdioRange {
+            start: area as u64,
+            len: 2 * PAGE_SIZE as u64,
+        },
+        mode: UFFDIO_REGISTER_MODE_MISSING,
+        ioctls: 0,
+    };
+    assert_eq!(ioctl(uffd, UFFDIO_REGISTER, &mut reg as *mut _ as usize), 0);
+    // UFFDIO_WAKE, UFFDIO_COPY and UFFDIO_ZEROPAGE work on the range.
+    assert_eq!(reg.ioctls, 0b11100);
+
+    #[allow(static_mut_refs)]
+    let stack = unsafe { &mut HANDLER_STACK };
+    assert!(spawn_thread(handler, uffd, stack) > 0);
+
+    // Each touch sleeps until the handler thread fills the page.
+    let first = unsafe { core::slice::from_raw_parts(area, PAGE_SIZE) };
+    assert_eq!(unsafe { ptr::read_volatile(area) }, 0x5a);
+    assert!(first.iter().all(|&byte| byte == 0x5a));
+    let second = unsafe { core::slice::from_raw_parts(area.add(PAGE_SIZE), PAGE_SIZE) };
+    assert_eq!(unsafe { ptr::read_volatile(area.add(PAGE_SIZE)) }, 0);
+    assert!(second.iter().all(|&byte| byte == 0));
+
+    while !HANDLER_DONE.load(Ordering::SeqCst) {
+        yield_();
+    }
+    assert_eq!(FAULTS[0].load(Ordering::SeqCst), area as u64);
+    assert_eq!(
+        FAULTS[1].load(Ordering::SeqCst),
+        area as u64 + PAGE_SIZE as u64
+    );
+    assert_eq!(COPIED.load(Ordering::SeqCst), PAGE_SIZE as i64);
+    assert_eq!(ZEROED.load(Ordering::SeqCst), PAGE_SIZE as i64);
+    close(uffd);
+    println!("userfaultfd pass.");
+    0
+}
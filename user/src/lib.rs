@@ -108,6 +108,9 @@ pub fn openat(path: &str, flags: OpenFlags) -> isize {
     // TODO: change to the version that has `mode` arg
     sys_openat(AT_FDCWD as usize, path.as_ptr(), flags.bits() as usize, 0)
 }
+pub fn ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_ioctl(fd, cmd, arg)
+}
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf.as_mut_ptr(), buf.len())
 }
@@ -131,6 +134,71 @@ pub fn mmap(
         offset,
     )
 }
+pub fn munmap(addr: *const u8, length: usize) -> isize {
+    sys_munmap(addr as usize, length)
+}
+pub fn mincore(addr: *const u8, length: usize, vec: &mut [u8]) -> isize {
+    sys_mincore(addr as usize, length, vec.as_mut_ptr())
+}
+pub fn userfaultfd(flags: usize) -> isize {
+    sys_userfaultfd(flags)
+}
+pub fn process_vm_readv(pid: usize, local_iov: &[IoVec], remote_iov: &[IoVec]) -> isize {
+    sys_process_vm_readv(
+        pid,
+        local_iov.as_ptr(),
+        local_iov.len(),
+        remote_iov.as_ptr(),
+        remote_iov.len(),
+        0,
+    )
+}
+
+//************ net ***************/
+pub fn socket(domain: i32, ty: i32, protocol: i32) -> isize {
+    sys_socket(domain as usize, ty as usize, protocol as usize)
+}
+pub fn bind(fd: usize, addr: &SockAddrIn) -> isize {
+    sys_bind(
+        fd,
+        addr as *const SockAddrIn as *const u8,
+        core::mem::size_of::<SockAddrIn>(),
+    )
+}
+pub fn listen(fd: usize, backlog: usize) -> isize {
+    sys_listen(fd, backlog)
+}
+pub fn accept(fd: usize) -> isize {
+    sys_accept(fd, core::ptr::null_mut(), core::ptr::null_mut())
+}
+pub fn connect(fd: usize, addr: &SockAddrIn) -> isize {
+    sys_connect(
+        fd,
+        addr as *const SockAddrIn as *const u8,
+        core::mem::size_of::<SockAddrIn>(),
+    )
+}
+pub fn setsockopt(fd: usize, level: i32, optname: i32, optval: &[u8]) -> isize {
+    sys_setsockopt(
+        fd,
+        level as usize,
+        optname as usize,
+        optval.as_ptr(),
+        optval.len(),
+    )
+}
+/// Returns the length of the value written to `optval`.
+pub fn getsockopt(fd: usize, level: i32, optname: i32, optval: &mut [u8]) -> isize {
+    let mut optlen = optval.len() as u32;
+    let ret = sys_getsockopt(
+        fd,
+        level as usize,
+        optname as usize,
+        optval.as_mut_ptr(),
+        &mut optlen,
+    );
+    if ret < 0 { ret } else { optlen as isize }
+}
 
 //************ task ***************/
 pub fn exit(exit_code: i32) -> ! {
@@ -149,6 +217,10 @@ pub fn getpid() -> isize {
     sys_getpid()
 }
 
+pub fn getppid() -> isize {
+    sys_getppid()
+}
+
 pub fn fork() -> isize {
     sys_fork()
 }
@@ -158,6 +230,17 @@ pub fn create_thread(flags: CloneFlags) -> isize {
     sys_clone(flags.bits() as _, stack.as_mut_ptr() as usize, 0, 0)
 }
 
+/// Run `entry` with `arg` in a new thread of this process on `stack`.
+pub fn spawn_thread(entry: extern "C" fn(usize) -> !, arg: usize, stack: &mut [u8]) -> isize {
+    let flags = CloneFlags::VM
+        | CloneFlags::FS
+        | CloneFlags::FILES
+        | CloneFlags::SIGHAND
+        | CloneFlags::THREAD;
+    let sp = (stack.as_mut_ptr() as usize + stack.len()) & !0xf;
+    sys_clone_entry(flags.bits() as usize, sp, entry, arg)
+}
+
 pub fn kill(pid: isize, sig: Sig) -> isize {
     sys_kill(pid as usize, sig.raw() as i32)
 }
@@ -180,6 +263,10 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     sys_waitpid(pid as isize, exit_code as *mut _)
 }
 
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    sys_ptrace(request, pid, addr, data)
+}
+
 pub fn pipe(pipe_fd: &mut [i32]) -> isize {
     sys_pipe(pipe_fd[0] as *mut _)
 }
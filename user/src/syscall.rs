@@ -1,5 +1,7 @@
 use core::arch::asm;
 
+use crate::IoVec;
+
 const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 23;
 const SYSCALL_DUP3: usize = 24;
@@ -49,6 +51,7 @@ const SYSCALL_CLOCK_GETTIME: usize = 113;
 const SYSCALL_CLOCK_GETRES: usize = 114;
 const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 const SYSCALL_SYSLOG: usize = 116;
+const SYSCALL_PTRACE: usize = 117;
 const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
 const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
 const SYSCALL_SCHED_GETPARAM: usize = 121;
@@ -102,11 +105,14 @@ const SYSCALL_EXECVE: usize = 221;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_MPROTECT: usize = 226;
 const SYSCALL_MSYNC: usize = 227;
+const SYSCALL_MINCORE: usize = 232;
 const SYSCALL_MADVISE: usize = 233;
 const SYSCALL_WAIT4: usize = 260;
 const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_PROCESS_VM_READV: usize = 270;
 const SYSCALL_REMANEAT2: usize = 276;
 const SYSCALL_GETRANDOM: usize = 278;
+const SYSCALL_USERFAULTFD: usize = 282;
 const SYSCALL_MEMBARRIER: usize = 283;
 const SYSCALL_COPY_FILE_RANGE: usize = 285;
 
@@ -209,6 +215,7 @@ syscall!(sys_mkdir, SYSCALL_MKDIR, *const u8);
 syscall!(sys_uname, SYSCALL_UNAME, *mut usize);
 syscall!(sys_dup, SYSCALL_DUP, usize);
 syscall!(sys_dup3, SYSCALL_DUP3, usize, usize, usize);
+syscall!(sys_ioctl, SYSCALL_IOCTL, usize, usize, usize);
 syscall!(sys_read, SYSCALL_READ, usize, *mut u8, usize);
 syscall!(sys_write, SYSCALL_WRITE, usize, *const u8, usize);
 syscall!(
@@ -222,17 +229,85 @@ syscall!(
     usize
 );
 syscall!(sys_openat, SYSCALL_OPEN, usize, *const u8, usize, usize);
+syscall!(sys_munmap, SYSCALL_MUNMAP, usize, usize);
+syscall!(sys_mincore, SYSCALL_MINCORE, usize, usize, *mut u8);
+syscall!(sys_userfaultfd, SYSCALL_USERFAULTFD, usize);
+syscall!(
+    sys_process_vm_readv,
+    SYSCALL_PROCESS_VM_READV,
+    usize,
+    *const IoVec,
+    usize,
+    *const IoVec,
+    usize,
+    usize
+);
+
+// net
+syscall!(sys_socket, SYSCALL_SOCKET, usize, usize, usize);
+syscall!(sys_bind, SYSCALL_BIND, usize, *const u8, usize);
+syscall!(sys_listen, SYSCALL_LISTEN, usize, usize);
+syscall!(sys_accept, SYSCALL_ACCEPT, usize, *mut u8, *mut u32);
+syscall!(sys_connect, SYSCALL_CONNECT, usize, *const u8, usize);
+syscall!(
+    sys_setsockopt,
+    SYSCALL_SETSOCKOPT,
+    usize,
+    usize,
+    usize,
+    *const u8,
+    usize
+);
+syscall!(
+    sys_getsockopt,
+    SYSCALL_GETSOCKOPT,
+    usize,
+    usize,
+    usize,
+    *mut u8,
+    *mut u32
+);
 
 // task
 syscall!(sys_getpid, SYSCALL_GETPID);
+syscall!(sys_getppid, SYSCALL_GETPPID);
 syscall!(sys_exit, SYSCALL_EXIT, i32);
 syscall!(sys_exit_group, SYSCALL_EXIT_GROUP, i32);
 syscall!(sys_kill, SYSCALL_KILL, usize, i32);
 syscall!(sys_fork, SYSCALL_CLONE);
 syscall!(sys_clone, SYSCALL_CLONE, usize, usize, usize, usize);
+
+/// Clone with stack `sp`, the child calls `entry` with `arg` there.
+pub fn sys_clone_entry(
+    flags: usize,
+    sp: usize,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            "bnez a0, 1f",
+            "mv a0, {arg}",
+            "jalr {entry}",
+            "1:",
+            entry = in(reg) entry,
+            arg = in(reg) arg,
+            inlateout("x10") flags => ret,
+            in("x11") sp,
+            in("x12") 0,
+            in("x13") 0,
+            in("x14") 0,
+            in("x17") SYSCALL_CLONE
+        );
+    }
+    ret
+}
 syscall!(sys_waitpid, SYSCALL_WAIT4, isize, *mut i32);
 syscall!(sys_pipe, SYSCALL_PIPE, *mut i32);
 syscall!(sys_brk, SYSCALL_BRK, usize);
+syscall!(sys_ptrace, SYSCALL_PTRACE, usize, usize, usize, usize);
 syscall!(sys_yield, SYSCALL_SCHED_YIELD);
 syscall!(
     sys_execve,
@@ -51,6 +51,57 @@ pub const FUTEX_WAKE: i32 = 1;
 pub const FUTEX_REQUEUE: i32 = 3;
 pub const FUTEX_CMP_REQUEUE: i32 = 4;
 
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_ATTACH: usize = 16;
+
+pub const PROT_READ: i32 = 0x1;
+pub const PROT_WRITE: i32 = 0x2;
+pub const MAP_PRIVATE: i32 = 0x02;
+pub const MAP_ANONYMOUS: i32 = 0x20;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct IoVec {
+    pub base: usize,
+    pub len: usize,
+}
+
+pub const AF_INET: i32 = 2;
+pub const SOCK_STREAM: i32 = 1;
+pub const SOCK_DGRAM: i32 = 2;
+pub const SOCK_SEQPACKET: i32 = 5;
+pub const SOCK_NONBLOCK: i32 = 0x800;
+pub const IPPROTO_TCP: i32 = 6;
+pub const IPPROTO_UDP: i32 = 17;
+pub const IPPROTO_IP: i32 = 0;
+pub const SOL_SOCKET: i32 = 1;
+pub const SO_REUSEADDR: i32 = 2;
+pub const SO_LINGER: i32 = 13;
+pub const SO_REUSEPORT: i32 = 15;
+pub const IP_TTL: i32 = 2;
+pub const TCP_NODELAY: i32 = 1;
+
+/// `struct sockaddr_in`, with the port and address in network byte order.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SockAddrIn {
+    pub family: u16,
+    pub port: [u8; 2],
+    pub addr: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+impl SockAddrIn {
+    pub fn new(addr: [u8; 4], port: u16) -> Self {
+        Self {
+            family: AF_INET as u16,
+            port: port.to_be_bytes(),
+            addr,
+            zero: [0; 8],
+        }
+    }
+}
+
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
     /// Defined in <bits/sched.h>